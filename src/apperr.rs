@@ -8,6 +8,18 @@ use std::{io, result};
 use crate::sys;
 
 pub const APP_ICU_MISSING: Error = Error::new_app(0);
+/// A swap file record can't be larger than 4GiB.
+pub const APP_SWAP_RECORD_TOO_LARGE: Error = Error::new_app(1);
+/// The file isn't a session file.
+pub const APP_SESSION_INVALID: Error = Error::new_app(2);
+/// The file isn't a prompt history file.
+pub const APP_HISTORY_INVALID: Error = Error::new_app(3);
+/// The file isn't a recent files list.
+pub const APP_RECENT_FILES_INVALID: Error = Error::new_app(4);
+/// The file is too large to be loaded and was opened read-only.
+pub const APP_READ_ONLY_STREAMING: Error = Error::new_app(5);
+/// Unsaved changes were recovered from a swap file. Undoing discards them.
+pub const APP_SWAP_RECOVERED: Error = Error::new_app(6);
 
 /// Edit's transparent `Result` type.
 pub type Result<T> = result::Result<T, Error>;
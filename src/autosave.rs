@@ -0,0 +1,224 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Bookkeeping for saving dirty documents automatically after a period of inactivity.
+//!
+//! The main loop reports the state of each document via [`Autosave::observe`] once per frame,
+//! sleeps until [`Autosave::next_deadline`] at the latest, and then calls [`Autosave::fire_due`].
+//! Time is passed in explicitly, so that all of this can be tested without waiting.
+//!
+//! Whether a document is dirty comes from [`TextBuffer::is_dirty`](crate::buffer::TextBuffer::is_dirty),
+//! which compares the buffer generation against the one it was saved at. Since undo restores
+//! the generation, undoing back to the saved state makes the document clean again,
+//! and its pending autosave is canceled.
+
+use std::time::{Duration, Instant};
+
+use crate::apperr;
+
+struct Entry<K> {
+    key: K,
+    enabled: bool,
+    /// The generation at the last `observe` call.
+    generation: Option<u32>,
+    deadline: Option<Instant>,
+}
+
+/// Autosave timers for a set of documents, identified by `K`.
+pub struct Autosave<K> {
+    delay: Duration,
+    entries: Vec<Entry<K>>,
+}
+
+impl<K: Copy + PartialEq> Autosave<K> {
+    /// Documents are saved once they haven't been edited for `delay`.
+    pub fn new(delay: Duration) -> Self {
+        Self { delay, entries: Vec::new() }
+    }
+
+    /// Enables or disables autosave for the document `key`. It's disabled by default.
+    pub fn set_enabled(&mut self, key: K, enabled: bool) {
+        let entry = self.entry(key);
+        entry.enabled = enabled;
+        entry.generation = None;
+        entry.deadline = None;
+    }
+
+    pub fn is_enabled(&self, key: K) -> bool {
+        self.entries.iter().any(|e| e.key == key && e.enabled)
+    }
+
+    /// Forgets about the document `key`, for instance once it was closed.
+    pub fn remove(&mut self, key: K) {
+        self.entries.retain(|e| e.key != key);
+    }
+
+    /// Reports the current `generation` of the document `key` and whether it's `dirty`.
+    ///
+    /// Each change of the generation restarts the timer, as long as the document is dirty.
+    /// If it's clean, for instance because it was saved or the edits were undone, the timer stops.
+    pub fn observe(&mut self, key: K, generation: u32, dirty: bool, now: Instant) {
+        let delay = self.delay;
+        let entry = self.entry(key);
+        if !entry.enabled {
+            return;
+        }
+
+        if !dirty {
+            entry.deadline = None;
+        } else if entry.generation != Some(generation) {
+            entry.deadline = Some(now + delay);
+        }
+        entry.generation = Some(generation);
+    }
+
+    /// The earliest time at which [`Autosave::fire_due`] has something to do.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.entries.iter().filter_map(|e| e.deadline).min()
+    }
+
+    /// Calls `save` for every document whose timer has expired.
+    ///
+    /// If saving fails, the document stays dirty and is retried after another delay.
+    /// The errors are returned, so that they can be shown to the user.
+    pub fn fire_due(
+        &mut self,
+        now: Instant,
+        mut save: impl FnMut(K) -> apperr::Result<()>,
+    ) -> Vec<(K, apperr::Error)> {
+        let mut errors = Vec::new();
+
+        for entry in &mut self.entries {
+            if entry.deadline.is_none_or(|d| d > now) {
+                continue;
+            }
+
+            entry.deadline = None;
+            if let Err(err) = save(entry.key) {
+                entry.deadline = Some(now + self.delay);
+                errors.push((entry.key, err));
+            }
+        }
+
+        errors
+    }
+
+    fn entry(&mut self, key: K) -> &mut Entry<K> {
+        let i = match self.entries.iter().position(|e| e.key == key) {
+            Some(i) => i,
+            None => {
+                self.entries.push(Entry { key, enabled: false, generation: None, deadline: None });
+                self.entries.len() - 1
+            }
+        };
+        &mut self.entries[i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io;
+
+    use super::*;
+    use crate::buffer::TextBuffer;
+    use crate::testing::TempDir;
+
+    const DELAY: Duration = Duration::from_secs(5);
+
+    fn observe(autosave: &mut Autosave<usize>, tb: &TextBuffer, now: Instant) {
+        autosave.observe(0, tb.generation(), tb.is_dirty(), now);
+    }
+
+    #[test]
+    fn test_debounce() {
+        let t0 = Instant::now();
+        let mut tb = TextBuffer::new(false).unwrap();
+        let mut autosave = Autosave::new(DELAY);
+        autosave.set_enabled(0, true);
+
+        observe(&mut autosave, &tb, t0);
+        assert_eq!(autosave.next_deadline(), None);
+
+        tb.write_raw(b"a");
+        observe(&mut autosave, &tb, t0);
+        assert_eq!(autosave.next_deadline(), Some(t0 + DELAY));
+
+        // Nothing changed, so the timer keeps running...
+        observe(&mut autosave, &tb, t0 + Duration::from_secs(2));
+        assert_eq!(autosave.next_deadline(), Some(t0 + DELAY));
+
+        // ...until another edit restarts it.
+        tb.write_raw(b"b");
+        let t1 = t0 + Duration::from_secs(3);
+        observe(&mut autosave, &tb, t1);
+        assert_eq!(autosave.next_deadline(), Some(t1 + DELAY));
+
+        let mut saved = 0;
+        let mut save = |_| {
+            saved += 1;
+            Ok(())
+        };
+        assert!(autosave.fire_due(t0 + DELAY, &mut save).is_empty());
+        assert!(autosave.fire_due(t1 + DELAY, &mut save).is_empty());
+        assert_eq!(saved, 1);
+        assert_eq!(autosave.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_undo_to_clean() {
+        let t0 = Instant::now();
+        let mut tb = TextBuffer::new(false).unwrap();
+        tb.write_raw(b"hello");
+        let dir = TempDir::new("autosave");
+        let path = dir.join("file.txt");
+        tb.write_file(&mut File::create(&path).unwrap()).unwrap();
+
+        let mut autosave = Autosave::new(DELAY);
+        autosave.set_enabled(0, true);
+
+        tb.write_raw(b"!");
+        observe(&mut autosave, &tb, t0);
+        assert!(autosave.next_deadline().is_some());
+
+        // Undoing the edit returns to the saved state, which cancels the autosave.
+        tb.undo();
+        assert!(!tb.is_dirty());
+        observe(&mut autosave, &tb, t0);
+        assert_eq!(autosave.next_deadline(), None);
+
+        // Redoing it makes the document dirty again.
+        tb.redo();
+        observe(&mut autosave, &tb, t0);
+        assert!(autosave.next_deadline().is_some());
+    }
+
+    #[test]
+    fn test_save_failure() {
+        let t0 = Instant::now();
+        let mut tb = TextBuffer::new(false).unwrap();
+        let mut autosave = Autosave::new(DELAY);
+        autosave.set_enabled(0, true);
+
+        tb.write_raw(b"a");
+        observe(&mut autosave, &tb, t0);
+
+        let t1 = t0 + DELAY;
+        let errors = autosave.fire_due(t1, |_| {
+            // A file opened for reading can't be written to.
+            let mut file = File::open(std::env::current_exe()?)?;
+            tb.write_file(&mut file)
+        });
+        assert_eq!(errors.len(), 1);
+        assert!(tb.is_dirty());
+        assert_eq!(autosave.next_deadline(), Some(t1 + DELAY));
+
+        // Disabled documents aren't tracked.
+        autosave.set_enabled(0, false);
+        assert_eq!(autosave.next_deadline(), None);
+        tb.write_raw(b"b");
+        observe(&mut autosave, &tb, t1);
+        assert_eq!(autosave.next_deadline(), None);
+        assert!(autosave.fire_due(t1 + DELAY, |_| Err(io::Error::other("").into())).is_empty());
+    }
+}
@@ -0,0 +1,219 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Keeping the previous version of a file around when saving over it.
+//!
+//! Backups are either a single file with a suffix (`foo.txt~`) or numbered
+//! (`foo.txt.~1~` for the most recent one, up to a limit). If the saved path is a symlink,
+//! the backup is placed next to the link, but contains the contents of the file it points to.
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{apperr, sys};
+
+/// How backups are named.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackupMode {
+    /// A single backup named after the file plus the suffix, e.g. `~` or `.bak`.
+    Simple(String),
+    /// Numbered backups `.~1~` to `.~N~`, with `.~1~` being the most recent one.
+    Numbered(usize),
+}
+
+/// The result of [`backup_before_save`].
+#[derive(Debug)]
+pub enum BackupOutcome {
+    /// There was no file to back up.
+    NotNeeded,
+    Created(PathBuf),
+    /// The backup couldn't be created. This shouldn't prevent the save,
+    /// but the user should be told about it.
+    Failed(apperr::Error),
+}
+
+/// Backs up the file at `path` before it gets overwritten.
+///
+/// `save_replaces_file` says whether the save writes a new file and renames it over the old one.
+/// In that case the old file can simply be hard-linked, which is cheaper than copying it.
+/// Otherwise, or if the file system doesn't support hard links, it's copied.
+pub fn backup_before_save(
+    path: &Path,
+    mode: &BackupMode,
+    save_replaces_file: bool,
+) -> BackupOutcome {
+    // Back up the contents, not the symlink.
+    let source = match sys::canonicalize(path) {
+        Ok(source) => source,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return BackupOutcome::NotNeeded,
+        Err(err) => return BackupOutcome::Failed(err.into()),
+    };
+
+    match create_backup(path, &source, mode, save_replaces_file) {
+        Ok(backup) => BackupOutcome::Created(backup),
+        Err(err) => BackupOutcome::Failed(err),
+    }
+}
+
+fn create_backup(
+    path: &Path,
+    source: &Path,
+    mode: &BackupMode,
+    save_replaces_file: bool,
+) -> apperr::Result<PathBuf> {
+    // The new backup is created under a temporary name first, so that
+    // the existing ones stay untouched if that fails.
+    let tmp = with_suffix(path, &format!(".~{}.tmp~", std::process::id()));
+    remove_if_exists(&tmp)?;
+    let res = if save_replaces_file && fs::hard_link(source, &tmp).is_ok() {
+        Ok(())
+    } else {
+        fs::copy(source, &tmp).map(|_| ())
+    };
+    if let Err(err) = res {
+        _ = fs::remove_file(&tmp);
+        return Err(err.into());
+    }
+
+    let backup = match mode {
+        BackupMode::Simple(suffix) => with_suffix(path, suffix),
+        BackupMode::Numbered(limit) => {
+            let limit = (*limit).max(1);
+            let numbered = |n: usize| with_suffix(path, &format!(".~{n}~"));
+
+            // Make room for the new one by shifting all others up, dropping the oldest.
+            let mut res = remove_if_exists(&numbered(limit));
+            for n in (1..limit).rev() {
+                if res.is_err() {
+                    break;
+                }
+                res = match fs::rename(numbered(n), numbered(n + 1)) {
+                    Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+                    _ => Ok(()),
+                };
+            }
+            if let Err(err) = res {
+                _ = fs::remove_file(&tmp);
+                return Err(err);
+            }
+
+            numbered(1)
+        }
+    };
+
+    if let Err(err) = fs::rename(&tmp, &backup) {
+        _ = fs::remove_file(&tmp);
+        return Err(err.into());
+    }
+    Ok(backup)
+}
+
+fn remove_if_exists(path: &Path) -> apperr::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    /// Backs up `path` and then "saves" `contents` to it.
+    fn save(path: &Path, mode: &BackupMode, contents: &str) -> BackupOutcome {
+        let outcome = backup_before_save(path, mode, false);
+        fs::write(path, contents).unwrap();
+        outcome
+    }
+
+    #[test]
+    fn test_simple() {
+        let dir = TempDir::new("backup-simple");
+        let path = dir.join("foo.txt");
+        let mode = BackupMode::Simple("~".to_string());
+
+        assert!(matches!(save(&path, &mode, "1"), BackupOutcome::NotNeeded));
+        assert!(
+            matches!(save(&path, &mode, "2"), BackupOutcome::Created(p) if p == dir.join("foo.txt~"))
+        );
+        save(&path, &mode, "3");
+        assert_eq!(fs::read_to_string(dir.join("foo.txt~")).unwrap(), "2");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "3");
+
+        // A save that renames a new file over the old one leaves a hard-linked backup intact.
+        let mode = BackupMode::Simple(".bak".to_string());
+        backup_before_save(&path, &mode, true);
+        let tmp = dir.join("foo.txt.tmp");
+        fs::write(&tmp, "4").unwrap();
+        fs::rename(&tmp, &path).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("foo.txt.bak")).unwrap(), "3");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "4");
+    }
+
+    #[test]
+    fn test_numbered() {
+        let dir = TempDir::new("backup-numbered");
+        let path = dir.join("foo.txt");
+        let mode = BackupMode::Numbered(3);
+
+        for i in 0..6 {
+            save(&path, &mode, &i.to_string());
+        }
+
+        let read = |n: usize| fs::read_to_string(dir.join(format!("foo.txt.~{n}~"))).ok();
+        assert_eq!(read(1).as_deref(), Some("4"));
+        assert_eq!(read(2).as_deref(), Some("3"));
+        assert_eq!(read(3).as_deref(), Some("2"));
+        assert_eq!(read(4), None);
+    }
+
+    #[test]
+    fn test_failure() {
+        let dir = TempDir::new("backup-failure");
+        let path = dir.join("foo.txt");
+        fs::write(&path, "1").unwrap();
+
+        // The backup would have to be inside the file, which can't work.
+        let mode = BackupMode::Simple("/backup".to_string());
+        assert!(matches!(save(&path, &mode, "2"), BackupOutcome::Failed(_)));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "2");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // If the new backup can't be created, the existing ones are left as they were.
+        let mode = BackupMode::Numbered(3);
+        save(&path, &mode, "3");
+        fs::remove_file(&path).unwrap();
+        fs::create_dir(&path).unwrap();
+        assert!(matches!(backup_before_save(&path, &mode, false), BackupOutcome::Failed(_)));
+        assert_eq!(fs::read_to_string(dir.join("foo.txt.~1~")).unwrap(), "2");
+        assert!(!dir.join("foo.txt.~2~").exists());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink() {
+        let dir = TempDir::new("backup-symlink");
+        let target = dir.join("target.txt");
+        let link = dir.join("link.txt");
+        fs::write(&target, "1").unwrap();
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        let mode = BackupMode::Simple("~".to_string());
+        backup_before_save(&link, &mode, false);
+        fs::write(&link, "2").unwrap();
+
+        let backup = dir.join("link.txt~");
+        assert!(!fs::symlink_metadata(&backup).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "1");
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+    }
+}
@@ -77,9 +77,62 @@ pub fn encode(dst: &mut ArenaString, src: &[u8]) {
     }
 }
 
+/// Decodes the given base64 string. Whitespace is ignored and padding is optional.
+///
+/// Returns `None` if `src` contains any other characters or isn't validly terminated.
+pub fn decode(src: &[u8]) -> Option<Vec<u8>> {
+    let mut dst = Vec::with_capacity(src.len() / 4 * 3 + 3);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut count = 0;
+    let mut padding = 0;
+
+    for &c in src {
+        let val = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => {
+                padding += 1;
+                continue;
+            }
+            b' ' | b'\t' | b'\r' | b'\n' => continue,
+            _ => return None,
+        };
+
+        // Data after padding.
+        if padding != 0 {
+            return None;
+        }
+
+        acc = acc << 6 | val as u32;
+        bits += 6;
+        count += 1;
+        if bits >= 8 {
+            bits -= 8;
+            dst.push((acc >> bits) as u8);
+        }
+    }
+
+    // A single leftover character can't encode a full byte,
+    // and if there's padding, it must fill up the last group of 4.
+    let valid = match count % 4 {
+        0 => padding == 0,
+        1 => false,
+        rem => padding == 0 || padding == 4 - rem,
+    };
+    if !valid {
+        return None;
+    }
+
+    Some(dst)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::encode;
+    use super::{decode, encode};
     use crate::arena::{Arena, ArenaString};
 
     #[test]
@@ -118,4 +171,33 @@ mod tests {
         assert_eq!(enc(b"abcdefghijklmNOPQRSTUVWXY"), "YWJjZGVmZ2hpamtsbU5PUFFSU1RVVldYWQ==");
         assert_eq!(enc(b"abcdefghijklmNOPQRSTUVWXYZ"), "YWJjZGVmZ2hpamtsbU5PUFFSU1RVVldYWVo=");
     }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(b"").unwrap(), b"");
+        assert_eq!(decode(b"YQ==").unwrap(), b"a");
+        assert_eq!(decode(b"YWI=").unwrap(), b"ab");
+        assert_eq!(decode(b"YWJj").unwrap(), b"abc");
+        assert_eq!(decode(b"YWJjZA").unwrap(), b"abcd");
+        assert_eq!(decode(b"YWJj\r\nZGVm").unwrap(), b"abcdef");
+        assert_eq!(decode(b"+/+/").unwrap(), [0xfb, 0xff, 0xbf]);
+
+        assert_eq!(decode(b"YWJjZ"), None);
+        assert_eq!(decode(b"YQ==YQ=="), None);
+        assert_eq!(decode(b"YQ==="), None);
+        assert_eq!(decode(b"YWI=="), None);
+        assert_eq!(decode(b"YW!j"), None);
+        assert_eq!(decode(b"YW\x1bj"), None);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let arena = Arena::new(4 * 1024).unwrap();
+        let data: Vec<u8> = (0..=255).collect();
+        for len in 0..data.len() {
+            let mut dst = ArenaString::new_in(&arena);
+            encode(&mut dst, &data[..len]);
+            assert_eq!(decode(dst.as_bytes()).unwrap(), &data[..len]);
+        }
+    }
 }
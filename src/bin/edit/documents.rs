@@ -2,13 +2,17 @@
 // Licensed under the MIT License.
 
 use std::collections::LinkedList;
-use std::ffi::OsStr;
 use std::fs::File;
+use std::mem;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
+use edit::backup::{BackupOutcome, backup_before_save};
 use edit::buffer::{RcTextBuffer, TextBuffer};
-use edit::helpers::{CoordType, Point};
-use edit::{apperr, path, sys};
+use edit::config::Config;
+use edit::languages::Languages;
+use edit::swap::{self, SwapWriter};
+use edit::{apperr, path, position, save_policy, sys};
 
 use crate::state::DisplayablePathBuf;
 
@@ -19,16 +23,39 @@ pub struct Document {
     pub filename: String,
     pub file_id: Option<sys::FileId>,
     pub new_file_counter: usize,
+    /// Shared with the [`DocumentManager`], for looking up the language when saving.
+    languages: Rc<Languages>,
+    /// Receives the unsaved changes, so that they can be recovered after a crash.
+    /// It's created on the first change after opening or saving the document.
+    swap: Option<SwapWriter>,
+    /// Whether unsaved changes were recovered from a swap file and the user wasn't told yet.
+    recovered: bool,
 }
 
 impl Document {
-    pub fn save(&mut self, new_path: Option<PathBuf>) -> apperr::Result<()> {
+    /// Saves the document, backing up the file it overwrites if enabled.
+    /// A backup that couldn't be created doesn't fail the save, but is returned instead.
+    pub fn save(&mut self, new_path: Option<PathBuf>) -> apperr::Result<BackupOutcome> {
         let path = new_path.as_deref().unwrap_or_else(|| self.path.as_ref().unwrap().as_path());
+
+        // The file is overwritten in place, so it must be backed up before it's opened.
+        // Like in `apply_save_policy`, there's no config file yet, so this uses the defaults.
+        let backup = match &Config::default().global.backup {
+            Some(mode) => backup_before_save(path, mode, false),
+            None => BackupOutcome::NotNeeded,
+        };
+
         let mut file = DocumentManager::open_for_writing(path)?;
 
         {
             let mut tb = self.buffer.borrow_mut();
+            apply_save_policy(&mut tb, path, &self.languages);
             tb.write_file(&mut file)?;
+            // The changes are safe now, including those made by the save policy.
+            tb.take_journal();
+        }
+        if let Some(swap) = self.swap.take() {
+            _ = swap.remove();
         }
 
         if let Ok(id) = sys::file_id(None, path) {
@@ -39,7 +66,7 @@ impl Document {
             self.set_path(path);
         }
 
-        Ok(())
+        Ok(backup)
     }
 
     pub fn reread(&mut self, encoding: Option<&'static str>) -> apperr::Result<()> {
@@ -49,6 +76,11 @@ impl Document {
         {
             let mut tb = self.buffer.borrow_mut();
             tb.read_file(&mut file, encoding)?;
+            // The document matches the file again.
+            tb.take_journal();
+        }
+        if let Some(swap) = self.swap.take() {
+            _ = swap.remove();
         }
 
         if let Ok(id) = sys::file_id(None, path) {
@@ -58,12 +90,36 @@ impl Document {
         Ok(())
     }
 
+    /// Appends the changes since the last call to the swap file, creating it if needed.
+    /// If that fails, the swap file is abandoned and the error returned.
+    fn update_swap(&mut self) -> apperr::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut tb = self.buffer.borrow_mut();
+        let edits = tb.take_journal();
+        if edits.is_empty() {
+            return Ok(());
+        }
+
+        let res = match &mut self.swap {
+            Some(swap) => swap.append_journal(&edits),
+            None => create_swap(&tb, path).map(|swap| self.swap = Some(swap)),
+        };
+        if res.is_err() {
+            tb.set_journaling(false);
+            self.swap = None;
+        }
+        res
+    }
+
     fn set_path(&mut self, path: PathBuf) {
         let filename = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
         let dir = path.parent().map(ToOwned::to_owned).unwrap_or_default();
         self.filename = filename;
         self.dir = Some(DisplayablePathBuf::from_path(dir));
         self.path = Some(path);
+        self.buffer.borrow_mut().set_journaling(true);
         self.update_file_mode();
     }
 
@@ -73,9 +129,74 @@ impl Document {
     }
 }
 
-#[derive(Default)]
+/// Converts newlines, the encoding and the final newline as described in [`save_policy`].
+/// There's no config file yet, so only the built-in defaults and the document's directives apply.
+fn apply_save_policy(tb: &mut TextBuffer, path: &Path, languages: &Languages) {
+    let mut first_line = Vec::new();
+    while first_line.len() < 256 {
+        let chunk = tb.read_forward(first_line.len());
+        let Some(&c) = chunk.first() else {
+            break;
+        };
+        if c == b'\n' {
+            break;
+        }
+        let len = chunk.iter().position(|&c| c == b'\n').unwrap_or(chunk.len());
+        first_line.extend_from_slice(&chunk[..len]);
+    }
+
+    let language = languages.language_for_path(path, &first_line).map(|l| l.name.as_str());
+    let (policy, _) = save_policy::resolve(&Config::default(), language, tb);
+    // If the encoding can't represent the text, it's kept instead of failing the save.
+    _ = tb.apply_save_policy(&policy);
+}
+
+/// Creates the swap file for the document at `path`, starting with its current contents.
+fn create_swap(tb: &TextBuffer, path: &Path) -> apperr::Result<SwapWriter> {
+    let mut text = Vec::new();
+    loop {
+        let chunk = tb.read_forward(text.len());
+        if chunk.is_empty() {
+            break;
+        }
+        text.extend_from_slice(chunk);
+    }
+
+    let mut swap = SwapWriter::create(&swap::swap_path_for(path))?;
+    swap.append_snapshot(&text)?;
+    Ok(swap)
+}
+
+/// Applies the changes in the swap file of `path` that weren't saved, if any.
+/// They form a single undo step, so that the user can easily discard them.
+/// Returns whether there was anything to recover.
+fn recover_swap(tb: &mut TextBuffer, path: &Path) -> bool {
+    let swap_path = swap::swap_path_for(path);
+    let Ok(Some(recovery)) = swap::recover(&swap_path) else {
+        return false;
+    };
+
+    let generation = tb.generation();
+    tb.reload_from_bytes(&recovery.text);
+    if tb.generation() == generation {
+        // The changes were saved after all.
+        _ = std::fs::remove_file(&swap_path);
+        return false;
+    }
+    tb.mark_as_dirty();
+    true
+}
+
 pub struct DocumentManager {
     list: LinkedList<Document>,
+    /// Building them isn't free, so it's done once instead of on every save.
+    languages: Rc<Languages>,
+}
+
+impl Default for DocumentManager {
+    fn default() -> Self {
+        Self { list: LinkedList::new(), languages: Rc::new(Languages::new()) }
+    }
 }
 
 impl DocumentManager {
@@ -108,8 +229,28 @@ impl DocumentManager {
         false
     }
 
+    /// Closes the active document. Its unsaved changes are discarded, including its swap file.
     pub fn remove_active(&mut self) {
-        self.list.pop_front();
+        if let Some(doc) = self.list.pop_front()
+            && let Some(swap) = doc.swap
+        {
+            _ = swap.remove();
+        }
+    }
+
+    /// Appends the changes of all documents to their swap files.
+    /// Returns the problems and notices, like recovered changes, to show to the user.
+    pub fn update_swap_files(&mut self) -> Vec<apperr::Error> {
+        let mut errors = Vec::new();
+        for doc in &mut self.list {
+            if mem::take(&mut doc.recovered) {
+                errors.push(apperr::APP_SWAP_RECOVERED);
+            }
+            if let Err(err) = doc.update_swap() {
+                errors.push(err);
+            }
+        }
+        errors
     }
 
     pub fn add_untitled(&mut self) -> apperr::Result<&mut Document> {
@@ -121,6 +262,9 @@ impl DocumentManager {
             filename: Default::default(),
             file_id: None,
             new_file_counter: 0,
+            languages: self.languages.clone(),
+            swap: None,
+            recovered: false,
         };
         self.gen_untitled_name(&mut doc);
 
@@ -140,7 +284,7 @@ impl DocumentManager {
     }
 
     pub fn add_file_path(&mut self, path: &Path) -> apperr::Result<&mut Document> {
-        let (path, goto) = Self::parse_filename_goto(path);
+        let (path, goto) = position::parse_path_position(path);
         let path = path::normalize(path);
 
         let mut file = match Self::open_for_reading(&path) {
@@ -161,16 +305,21 @@ impl DocumentManager {
         }
 
         let buffer = Self::create_buffer()?;
+        let recovered;
+        let swap;
         {
+            let mut tb = buffer.borrow_mut();
             if let Some(file) = &mut file {
-                let mut tb = buffer.borrow_mut();
                 tb.read_file(file, None)?;
+            }
+            recovered = recover_swap(&mut tb, &path);
+            // Start a new swap file right away, so that it's removed if the changes are discarded.
+            swap = if recovered { create_swap(&tb, &path).ok() } else { None };
 
-                if let Some(goto) = goto
-                    && goto != Default::default()
-                {
-                    tb.cursor_move_to_logical(goto);
-                }
+            if let Some(goto) = goto
+                && goto != Default::default()
+            {
+                tb.cursor_move_to_logical(goto);
             }
         }
 
@@ -181,6 +330,9 @@ impl DocumentManager {
             filename: Default::default(),
             file_id,
             new_file_counter: 0,
+            languages: self.languages.clone(),
+            swap,
+            recovered,
         };
         doc.set_path(path);
 
@@ -223,93 +375,4 @@ impl DocumentManager {
         }
         Ok(buffer)
     }
-
-    // Parse a filename in the form of "filename:line:char".
-    // Returns the position of the first colon and the line/char coordinates.
-    fn parse_filename_goto(path: &Path) -> (&Path, Option<Point>) {
-        fn parse(s: &[u8]) -> Option<CoordType> {
-            if s.is_empty() {
-                return None;
-            }
-
-            let mut num: CoordType = 0;
-            for &b in s {
-                if !b.is_ascii_digit() {
-                    return None;
-                }
-                let digit = (b - b'0') as CoordType;
-                num = num.checked_mul(10)?.checked_add(digit)?;
-            }
-            Some(num)
-        }
-
-        fn find_colon_rev(bytes: &[u8], offset: usize) -> Option<usize> {
-            (0..offset.min(bytes.len())).rev().find(|&i| bytes[i] == b':')
-        }
-
-        let bytes = path.as_os_str().as_encoded_bytes();
-        let colend = match find_colon_rev(bytes, bytes.len()) {
-            // Reject filenames that would result in an empty filename after stripping off the :line:char suffix.
-            // For instance, a filename like ":123:456" will not be processed by this function.
-            Some(colend) if colend > 0 => colend,
-            _ => return (path, None),
-        };
-
-        let last = match parse(&bytes[colend + 1..]) {
-            Some(last) => last,
-            None => return (path, None),
-        };
-        let last = (last - 1).max(0);
-        let mut len = colend;
-        let mut goto = Point { x: 0, y: last };
-
-        if let Some(colbeg) = find_colon_rev(bytes, colend) {
-            // Same here: Don't allow empty filenames.
-            if colbeg != 0
-                && let Some(first) = parse(&bytes[colbeg + 1..colend])
-            {
-                let first = (first - 1).max(0);
-                len = colbeg;
-                goto = Point { x: last, y: first };
-            }
-        }
-
-        // Strip off the :line:char suffix.
-        let path = &bytes[..len];
-        let path = unsafe { OsStr::from_encoded_bytes_unchecked(path) };
-        let path = Path::new(path);
-        (path, Some(goto))
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_parse_last_numbers() {
-        fn parse(s: &str) -> (&str, Option<Point>) {
-            let (p, g) = DocumentManager::parse_filename_goto(Path::new(s));
-            (p.to_str().unwrap(), g)
-        }
-
-        assert_eq!(parse("123"), ("123", None));
-        assert_eq!(parse("abc"), ("abc", None));
-        assert_eq!(parse(":123"), (":123", None));
-        assert_eq!(parse("abc:123"), ("abc", Some(Point { x: 0, y: 122 })));
-        assert_eq!(parse("45:123"), ("45", Some(Point { x: 0, y: 122 })));
-        assert_eq!(parse(":45:123"), (":45", Some(Point { x: 0, y: 122 })));
-        assert_eq!(parse("abc:45:123"), ("abc", Some(Point { x: 122, y: 44 })));
-        assert_eq!(parse("abc:def:123"), ("abc:def", Some(Point { x: 0, y: 122 })));
-        assert_eq!(parse("1:2:3"), ("1", Some(Point { x: 2, y: 1 })));
-        assert_eq!(parse("::3"), (":", Some(Point { x: 0, y: 2 })));
-        assert_eq!(parse("1::3"), ("1:", Some(Point { x: 0, y: 2 })));
-        assert_eq!(parse(""), ("", None));
-        assert_eq!(parse(":"), (":", None));
-        assert_eq!(parse("::"), ("::", None));
-        assert_eq!(parse("a:1"), ("a", Some(Point { x: 0, y: 0 })));
-        assert_eq!(parse("1:a"), ("1:a", None));
-        assert_eq!(parse("file.txt:10"), ("file.txt", Some(Point { x: 0, y: 9 })));
-        assert_eq!(parse("file.txt:10:5"), ("file.txt", Some(Point { x: 4, y: 9 })));
-    }
 }
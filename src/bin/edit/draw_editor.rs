@@ -3,6 +3,7 @@
 
 use std::num::ParseIntError;
 
+use edit::backup::BackupOutcome;
 use edit::framebuffer::IndexedColor;
 use edit::helpers::*;
 use edit::icu;
@@ -197,8 +198,9 @@ pub fn search_execute(ctx: &mut Context, state: &mut State, action: SearchAction
 pub fn draw_handle_save(ctx: &mut Context, state: &mut State) {
     if let Some(doc) = state.documents.active_mut() {
         if doc.path.is_some() {
-            if let Err(err) = doc.save(None) {
-                error_log_add(ctx, state, err);
+            match doc.save(None) {
+                Ok(BackupOutcome::Failed(err)) | Err(err) => error_log_add(ctx, state, err),
+                Ok(_) => {}
             }
         } else {
             // No path? Show the file picker.
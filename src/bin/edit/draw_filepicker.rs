@@ -6,6 +6,7 @@ use std::fs;
 use std::path::{Path, PathBuf};
 
 use edit::arena::scratch_arena;
+use edit::backup::BackupOutcome;
 use edit::framebuffer::IndexedColor;
 use edit::helpers::*;
 use edit::input::{kbmod, vk};
@@ -241,14 +242,17 @@ pub fn draw_file_picker(ctx: &mut Context, state: &mut State) {
 
     if let Some(path) = doit {
         let res = if state.wants_file_picker == StateFilePicker::Open {
-            state.documents.add_file_path(&path).map(|_| ())
+            state.documents.add_file_path(&path).map(|_| BackupOutcome::NotNeeded)
         } else if let Some(doc) = state.documents.active_mut() {
             doc.save(Some(path))
         } else {
-            Ok(())
+            Ok(BackupOutcome::NotNeeded)
         };
         match res {
-            Ok(..) => {
+            Ok(backup) => {
+                if let BackupOutcome::Failed(err) = backup {
+                    error_log_add(ctx, state, err);
+                }
                 ctx.needs_rerender();
                 done = true;
             }
@@ -2,6 +2,7 @@
 // Licensed under the MIT License.
 
 use edit::arena::scratch_arena;
+use edit::backup::BackupOutcome;
 use edit::framebuffer::{Attributes, IndexedColor};
 use edit::fuzzy::score_fuzzy;
 use edit::helpers::*;
@@ -257,15 +258,14 @@ pub fn draw_dialog_encoding_change(ctx: &mut Context, state: &mut State) {
         && let Some(doc) = state.documents.active_mut()
     {
         if reopen && doc.path.is_some() {
-            let mut res = Ok(());
+            let mut res = Ok(BackupOutcome::NotNeeded);
             if doc.buffer.borrow().is_dirty() {
                 res = doc.save(None);
             }
-            if res.is_ok() {
-                res = doc.reread(Some(encoding));
-            }
-            if let Err(err) = res {
-                error_log_add(ctx, state, err);
+            let res = res.and_then(|backup| doc.reread(Some(encoding)).map(|_| backup));
+            match res {
+                Ok(BackupOutcome::Failed(err)) | Err(err) => error_log_add(ctx, state, err),
+                Ok(_) => {}
             }
         } else {
             doc.buffer.borrow_mut().set_encoding(encoding);
@@ -301,7 +301,7 @@ fn encoding_picker_update_list(state: &mut State) {
         }
     }
 
-    matches.sort_by(|a, b| b.0.cmp(&a.0));
+    matches.sort_by_key(|a| std::cmp::Reverse(a.0));
     state.encoding_picker_results = Some(Vec::from_iter(matches.iter().map(|(_, enc)| *enc)));
 }
 
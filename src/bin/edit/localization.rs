@@ -70,6 +70,12 @@ pub enum LocId {
     // Error dialog
     ErrorDialogTitle,
     ErrorIcuMissing,
+    ErrorSwapRecordTooLarge,
+    ErrorSessionInvalid,
+    ErrorHistoryInvalid,
+    ErrorRecentFilesInvalid,
+    ErrorReadOnlyStreaming,
+    ErrorSwapRecovered,
 
     SearchNeedleLabel,
     SearchReplacementLabel,
@@ -744,6 +750,90 @@ const S_LANG_LUT: [[&str; LangId::Count as usize]; LocId::Count as usize] = [
         /* zh_hans */ "此操作需要 ICU 库",
         /* zh_hant */ "此操作需要 ICU 庫",
     ],
+    // ErrorSwapRecordTooLarge
+    [
+        /* en      */ "A swap file record can't be larger than 4 GiB",
+        /* de      */ "Ein Eintrag in der Auslagerungsdatei darf nicht größer als 4 GiB sein",
+        /* es      */ "Un registro del archivo de intercambio no puede superar los 4 GiB",
+        /* fr      */ "Un enregistrement du fichier d'échange ne peut pas dépasser 4 Gio",
+        /* it      */ "Un record del file di swap non può superare 4 GiB",
+        /* ja      */ "スワップファイルのレコードは 4 GiB を超えることはできません",
+        /* ko      */ "스왑 파일 레코드는 4GiB를 초과할 수 없습니다",
+        /* pt_br   */ "Um registro do arquivo de troca não pode ser maior que 4 GiB",
+        /* ru      */ "Запись файла подкачки не может превышать 4 ГиБ",
+        /* zh_hans */ "交换文件记录不能大于 4 GiB",
+        /* zh_hant */ "交換檔記錄不能大於 4 GiB",
+    ],
+    // ErrorSessionInvalid
+    [
+        /* en      */ "The file isn't a session file",
+        /* de      */ "Die Datei ist keine Sitzungsdatei",
+        /* es      */ "El archivo no es un archivo de sesión",
+        /* fr      */ "Le fichier n'est pas un fichier de session",
+        /* it      */ "Il file non è un file di sessione",
+        /* ja      */ "このファイルはセッション ファイルではありません",
+        /* ko      */ "이 파일은 세션 파일이 아닙니다",
+        /* pt_br   */ "O arquivo não é um arquivo de sessão",
+        /* ru      */ "Файл не является файлом сеанса",
+        /* zh_hans */ "该文件不是会话文件",
+        /* zh_hant */ "該檔案不是工作階段檔案",
+    ],
+    // ErrorHistoryInvalid
+    [
+        /* en      */ "The file isn't a prompt history file",
+        /* de      */ "Die Datei ist keine Eingabeverlaufsdatei",
+        /* es      */ "El archivo no es un archivo de historial de entradas",
+        /* fr      */ "Le fichier n'est pas un fichier d'historique des saisies",
+        /* it      */ "Il file non è un file di cronologia degli input",
+        /* ja      */ "このファイルは入力履歴ファイルではありません",
+        /* ko      */ "이 파일은 입력 기록 파일이 아닙니다",
+        /* pt_br   */ "O arquivo não é um arquivo de histórico de entradas",
+        /* ru      */ "Файл не является файлом истории ввода",
+        /* zh_hans */ "该文件不是输入历史记录文件",
+        /* zh_hant */ "該檔案不是輸入歷程記錄檔案",
+    ],
+    // ErrorRecentFilesInvalid
+    [
+        /* en      */ "The file isn't a recent files list",
+        /* de      */ "Die Datei ist keine Liste zuletzt verwendeter Dateien",
+        /* es      */ "El archivo no es una lista de archivos recientes",
+        /* fr      */ "Le fichier n'est pas une liste de fichiers récents",
+        /* it      */ "Il file non è un elenco di file recenti",
+        /* ja      */ "このファイルは最近使用したファイルの一覧ではありません",
+        /* ko      */ "이 파일은 최근 파일 목록이 아닙니다",
+        /* pt_br   */ "O arquivo não é uma lista de arquivos recentes",
+        /* ru      */ "Файл не является списком недавних файлов",
+        /* zh_hans */ "该文件不是最近使用的文件列表",
+        /* zh_hant */ "該檔案不是最近使用的檔案清單",
+    ],
+    // ErrorReadOnlyStreaming
+    [
+        /* en      */ "The file is too large to be loaded and was opened read-only",
+        /* de      */ "Die Datei ist zu groß zum Laden und wurde schreibgeschützt geöffnet",
+        /* es      */ "El archivo es demasiado grande para cargarlo y se abrió en modo de solo lectura",
+        /* fr      */ "Le fichier est trop volumineux pour être chargé et a été ouvert en lecture seule",
+        /* it      */ "Il file è troppo grande per essere caricato ed è stato aperto in sola lettura",
+        /* ja      */ "ファイルが大きすぎて読み込めないため、読み取り専用で開きました",
+        /* ko      */ "파일이 너무 커서 불러올 수 없어 읽기 전용으로 열었습니다",
+        /* pt_br   */ "O arquivo é grande demais para ser carregado e foi aberto somente para leitura",
+        /* ru      */ "Файл слишком велик для загрузки и открыт только для чтения",
+        /* zh_hans */ "文件过大，无法加载，已以只读方式打开",
+        /* zh_hant */ "檔案過大，無法載入，已以唯讀方式開啟",
+    ],
+    // ErrorSwapRecovered
+    [
+        /* en      */ "Unsaved changes were recovered from a swap file. Undo to discard them",
+        /* de      */ "Nicht gespeicherte Änderungen wurden aus einer Auslagerungsdatei wiederhergestellt. Rückgängig machen verwirft sie",
+        /* es      */ "Se recuperaron cambios no guardados de un archivo de intercambio. Deshaga para descartarlos",
+        /* fr      */ "Des modifications non enregistrées ont été récupérées d'un fichier d'échange. Annulez pour les abandonner",
+        /* it      */ "Le modifiche non salvate sono state recuperate da un file di swap. Annulla per scartarle",
+        /* ja      */ "保存されていない変更をスワップファイルから復元しました。元に戻すと破棄されます",
+        /* ko      */ "저장되지 않은 변경 내용을 스왑 파일에서 복구했습니다. 실행 취소하면 삭제됩니다",
+        /* pt_br   */ "Alterações não salvas foram recuperadas de um arquivo de troca. Desfaça para descartá-las",
+        /* ru      */ "Несохранённые изменения восстановлены из файла подкачки. Отмените, чтобы их удалить",
+        /* zh_hans */ "已从交换文件恢复未保存的更改。撤消即可放弃这些更改",
+        /* zh_hant */ "已從交換檔還原未儲存的變更。復原即可捨棄這些變更",
+    ],
 
     // SearchNeedleLabel (for input field)
     [
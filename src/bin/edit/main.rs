@@ -1,7 +1,7 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-#![feature(allocator_api, let_chains, linked_list_cursors, string_from_utf8_lossy_owned)]
+#![feature(allocator_api, linked_list_cursors, string_from_utf8_lossy_owned)]
 
 mod documents;
 mod draw_editor;
@@ -23,13 +23,14 @@ use draw_filepicker::*;
 use draw_menubar::*;
 use draw_statusbar::*;
 use edit::arena::{self, Arena, ArenaString, scratch_arena};
+use edit::clipboard::{Osc52Selection, osc52_copy};
 use edit::framebuffer::{self, IndexedColor};
 use edit::helpers::{CoordType, KIBI, MEBI, MetricFormatter, Rect, Size};
 use edit::input::{self, kbmod, vk};
 use edit::oklab::oklab_blend;
 use edit::tui::*;
 use edit::vt::{self, Token};
-use edit::{apperr, arena_format, base64, path, sys, unicode};
+use edit::{apperr, arena_format, path, sys, unicode};
 use localization::*;
 use state::*;
 
@@ -320,6 +321,7 @@ fn draw(ctx: &mut Context, state: &mut State) {
     if ctx.clipboard_ref().wants_host_sync() {
         draw_handle_clipboard_change(ctx, state);
     }
+    draw_handle_swap(ctx, state);
     if state.error_log_count != 0 {
         draw_error_log(ctx, state);
     }
@@ -363,6 +365,12 @@ fn draw(ctx: &mut Context, state: &mut State) {
     }
 }
 
+fn draw_handle_swap(ctx: &mut Context, state: &mut State) {
+    for err in state.documents.update_swap_files() {
+        error_log_add(ctx, state, err);
+    }
+}
+
 fn draw_handle_wants_exit(_ctx: &mut Context, state: &mut State) {
     while let Some(doc) = state.documents.active() {
         if doc.buffer.borrow().is_dirty() {
@@ -488,14 +496,8 @@ fn write_osc_clipboard(tui: &mut Tui, state: &mut State, output: &mut ArenaStrin
     let data = clipboard.read();
 
     if !data.is_empty() {
-        // Rust doubles the size of a string when it needs to grow it.
-        // If `data` is *really* large, this may then double
-        // the size of the `output` from e.g. 100MB to 200MB. Not good.
-        // We can avoid that by reserving the needed size in advance.
-        output.reserve_exact(base64::encode_len(data.len()) + 16);
-        output.push_str("\x1b]52;c;");
-        base64::encode(output, data);
-        output.push_str("\x1b\\");
+        // The user already confirmed sending large clipboards in `draw_handle_clipboard_change`.
+        osc52_copy(output, data, Osc52Selection::Clipboard, usize::MAX);
     }
 
     state.osc_clipboard_sync = false;
@@ -27,6 +27,12 @@ impl std::fmt::Display for FormatApperr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self.0 {
             apperr::APP_ICU_MISSING => f.write_str(loc(LocId::ErrorIcuMissing)),
+            apperr::APP_SWAP_RECORD_TOO_LARGE => f.write_str(loc(LocId::ErrorSwapRecordTooLarge)),
+            apperr::APP_SESSION_INVALID => f.write_str(loc(LocId::ErrorSessionInvalid)),
+            apperr::APP_HISTORY_INVALID => f.write_str(loc(LocId::ErrorHistoryInvalid)),
+            apperr::APP_RECENT_FILES_INVALID => f.write_str(loc(LocId::ErrorRecentFilesInvalid)),
+            apperr::APP_READ_ONLY_STREAMING => f.write_str(loc(LocId::ErrorReadOnlyStreaming)),
+            apperr::APP_SWAP_RECOVERED => f.write_str(loc(LocId::ErrorSwapRecovered)),
             apperr::Error::App(code) => write!(f, "Unknown app error code: {code}"),
             apperr::Error::Icu(code) => icu::apperr_format(f, code),
             apperr::Error::Sys(code) => sys::apperr_format(f, code),
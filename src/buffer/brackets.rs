@@ -0,0 +1,341 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Matching of `()`, `[]` and `{}` pairs.
+//!
+//! Brackets inside string literals and line comments are skipped, if the
+//! [`LexicalConfig`] describes them. To keep this simple (and fast), it's
+//! assumed that neither strings nor comments span multiple lines. This allows
+//! us to lex each line on its own, in either direction.
+
+use super::TextBuffer;
+use crate::document::ReadableDocument;
+
+/// The simple lexical rules of a language, as needed for bracket matching.
+#[derive(Default, Clone, Copy)]
+pub struct LexicalConfig<'a> {
+    /// The prefix that starts a line comment, e.g. `"//"`. Empty if there's none.
+    pub line_comment: &'a str,
+    /// The ASCII characters that start and end a string literal, e.g. `"\"'"`.
+    pub quotes: &'a str,
+    /// The character that escapes the next one within a string, e.g. `b'\\'`.
+    pub escape: Option<u8>,
+}
+
+/// A bracket that was found outside of strings and comments.
+#[derive(Clone, Copy)]
+struct Bracket {
+    offset: usize,
+    ch: u8,
+}
+
+impl TextBuffer {
+    /// See [`find_matching_bracket`].
+    pub fn find_matching_bracket(
+        &self,
+        offset: usize,
+        cfg: &LexicalConfig,
+        limit: usize,
+    ) -> Option<usize> {
+        find_matching_bracket(&self.buffer, offset, cfg, limit)
+    }
+
+    /// See [`surrounding_pair`].
+    pub fn surrounding_bracket_pair(
+        &self,
+        offset: usize,
+        cfg: &LexicalConfig,
+        limit: usize,
+    ) -> Option<(usize, usize)> {
+        surrounding_pair(&self.buffer, offset, cfg, limit)
+    }
+}
+
+/// Given the `offset` of a bracket, this returns the offset of its partner.
+///
+/// Returns `None` if there's no bracket at `offset`, if it's inside a string or comment,
+/// if it's unmatched, or if finding the partner would require scanning more than `limit` bytes.
+pub fn find_matching_bracket(
+    doc: &dyn ReadableDocument,
+    offset: usize,
+    cfg: &LexicalConfig,
+    limit: usize,
+) -> Option<usize> {
+    let mut scanner = Scanner::new(doc, cfg, limit);
+    let mut line = scanner.line_at(offset)?;
+    let bracket = *line.brackets.iter().find(|b| b.offset == offset)?;
+    let (open, close, forward) = match bracket.ch {
+        b'(' => (b'(', b')', true),
+        b'[' => (b'[', b']', true),
+        b'{' => (b'{', b'}', true),
+        b')' => (b'(', b')', false),
+        b']' => (b'[', b']', false),
+        b'}' => (b'{', b'}', false),
+        _ => return None,
+    };
+    let mut depth = 0usize;
+
+    loop {
+        let mut visit = |b: &Bracket| {
+            if b.ch == open || b.ch == close {
+                if (b.ch == open) == forward {
+                    depth += 1;
+                } else {
+                    depth -= 1;
+                }
+            }
+            depth == 0
+        };
+
+        let hit = if forward {
+            line.brackets.iter().filter(|b| b.offset >= offset).find(|b| visit(b))
+        } else {
+            line.brackets.iter().rev().filter(|b| b.offset <= offset).find(|b| visit(b))
+        };
+        if let Some(b) = hit {
+            return Some(b.offset);
+        }
+
+        line = if forward { scanner.line_after(&line)? } else { scanner.line_before(&line)? };
+    }
+}
+
+/// Returns the offsets of the innermost bracket pair that encloses `offset`.
+/// The opening bracket is before `offset` and the closing one at or after it.
+///
+/// Returns `None` if there's no such pair within `limit` bytes.
+pub fn surrounding_pair(
+    doc: &dyn ReadableDocument,
+    offset: usize,
+    cfg: &LexicalConfig,
+    limit: usize,
+) -> Option<(usize, usize)> {
+    let mut scanner = Scanner::new(doc, cfg, limit);
+    let mut line = scanner.line_at(offset)?;
+    // The number of closing brackets we've seen, per bracket type, which are still unmatched.
+    let mut depth = [0usize; 3];
+
+    let open = loop {
+        let hit = line.brackets.iter().rev().filter(|b| b.offset < offset).find(|b| {
+            let (i, is_open) = match b.ch {
+                b'(' => (0, true),
+                b'[' => (1, true),
+                b'{' => (2, true),
+                b')' => (0, false),
+                b']' => (1, false),
+                _ => (2, false),
+            };
+            if !is_open {
+                depth[i] += 1;
+                false
+            } else if depth[i] > 0 {
+                depth[i] -= 1;
+                false
+            } else {
+                true
+            }
+        });
+        if let Some(b) = hit {
+            break b.offset;
+        }
+
+        line = scanner.line_before(&line)?;
+    };
+
+    // The remaining budget applies to the search for the partner.
+    let close = find_matching_bracket(doc, open, cfg, scanner.remaining)?;
+    Some((open, close))
+}
+
+/// The brackets of a single line.
+struct Line {
+    /// Offset of the first character of the line.
+    beg: usize,
+    /// Offset past the line's newline.
+    end: usize,
+    brackets: Vec<Bracket>,
+}
+
+/// Reads and lexes lines while keeping track of the scan limit.
+struct Scanner<'a> {
+    doc: &'a dyn ReadableDocument,
+    cfg: &'a LexicalConfig<'a>,
+    remaining: usize,
+    text: Vec<u8>,
+}
+
+impl<'a> Scanner<'a> {
+    fn new(doc: &'a dyn ReadableDocument, cfg: &'a LexicalConfig<'a>, limit: usize) -> Self {
+        Self { doc, cfg, remaining: limit, text: Vec::new() }
+    }
+
+    /// Lexes the line containing `offset`.
+    fn line_at(&mut self, offset: usize) -> Option<Line> {
+        let mut beg = offset;
+
+        // Seek backwards to the start of the line.
+        loop {
+            let chunk = self.doc.read_backward(beg);
+            if chunk.is_empty() {
+                break;
+            }
+            if let Some(i) = chunk.iter().rposition(|&c| c == b'\n') {
+                self.consume(chunk.len() - i - 1)?;
+                beg -= chunk.len() - i - 1;
+                break;
+            }
+            self.consume(chunk.len())?;
+            beg -= chunk.len();
+        }
+
+        self.lex(beg)
+    }
+
+    /// Lexes the line following the given one.
+    fn line_after(&mut self, line: &Line) -> Option<Line> {
+        if line.end == line.beg || self.doc.read_forward(line.end).is_empty() {
+            return None;
+        }
+        self.lex(line.end)
+    }
+
+    /// Lexes the line preceding the given one.
+    fn line_before(&mut self, line: &Line) -> Option<Line> {
+        if line.beg == 0 {
+            return None;
+        }
+        // `line.beg - 1` is the newline of the preceding line.
+        self.line_at(line.beg - 1)
+    }
+
+    /// Accounts for `len` scanned bytes. Returns `None` once the limit is exceeded.
+    fn consume(&mut self, len: usize) -> Option<()> {
+        self.remaining = self.remaining.checked_sub(len)?;
+        Some(())
+    }
+
+    /// Reads the line starting at `beg` and finds all brackets outside of strings and comments.
+    fn lex(&mut self, beg: usize) -> Option<Line> {
+        self.text.clear();
+
+        let mut end = beg;
+        loop {
+            let chunk = self.doc.read_forward(end);
+            if chunk.is_empty() {
+                break;
+            }
+            let len = chunk.iter().position(|&c| c == b'\n').map_or(chunk.len(), |i| i + 1);
+            self.consume(len)?;
+            self.text.extend_from_slice(&chunk[..len]);
+            end += len;
+            if self.text.ends_with(b"\n") {
+                break;
+            }
+        }
+
+        let text = &self.text[..];
+        let comment = self.cfg.line_comment.as_bytes();
+        let quotes = self.cfg.quotes.as_bytes();
+        let mut brackets = Vec::new();
+        let mut quote = None;
+        let mut i = 0;
+
+        while i < text.len() {
+            let c = text[i];
+
+            match quote {
+                Some(q) => {
+                    if Some(c) == self.cfg.escape {
+                        i += 1;
+                    } else if c == q {
+                        quote = None;
+                    }
+                }
+                None => {
+                    if !comment.is_empty() && text[i..].starts_with(comment) {
+                        break;
+                    }
+                    if quotes.contains(&c) {
+                        quote = Some(c);
+                    } else if matches!(c, b'(' | b')' | b'[' | b']' | b'{' | b'}') {
+                        brackets.push(Bracket { offset: beg + i, ch: c });
+                    }
+                }
+            }
+
+            i += 1;
+        }
+
+        Some(Line { beg, end, brackets })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const C_LIKE: LexicalConfig =
+        LexicalConfig { line_comment: "//", quotes: "\"'", escape: Some(b'\\') };
+
+    fn find(text: &str, offset: usize) -> Option<usize> {
+        find_matching_bracket(&text.as_bytes(), offset, &C_LIKE, usize::MAX)
+    }
+
+    #[test]
+    fn test_nested() {
+        let text = "f(a[0], {b: (c)})\n{\n  (x)\n}";
+        assert_eq!(find(text, 1), Some(16));
+        assert_eq!(find(text, 16), Some(1));
+        assert_eq!(find(text, 3), Some(5));
+        assert_eq!(find(text, 8), Some(15));
+        assert_eq!(find(text, 18), Some(26));
+        assert_eq!(find(text, 26), Some(18));
+        // Not a bracket.
+        assert_eq!(find(text, 0), None);
+    }
+
+    #[test]
+    fn test_strings_and_comments() {
+        let text = "f(\")\", ')', \"\\\")\") // )\n)";
+        assert_eq!(find(text, 1), Some(17));
+        assert_eq!(find(text, 17), Some(1));
+        // Brackets inside strings are not matched themselves.
+        assert_eq!(find(text, 3), None);
+
+        // Without lexical rules, all brackets count.
+        let plain = LexicalConfig::default();
+        assert_eq!(find_matching_bracket(&text.as_bytes(), 1, &plain, usize::MAX), Some(3));
+    }
+
+    #[test]
+    fn test_unmatched() {
+        assert_eq!(find("(()", 0), None);
+        assert_eq!(find("())", 2), None);
+        assert_eq!(find("(]", 0), None);
+    }
+
+    #[test]
+    fn test_limit() {
+        let text = format!("({})", "x".repeat(1000));
+        assert_eq!(find_matching_bracket(&text.as_bytes(), 0, &C_LIKE, 2000), Some(1001));
+        assert_eq!(find_matching_bracket(&text.as_bytes(), 0, &C_LIKE, 500), None);
+
+        let text = format!("(\n{})", "x\n".repeat(1000));
+        assert_eq!(find_matching_bracket(&text.as_bytes(), 0, &C_LIKE, 500), None);
+        assert_eq!(find_matching_bracket(&text.as_bytes(), 2002, &C_LIKE, 500), None);
+        assert_eq!(find_matching_bracket(&text.as_bytes(), 2002, &C_LIKE, 5000), Some(0));
+    }
+
+    #[test]
+    fn test_surrounding_pair() {
+        let text = "a(b[c]\n \")\" d)e";
+        let pair = |offset| surrounding_pair(&text.as_bytes(), offset, &C_LIKE, usize::MAX);
+        assert_eq!(pair(2), Some((1, 13)));
+        assert_eq!(pair(4), Some((3, 5)));
+        assert_eq!(pair(6), Some((1, 13)));
+        assert_eq!(pair(12), Some((1, 13)));
+        assert_eq!(pair(13), Some((1, 13)));
+        assert_eq!(pair(14), None);
+        assert_eq!(pair(0), None);
+    }
+}
@@ -0,0 +1,237 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Changing the encoding of a document after it was opened.
+//!
+//! [`TextBuffer::reinterpret_as`] decodes the file again with a different encoding, for when
+//! the detection guessed wrong. [`TextBuffer::set_save_encoding`] changes the encoding the
+//! document is saved with, but only if all of its text can be represented in it.
+//!
+//! ICU replaces characters it can't convert with a substitute, so it can't tell which ones
+//! an encoding lacks. Only the Unicode encodings and the two most common single-byte ones,
+//! Windows-1252 and ISO-8859-1, can be checked, using the tables below.
+
+use std::fs::File;
+use std::io::{Seek as _, SeekFrom};
+use std::ops::Range;
+
+use super::TextBuffer;
+use crate::arena::scratch_arena;
+use crate::helpers::*;
+use crate::unicode::Utf8Chars;
+use crate::{apperr, icu};
+
+/// Windows-1252 differs from ISO-8859-1 in the range 0x80-0x9F. The 5 bytes it leaves
+/// undefined are mapped to the C1 control characters, just like ISO-8859-1 does.
+const WINDOWS_1252_C1: [u16; 32] = [
+    0x20AC, 0x0081, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160, 0x2039,
+    0x0152, 0x008D, 0x017D, 0x008F, 0x0090, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+    0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0x009D, 0x017E, 0x0178,
+];
+
+/// The single-byte encodings whose coverage can be checked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SingleByte {
+    Latin1,
+    Windows1252,
+}
+
+impl SingleByte {
+    /// Recognizes the encoding by its MIME name, its ICU name or a common alias.
+    fn from_name(encoding: &str) -> Option<Self> {
+        const LATIN1: [&str; 3] = ["ISO-8859-1", "ISO_8859-1:1987", "latin1"];
+        const WINDOWS_1252: [&str; 3] = ["windows-1252", "ibm-5348_P100-1997", "cp1252"];
+
+        if LATIN1.iter().any(|n| n.eq_ignore_ascii_case(encoding)) {
+            Some(Self::Latin1)
+        } else if WINDOWS_1252.iter().any(|n| n.eq_ignore_ascii_case(encoding)) {
+            Some(Self::Windows1252)
+        } else {
+            None
+        }
+    }
+
+    fn encode(self, c: char) -> Option<u8> {
+        let cp = c as u32;
+        match self {
+            Self::Windows1252 if cp >= 0x80 => {
+                if let Some(i) = WINDOWS_1252_C1.iter().position(|&m| m as u32 == cp) {
+                    Some(0x80 + i as u8)
+                } else if (0xA0..0x100).contains(&cp) {
+                    Some(cp as u8)
+                } else {
+                    None
+                }
+            }
+            _ => u8::try_from(cp).ok(),
+        }
+    }
+}
+
+/// Why [`TextBuffer::set_save_encoding`] refused an encoding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodingError {
+    /// The characters at these offsets can't be represented in it.
+    Unmappable(Vec<Range<usize>>),
+    /// It's unknown which characters it can represent.
+    Unchecked,
+}
+
+/// The result of [`TextBuffer::reinterpret_as`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReinterpretOutcome {
+    Reinterpreted,
+    /// The document had unsaved changes, which were lost.
+    /// The UI should ask for confirmation before calling it in that case.
+    DiscardedEdits,
+}
+
+impl TextBuffer {
+    /// Reads `file` again, decoding it with `encoding` this time.
+    ///
+    /// The newline type, indentation and BOM are detected anew, and the undo history
+    /// is cleared, since it refers to the text as it was decoded before.
+    /// If the encoding isn't supported, the contents are left untouched.
+    pub fn reinterpret_as(
+        &mut self,
+        file: &mut File,
+        encoding: &'static str,
+    ) -> apperr::Result<ReinterpretOutcome> {
+        // The UTF-8 reader detects the BOM by itself.
+        let encoding = if encoding == "UTF-8 BOM" { "UTF-8" } else { encoding };
+
+        if encoding != "UTF-8" {
+            // Fail before clearing the buffer if ICU doesn't know it.
+            let scratch = scratch_arena(None);
+            icu::Converter::new(scratch.alloc_uninit_slice(KIBI), encoding, "UTF-8")?;
+        }
+
+        let outcome = if self.is_dirty() {
+            ReinterpretOutcome::DiscardedEdits
+        } else {
+            ReinterpretOutcome::Reinterpreted
+        };
+
+        file.seek(SeekFrom::Start(0))?;
+        self.read_file(file, Some(encoding))?;
+        Ok(outcome)
+    }
+
+    /// Returns the offsets of the characters that can't be represented in `encoding`.
+    ///
+    /// Fails with [`EncodingError::Unchecked`] for encodings other than the Unicode ones,
+    /// Windows-1252 and ISO-8859-1, since there's no way to tell for them.
+    pub fn unmappable_ranges(&self, encoding: &str) -> Result<Vec<Range<usize>>, EncodingError> {
+        if is_unicode(encoding) {
+            return Ok(Vec::new());
+        }
+        let Some(sb) = SingleByte::from_name(encoding) else {
+            return Err(EncodingError::Unchecked);
+        };
+
+        let mut text = Vec::new();
+        self.buffer.extract_raw(0..self.buffer.len(), &mut text, 0);
+
+        let mut ranges = Vec::new();
+        let mut it = Utf8Chars::new(&text, 0);
+        loop {
+            let beg = it.offset();
+            let Some(c) = it.next() else {
+                break;
+            };
+            if sb.encode(c).is_none() {
+                ranges.push(beg..it.offset());
+            }
+        }
+        Ok(ranges)
+    }
+
+    /// Changes the encoding the document is saved with, if it's known to represent all of the text.
+    /// Otherwise, the encoding stays unchanged. See [`TextBuffer::unmappable_ranges`].
+    pub fn set_save_encoding(&mut self, encoding: &'static str) -> Result<(), EncodingError> {
+        let unmappable = self.unmappable_ranges(encoding)?;
+        if !unmappable.is_empty() {
+            return Err(EncodingError::Unmappable(unmappable));
+        }
+        self.set_encoding(encoding);
+        Ok(())
+    }
+}
+
+/// Whether `encoding` can represent every character.
+fn is_unicode(encoding: &str) -> bool {
+    encoding.starts_with("UTF-") || encoding == "GB18030"
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::testing::{TempDir, contents};
+
+    fn temp_file(name: &str, contents: &[u8]) -> (TempDir, PathBuf) {
+        let dir = TempDir::new("encoding");
+        let path = dir.write(name, contents);
+        (dir, path)
+    }
+
+    #[test]
+    fn test_reinterpret() {
+        let (_dir, path) = temp_file("reinterpret", b"Caf\xE9 \x80 5\r\nna\xEFve\r\n");
+        let mut file = File::open(&path).unwrap();
+
+        // Detection can't tell that this isn't UTF-8.
+        let mut tb = TextBuffer::new(false).unwrap();
+        tb.read_file(&mut file, None).unwrap();
+        assert_eq!(tb.encoding(), "UTF-8");
+        assert_ne!(contents(&tb), "Café € 5\r\nnaïve\r\n");
+
+        tb.write_raw(b"edit");
+        let outcome = tb.reinterpret_as(&mut file, "windows-1252").unwrap();
+        assert_eq!(outcome, ReinterpretOutcome::DiscardedEdits);
+        assert_eq!(contents(&tb), "Café € 5\r\nnaïve\r\n");
+        assert_eq!(tb.encoding(), "windows-1252");
+        assert!(tb.is_crlf());
+        assert!(!tb.is_dirty());
+        assert!(tb.undo_stack.is_empty());
+
+        // And back again, this time without unsaved edits.
+        let outcome = tb.reinterpret_as(&mut file, "UTF-8 BOM").unwrap();
+        assert_eq!(outcome, ReinterpretOutcome::Reinterpreted);
+        assert_eq!(tb.encoding(), "UTF-8");
+    }
+
+    #[test]
+    fn test_set_save_encoding() {
+        let mut tb = TextBuffer::new(false).unwrap();
+        tb.write_raw("a😀b € é\n😀".as_bytes());
+
+        // The emoji are at bytes 1-5 and 14-18.
+        let unmappable = |ranges: &[Range<usize>]| Err(EncodingError::Unmappable(ranges.to_vec()));
+        assert_eq!(tb.set_save_encoding("windows-1252"), unmappable(&[1..5, 14..18]));
+        assert_eq!(tb.encoding(), "UTF-8");
+        assert_eq!(tb.set_save_encoding("ISO-8859-1"), unmappable(&[1..5, 7..10, 14..18]));
+
+        // Unicode encodings can represent anything. Others can't be checked.
+        assert_eq!(tb.unmappable_ranges("UTF-16LE"), Ok(Vec::new()));
+        assert_eq!(tb.set_save_encoding("Shift_JIS"), Err(EncodingError::Unchecked));
+        assert_eq!(tb.encoding(), "UTF-8");
+
+        tb.select_all();
+        tb.write_raw("ab € é\n".as_bytes());
+        assert_eq!(tb.set_save_encoding("windows-1252"), Ok(()));
+        assert_eq!(tb.encoding(), "windows-1252");
+        assert!(tb.is_dirty());
+
+        let (_dir, path) = temp_file("save", b"");
+        tb.write_file(&mut File::create(&path).unwrap()).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"ab \x80 \xE9\n");
+
+        // The saved file reads back the same.
+        let mut other = TextBuffer::new(false).unwrap();
+        other.read_file(&mut File::open(&path).unwrap(), Some("windows-1252")).unwrap();
+        assert_eq!(contents(&other), "ab € é\n");
+    }
+}
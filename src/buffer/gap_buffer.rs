@@ -7,6 +7,7 @@ use std::slice;
 
 use crate::document::{ReadableDocument, WriteableDocument};
 use crate::helpers::*;
+use crate::mem_report::{MemoryReport, MemoryUsage};
 use crate::{apperr, sys};
 
 #[cfg(target_pointer_width = "32")]
@@ -37,6 +38,15 @@ impl Drop for BackingBuffer {
     }
 }
 
+/// A change to the contents of a [`GapBuffer`], as recorded by its journal.
+/// The `deleted` bytes at `offset` were replaced with `inserted`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub offset: usize,
+    pub deleted: usize,
+    pub inserted: Vec<u8>,
+}
+
 /// Most people know how `Vec<T>` works: It has some spare capacity at the end,
 /// so that pushing into it doesn't reallocate every single time. A gap buffer
 /// is the same thing, but the spare capacity can be anywhere in the buffer.
@@ -56,6 +66,8 @@ pub struct GapBuffer {
     gap_len: usize,
     /// Increments every time the buffer is modified.
     generation: u32,
+    /// The changes since the last [`GapBuffer::take_journal`], if journaling is enabled.
+    journal: Option<Vec<JournalEntry>>,
     /// If `Vec(..)`, the buffer is optimized for small amounts of text
     /// and uses the standard heap. Otherwise, it uses virtual memory.
     buffer: BackingBuffer,
@@ -85,6 +97,7 @@ impl GapBuffer {
             gap_off: 0,
             gap_len: 0,
             generation: 0,
+            journal: None,
             buffer,
         })
     }
@@ -102,12 +115,46 @@ impl GapBuffer {
         self.generation = generation;
     }
 
+    /// Starts or stops recording all changes. Stopping discards those not yet taken.
+    pub fn set_journaling(&mut self, enabled: bool) {
+        if !enabled {
+            self.journal = None;
+        } else if self.journal.is_none() {
+            self.journal = Some(Vec::new());
+        }
+    }
+
+    /// Returns the changes recorded since the last call, in the order they were made.
+    pub fn take_journal(&mut self) -> Vec<JournalEntry> {
+        self.journal.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    fn record(&mut self, offset: usize, deleted: usize, inserted: &[u8]) {
+        let Some(journal) = &mut self.journal else {
+            return;
+        };
+        // Text written right after the previous change extends it,
+        // e.g. a deletion followed by the text replacing it.
+        if deleted == 0
+            && let Some(last) = journal.last_mut()
+            && last.offset + last.inserted.len() == offset
+        {
+            last.inserted.extend_from_slice(inserted);
+            return;
+        }
+        journal.push(JournalEntry { offset, deleted, inserted: inserted.to_vec() });
+    }
+
     /// WARNING: The returned slice must not necessarily be the same length as `len` (due to OOM).
     pub fn allocate_gap(&mut self, off: usize, len: usize, delete: usize) -> &mut [u8] {
         // Sanitize parameters
         let off = off.min(self.text_length);
         let delete = delete.min(self.text_length - off);
 
+        if delete > 0 {
+            self.record(off, delete, &[]);
+        }
+
         // Move the existing gap if it exists
         if off != self.gap_off {
             self.move_gap(off);
@@ -227,6 +274,12 @@ impl GapBuffer {
 
     pub fn commit_gap(&mut self, len: usize) {
         assert!(len <= self.gap_len);
+        if len > 0 && self.journal.is_some() {
+            // SAFETY: The first `len` bytes of the gap were just written by the caller.
+            // The journal doesn't live in the buffer, so recording can't invalidate them.
+            let text = unsafe { slice::from_raw_parts(self.text.add(self.gap_off).as_ptr(), len) };
+            self.record(self.gap_off, 0, text);
+        }
         self.text_length += len;
         self.gap_off += len;
         self.gap_len -= len;
@@ -239,6 +292,9 @@ impl GapBuffer {
     }
 
     pub fn clear(&mut self) {
+        if self.text_length > 0 {
+            self.record(0, self.text_length, &[]);
+        }
         self.gap_off = 0;
         self.gap_len += self.text_length;
         self.generation = self.generation.wrapping_add(1);
@@ -328,6 +384,13 @@ impl GapBuffer {
     }
 }
 
+/// The gap counts as spare. Virtual memory that's only reserved doesn't count at all.
+impl MemoryUsage for GapBuffer {
+    fn memory_usage(&self) -> MemoryReport {
+        MemoryReport::leaf("GapBuffer", self.text_length, self.commit - self.text_length)
+    }
+}
+
 impl ReadableDocument for GapBuffer {
     fn read_forward(&self, off: usize) -> &[u8] {
         let off = off.min(self.text_length);
@@ -0,0 +1,248 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Computes the indentation of new lines.
+
+use super::TextBuffer;
+use crate::document::ReadableDocument;
+use crate::helpers::CoordType;
+
+/// Describes how to indent new lines.
+#[derive(Clone, Copy)]
+pub struct AutoIndentConfig<'a> {
+    /// The width of a tab and of a single level of indentation. Values below 1 are treated as 1.
+    pub tab_size: CoordType,
+    /// Whether to indent with tabs or spaces.
+    pub indent_with_tabs: bool,
+    /// Lines ending in one of these characters increase the indentation of the next line, e.g. `b"{[(:"`.
+    pub increase_after: &'a [u8],
+    /// Lines starting with one of these characters are indented one level less, e.g. `b"}])"`.
+    pub decrease_before: &'a [u8],
+}
+
+impl AutoIndentConfig<'_> {
+    fn tab_size(&self) -> CoordType {
+        self.tab_size.max(1)
+    }
+}
+
+impl TextBuffer {
+    /// See [`compute_auto_indent`].
+    pub fn compute_auto_indent(&self, offset: usize, cfg: &AutoIndentConfig) -> String {
+        compute_auto_indent(&self.buffer, offset, cfg)
+    }
+
+    /// See [`compute_closing_indent`].
+    pub fn compute_closing_indent(&self, offset: usize, cfg: &AutoIndentConfig) -> String {
+        compute_closing_indent(&self.buffer, offset, cfg)
+    }
+}
+
+/// Returns the indentation for the new line that's created when splitting the line at `offset`.
+///
+/// The indentation is copied from the text before `offset`, or if that's blank,
+/// from the closest non-blank line above. It's increased by one level if that text
+/// ends in one of [`AutoIndentConfig::increase_after`], and decreased by one level
+/// if the text after `offset` starts with one of [`AutoIndentConfig::decrease_before`].
+pub fn compute_auto_indent(
+    doc: &dyn ReadableDocument,
+    offset: usize,
+    cfg: &AutoIndentConfig,
+) -> String {
+    let (beg, prefix) = read_line_before(doc, offset);
+    let mut columns = if is_blank(&prefix) {
+        match last_non_blank_line(doc, beg) {
+            Some(line) => reference_indent(&line, cfg),
+            None => 0,
+        }
+    } else {
+        reference_indent(&prefix, cfg)
+    };
+
+    // The text after the cursor moves to the new line. If it starts with a closing
+    // character, like in `{|}`, it should be indented as if it was typed there.
+    if first_non_blank_after(doc, offset).is_some_and(|c| cfg.decrease_before.contains(&c)) {
+        columns = prev_level(columns, cfg);
+    }
+
+    build_indent(columns, cfg)
+}
+
+/// Returns the indentation for the line containing `offset`,
+/// when one of [`AutoIndentConfig::decrease_before`] is typed as its first character.
+pub fn compute_closing_indent(
+    doc: &dyn ReadableDocument,
+    offset: usize,
+    cfg: &AutoIndentConfig,
+) -> String {
+    let (beg, _) = read_line_before(doc, offset);
+    let columns = match last_non_blank_line(doc, beg) {
+        Some(line) => prev_level(reference_indent(&line, cfg), cfg),
+        None => 0,
+    };
+    build_indent(columns, cfg)
+}
+
+/// Returns the offset of the start of the line containing `offset` and the text in between.
+fn read_line_before(doc: &dyn ReadableDocument, offset: usize) -> (usize, Vec<u8>) {
+    let mut beg = offset;
+    let mut text = Vec::new();
+
+    loop {
+        let chunk = doc.read_backward(beg);
+        if chunk.is_empty() {
+            break;
+        }
+        let i = chunk.iter().rposition(|&c| c == b'\n').map_or(0, |i| i + 1);
+        text.splice(0..0, chunk[i..].iter().copied());
+        beg -= chunk.len() - i;
+        if i > 0 {
+            break;
+        }
+    }
+
+    (beg, text)
+}
+
+/// Walks upwards from the line starting at `beg` and returns the first non-blank line.
+fn last_non_blank_line(doc: &dyn ReadableDocument, mut beg: usize) -> Option<Vec<u8>> {
+    while beg > 0 {
+        // `beg - 1` is the newline of the preceding line.
+        let (prev, text) = read_line_before(doc, beg - 1);
+        if !is_blank(&text) {
+            return Some(text);
+        }
+        beg = prev;
+    }
+    None
+}
+
+fn first_non_blank_after(doc: &dyn ReadableDocument, mut offset: usize) -> Option<u8> {
+    loop {
+        let chunk = doc.read_forward(offset);
+        if chunk.is_empty() {
+            return None;
+        }
+        if let Some(&c) = chunk.iter().find(|&&c| c != b' ' && c != b'\t') {
+            return if c == b'\r' || c == b'\n' { None } else { Some(c) };
+        }
+        offset += chunk.len();
+    }
+}
+
+/// Returns the indentation of `line` in columns, plus a level if it ends in a trigger character.
+fn reference_indent(line: &[u8], cfg: &AutoIndentConfig) -> CoordType {
+    let mut columns = 0;
+    for &c in line {
+        match c {
+            b' ' => columns += 1,
+            b'\t' => columns += cfg.tab_size() - columns % cfg.tab_size(),
+            _ => break,
+        }
+    }
+
+    if line.trim_ascii_end().last().is_some_and(|c| cfg.increase_after.contains(c)) {
+        columns += cfg.tab_size();
+    }
+
+    columns
+}
+
+/// Removes a level of indentation from `columns`.
+fn prev_level(columns: CoordType, cfg: &AutoIndentConfig) -> CoordType {
+    (columns - cfg.tab_size()).max(0)
+}
+
+fn build_indent(columns: CoordType, cfg: &AutoIndentConfig) -> String {
+    let columns = columns as usize;
+    let tab_size = cfg.tab_size() as usize;
+    let mut indent = String::with_capacity(columns);
+    if cfg.indent_with_tabs {
+        indent.extend(std::iter::repeat_n('\t', columns / tab_size));
+        indent.extend(std::iter::repeat_n(' ', columns % tab_size));
+    } else {
+        indent.extend(std::iter::repeat_n(' ', columns));
+    }
+    indent
+}
+
+fn is_blank(text: &[u8]) -> bool {
+    text.iter().all(|c| c.is_ascii_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPACES: AutoIndentConfig = AutoIndentConfig {
+        tab_size: 4,
+        indent_with_tabs: false,
+        increase_after: b"{[(:",
+        decrease_before: b"}])",
+    };
+    const TABS: AutoIndentConfig = AutoIndentConfig { indent_with_tabs: true, ..SPACES };
+
+    /// Computes the indentation for splitting `text` at the `|` marker.
+    fn indent(text: &str, cfg: &AutoIndentConfig) -> String {
+        let offset = text.find('|').unwrap();
+        let text = text.replace('|', "");
+        compute_auto_indent(&text.as_bytes(), offset, cfg)
+    }
+
+    #[test]
+    fn test_copy_indent() {
+        assert_eq!(indent("|", &SPACES), "");
+        assert_eq!(indent("foo|", &SPACES), "");
+        assert_eq!(indent("a\n  foo|\nb", &SPACES), "  ");
+        assert_eq!(indent("\t  foo|", &SPACES), "      ");
+        assert_eq!(indent("\t  foo|", &TABS), "\t  ");
+        assert_eq!(indent("    foo;\r\n|", &TABS), "\t");
+    }
+
+    #[test]
+    fn test_increase() {
+        assert_eq!(indent("if x {|", &SPACES), "    ");
+        assert_eq!(indent("  def f():  |", &SPACES), "      ");
+        assert_eq!(indent("\tif x {|", &TABS), "\t\t");
+        // Only the text before the cursor counts.
+        assert_eq!(indent("  if x {\n    foo(|bar", &SPACES), "        ");
+        assert_eq!(indent("  if x {|foo", &SPACES), "      ");
+        assert_eq!(indent("  if x |{", &SPACES), "  ");
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        assert_eq!(indent("    foo\n\n  \n|", &SPACES), "    ");
+        assert_eq!(indent("  if x {\n\n|", &SPACES), "      ");
+        assert_eq!(indent("  if x {\n   |", &SPACES), "      ");
+        assert_eq!(indent("\n\n|", &SPACES), "");
+    }
+
+    #[test]
+    fn test_decrease() {
+        assert_eq!(indent("  if x {|}", &SPACES), "  ");
+        assert_eq!(indent("        foo(|  )", &SPACES), "        ");
+        assert_eq!(indent("      foo|}", &SPACES), "  ");
+        assert_eq!(indent("foo|}", &SPACES), "");
+    }
+
+    #[test]
+    fn test_closing_indent() {
+        let closing = |text: &str, cfg| {
+            let offset = text.find('|').unwrap();
+            let text = text.replace('|', "");
+            compute_closing_indent(&text.as_bytes(), offset, cfg)
+        };
+        assert_eq!(closing("if x {\n    foo;\n    |", &SPACES), "");
+        assert_eq!(closing("if x {\n|", &SPACES), "");
+        assert_eq!(closing("\tif x {\n\t\tfoo;\n\n\t\t|", &TABS), "\t");
+        assert_eq!(closing("|", &SPACES), "");
+    }
+
+    #[test]
+    fn test_zero_tab_size() {
+        let cfg = AutoIndentConfig { tab_size: 0, ..TABS };
+        assert_eq!(indent("\tif x {|", &cfg), "\t\t");
+        assert_eq!(indent("  foo|}", &cfg), "\t");
+    }
+}
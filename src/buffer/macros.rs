@@ -0,0 +1,219 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Keyboard macros: Recording editing operations and replaying them.
+//!
+//! Macros are recorded at the level of [`EditOp`]s, not keystrokes,
+//! so that replaying them doesn't depend on the keybindings or the UI state.
+
+use super::{CursorMovement, SearchOptions, TextBuffer};
+use crate::helpers::{CoordType, Point};
+
+/// A single operation of a macro.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditOp {
+    /// Types `text`, like [`TextBuffer::write_canon`].
+    Write(Vec<u8>),
+    /// Like [`TextBuffer::delete`].
+    Delete(CursorMovement, CoordType),
+    /// Like [`TextBuffer::cursor_move_delta`].
+    Move(CursorMovement, CoordType),
+    /// Like [`TextBuffer::selection_update_delta`].
+    Select(CursorMovement, CoordType),
+    /// Moves the cursor up (negative) or down by the given number of lines, keeping its column.
+    MoveLines(CoordType),
+    LineStart,
+    LineEnd,
+    /// Selects the next match, like [`TextBuffer::find_and_select`]. Fails if there's none.
+    FindNext {
+        pattern: String,
+        options: SearchOptions,
+    },
+}
+
+/// Records the [`EditOp`]s applied through it, while recording is active.
+#[derive(Debug, Default, Clone)]
+pub struct MacroRecorder {
+    recording: Option<Vec<EditOp>>,
+    last: Vec<EditOp>,
+}
+
+impl MacroRecorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Starts recording a new macro. A macro that is being recorded is discarded.
+    pub fn start(&mut self) {
+        self.recording = Some(Vec::new());
+    }
+
+    /// Stops recording. The recorded macro replaces the previous one.
+    pub fn stop(&mut self) {
+        if let Some(ops) = self.recording.take() {
+            self.last = ops;
+        }
+    }
+
+    /// The most recently recorded macro.
+    pub fn last_macro(&self) -> &[EditOp] {
+        &self.last
+    }
+
+    /// Applies `op` to `tb` and records it, if it succeeded.
+    pub fn apply(&mut self, tb: &mut TextBuffer, op: EditOp) -> bool {
+        let ok = tb.apply_edit_op(&op);
+        if ok {
+            self.record(op);
+        }
+        ok
+    }
+
+    /// Records `op` without applying it. Consecutive writes are merged into one.
+    pub fn record(&mut self, op: EditOp) {
+        let Some(ops) = &mut self.recording else {
+            return;
+        };
+        if let EditOp::Write(text) = &op
+            && let Some(EditOp::Write(prev)) = ops.last_mut()
+        {
+            prev.extend_from_slice(text);
+            return;
+        }
+        ops.push(op);
+    }
+}
+
+impl TextBuffer {
+    /// Applies a single operation. Returns `false` if it failed,
+    /// which is only the case for a search without a match.
+    pub fn apply_edit_op(&mut self, op: &EditOp) -> bool {
+        match op {
+            EditOp::Write(text) => self.write_canon(text),
+            EditOp::Delete(granularity, delta) => self.delete(*granularity, *delta),
+            EditOp::Move(granularity, delta) => self.cursor_move_delta(*granularity, *delta),
+            EditOp::Select(granularity, delta) => self.selection_update_delta(*granularity, *delta),
+            EditOp::MoveLines(delta) => {
+                let pos = self.cursor.logical_pos;
+                self.cursor_move_to_logical(Point { x: pos.x, y: (pos.y + delta).max(0) });
+            }
+            EditOp::LineStart => {
+                self.cursor_move_to_logical(Point { x: 0, y: self.cursor.logical_pos.y })
+            }
+            EditOp::LineEnd => self
+                .cursor_move_to_logical(Point { x: CoordType::MAX, y: self.cursor.logical_pos.y }),
+            EditOp::FindNext { pattern, options } => {
+                return self.find_and_select(pattern, *options).is_ok() && self.has_selection();
+            }
+        }
+        true
+    }
+
+    /// Replays `ops` up to `times` times and returns how many times it completed.
+    ///
+    /// Each replay is a single undo step. If an operation fails, the replay stops,
+    /// and the changes made by the incomplete replay are undone.
+    pub fn replay_macro(&mut self, ops: &[EditOp], times: usize) -> usize {
+        for i in 0..times {
+            let cursor = self.cursor.logical_pos;
+            let generation = self.buffer.generation();
+
+            self.edit_begin_grouping();
+            let ok = ops.iter().all(|op| self.apply_edit_op(op));
+            self.edit_end_grouping();
+
+            if !ok {
+                if self.buffer.generation() != generation {
+                    self.undo();
+                }
+                self.clear_selection();
+                self.cursor_move_to_logical(cursor);
+                return i;
+            }
+        }
+        times
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{buffer_from, contents};
+
+    #[test]
+    fn test_wrap_in_quotes() {
+        let mut tb = buffer_from("foo bar\nbaz qux\nhello world\nlast one\n");
+        tb.cursor_move_to_logical(Point { x: 4, y: 0 });
+
+        let mut recorder = MacroRecorder::new();
+        recorder.start();
+        for op in [
+            EditOp::Write(b"\"".to_vec()),
+            EditOp::Move(CursorMovement::Word, 1),
+            EditOp::Write(b"\"".to_vec()),
+            EditOp::MoveLines(1),
+            EditOp::LineStart,
+            EditOp::Move(CursorMovement::Word, 1),
+            EditOp::Move(CursorMovement::Grapheme, 1),
+        ] {
+            assert!(recorder.apply(&mut tb, op));
+        }
+        recorder.stop();
+        assert!(!recorder.is_recording());
+        assert_eq!(recorder.last_macro().len(), 7);
+        assert_eq!(contents(&tb), "foo \"bar\"\nbaz qux\nhello world\nlast one\n");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 4, y: 1 });
+
+        let ops = recorder.last_macro().to_vec();
+        assert_eq!(tb.replay_macro(&ops, 3), 3);
+        assert_eq!(contents(&tb), "foo \"bar\"\nbaz \"qux\"\nhello \"world\"\nlast \"one\"\n");
+
+        // Each replay is a single undo step.
+        tb.undo();
+        assert_eq!(contents(&tb), "foo \"bar\"\nbaz \"qux\"\nhello \"world\"\nlast one\n");
+    }
+
+    #[test]
+    fn test_record_merges_writes() {
+        let mut tb = buffer_from("");
+        let mut recorder = MacroRecorder::new();
+        recorder.apply(&mut tb, EditOp::Write(b"ignored".to_vec()));
+
+        recorder.start();
+        recorder.apply(&mut tb, EditOp::Write(b"a".to_vec()));
+        recorder.apply(&mut tb, EditOp::Write(b"b".to_vec()));
+        recorder.apply(&mut tb, EditOp::LineStart);
+        recorder.apply(&mut tb, EditOp::Write(b"c".to_vec()));
+        recorder.stop();
+        assert_eq!(
+            recorder.last_macro(),
+            [EditOp::Write(b"ab".to_vec()), EditOp::LineStart, EditOp::Write(b"c".to_vec())]
+        );
+    }
+
+    #[test]
+    fn test_failing_search() {
+        if crate::icu::init().is_err() {
+            return;
+        }
+
+        let mut tb = buffer_from("a x\nb x\nc\n");
+        tb.cursor_move_to_offset(0);
+
+        let find = EditOp::FindNext { pattern: "x".to_string(), options: SearchOptions::default() };
+        let ops = [find.clone(), EditOp::Write(b"y".to_vec())];
+        assert_eq!(tb.replay_macro(&ops, 5), 2);
+        assert_eq!(contents(&tb), "a y\nb y\nc\n");
+
+        // The operations of an incomplete replay are undone.
+        let ops = [EditOp::Write(b"z".to_vec()), find];
+        tb.cursor_move_to_offset(0);
+        assert_eq!(tb.replay_macro(&ops, 2), 0);
+        assert_eq!(contents(&tb), "a y\nb y\nc\n");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 0, y: 0 });
+    }
+}
@@ -20,8 +20,18 @@
 //! The solution to the former is to keep line caches, which further complicates the architecture.
 //! There's no solution for the latter. However, there's a chance that the performance will still be sufficient.
 
+mod brackets;
+mod encoding;
 mod gap_buffer;
+mod indent;
+mod macros;
 mod navigation;
+mod pipeline;
+mod reload;
+mod snippet;
+mod stats;
+mod transforms;
+mod viewport;
 
 use std::borrow::Cow;
 use std::cell::UnsafeCell;
@@ -33,15 +43,29 @@ use std::mem::{self, MaybeUninit};
 use std::ops::Range;
 use std::rc::Rc;
 use std::str;
-
-pub use gap_buffer::GapBuffer;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+pub use brackets::*;
+pub use encoding::*;
+pub use gap_buffer::{GapBuffer, JournalEntry};
+pub use indent::*;
+pub use macros::*;
+pub use pipeline::*;
+pub use reload::*;
+pub use snippet::*;
+pub use stats::*;
+pub use transforms::*;
+pub use viewport::*;
 
 use crate::arena::{Arena, ArenaString, scratch_arena};
 use crate::cell::SemiRefCell;
-use crate::clipboard::Clipboard;
+use crate::clipboard::{Clipboard, ClipboardRing, KillDirection, RingKill, RingPaste};
+use crate::content_hash::ContentHashes;
+use crate::damage::Damage;
 use crate::document::{ReadableDocument, WriteableDocument};
 use crate::framebuffer::{Framebuffer, IndexedColor};
 use crate::helpers::*;
+use crate::mem_report::{MemoryReport, MemoryUsage};
 use crate::oklab::oklab_blend;
 use crate::simd::memchr2;
 use crate::unicode::{self, Cursor, MeasurementConfig, Utf8Chars};
@@ -64,6 +88,7 @@ const VISUAL_TAB_PREFIX_ADD: usize = '￫'.len_utf8() - 1;
 pub struct TextBufferStatistics {
     logical_lines: CoordType,
     visual_lines: CoordType,
+    counts: DocumentStats,
 }
 
 /// Stores the active text selection anchors.
@@ -132,7 +157,7 @@ struct ActiveSearch {
 }
 
 /// Options for a search operation.
-#[derive(Default, Clone, Copy, Eq, PartialEq)]
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
 pub struct SearchOptions {
     /// If true, the search is case-sensitive.
     pub match_case: bool,
@@ -179,6 +204,7 @@ struct ActiveEditGroupInfo {
 }
 
 /// Char- or word-wise navigation? Your choice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CursorMovement {
     Grapheme,
     Word,
@@ -218,9 +244,21 @@ pub struct TextBuffer {
     active_edit_line_info: Option<ActiveEditLineInfo>,
     active_edit_depth: i32,
     active_edit_off: usize,
+    /// The first visual row the active edit touched and the visual line count before it.
+    active_edit_damage: (CoordType, CoordType),
+
+    /// The visual rows changed since the last [`TextBuffer::take_damage`].
+    damage: Damage,
+    /// The buffer generation `damage` is up to date with.
+    damage_generation: u32,
+    viewport: ViewportCache,
 
+    /// Identifies this buffer among all others. See [`RingKill::buffer`].
+    id: u32,
     stats: TextBufferStatistics,
     cursor: Cursor,
+    /// Incremented whenever the cursor is moved. See [`TextBuffer::cursor_generation`].
+    cursor_generation: u32,
     // When scrolling significant amounts of text away from the cursor,
     // rendering will naturally slow down proportionally to the distance.
     // To avoid this, we cache the cursor position for rendering.
@@ -258,6 +296,8 @@ impl TextBuffer {
     /// Creates a new text buffer. With `small` you can control
     /// if the buffer is optimized for <1MiB contents.
     pub fn new(small: bool) -> apperr::Result<Self> {
+        static NEXT_ID: AtomicU32 = AtomicU32::new(0);
+
         Ok(Self {
             buffer: GapBuffer::new(small)?,
 
@@ -270,9 +310,20 @@ impl TextBuffer {
             active_edit_line_info: None,
             active_edit_depth: 0,
             active_edit_off: 0,
-
-            stats: TextBufferStatistics { logical_lines: 1, visual_lines: 1 },
+            active_edit_damage: (0, 0),
+
+            damage: Damage::new(),
+            damage_generation: 0,
+            viewport: ViewportCache::new(),
+
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            stats: TextBufferStatistics {
+                logical_lines: 1,
+                visual_lines: 1,
+                counts: DocumentStats::default(),
+            },
             cursor: Default::default(),
+            cursor_generation: 0,
             cursor_for_rendering: None,
             selection: None,
             selection_generation: 0,
@@ -325,6 +376,43 @@ impl TextBuffer {
         self.buffer.generation()
     }
 
+    /// Returns the visual rows that changed since the last call and resets them.
+    /// Changes that weren't made through an edit, like loading a file, damage all rows.
+    pub fn take_damage(&mut self) -> Damage {
+        self.damage_untracked_changes();
+        self.damage.take()
+    }
+
+    fn damage_untracked_changes(&mut self) {
+        if self.damage_generation != self.buffer.generation() {
+            self.damage.mark_all();
+            self.damage_generation = self.buffer.generation();
+        }
+    }
+
+    /// Starts or stops recording the changes to the contents, e.g. for [`crate::swap`].
+    /// Stopping discards those not yet taken.
+    pub fn set_journaling(&mut self, enabled: bool) {
+        self.buffer.set_journaling(enabled);
+    }
+
+    /// Returns the changes to the contents since the last call, if journaling is enabled.
+    /// Replaying them in order on the previous contents results in the current ones.
+    pub fn take_journal(&mut self) -> Vec<JournalEntry> {
+        self.buffer.take_journal()
+    }
+
+    /// Like [`TextBuffer::generation`], but changes whenever the cursor is moved,
+    /// even if it ends up where it was.
+    pub fn cursor_generation(&self) -> u32 {
+        self.cursor_generation
+    }
+
+    /// Hashes the buffer contents for [`ContentHashes::check_file`].
+    pub fn content_hashes(&self) -> ContentHashes {
+        ContentHashes::of_document(&self.buffer)
+    }
+
     /// Force the buffer to be dirty.
     pub fn mark_as_dirty(&mut self) {
         self.last_save_generation = self.buffer.generation().wrapping_sub(1);
@@ -424,6 +512,7 @@ impl TextBuffer {
         }
 
         self.newlines_are_crlf = crlf;
+        self.recount_document_stats();
     }
 
     /// If enabled, automatically insert a final newline
@@ -613,6 +702,8 @@ impl TextBuffer {
         self.cursor_for_rendering = None;
 
         if force || self.word_wrap_column != word_wrap_column_before {
+            self.damage.mark_all();
+
             // Recalculate the cursor position.
             self.cursor = self.cursor_move_to_logical_internal(
                 if self.word_wrap_column > 0 {
@@ -643,6 +734,8 @@ impl TextBuffer {
             let delete = self.buffer.len() - self.cursor.offset;
             if delete != 0 {
                 self.buffer.allocate_gap(self.cursor.offset, 0, delete);
+                // The counts still include the text that was just cut off.
+                self.recount_document_stats();
             }
         }
     }
@@ -655,6 +748,7 @@ impl TextBuffer {
         self.cursor = Default::default();
         self.set_selection(None);
         self.mark_as_clean();
+        self.recount_document_stats();
         self.reflow();
     }
 
@@ -1697,6 +1791,7 @@ impl TextBuffer {
                 && cursor.visual_pos.y <= self.stats.visual_lines
         );
         self.cursor = cursor;
+        self.cursor_generation = self.cursor_generation.wrapping_add(1);
     }
 
     /// Extracts a rectangular region of the text buffer and writes it to the framebuffer.
@@ -1722,14 +1817,11 @@ impl TextBuffer {
         let mut line = ArenaString::new_in(&scratch);
         let mut visual_pos_x_max = 0;
 
-        // Pick the cursor closer to the `origin.y`.
-        let mut cursor = {
-            let a = self.cursor;
-            let b = self.cursor_for_rendering.unwrap_or_default();
-            let da = (a.visual_pos.y - origin.y).abs();
-            let db = (b.visual_pos.y - origin.y).abs();
-            if da < db { a } else { b }
-        };
+        // Only the rows that were edited or scrolled into view are laid out again.
+        let mut viewport = mem::take(&mut self.viewport);
+        viewport.invalidate(&self.take_damage());
+        viewport.update(self, origin, Size { width: text_width, height });
+        let rows = viewport.rows();
 
         let [selection_beg, selection_end] = match self.selection {
             None => [Point::MIN, Point::MIN],
@@ -1742,12 +1834,7 @@ impl TextBuffer {
             line.clear();
 
             let visual_line = origin.y + y;
-            let mut cursor_beg =
-                self.cursor_move_to_visual_internal(cursor, Point { x: origin.x, y: visual_line });
-            let cursor_end = self.cursor_move_to_visual_internal(
-                cursor_beg,
-                Point { x: origin.x + text_width, y: visual_line },
-            );
+            let ViewportRow { beg: mut cursor_beg, end: cursor_end } = rows[y as usize];
 
             // Accelerate the next render pass by remembering where we started off.
             if y == 0 {
@@ -1962,10 +2049,10 @@ impl TextBuffer {
             }
 
             fb.replace_text(destination.top + y, destination.left, destination.right, &line);
-
-            cursor = cursor_end;
         }
 
+        self.viewport = viewport;
+
         // Colorize the margin that we wrote above.
         if self.margin_width > 0 {
             let margin = Rect {
@@ -2066,6 +2153,109 @@ impl TextBuffer {
         }
     }
 
+    /// Pastes the newest entry of the `ring` and remembers where, for [`TextBuffer::paste_cycle`].
+    pub fn paste_from_ring(&mut self, ring: &mut ClipboardRing) {
+        let Some(entry) = ring.get(0) else {
+            return;
+        };
+
+        let beg = match self.selection_range_internal(false) {
+            Some((beg, _)) => beg.offset,
+            None => self.cursor.offset,
+        };
+        self.write(entry, self.cursor, true);
+        ring.set_last_paste(Some(RingPaste {
+            index: 0,
+            beg,
+            end: self.cursor.offset,
+            generation: self.generation(),
+        }));
+    }
+
+    /// Replaces the text that was just pasted via [`TextBuffer::paste_from_ring`]
+    /// with the next older entry of the `ring`, wrapping around at the end.
+    ///
+    /// Returns `false` if the document was modified since the paste.
+    pub fn paste_cycle(&mut self, ring: &mut ClipboardRing) -> bool {
+        let Some(paste) = ring.last_paste() else {
+            return false;
+        };
+        if paste.generation != self.generation() || ring.is_empty() {
+            return false;
+        }
+
+        let index = (paste.index + 1) % ring.len();
+        let text = self.normalize_newlines_of(ring.get(index).unwrap_or_default()).into_owned();
+        let beg = self.cursor_move_to_offset_internal(self.cursor, paste.beg);
+        let end = self.cursor_move_to_offset_internal(beg, paste.end);
+
+        self.set_selection(None);
+        self.edit_replace(beg, end, &text);
+        ring.set_last_paste(Some(RingPaste {
+            index,
+            beg: paste.beg,
+            end: self.cursor.offset,
+            generation: self.generation(),
+        }));
+        true
+    }
+
+    /// Deletes text from the cursor like [`TextBuffer::delete`] and adds it to the `ring`.
+    /// If there's a selection, it's deleted instead.
+    ///
+    /// Consecutive kills are collected into the same entry. See [`ClipboardRing::kill`].
+    pub fn kill(
+        &mut self,
+        ring: &mut ClipboardRing,
+        granularity: CursorMovement,
+        delta: CoordType,
+    ) {
+        let target = self.cursor_move_delta_internal(self.cursor, granularity, delta);
+        self.kill_to(ring, target);
+    }
+
+    /// Kills the rest of the line, including the newline, like [`TextBuffer::kill`].
+    pub fn kill_line(&mut self, ring: &mut ClipboardRing) {
+        let y = self.cursor.logical_pos.y;
+        let line_end =
+            self.cursor_move_to_logical_internal(self.cursor, Point { x: CoordType::MAX, y });
+        let target = self.cursor_move_delta_internal(line_end, CursorMovement::Grapheme, 1);
+        self.kill_to(ring, target);
+    }
+
+    fn kill_to(&mut self, ring: &mut ClipboardRing, target: Cursor) {
+        let before = self.ring_kill_state();
+        let (beg, end, direction) = if let Some((beg, end)) = self.selection_range_internal(false) {
+            (beg, end, KillDirection::Forward)
+        } else if target.offset < self.cursor.offset {
+            (target, self.cursor, KillDirection::Backward)
+        } else {
+            (self.cursor, target, KillDirection::Forward)
+        };
+        if beg.offset == end.offset {
+            return;
+        }
+
+        let mut text = Vec::new();
+        self.buffer.extract_raw(beg.offset..end.offset, &mut text, 0);
+
+        self.edit_begin(HistoryType::Delete, beg);
+        self.edit_delete(end);
+        self.edit_end();
+        self.set_selection(None);
+
+        let after = self.ring_kill_state();
+        ring.kill(text, direction, before, after);
+    }
+
+    fn ring_kill_state(&self) -> RingKill {
+        RingKill {
+            buffer: self.id,
+            generation: self.generation(),
+            cursor_generation: self.cursor_generation,
+        }
+    }
+
     /// Inserts the user input `text` at the current cursor position.
     /// Replaces tabs with whitespace if needed, etc.
     pub fn write_canon(&mut self, text: &[u8]) {
@@ -2105,12 +2295,43 @@ impl TextBuffer {
             self.edit_begin(history_type, at);
         }
 
+        if raw {
+            // Raw writes are pastes most of the time, which may be megabytes large.
+            // Normalizing the newlines upfront allows us to insert them in one go.
+            let text = self.normalize_newlines_of(text);
+            self.edit_write(&text);
+        } else {
+            self.write_lines_canon(text);
+        }
+
+        // POSIX mandates that all valid lines end in a newline.
+        // This isn't all that common on Windows and so we have
+        // `self.final_newline` to control this.
+        //
+        // In order to not annoy people with this, we only add a
+        // newline if you just edited the very end of the buffer.
+        if self.insert_final_newline
+            && self.cursor.offset == self.text_length()
+            && let Some(edit) = (EnsureFinalNewline { crlf: self.newlines_are_crlf })
+                .edit_at_end(self.text_length(), self.read_backward(self.text_length()))
+        {
+            let cursor = self.cursor;
+            self.edit_write(&edit.text);
+            self.set_cursor_internal(cursor);
+        }
+
+        self.edit_end();
+    }
+
+    /// Writes user input line by line, translating tabs and
+    /// giving each new line the indentation of the previous one.
+    fn write_lines_canon(&mut self, text: &[u8]) {
         let mut offset = 0;
         let scratch = scratch_arena(None);
         let mut newline_buffer = ArenaString::new_in(&scratch);
 
         loop {
-            // Can't use `unicode::newlines_forward` because bracketed paste uses CR instead of LF/CRLF.
+            // Can't use `unicode::newlines_forward` because some terminals send CR instead of LF/CRLF.
             let offset_next = memchr2(b'\r', b'\n', text, offset);
             let line = &text[offset..offset_next];
             let column_before = self.cursor.logical_pos.x;
@@ -2120,7 +2341,7 @@ impl TextBuffer {
             while line_off < line.len() {
                 // Split the line into chunks of non-tabs and tabs.
                 let mut plain = line;
-                if !raw && !self.indent_with_tabs {
+                if !self.indent_with_tabs {
                     let end = memchr2(b'\t', b'\t', line, line_off);
                     plain = &line[line_off..end];
                 }
@@ -2138,7 +2359,7 @@ impl TextBuffer {
                 }
             }
 
-            if !raw && self.overtype {
+            if self.overtype {
                 let delete = self.cursor.logical_pos.x - column_before;
                 let end = self.cursor_move_to_logical_internal(
                     self.cursor,
@@ -2156,46 +2377,44 @@ impl TextBuffer {
             newline_buffer.clear();
             newline_buffer.push_str(if self.newlines_are_crlf { "\r\n" } else { "\n" });
 
-            if !raw {
-                // We'll give the next line the same indentation as the previous one.
-                // This block figures out how much that is. We can't reuse that value,
-                // because "  a\n  a\n" should give the 3rd line a total indentation of 4.
-                // Assuming your terminal has bracketed paste, this won't be a concern though.
-                // (If it doesn't, use a different terminal.)
-                let line_beg = self.goto_line_start(self.cursor, self.cursor.logical_pos.y);
-                let limit = self.cursor.offset;
-                let mut off = line_beg.offset;
-                let mut newline_indentation = 0;
-
-                'outer: while off < limit {
-                    let chunk = self.read_forward(off);
-                    let chunk = &chunk[..chunk.len().min(limit - off)];
-
-                    for &c in chunk {
-                        if c == b' ' {
-                            newline_indentation += 1;
-                        } else if c == b'\t' {
-                            newline_indentation += self.tab_size_eval(newline_indentation);
-                        } else {
-                            break 'outer;
-                        }
-                    }
+            // We'll give the next line the same indentation as the previous one.
+            // This block figures out how much that is. We can't reuse that value,
+            // because "  a\n  a\n" should give the 3rd line a total indentation of 4.
+            // Assuming your terminal has bracketed paste, this won't be a concern though.
+            // (If it doesn't, use a different terminal.)
+            let line_beg = self.goto_line_start(self.cursor, self.cursor.logical_pos.y);
+            let limit = self.cursor.offset;
+            let mut off = line_beg.offset;
+            let mut newline_indentation = 0;
+
+            'outer: while off < limit {
+                let chunk = self.read_forward(off);
+                let chunk = &chunk[..chunk.len().min(limit - off)];
 
-                    off += chunk.len();
+                for &c in chunk {
+                    if c == b' ' {
+                        newline_indentation += 1;
+                    } else if c == b'\t' {
+                        newline_indentation += self.tab_size_eval(newline_indentation);
+                    } else {
+                        break 'outer;
+                    }
                 }
 
-                // If tabs are enabled, add as many tabs as we can.
-                if self.indent_with_tabs {
-                    let tab_count = newline_indentation / self.tab_size;
-                    newline_buffer.push_repeat('\t', tab_count as usize);
-                    newline_indentation -= tab_count * self.tab_size;
-                }
+                off += chunk.len();
+            }
 
-                // If tabs are disabled, or if the indentation wasn't a multiple of the tab size,
-                // add spaces to make up the difference.
-                newline_buffer.push_repeat(' ', newline_indentation as usize);
+            // If tabs are enabled, add as many tabs as we can.
+            if self.indent_with_tabs {
+                let tab_count = newline_indentation / self.tab_size;
+                newline_buffer.push_repeat('\t', tab_count as usize);
+                newline_indentation -= tab_count * self.tab_size;
             }
 
+            // If tabs are disabled, or if the indentation wasn't a multiple of the tab size,
+            // add spaces to make up the difference.
+            newline_buffer.push_repeat(' ', newline_indentation as usize);
+
             self.edit_write(newline_buffer.as_bytes());
 
             // Skip one CR/LF/CRLF.
@@ -2215,24 +2434,39 @@ impl TextBuffer {
                 break;
             }
         }
+    }
 
-        // POSIX mandates that all valid lines end in a newline.
-        // This isn't all that common on Windows and so we have
-        // `self.final_newline` to control this.
-        //
-        // In order to not annoy people with this, we only add a
-        // newline if you just edited the very end of the buffer.
-        if self.insert_final_newline
-            && self.cursor.offset > 0
-            && self.cursor.offset == self.text_length()
-            && self.cursor.logical_pos.x > 0
-        {
-            let cursor = self.cursor;
-            self.edit_write(if self.newlines_are_crlf { b"\r\n" } else { b"\n" });
-            self.set_cursor_internal(cursor);
+    /// Converts all CR, LF and CRLF in `text` to the newline type of the document.
+    fn normalize_newlines_of<'a>(&self, text: &'a [u8]) -> Cow<'a, [u8]> {
+        let newline: &[u8] = if self.newlines_are_crlf { b"\r\n" } else { b"\n" };
+        let mut res = Vec::new();
+        let mut beg = 0;
+        let mut off = 0;
+
+        loop {
+            off = memchr2(b'\r', b'\n', text, off);
+            if off >= text.len() {
+                break;
+            }
+
+            let len = if text[off..].starts_with(b"\r\n") { 2 } else { 1 };
+            if &text[off..off + len] != newline {
+                if res.is_empty() {
+                    res.reserve(text.len() + text.len() / 16);
+                }
+                res.extend_from_slice(&text[beg..off]);
+                res.extend_from_slice(newline);
+                beg = off + len;
+            }
+            off += len;
         }
 
-        self.edit_end();
+        if beg == 0 {
+            Cow::Borrowed(text)
+        } else {
+            res.extend_from_slice(&text[beg..]);
+            Cow::Owned(res)
+        }
     }
 
     /// Deletes 1 grapheme cluster from the buffer.
@@ -2586,6 +2820,8 @@ impl TextBuffer {
         }
 
         self.active_edit_off = cursor.offset;
+        self.damage_untracked_changes();
+        self.active_edit_damage = (cursor.visual_pos.y, self.stats.visual_lines);
 
         // If word-wrap is enabled, the visual layout of all logical lines affected by the write
         // may have changed. This includes even text before the insertion point up to the line
@@ -2597,6 +2833,7 @@ impl TextBuffer {
                 cursor,
                 Point { x: 0, y: cursor.logical_pos.y + 1 },
             );
+            self.active_edit_damage.0 = safe_start.visual_pos.y;
             self.active_edit_line_info = Some(ActiveEditLineInfo {
                 safe_start,
                 line_height_in_rows: next_line.visual_pos.y - safe_start.visual_pos.y,
@@ -2605,6 +2842,24 @@ impl TextBuffer {
         }
     }
 
+    /// Replaces the text between `beg` and `end` with `text` as a single undo step.
+    /// Afterwards, the cursor is placed at the end of the inserted text.
+    fn edit_replace(&mut self, beg: Cursor, end: Cursor, text: &[u8]) {
+        debug_assert!(beg.offset <= end.offset);
+        if beg.offset == end.offset && text.is_empty() {
+            return;
+        }
+
+        self.edit_begin(HistoryType::Other, beg);
+        if beg.offset != end.offset {
+            self.edit_delete(end);
+        }
+        if !text.is_empty() {
+            self.edit_write(text);
+        }
+        self.edit_end();
+    }
+
     /// Writes `text` into the buffer at the current cursor position.
     /// It records the change in the undo stack.
     fn edit_write(&mut self, text: &[u8]) {
@@ -2616,6 +2871,10 @@ impl TextBuffer {
             undo.added.extend_from_slice(text);
         }
 
+        let prev = self.buffer.read_backward(self.active_edit_off).last().copied();
+        let next = self.buffer.read_forward(self.active_edit_off).first().copied();
+        self.stats.counts.apply_edit(prev, b"", text, next);
+
         // Write!
         self.buffer.replace(self.active_edit_off..self.active_edit_off, text);
 
@@ -2648,8 +2907,14 @@ impl TextBuffer {
         let deleted = &mut undo.deleted;
         self.buffer.extract_raw(off..to.offset, deleted, out_off);
 
-        // Delete the portion from the buffer by enlarging the gap.
         let count = to.offset - off;
+        let removed =
+            if out_off == 0 { &deleted[..count] } else { &deleted[deleted.len() - count..] };
+        let prev = self.buffer.read_backward(off).last().copied();
+        let next = self.buffer.read_forward(to.offset).first().copied();
+        self.stats.counts.apply_edit(prev, removed, b"", next);
+
+        // Delete the portion from the buffer by enlarging the gap.
         self.buffer.allocate_gap(off, 0, count);
 
         self.stats.logical_lines += logical_y_before - to.logical_pos.y;
@@ -2701,6 +2966,24 @@ impl TextBuffer {
         }
 
         self.recalc_after_content_changed();
+
+        // The edited lines are damaged. If the line count changed, so is everything below them.
+        let (top, visual_lines_before) = self.active_edit_damage;
+        let bottom = if self.stats.visual_lines != visual_lines_before {
+            CoordType::MAX
+        } else {
+            let next_line = self.cursor_move_to_logical_internal(
+                self.cursor,
+                Point { x: 0, y: self.cursor.logical_pos.y + 1 },
+            );
+            if next_line.logical_pos.y > self.cursor.logical_pos.y {
+                next_line.visual_pos.y
+            } else {
+                CoordType::MAX
+            }
+        };
+        self.damage.add_rows(top..bottom);
+        self.damage_generation = self.buffer.generation();
     }
 
     /// Undo the last edit operation.
@@ -2822,6 +3105,7 @@ impl TextBuffer {
                 change.cursor_before = self.cursor.logical_pos;
                 // Can't use `set_cursor_internal` here, because we haven't updated the line stats yet.
                 self.cursor = cursor_before;
+                self.cursor_generation = self.cursor_generation.wrapping_add(1);
 
                 if self.undo_stack.is_empty() {
                     self.last_history_type = HistoryType::Other;
@@ -2830,6 +3114,8 @@ impl TextBuffer {
         }
 
         if entry_buffer_generation.is_some() {
+            // The generation was rewound above, so `take_damage` can't tell that it changed.
+            self.damage.mark_all();
             self.recalc_after_content_changed();
         }
     }
@@ -2855,6 +3141,37 @@ pub enum Bom {
     GB18030,
 }
 
+impl MemoryUsage for TextBuffer {
+    fn memory_usage(&self) -> MemoryReport {
+        MemoryReport::node(
+            "TextBuffer",
+            vec![
+                self.buffer.memory_usage().named("text"),
+                history_memory_usage("undo", &self.undo_stack),
+                history_memory_usage("redo", &self.redo_stack),
+            ],
+        )
+    }
+}
+
+fn history_memory_usage(
+    name: &'static str,
+    stack: &LinkedList<SemiRefCell<HistoryEntry>>,
+) -> MemoryReport {
+    // Each list node holds the entry and two pointers.
+    let mut used =
+        stack.len() * (mem::size_of::<SemiRefCell<HistoryEntry>>() + 2 * mem::size_of::<usize>());
+    let mut spare = 0;
+    for entry in stack {
+        let entry = entry.borrow();
+        for v in [&entry.deleted, &entry.added] {
+            used += v.len();
+            spare += v.capacity() - v.len();
+        }
+    }
+    MemoryReport::leaf(name, used, spare)
+}
+
 const BOM_MAX_LEN: usize = 4;
 
 fn detect_bom(bytes: &[u8]) -> Option<&'static str> {
@@ -2882,3 +3199,250 @@ fn detect_bom(bytes: &[u8]) -> Option<&'static str> {
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{buffer_from, contents};
+
+    #[test]
+    fn test_damage() {
+        let mut tb = buffer_from("a\nb\nc\nd\n");
+        tb.take_damage();
+
+        // An edit within a line damages just that line.
+        tb.cursor_move_to_logical(Point { x: 1, y: 1 });
+        tb.write_raw(b"x");
+        let damage = tb.take_damage();
+        assert!(!damage.is_all());
+        assert_eq!(damage.rows().len(), 1);
+        assert_eq!(damage.rows()[0], 1..2);
+        assert!(tb.take_damage().is_empty());
+
+        // Adding a line damages all lines below it as well.
+        tb.write_raw(b"\n");
+        let damage = tb.take_damage();
+        assert_eq!(damage.rows().len(), 1);
+        assert_eq!(damage.rows()[0], 1..CoordType::MAX);
+
+        // Other changes damage everything.
+        tb.undo();
+        assert!(tb.take_damage().is_all());
+        tb.copy_from_str(&"a\nb\nc\nd\n".to_string());
+        assert!(tb.take_damage().is_all());
+    }
+
+    #[test]
+    fn test_render_damage() {
+        let size = Size { width: 20, height: 10 };
+        let destination = Rect { left: 0, top: 0, right: size.width, bottom: size.height };
+        let mut fb = Framebuffer::new();
+        let text: String = (0..100).map(|i| format!("line {i}\n")).collect();
+        let mut tb = buffer_from(&text);
+        tb.set_width(size.width);
+
+        // Renders and checks the cached rows against freshly laid out ones.
+        let mut render = |tb: &mut TextBuffer, origin: Point| {
+            fb.flip(size);
+            tb.render(origin, destination, false, &mut fb);
+            let mut fresh = ViewportCache::new();
+            fresh.update(tb, origin, size);
+            assert_eq!(tb.viewport.rows(), fresh.rows());
+            tb.viewport.computed()
+        };
+
+        assert_eq!(render(&mut tb, Point { x: 0, y: 0 }), 10);
+
+        // Only the edited row is laid out again...
+        tb.cursor_move_to_logical(Point { x: 0, y: 3 });
+        tb.write_raw(b"x");
+        assert_eq!(render(&mut tb, Point { x: 0, y: 0 }), 11);
+
+        // ...and the one scrolled into view.
+        assert_eq!(render(&mut tb, Point { x: 0, y: 1 }), 12);
+
+        // Undoing changes the text without an edit, so everything is laid out again.
+        tb.undo();
+        assert_eq!(render(&mut tb, Point { x: 0, y: 1 }), 22);
+    }
+
+    #[test]
+    fn test_paste_large() {
+        let mut clipboard = Clipboard::default();
+        let line = "The quick brown fox jumps over the lazy dog.";
+        let lines = 100_000;
+        let mut payload = Vec::new();
+        for i in 0..lines {
+            payload.extend_from_slice(line.as_bytes());
+            payload.extend_from_slice(if i % 2 == 0 { b"\r" } else { b"\r\n" });
+        }
+        clipboard.write(payload);
+
+        // Small buffers are capped in size.
+        let mut tb = TextBuffer::new(false).unwrap();
+        tb.set_crlf(false);
+        tb.write_raw(b"ab");
+        tb.cursor_move_to_logical(Point { x: 1, y: 0 });
+        tb.paste(&clipboard);
+
+        // The paste is a single undo step...
+        assert_eq!(tb.undo_stack.len(), 2);
+        assert_eq!(tb.logical_line_count(), lines + 1);
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 0, y: lines });
+
+        // ...with its newlines normalized.
+        let expected = format!("a{}b", format!("{line}\n").repeat(lines as usize));
+        assert_eq!(contents(&tb), expected);
+
+        tb.undo();
+        assert_eq!(contents(&tb), "ab");
+    }
+
+    #[test]
+    fn test_paste_cycle() {
+        let mut ring = ClipboardRing::new(3);
+        ring.push(b"one".to_vec());
+        ring.push(b"two\nlines".to_vec());
+        ring.push(b"three".to_vec());
+
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_crlf(false);
+        tb.write_raw(b"<>");
+        tb.cursor_move_to_logical(Point { x: 1, y: 0 });
+
+        tb.paste_from_ring(&mut ring);
+        assert_eq!(contents(&tb), "<three>");
+        let undo_len = tb.undo_stack.len();
+
+        assert!(tb.paste_cycle(&mut ring));
+        assert_eq!(contents(&tb), "<two\nlines>");
+        assert_eq!(tb.undo_stack.len(), undo_len + 1);
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 5, y: 1 });
+
+        assert!(tb.paste_cycle(&mut ring));
+        assert_eq!(contents(&tb), "<one>");
+        assert_eq!(tb.undo_stack.len(), undo_len + 2);
+
+        // Wraps around to the newest entry.
+        assert!(tb.paste_cycle(&mut ring));
+        assert_eq!(contents(&tb), "<three>");
+
+        // Each cycle is undone on its own.
+        tb.undo();
+        assert_eq!(contents(&tb), "<one>");
+
+        // Any other modification ends the cycle.
+        assert!(!tb.paste_cycle(&mut ring));
+        tb.redo();
+        tb.write_raw(b"!");
+        assert!(!tb.paste_cycle(&mut ring));
+        assert_eq!(contents(&tb), "<three!>");
+    }
+
+    #[test]
+    fn test_kill_sequence() {
+        let mut ring = ClipboardRing::new(3);
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_crlf(false);
+        tb.write_raw(b"one\ntwo\nthree\nfour\nfoo bar baz");
+        tb.cursor_move_to_logical(Point { x: 0, y: 0 });
+
+        for _ in 0..3 {
+            tb.kill_line(&mut ring);
+        }
+        assert_eq!(contents(&tb), "four\nfoo bar baz");
+        assert_eq!(ring.len(), 1);
+        assert_eq!(ring.get(0), Some(&b"one\ntwo\nthree\n"[..]));
+
+        // Moving the cursor in between starts a new entry, even if it ends up where it was.
+        tb.cursor_move_delta(CursorMovement::Grapheme, 1);
+        tb.cursor_move_delta(CursorMovement::Grapheme, -1);
+        tb.kill_line(&mut ring);
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.get(0), Some(&b"four\n"[..]));
+
+        // Backward kills are prepended.
+        tb.cursor_move_to_logical(Point { x: CoordType::MAX, y: 0 });
+        tb.kill(&mut ring, CursorMovement::Word, -1);
+        tb.kill(&mut ring, CursorMovement::Word, -1);
+        assert_eq!(contents(&tb), "foo ");
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.get(0), Some(&b"bar baz"[..]));
+
+        // So do other edits in between.
+        tb.write_raw(b"x");
+        tb.kill(&mut ring, CursorMovement::Grapheme, -1);
+        assert_eq!(ring.get(0), Some(&b"x"[..]));
+        assert_eq!(ring.get(1), Some(&b"bar baz"[..]));
+
+        // And undo/redo, even though redo restores the generation from right after the kill.
+        tb.write_raw(b"y");
+        tb.kill(&mut ring, CursorMovement::Grapheme, -1);
+        tb.undo();
+        tb.redo();
+        tb.kill(&mut ring, CursorMovement::Grapheme, -1);
+        assert_eq!(contents(&tb), "foo");
+        assert_eq!(ring.get(0), Some(&b" "[..]));
+        assert_eq!(ring.get(1), Some(&b"y"[..]));
+    }
+
+    #[test]
+    fn test_kill_sequence_buffers() {
+        let mut ring = ClipboardRing::new(3);
+        let mut a = TextBuffer::new(true).unwrap();
+        let mut b = TextBuffer::new(true).unwrap();
+        a.write_raw(b"a");
+        b.write_raw(b"b");
+
+        // Both buffers are at the same generations, but kills in another buffer never chain.
+        assert_eq!(a.generation(), b.generation());
+        a.kill(&mut ring, CursorMovement::Grapheme, -1);
+        b.kill(&mut ring, CursorMovement::Grapheme, -1);
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.get(0), Some(&b"b"[..]));
+        assert_eq!(ring.get(1), Some(&b"a"[..]));
+    }
+
+    #[test]
+    fn test_normalize_newlines() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_crlf(false);
+        assert!(matches!(tb.normalize_newlines_of(b"a\nb\n"), Cow::Borrowed(_)));
+        assert_eq!(&*tb.normalize_newlines_of(b"a\r\nb\rc\r\r\nd\n"), b"a\nb\nc\n\nd\n");
+
+        tb.set_crlf(true);
+        assert!(matches!(tb.normalize_newlines_of(b"a\r\nb"), Cow::Borrowed(_)));
+        assert_eq!(&*tb.normalize_newlines_of(b"\na\rb\r\n"), b"\r\na\r\nb\r\n");
+    }
+
+    #[test]
+    fn test_normalize_newlines_cursor() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_crlf(false);
+        tb.write_raw(b"a\nb\nc");
+        tb.cursor_move_to_logical(Point { x: 1, y: 1 });
+
+        tb.normalize_newlines(true);
+        assert!(tb.is_crlf());
+        let mut s = String::new();
+        tb.save_as_string(&mut s);
+        assert_eq!(s, "a\r\nb\r\nc");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 1, y: 1 });
+        assert_eq!(tb.cursor.offset, 4);
+    }
+
+    #[test]
+    fn test_memory_usage() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.write_raw(b"hello");
+        tb.write_raw(b"\n");
+        tb.undo();
+
+        let report = tb.memory_usage();
+        let [text, undo, redo] = &report.children[..] else { panic!() };
+        assert_eq!(text.used, 5);
+        assert!(text.spare > 0);
+        assert!(undo.used >= 5 && redo.used >= 1);
+        assert_eq!(report.total_used(), text.used + undo.used + redo.used);
+    }
+}
@@ -0,0 +1,491 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Whole-document transforms, such as trimming trailing whitespace on save.
+//!
+//! A [`BufferTransform`] doesn't modify the buffer itself. It looks at the text and returns
+//! the edits it would like to make. [`TextBuffer::apply_transforms`] runs several of them
+//! against the same text, merges their edits and applies them as a single undo step.
+//! Since all transforms see the original text, they can't step on each other's toes,
+//! except by editing the same bytes, which is reported as a [`TransformConflict`].
+
+use std::ops::Range;
+
+use super::{EncodingError, TextBuffer};
+use crate::save_policy::{Eol, SavePolicy};
+
+/// Replaces the bytes in `range` with `text`.
+///
+/// The range must not split grapheme clusters, which includes CRLF.
+/// Otherwise, it's widened to the enclosing cluster boundaries when applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: Vec<u8>,
+}
+
+/// A transform over the entire document. See the module documentation.
+pub trait BufferTransform {
+    /// A short name for reporting, e.g. "trim trailing whitespace".
+    fn name(&self) -> &str;
+
+    /// Returns the edits to make to `text`, in any order.
+    fn apply(&self, text: &[u8]) -> Vec<TextEdit>;
+}
+
+/// How many changes a transform made. Returned by [`TextBuffer::apply_transforms`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransformReport {
+    pub name: String,
+    pub changes: usize,
+}
+
+/// Why [`TextBuffer::apply_transforms`] didn't change anything.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransformError {
+    Conflict(TransformConflict),
+    /// The named transform returned an edit whose range is inverted or past the end of the text.
+    InvalidRange {
+        name: String,
+        range: Range<usize>,
+    },
+}
+
+/// Two transforms (or the same one twice) tried to edit overlapping bytes.
+/// Insertions at the same offset don't conflict: They're applied in pipeline order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransformConflict {
+    pub first: String,
+    pub second: String,
+    /// The offset at which the edits overlap.
+    pub offset: usize,
+}
+
+/// Removes spaces and tabs at the end of each line.
+pub struct TrimTrailingWhitespace;
+
+impl BufferTransform for TrimTrailingWhitespace {
+    fn name(&self) -> &str {
+        "trim trailing whitespace"
+    }
+
+    fn apply(&self, text: &[u8]) -> Vec<TextEdit> {
+        let mut edits = Vec::new();
+        let mut beg = 0;
+
+        for line in text.split_inclusive(|&b| b == b'\n') {
+            let content = line.strip_suffix(b"\n").unwrap_or(line);
+            let content = content.strip_suffix(b"\r").unwrap_or(content);
+            let trimmed =
+                content.iter().rposition(|&b| b != b' ' && b != b'\t').map_or(0, |i| i + 1);
+            if trimmed < content.len() {
+                edits
+                    .push(TextEdit { range: beg + trimmed..beg + content.len(), text: Vec::new() });
+            }
+            beg += line.len();
+        }
+
+        edits
+    }
+}
+
+/// Converts all newlines to CRLF or LF. Unlike [`TextBuffer::normalize_newlines`],
+/// this can be undone, but it doesn't change the newline type used for typing.
+pub struct NormalizeNewlines {
+    pub crlf: bool,
+}
+
+impl BufferTransform for NormalizeNewlines {
+    fn name(&self) -> &str {
+        "normalize newlines"
+    }
+
+    fn apply(&self, text: &[u8]) -> Vec<TextEdit> {
+        let mut edits = Vec::new();
+
+        for (i, _) in text.iter().enumerate().filter(|&(_, &b)| b == b'\n') {
+            let is_crlf = i > 0 && text[i - 1] == b'\r';
+            // The whole newline is replaced, since an edit can't split a CRLF.
+            if self.crlf && !is_crlf {
+                edits.push(TextEdit { range: i..i + 1, text: b"\r\n".to_vec() });
+            } else if !self.crlf && is_crlf {
+                edits.push(TextEdit { range: i - 1..i + 1, text: b"\n".to_vec() });
+            }
+        }
+
+        edits
+    }
+}
+
+/// Appends a newline to documents that don't end in one. Empty documents are left alone.
+/// This is the on-demand counterpart to [`TextBuffer::set_insert_final_newline`].
+pub struct EnsureFinalNewline {
+    pub crlf: bool,
+}
+
+impl BufferTransform for EnsureFinalNewline {
+    fn name(&self) -> &str {
+        "ensure final newline"
+    }
+
+    fn apply(&self, text: &[u8]) -> Vec<TextEdit> {
+        self.edit_at_end(text.len(), text).into_iter().collect()
+    }
+}
+
+impl EnsureFinalNewline {
+    /// Like [`BufferTransform::apply`], but only needs the end of the document:
+    /// `len` is the length of the document and `tail` is any part of it that ends at `len`.
+    /// This makes it cheap enough to run after each keystroke.
+    pub fn edit_at_end(&self, len: usize, tail: &[u8]) -> Option<TextEdit> {
+        if len == 0 || tail.ends_with(b"\n") {
+            return None;
+        }
+        let newline: &[u8] = if self.crlf { b"\r\n" } else { b"\n" };
+        Some(TextEdit { range: len..len, text: newline.to_vec() })
+    }
+}
+
+/// Converts the newlines and adds or removes the final newline as a [`SavePolicy`] asks.
+/// Its `encoding` and `raw` fields are up to [`TextBuffer::apply_save_policy`].
+pub struct ApplySavePolicy {
+    pub policy: SavePolicy,
+    /// The newline type for an added final newline, if the policy doesn't specify one.
+    pub crlf: bool,
+}
+
+impl BufferTransform for ApplySavePolicy {
+    fn name(&self) -> &str {
+        "apply save policy"
+    }
+
+    fn apply(&self, text: &[u8]) -> Vec<TextEdit> {
+        let mut edits = Vec::new();
+        let crlf = self.policy.eol.map_or(self.crlf, |eol| eol == Eol::Crlf);
+
+        // The final newline is removed as a whole, so it mustn't be normalized as well.
+        let mut body_end = text.len();
+        if self.policy.final_newline == Some(false) && text.ends_with(b"\n") {
+            body_end -= if text.ends_with(b"\r\n") { 2 } else { 1 };
+            edits.push(TextEdit { range: body_end..text.len(), text: Vec::new() });
+        }
+
+        if self.policy.eol.is_some() {
+            edits.extend(NormalizeNewlines { crlf }.apply(&text[..body_end]));
+        }
+        if self.policy.final_newline == Some(true) {
+            edits.extend(EnsureFinalNewline { crlf }.apply(text));
+        }
+
+        edits
+    }
+}
+
+impl TextBuffer {
+    /// Prepares the document for saving according to `policy`, see [`crate::save_policy`].
+    /// The newline changes are a single undo step, and the newline type used for typing
+    /// follows the policy's `eol`. Raw policies don't change anything.
+    ///
+    /// If the policy's encoding isn't known to represent the text, the encoding stays unchanged
+    /// and the reason is returned, see [`TextBuffer::set_save_encoding`].
+    /// The newlines are still applied.
+    pub fn apply_save_policy(
+        &mut self,
+        policy: &SavePolicy,
+    ) -> Result<TransformReport, EncodingError> {
+        let transform = ApplySavePolicy { policy: *policy, crlf: self.is_crlf() };
+        if policy.raw == Some(true) {
+            return Ok(TransformReport { name: transform.name().to_string(), changes: 0 });
+        }
+
+        // Most policies don't change the text. Those don't need to copy it for the transforms.
+        let report = if policy.has_transforms() {
+            // A single transform never conflicts with itself.
+            self.apply_transforms(&[&transform]).unwrap().remove(0)
+        } else {
+            TransformReport { name: transform.name().to_string(), changes: 0 }
+        };
+        if let Some(eol) = policy.eol {
+            self.set_crlf(eol == Eol::Crlf);
+        }
+        if let Some(encoding) = policy.encoding {
+            self.set_save_encoding(encoding)?;
+        }
+        Ok(report)
+    }
+
+    /// Runs `transforms` against the current contents and applies all of their edits
+    /// as a single undo step. The cursor stays at the same line and column, if possible,
+    /// and the selection is cleared.
+    ///
+    /// If any two edits overlap or an edit is out of bounds, nothing is changed
+    /// and the problem is returned.
+    pub fn apply_transforms(
+        &mut self,
+        transforms: &[&dyn BufferTransform],
+    ) -> Result<Vec<TransformReport>, TransformError> {
+        let mut text = Vec::new();
+        self.buffer.extract_raw(0..self.buffer.len(), &mut text, 0);
+
+        let mut reports = Vec::with_capacity(transforms.len());
+        // Each edit along with the index of the transform that made it.
+        let mut edits = Vec::new();
+
+        for (i, t) in transforms.iter().enumerate() {
+            let before = edits.len();
+            for e in t.apply(&text) {
+                let Some(old) = text.get(e.range.clone()) else {
+                    return Err(TransformError::InvalidRange {
+                        name: t.name().to_string(),
+                        range: e.range,
+                    });
+                };
+                if *old != e.text[..] {
+                    edits.push((e, i));
+                }
+            }
+            reports.push(TransformReport {
+                name: t.name().to_string(),
+                changes: edits.len() - before,
+            });
+        }
+
+        // A stable sort keeps insertions at the same offset in pipeline order,
+        // and puts insertions in front of replacements starting at the same offset.
+        edits.sort_by_key(|(e, _)| (e.range.start, e.range.end));
+
+        let mut prev: Option<(usize, usize)> = None;
+        for (e, i) in &edits {
+            if let Some((end, j)) = prev
+                && e.range.start < end
+            {
+                return Err(TransformError::Conflict(TransformConflict {
+                    first: transforms[j].name().to_string(),
+                    second: transforms[*i].name().to_string(),
+                    offset: e.range.start,
+                }));
+            }
+            if prev.is_none_or(|(end, _)| e.range.end >= end) {
+                prev = Some((e.range.end, *i));
+            }
+        }
+
+        if edits.is_empty() {
+            return Ok(reports);
+        }
+
+        let cursor = self.cursor.logical_pos;
+
+        // Going back to front keeps the offsets of the edits that are yet to be applied valid.
+        self.edit_begin_grouping();
+        for (e, _) in edits.iter().rev() {
+            let beg = self.cursor_move_to_offset_internal(self.cursor, e.range.start);
+            let end = self.cursor_move_to_offset_internal(beg, e.range.end);
+            self.edit_replace(beg, end, &e.text);
+        }
+        self.edit_end_grouping();
+
+        self.set_cursor_internal(self.cursor_move_to_logical_internal(self.cursor, cursor));
+        self.set_selection(None);
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::Point;
+    use crate::testing::{TempDir, buffer_from_unnormalized, contents};
+
+    /// Inserts `text` at `offset`, for testing the ordering of edits.
+    struct Insert(&'static str, usize, &'static str);
+
+    impl BufferTransform for Insert {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn apply(&self, _: &[u8]) -> Vec<TextEdit> {
+            vec![TextEdit { range: self.1..self.1, text: self.2.as_bytes().to_vec() }]
+        }
+    }
+
+    /// Replaces the bytes in `range` with `text`.
+    struct Replace(&'static str, Range<usize>, &'static str);
+
+    impl BufferTransform for Replace {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn apply(&self, _: &[u8]) -> Vec<TextEdit> {
+            vec![TextEdit { range: self.1.clone(), text: self.2.as_bytes().to_vec() }]
+        }
+    }
+
+    #[test]
+    fn test_builtin_transforms() {
+        let mut tb = buffer_from_unnormalized("a  \r\nb\t\n \nc \r\n\nd ");
+        tb.cursor_move_to_logical(Point { x: 3, y: 0 });
+
+        let reports = tb
+            .apply_transforms(&[
+                &TrimTrailingWhitespace,
+                &NormalizeNewlines { crlf: false },
+                &EnsureFinalNewline { crlf: false },
+            ])
+            .unwrap();
+        assert_eq!(contents(&tb), "a\nb\n\nc\n\nd\n");
+        let changes: Vec<_> = reports.iter().map(|r| r.changes).collect();
+        assert_eq!(changes, [5, 2, 1]);
+        assert_eq!(reports[0].name, "trim trailing whitespace");
+
+        // The cursor was past the trimmed whitespace and is clamped to the line end.
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 1, y: 0 });
+
+        // Running them again changes nothing.
+        let reports = tb.apply_transforms(&[&TrimTrailingWhitespace]).unwrap();
+        assert_eq!(reports[0].changes, 0);
+
+        let mut tb = buffer_from_unnormalized("a\nb\r\nc");
+        tb.apply_transforms(&[&NormalizeNewlines { crlf: true }]).unwrap();
+        assert_eq!(contents(&tb), "a\r\nb\r\nc");
+    }
+
+    #[test]
+    fn test_invalid_range() {
+        let mut tb = buffer_from_unnormalized("hello\n");
+
+        for range in [Range { start: 3, end: 2 }, 4..7, 7..7] {
+            let err = tb
+                .apply_transforms(&[&Insert("fine", 0, "x"), &Replace("broken", range.clone(), "")])
+                .unwrap_err();
+            assert_eq!(err, TransformError::InvalidRange { name: "broken".to_string(), range });
+        }
+        assert_eq!(contents(&tb), "hello\n");
+        assert!(!tb.is_dirty());
+    }
+
+    #[test]
+    fn test_conflict() {
+        let mut tb = buffer_from_unnormalized("hello world\n");
+
+        let err = tb
+            .apply_transforms(&[&Replace("first", 0..5, "howdy"), &Replace("second", 4..7, "")])
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TransformError::Conflict(TransformConflict {
+                first: "first".to_string(),
+                second: "second".to_string(),
+                offset: 4
+            })
+        );
+
+        // An insertion within a replaced range conflicts as well. Nothing was changed.
+        let err = tb
+            .apply_transforms(&[&Insert("insert", 2, "x"), &Replace("replace", 0..5, "howdy")])
+            .unwrap_err();
+        assert!(matches!(err, TransformError::Conflict(TransformConflict { offset: 2, .. })));
+        assert_eq!(contents(&tb), "hello world\n");
+        assert!(!tb.is_dirty());
+
+        // Edits that merely touch are fine, and so are no-op edits.
+        tb.apply_transforms(&[
+            &Replace("first", 0..5, "howdy"),
+            &Replace("second", 5..6, "_"),
+            &Replace("noop", 2..9, "llo wor"),
+        ])
+        .unwrap();
+        assert_eq!(contents(&tb), "howdy_world\n");
+    }
+
+    #[test]
+    fn test_ordering() {
+        // Insertions at the same offset are applied in pipeline order.
+        let mut tb = buffer_from_unnormalized("ab");
+        tb.apply_transforms(&[&Insert("x", 1, "X"), &Insert("y", 1, "Y")]).unwrap();
+        assert_eq!(contents(&tb), "aXYb");
+
+        let mut tb = buffer_from_unnormalized("ab");
+        tb.apply_transforms(&[&Insert("y", 1, "Y"), &Insert("x", 1, "X")]).unwrap();
+        assert_eq!(contents(&tb), "aYXb");
+
+        // Each transform sees the original text, so a final newline isn't normalized
+        // in the same run, unless it's added with the right type in the first place.
+        let mut tb = buffer_from_unnormalized("a\nb");
+        tb.apply_transforms(&[
+            &EnsureFinalNewline { crlf: false },
+            &NormalizeNewlines { crlf: true },
+        ])
+        .unwrap();
+        assert_eq!(contents(&tb), "a\r\nb\n");
+    }
+
+    #[test]
+    fn test_single_undo() {
+        let mut tb = buffer_from_unnormalized("a \nb \nc");
+        let before = contents(&tb);
+
+        tb.apply_transforms(&[&TrimTrailingWhitespace, &EnsureFinalNewline { crlf: false }])
+            .unwrap();
+        assert_eq!(contents(&tb), "a\nb\nc\n");
+
+        tb.undo();
+        assert_eq!(contents(&tb), before);
+        tb.redo();
+        assert_eq!(contents(&tb), "a\nb\nc\n");
+    }
+
+    #[test]
+    fn test_save_policy() {
+        let policy = |text: &str| SavePolicy::parse(text, 1, &mut Vec::new());
+
+        let mut tb = buffer_from_unnormalized("a\r\nb\nc");
+        tb.apply_save_policy(&policy("eol=crlf final_newline=true")).unwrap();
+        assert_eq!(contents(&tb), "a\r\nb\r\nc\r\n");
+        assert!(tb.is_crlf());
+
+        // Removing the final newline doesn't conflict with normalizing it.
+        let mut tb = buffer_from_unnormalized("a\r\nb\r\n");
+        let report = tb.apply_save_policy(&policy("eol=lf noeol")).unwrap();
+        assert_eq!(contents(&tb), "a\nb");
+        assert_eq!(report.changes, 2);
+        assert!(!tb.is_crlf());
+
+        // Without an eol, an added final newline uses the buffer's type.
+        let mut tb = buffer_from_unnormalized("a\r\nb");
+        tb.set_crlf(true);
+        tb.apply_save_policy(&policy("final_newline=true")).unwrap();
+        assert_eq!(contents(&tb), "a\r\nb\r\n");
+
+        let mut tb = buffer_from_unnormalized("a\r\nb");
+        tb.apply_save_policy(&policy("raw eol=lf final_newline=true")).unwrap();
+        assert_eq!(contents(&tb), "a\r\nb");
+    }
+
+    #[test]
+    fn test_save_policy_roundtrip() {
+        let config = crate::config::Config::parse("[lang.shell]\nsave = encoding=iso-8859-1\n");
+        let mut tb = buffer_from_unnormalized("echo \"é\"\r\n# edit: noeol\r\n");
+        let (policy, diagnostics) = crate::save_policy::resolve(&config, Some("shell"), &tb);
+        assert_eq!(diagnostics, []);
+
+        tb.apply_save_policy(&policy).unwrap();
+        assert_eq!(tb.encoding(), "ISO-8859-1");
+
+        let dir = TempDir::new("pipeline");
+        let path = dir.join("save-policy");
+        tb.write_file(&mut std::fs::File::create(&path).unwrap()).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"echo \"\xE9\"\n# edit: noeol");
+        assert!(!tb.is_dirty());
+
+        // An encoding that can't represent the text is left alone.
+        let mut tb = buffer_from_unnormalized("😀a😀");
+        assert_eq!(
+            tb.apply_save_policy(&policy).unwrap_err(),
+            EncodingError::Unmappable(vec![0..4, 5..9])
+        );
+        assert_eq!(tb.encoding(), "UTF-8");
+    }
+}
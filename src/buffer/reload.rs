@@ -0,0 +1,216 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Reloading a file that was changed on disk, without losing the view state.
+//!
+//! The old and new contents are diffed line by line and only the differing lines are replaced.
+//! The cursor and selection are then mapped through the diff, so that they stay on the same
+//! lines of text, even if lines above them were inserted or removed.
+//!
+//! All replacements form a single undo step. Undoing it restores the pre-reload contents.
+
+use std::fs::File;
+
+use super::{TextBuffer, TextBufferSelection};
+use crate::apperr;
+use crate::diff::{Hunk, MAX_DIFF_COST, diff};
+use crate::hash::hash;
+use crate::helpers::*;
+
+/// The result of [`TextBuffer::reload`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReloadOutcome {
+    /// The changes were merged in and the cursor and selection follow their lines.
+    Merged,
+    /// The file changed too much to diff it. The contents were replaced wholesale
+    /// and the cursor was kept at the same line and column, if possible.
+    /// This is worth a warning to the user.
+    Replaced,
+}
+
+impl TextBuffer {
+    /// Reloads the contents from `file`, which is decoded with the current encoding.
+    ///
+    /// Unlike [`TextBuffer::read_file`], this preserves the undo history.
+    /// See [`TextBuffer::reload_from_bytes`].
+    pub fn reload(&mut self, file: &mut File) -> apperr::Result<ReloadOutcome> {
+        let mut fresh = TextBuffer::new(false)?;
+        fresh.read_file(file, Some(self.encoding))?;
+
+        let mut text = Vec::new();
+        fresh.buffer.extract_raw(0..fresh.buffer.len(), &mut text, 0);
+        Ok(self.reload_from_bytes(&text))
+    }
+
+    /// Replaces the contents with `text` as a single undo step,
+    /// and marks the buffer as clean, since it now matches the file.
+    pub fn reload_from_bytes(&mut self, text: &[u8]) -> ReloadOutcome {
+        let mut old = Vec::new();
+        self.buffer.extract_raw(0..self.buffer.len(), &mut old, 0);
+
+        let old_lines = split_lines(&old);
+        let new_lines = split_lines(text);
+        let (hunks, outcome) = match diff(&old_lines, &new_lines, MAX_DIFF_COST) {
+            Some(hunks) => (hunks, ReloadOutcome::Merged),
+            None => (
+                vec![Hunk { old: 0..old_lines.len(), new: 0..new_lines.len() }],
+                ReloadOutcome::Replaced,
+            ),
+        };
+
+        if hunks.is_empty() {
+            self.mark_as_clean();
+            return outcome;
+        }
+
+        let map = |pos: Point| match outcome {
+            ReloadOutcome::Merged => map_position(&hunks, pos),
+            ReloadOutcome::Replaced => pos,
+        };
+        let cursor = map(self.cursor.logical_pos);
+        let selection =
+            self.selection.map(|s| TextBufferSelection { beg: map(s.beg), end: map(s.end) });
+
+        // Going back to front keeps the offsets of the hunks that are yet to be applied valid.
+        self.edit_begin_grouping();
+        for h in hunks.iter().rev() {
+            let beg = line_offset(&old_lines, h.old.start);
+            let end = line_offset(&old_lines, h.old.end);
+            let replacement = line_range(&new_lines, text, h.new.clone());
+
+            let beg = self.cursor_move_to_offset_internal(self.cursor, beg);
+            let end = self.cursor_move_to_offset_internal(beg, end);
+            self.edit_replace(beg, end, replacement);
+        }
+        self.edit_end_grouping();
+
+        self.set_cursor_internal(self.cursor_move_to_logical_internal(self.cursor, cursor));
+        self.set_selection(selection);
+        self.mark_as_clean();
+        outcome
+    }
+}
+
+/// A line including its newline, along with its hash to speed up the diff.
+#[derive(PartialEq, Eq)]
+struct Line<'a> {
+    hash: u64,
+    text: &'a [u8],
+}
+
+fn split_lines(text: &[u8]) -> Vec<Line<'_>> {
+    text.split_inclusive(|&b| b == b'\n').map(|text| Line { hash: hash(0, text), text }).collect()
+}
+
+/// Returns the offset of the start of line `y`.
+fn line_offset(lines: &[Line], y: usize) -> usize {
+    lines[..y].iter().map(|l| l.text.len()).sum()
+}
+
+/// Returns the lines `range` as a slice of `text`.
+fn line_range<'a>(lines: &[Line], text: &'a [u8], range: std::ops::Range<usize>) -> &'a [u8] {
+    let beg = line_offset(lines, range.start);
+    let len: usize = lines[range].iter().map(|l| l.text.len()).sum();
+    &text[beg..beg + len]
+}
+
+/// Maps a logical position in the old contents to the new contents.
+/// Positions within a changed region move to its start.
+fn map_position(hunks: &[Hunk], pos: Point) -> Point {
+    let y = pos.y as usize;
+    let mut delta = 0isize;
+
+    for h in hunks {
+        if y < h.old.start {
+            break;
+        }
+        if y < h.old.end {
+            return Point { x: 0, y: h.new.start as CoordType };
+        }
+        delta += h.new.len() as isize - h.old.len() as isize;
+    }
+
+    Point { x: pos.x, y: (y as isize + delta) as CoordType }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::contents;
+
+    fn buffer_with(text: &str) -> TextBuffer {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.write_raw(text.as_bytes());
+        tb.mark_as_clean();
+        tb
+    }
+
+    #[test]
+    fn test_reload_keeps_cursor_line() {
+        let mut tb = buffer_with("one\ntwo\nthree\nfour\n");
+        tb.cursor_move_to_logical(Point { x: 2, y: 2 });
+
+        // Insert two lines above the cursor and change one below it.
+        let outcome = tb.reload_from_bytes(b"zero\nhalf\none\ntwo\nthree\nFOUR\n");
+        assert_eq!(outcome, ReloadOutcome::Merged);
+        assert_eq!(contents(&tb), "zero\nhalf\none\ntwo\nthree\nFOUR\n");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 2, y: 4 });
+        assert!(!tb.is_dirty());
+
+        // Removing lines above moves it back up.
+        tb.reload_from_bytes(b"two\nthree\nFOUR\n");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 2, y: 1 });
+    }
+
+    #[test]
+    fn test_reload_undo() {
+        let mut tb = buffer_with("a\nb\nc\n");
+        tb.cursor_move_to_logical(Point { x: 0, y: 1 });
+        tb.write_raw(b"x");
+        assert_eq!(contents(&tb), "a\nxb\nc\n");
+
+        tb.reload_from_bytes(b"A\nb\nc\nd\n");
+        assert_eq!(contents(&tb), "A\nb\nc\nd\n");
+        assert!(!tb.is_dirty());
+
+        // The first undo reverts the entire reload...
+        tb.undo();
+        assert_eq!(contents(&tb), "a\nxb\nc\n");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 1, y: 1 });
+        assert!(tb.is_dirty());
+
+        // ...and the next one the edit before it.
+        tb.undo();
+        assert_eq!(contents(&tb), "a\nb\nc\n");
+
+        tb.redo();
+        tb.redo();
+        assert_eq!(contents(&tb), "A\nb\nc\nd\n");
+        assert!(!tb.is_dirty());
+    }
+
+    #[test]
+    fn test_reload_fallback() {
+        let old: String = (0..5000).map(|i| format!("{i}\n")).collect();
+        let new: String = (0..5000).map(|i| format!("{}\n", i + 10000)).collect();
+        let mut tb = TextBuffer::new(false).unwrap();
+        tb.write_raw(old.as_bytes());
+        tb.cursor_move_to_logical(Point { x: 1, y: 20 });
+
+        assert_eq!(tb.reload_from_bytes(new.as_bytes()), ReloadOutcome::Replaced);
+        assert_eq!(contents(&tb), new);
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 1, y: 20 });
+    }
+
+    #[test]
+    fn test_map_position() {
+        let hunks = [Hunk { old: 1..2, new: 1..4 }, Hunk { old: 5..7, new: 7..7 }];
+        let map = |y| map_position(&hunks, Point { x: 3, y }).y;
+        assert_eq!(map(0), 0);
+        assert_eq!(map_position(&hunks, Point { x: 3, y: 1 }), Point { x: 0, y: 1 });
+        assert_eq!(map(2), 4);
+        assert_eq!(map(4), 6);
+        assert_eq!(map(5), 7);
+        assert_eq!(map(7), 7);
+    }
+}
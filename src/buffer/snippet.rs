@@ -0,0 +1,558 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Snippets: Templates with placeholders that are expanded from a trigger word.
+//!
+//! A snippet body may contain tab stops in the usual syntax:
+//! * `$1` or `${1}` is an empty tab stop,
+//! * `${1:default}` is a placeholder with default text, which may contain other tab stops,
+//! * `$0` is where the cursor ends up. It defaults to the end of the snippet.
+//!
+//! A tab stop that appears several times is mirrored: Typing into the first one
+//! updates all others. `\$`, `\}` and `\\` insert the character literally.
+//!
+//! [`TextBuffer::expand_snippet`] inserts a snippet and returns a [`SnippetSession`],
+//! which tracks the tab stops while the user types and moves between them.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use super::TextBuffer;
+
+/// A tab stop of a [`Snippet`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TabStop {
+    pub index: u32,
+    /// The range of the placeholder text, or the position of an empty tab stop.
+    pub range: Range<usize>,
+}
+
+/// A parsed snippet body. See the module documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    /// The body with all placeholders filled in with their defaults.
+    /// Its newlines are always LF.
+    pub text: String,
+    /// All tab stops in the order in which they appear, including `$0`.
+    /// Nested tab stops come after the placeholder that contains them.
+    pub stops: Vec<TabStop>,
+}
+
+enum Node {
+    Text(String),
+    Stop { index: u32, children: Vec<Node> },
+}
+
+impl Snippet {
+    /// Parses a snippet body. Parsing is lenient: A `$` that isn't followed by
+    /// a valid tab stop is literal, and an unterminated `${1:` ends at the end of the body.
+    pub fn parse(body: &str) -> Self {
+        let mut chars = body.chars().peekable();
+        let nodes = parse_nodes(&mut chars, false);
+
+        let mut defaults = HashMap::new();
+        collect_defaults(&nodes, &mut defaults);
+
+        let mut snippet = Self { text: String::new(), stops: Vec::new() };
+        snippet.render(&nodes, &defaults, 0);
+        if !snippet.stops.iter().any(|s| s.index == 0) {
+            let end = snippet.text.len();
+            snippet.stops.push(TabStop { index: 0, range: end..end });
+        }
+        snippet
+    }
+
+    fn render(&mut self, nodes: &[Node], defaults: &HashMap<u32, &[Node]>, depth: usize) {
+        for node in nodes {
+            match node {
+                Node::Text(text) => self.text.push_str(text),
+                Node::Stop { index, children } => {
+                    let beg = self.text.len();
+                    let i = self.stops.len();
+                    self.stops.push(TabStop { index: *index, range: beg..beg });
+
+                    // Mirrors without a default of their own show that of the first occurrence.
+                    // The depth limit guards against placeholders that contain themselves.
+                    let children = match defaults.get(index) {
+                        Some(default) if children.is_empty() && depth < 8 => default,
+                        _ => &children[..],
+                    };
+                    self.render(children, defaults, depth + 1);
+                    self.stops[i].range.end = self.text.len();
+                }
+            }
+        }
+    }
+}
+
+fn parse_nodes(chars: &mut std::iter::Peekable<std::str::Chars>, nested: bool) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if chars.peek().is_some_and(|c| matches!(c, '$' | '}' | '\\')) => {
+                text.push(chars.next().unwrap());
+            }
+            '}' if nested => break,
+            '$' => {
+                let braced = chars.next_if_eq(&'{').is_some();
+                let mut digits = String::new();
+                while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(d);
+                }
+
+                let index = digits.parse::<u32>().ok();
+                let children = match index {
+                    Some(_) if braced && chars.next_if_eq(&':').is_some() => {
+                        Some(parse_nodes(chars, true))
+                    }
+                    Some(_) if braced => chars.next_if_eq(&'}').map(|_| Vec::new()),
+                    Some(_) => Some(Vec::new()),
+                    None => None,
+                };
+
+                match (index, children) {
+                    (Some(index), Some(children)) => {
+                        if !text.is_empty() {
+                            nodes.push(Node::Text(std::mem::take(&mut text)));
+                        }
+                        nodes.push(Node::Stop { index, children });
+                    }
+                    _ => {
+                        text.push('$');
+                        if braced {
+                            text.push('{');
+                        }
+                        text.push_str(&digits);
+                    }
+                }
+            }
+            _ => text.push(ch),
+        }
+    }
+
+    if !text.is_empty() {
+        nodes.push(Node::Text(text));
+    }
+    nodes
+}
+
+fn collect_defaults<'a>(nodes: &'a [Node], defaults: &mut HashMap<u32, &'a [Node]>) {
+    for node in nodes {
+        if let Node::Stop { index, children } = node
+            && !children.is_empty()
+        {
+            defaults.entry(*index).or_insert(&children[..]);
+            collect_defaults(children, defaults);
+        }
+    }
+}
+
+/// The snippets available for expansion, by language and trigger word.
+#[derive(Debug, Default, Clone)]
+pub struct SnippetRegistry {
+    snippets: HashMap<(String, String), Snippet>,
+}
+
+impl SnippetRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a snippet, replacing any previous one with the same language and trigger.
+    /// An empty `language` makes the snippet available in all languages.
+    pub fn insert(&mut self, language: &str, trigger: &str, body: &str) {
+        self.snippets.insert((language.to_string(), trigger.to_string()), Snippet::parse(body));
+    }
+
+    /// Looks up the snippet for `trigger`, preferring the one specific to `language`.
+    pub fn get(&self, language: &str, trigger: &str) -> Option<&Snippet> {
+        let mut key = (language.to_string(), trigger.to_string());
+        self.snippets.get(&key).or_else(|| {
+            key.0.clear();
+            self.snippets.get(&key)
+        })
+    }
+}
+
+/// Moves between the tab stops of an expanded snippet and keeps mirrors in sync.
+///
+/// The owner is expected to call [`SnippetSession::sync`] after each edit.
+/// The session ends once the user moves to `$0`, or edits text outside the current tab stop.
+#[derive(Debug, Clone)]
+pub struct SnippetSession {
+    /// The tab stops with offsets into the buffer, in document order.
+    stops: Vec<TabStop>,
+    /// The tab stop indices in the order they're visited, with `0` last.
+    order: Vec<u32>,
+    current: usize,
+    /// The buffer length and the text of the current tab stop as of the last sync.
+    text_length: usize,
+    current_text: Vec<u8>,
+}
+
+impl SnippetSession {
+    /// The index of the current tab stop, as written in the snippet.
+    pub fn current_index(&self) -> u32 {
+        self.order[self.current]
+    }
+
+    /// The ranges of all tab stops in the buffer, for highlighting them.
+    pub fn stops(&self) -> &[TabStop] {
+        &self.stops
+    }
+
+    /// Selects the next tab stop. Returns `false` if that's `$0`,
+    /// in which case the cursor was moved there and the session is over.
+    pub fn next(&mut self, tb: &mut TextBuffer) -> bool {
+        if !self.sync(tb) {
+            return false;
+        }
+        self.current = (self.current + 1).min(self.order.len() - 1);
+        self.select(tb)
+    }
+
+    /// Selects the previous tab stop, if any.
+    pub fn prev(&mut self, tb: &mut TextBuffer) -> bool {
+        if !self.sync(tb) {
+            return false;
+        }
+        self.current = self.current.saturating_sub(1);
+        self.select(tb)
+    }
+
+    /// Ends the session. The buffer is left as it is.
+    pub fn cancel(self, tb: &mut TextBuffer) {
+        tb.clear_selection();
+    }
+
+    /// Picks up an edit of the current tab stop and copies its new text to its mirrors.
+    /// Returns `false` if the edit happened elsewhere, which ends the session.
+    ///
+    /// Edits are detected by the change in the buffer length, with the cursor
+    /// expected to be within the current tab stop afterwards.
+    pub fn sync(&mut self, tb: &mut TextBuffer) -> bool {
+        let Some(p) = self.primary(self.current_index()) else {
+            return false;
+        };
+
+        let range = self.stops[p].range.clone();
+        let delta = tb.text_length() as isize - self.text_length as isize;
+        let Some(end) = range.end.checked_add_signed(delta).filter(|&end| end >= range.start)
+        else {
+            return false;
+        };
+        let mut cursor = tb.cursor.offset;
+        if !(range.start..=end).contains(&cursor) {
+            return false;
+        }
+
+        let mut text = Vec::new();
+        tb.buffer.extract_raw(range.start..end, &mut text, 0);
+        if delta == 0 && text == self.current_text {
+            return true;
+        }
+
+        apply_edit(&mut self.stops, p, range, text.len());
+
+        // Going back to front keeps the offsets of the mirrors that are yet to be updated valid.
+        let index = self.current_index();
+        let mirrors: Vec<_> =
+            (p + 1..self.stops.len()).filter(|&i| self.stops[i].index == index).collect();
+        tb.edit_begin_grouping();
+        for &m in mirrors.iter().rev() {
+            let range = self.stops[m].range.clone();
+            let beg = tb.cursor_move_to_offset_internal(tb.cursor, range.start);
+            let end = tb.cursor_move_to_offset_internal(beg, range.end);
+            tb.edit_replace(beg, end, &text);
+            if cursor >= range.end {
+                cursor = cursor - range.len() + text.len();
+            }
+            apply_edit(&mut self.stops, m, range, text.len());
+        }
+        tb.edit_end_grouping();
+        self.stops.retain(|s| s.index != u32::MAX);
+
+        tb.cursor_move_to_offset(cursor);
+        self.text_length = tb.text_length();
+        self.current_text = text;
+        true
+    }
+
+    /// Selects the current tab stop. Returns `false` if it's `$0`.
+    fn select(&mut self, tb: &mut TextBuffer) -> bool {
+        // A nested tab stop may have been removed by overwriting its placeholder.
+        while self.primary(self.current_index()).is_none() {
+            self.current += 1;
+        }
+
+        let stop = &self.stops[self.primary(self.current_index()).unwrap()];
+        let range = stop.range.clone();
+        tb.clear_selection();
+        tb.cursor_move_to_offset(range.start);
+
+        if stop.index == 0 {
+            return false;
+        }
+        if !range.is_empty() {
+            tb.selection_update_offset(range.end);
+        }
+
+        self.text_length = tb.text_length();
+        self.current_text.clear();
+        tb.buffer.extract_raw(range, &mut self.current_text, 0);
+        true
+    }
+
+    /// The first occurrence of a tab stop is the one the user types into.
+    fn primary(&self, index: u32) -> Option<usize> {
+        self.stops.iter().position(|s| s.index == index)
+    }
+}
+
+/// Updates the tab stops after the text of `stops[edited]`, which was `range`, was replaced
+/// with `new_len` bytes. Tab stops within the replaced text are marked as removed,
+/// except for `$0`, which moves to the end of the new text.
+fn apply_edit(stops: &mut [TabStop], edited: usize, range: Range<usize>, new_len: usize) {
+    let shift = |off: usize| off - range.len() + new_len;
+
+    for (i, s) in stops.iter_mut().enumerate() {
+        if i == edited {
+            s.range = range.start..range.start + new_len;
+        } else if s.range.end <= range.start && (s.range.start < range.start || i < edited) {
+            // Before the edit.
+        } else if s.range.start >= range.end {
+            s.range = shift(s.range.start)..shift(s.range.end);
+        } else if s.range.start <= range.start && s.range.end >= range.end && i < edited {
+            // A placeholder that contains the edited one.
+            s.range.end = shift(s.range.end);
+        } else if s.index == 0 {
+            // `$0` must survive, so that the session can end there.
+            let end = range.start + new_len;
+            s.range = end..end;
+        } else {
+            s.index = u32::MAX;
+        }
+    }
+}
+
+impl TextBuffer {
+    /// Replaces the `trigger_len` bytes before the cursor with `snippet`, as a single undo step,
+    /// and selects its first tab stop. Lines after the first are indented like the current one.
+    ///
+    /// Returns `None` if the snippet has no tab stops other than `$0`,
+    /// in which case the cursor was moved there.
+    pub fn expand_snippet(
+        &mut self,
+        snippet: &Snippet,
+        trigger_len: usize,
+    ) -> Option<SnippetSession> {
+        let end = self.cursor;
+        let beg = self.cursor_move_to_offset_internal(end, end.offset.saturating_sub(trigger_len));
+        let line_start = self.goto_line_start(beg, beg.logical_pos.y);
+
+        let mut indent = Vec::new();
+        self.buffer.extract_raw(line_start.offset..beg.offset, &mut indent, 0);
+        let indent_len = indent.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+        indent.truncate(indent_len);
+
+        let mut newline = if self.newlines_are_crlf { b"\r\n".to_vec() } else { b"\n".to_vec() };
+        newline.extend_from_slice(&indent);
+
+        // Each LF in the body grows by the same number of bytes.
+        let mut text = Vec::with_capacity(snippet.text.len());
+        for (i, line) in snippet.text.split('\n').enumerate() {
+            if i > 0 {
+                text.extend_from_slice(&newline);
+            }
+            text.extend_from_slice(line.as_bytes());
+        }
+        let map = |off: usize| {
+            let newlines = snippet.text.as_bytes()[..off].iter().filter(|&&b| b == b'\n').count();
+            beg.offset + off + newlines * (newline.len() - 1)
+        };
+
+        self.clear_selection();
+        self.edit_begin_grouping();
+        self.edit_replace(beg, end, &text);
+        self.edit_end_grouping();
+
+        let stops: Vec<_> = snippet
+            .stops
+            .iter()
+            .map(|s| TabStop { index: s.index, range: map(s.range.start)..map(s.range.end) })
+            .collect();
+        let mut order: Vec<_> = stops.iter().map(|s| s.index).filter(|&i| i != 0).collect();
+        order.sort_unstable();
+        order.dedup();
+        order.push(0);
+
+        let mut session =
+            SnippetSession { stops, order, current: 0, text_length: 0, current_text: Vec::new() };
+        session.select(self).then_some(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::Point;
+    use crate::testing::{buffer_from, contents};
+
+    fn selection(tb: &mut TextBuffer) -> String {
+        let mut out = Vec::new();
+        if let Some((beg, end)) = tb.selection_range_internal(false) {
+            tb.buffer.extract_raw(beg.offset..end.offset, &mut out, 0);
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    fn stop(index: u32, range: Range<usize>) -> TabStop {
+        TabStop { index, range }
+    }
+
+    #[test]
+    fn test_parse() {
+        let s = Snippet::parse("for ${1:i} in ${2:0..${3:n}} {\n\t$0\n}");
+        assert_eq!(s.text, "for i in 0..n {\n\t\n}");
+        assert_eq!(s.stops, [stop(1, 4..5), stop(2, 9..13), stop(3, 12..13), stop(0, 17..17)]);
+
+        // Mirrors, escapes, literal dollars and an implicit `$0`.
+        let s = Snippet::parse("${1:x} = $1 + \\$2 \\} $ ${x} $12");
+        assert_eq!(s.text, "x = x + $2 } $ ${x} ");
+        assert_eq!(s.stops, [stop(1, 0..1), stop(1, 4..5), stop(12, 20..20), stop(0, 20..20)]);
+
+        // A mirror that comes first still shows the default.
+        let s = Snippet::parse("$1 ${1:ab} ${2:unterminated");
+        assert_eq!(s.text, "ab ab unterminated");
+        assert_eq!(s.stops[0], stop(1, 0..2));
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut registry = SnippetRegistry::new();
+        registry.insert("", "todo", "TODO: $0");
+        registry.insert("rust", "fn", "fn ${1:name}() {}");
+        assert_eq!(registry.get("rust", "fn").unwrap().text, "fn name() {}");
+        assert_eq!(registry.get("rust", "todo").unwrap().text, "TODO: ");
+        assert!(registry.get("c", "fn").is_none());
+    }
+
+    #[test]
+    fn test_mirrors() {
+        let mut tb = buffer_from("let fn");
+        let snippet = Snippet::parse("fn ${1:name}() { $1(); ${2:$1} }");
+        let mut session = tb.expand_snippet(&snippet, 2).unwrap();
+        assert_eq!(contents(&tb), "let fn name() { name(); name }");
+        assert_eq!(selection(&mut tb), "name");
+
+        // Typing over the selection updates the mirrors, including the one within `$2`.
+        tb.write_canon(b"go");
+        assert!(session.sync(&mut tb));
+        assert_eq!(contents(&tb), "let fn go() { go(); go }");
+        tb.write_canon(b"to");
+        assert!(session.sync(&mut tb));
+        assert_eq!(contents(&tb), "let fn goto() { goto(); goto }");
+        assert_eq!(tb.cursor.offset, 11);
+
+        assert!(session.next(&mut tb));
+        assert_eq!(session.current_index(), 2);
+        assert_eq!(selection(&mut tb), "goto");
+        tb.write_canon(b"done");
+        assert!(session.sync(&mut tb));
+
+        // Back to `$1`, which is unaffected by `$2` being overwritten.
+        assert!(session.prev(&mut tb));
+        assert_eq!(selection(&mut tb), "goto");
+        tb.write_canon(b"x");
+        assert!(session.sync(&mut tb));
+        assert_eq!(contents(&tb), "let fn x() { x(); done }");
+
+        // `$0` is at the end and ends the session.
+        assert!(session.next(&mut tb));
+        assert!(!session.next(&mut tb));
+        assert_eq!(tb.cursor.offset, tb.text_length());
+    }
+
+    #[test]
+    fn test_nested_defaults() {
+        let mut tb = buffer_from("");
+        let snippet = Snippet::parse("call(${1:a, ${2:b}})$0;");
+        let mut session = tb.expand_snippet(&snippet, 0).unwrap();
+        assert_eq!(selection(&mut tb), "a, b");
+
+        // Accepting the default of `$1` allows editing the nested `$2`.
+        assert!(session.next(&mut tb));
+        assert_eq!(selection(&mut tb), "b");
+        tb.write_canon(b"bee");
+        assert!(session.sync(&mut tb));
+        assert_eq!(contents(&tb), "call(a, bee);");
+        assert_eq!(session.stops()[0], stop(1, 5..11));
+
+        // Overwriting `$1` removes the nested `$2`, so the next stop is `$0`.
+        assert!(session.prev(&mut tb));
+        assert_eq!(selection(&mut tb), "a, bee");
+        tb.write_canon(b"z");
+        assert!(session.sync(&mut tb));
+        assert!(!session.next(&mut tb));
+        assert_eq!(contents(&tb), "call(z);");
+        assert_eq!(tb.cursor.offset, 7);
+
+        // The same goes for a `$0` within a placeholder.
+        let mut tb = buffer_from("");
+        let mut session = tb.expand_snippet(&Snippet::parse("<${1:a$0b}>"), 0).unwrap();
+        tb.write_canon(b"c");
+        assert!(session.sync(&mut tb));
+        assert!(!session.next(&mut tb));
+        assert_eq!(contents(&tb), "<c>");
+        assert_eq!(tb.cursor.offset, 2);
+    }
+
+    #[test]
+    fn test_end_of_buffer() {
+        let mut tb = buffer_from("x\n    if");
+        let snippet = Snippet::parse("if ${1:cond} {\n\t$0\n}");
+        let mut session = tb.expand_snippet(&snippet, 2).unwrap();
+        assert_eq!(contents(&tb), "x\n    if cond {\n    \t\n    }");
+        assert_eq!(selection(&mut tb), "cond");
+
+        assert!(!session.next(&mut tb));
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 5, y: 2 });
+
+        // The expansion is a single undo step.
+        tb.undo();
+        assert_eq!(contents(&tb), "x\n    if");
+
+        // Snippets without tab stops don't start a session.
+        assert!(tb.expand_snippet(&Snippet::parse("done"), 0).is_none());
+        assert_eq!(contents(&tb), "x\n    ifdone");
+        assert_eq!(tb.cursor.offset, tb.text_length());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut tb = buffer_from("");
+        let snippet = Snippet::parse("${1:a} ${1:a}");
+        let mut session = tb.expand_snippet(&snippet, 0).unwrap();
+        tb.write_canon(b"b");
+        assert!(session.sync(&mut tb));
+        session.cancel(&mut tb);
+        assert_eq!(contents(&tb), "b b");
+        assert!(!tb.has_selection());
+
+        // Edits elsewhere end the session without touching the mirrors.
+        tb.cursor_move_to_offset(3);
+        let mut session = tb.expand_snippet(&snippet, 0).unwrap();
+        assert_eq!(contents(&tb), "b ba a");
+        tb.cursor_move_to_offset(0);
+        tb.write_canon(b"c");
+        assert!(!session.sync(&mut tb));
+        assert_eq!(contents(&tb), "cb ba a");
+
+        // Everything can still be undone.
+        tb.undo();
+        tb.undo();
+        assert_eq!(contents(&tb), "b b");
+    }
+}
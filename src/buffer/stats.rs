@@ -0,0 +1,212 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Line, word, character and byte counts, for the status bar.
+//!
+//! The counts for the whole document are computed once when it's loaded.
+//! After that, each edit adjusts them by only scanning the removed and inserted text,
+//! plus the two bytes around the edit, since they decide whether a word was split or joined.
+
+use std::ops::Range;
+
+use super::TextBuffer;
+use crate::document::ReadableDocument;
+
+/// Counts for a document or a part of it.
+///
+/// A word is a run of bytes that aren't ASCII whitespace, like `wc` counts them.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DocumentStats {
+    pub newlines: usize,
+    pub words: usize,
+    /// The number of Unicode scalar values.
+    pub chars: usize,
+    pub bytes: usize,
+}
+
+impl DocumentStats {
+    /// Counts the text in `range` of `doc`.
+    pub fn of_range(doc: &dyn ReadableDocument, range: Range<usize>) -> Self {
+        let mut stats = Self::default();
+        let mut prev = None;
+        let mut off = range.start;
+
+        while off < range.end {
+            let chunk = doc.read_forward(off);
+            if chunk.is_empty() {
+                break;
+            }
+            let chunk = &chunk[..chunk.len().min(range.end - off)];
+            stats.add(prev, chunk);
+            prev = chunk.last().copied();
+            off += chunk.len();
+        }
+
+        stats
+    }
+
+    /// The number of lines, counting the one after the last newline.
+    pub fn lines(&self) -> usize {
+        self.newlines + 1
+    }
+
+    /// Adjusts the counts for replacing `removed` with `inserted`.
+    /// `prev` and `next` are the bytes before and after the edit, if any.
+    pub fn apply_edit(
+        &mut self,
+        prev: Option<u8>,
+        removed: &[u8],
+        inserted: &[u8],
+        next: Option<u8>,
+    ) {
+        // A word starting at `next` only counts as one if the byte before it isn't part of a word.
+        let next_starts_word = |text: &[u8]| {
+            next.is_some_and(is_word_byte)
+                && !text.last().copied().or(prev).is_some_and(is_word_byte)
+        };
+
+        let mut before = Self::default();
+        before.add(prev, removed);
+        before.words += next_starts_word(removed) as usize;
+
+        let mut after = Self::default();
+        after.add(prev, inserted);
+        after.words += next_starts_word(inserted) as usize;
+
+        self.newlines = self.newlines + after.newlines - before.newlines;
+        self.words = self.words + after.words - before.words;
+        self.chars = self.chars + after.chars - before.chars;
+        self.bytes = self.bytes + after.bytes - before.bytes;
+    }
+
+    /// Adds the counts for `text`, which follows the byte `prev`.
+    fn add(&mut self, prev: Option<u8>, text: &[u8]) {
+        let mut in_word = prev.is_some_and(is_word_byte);
+        for &b in text {
+            let is_word = is_word_byte(b);
+            self.words += (is_word && !in_word) as usize;
+            in_word = is_word;
+            self.newlines += (b == b'\n') as usize;
+            // Count all bytes except for UTF-8 continuation bytes.
+            self.chars += (b & 0xC0 != 0x80) as usize;
+        }
+        self.bytes += text.len();
+    }
+}
+
+fn is_word_byte(b: u8) -> bool {
+    !b.is_ascii_whitespace()
+}
+
+impl TextBuffer {
+    /// Returns the counts for the entire document.
+    pub fn document_stats(&self) -> DocumentStats {
+        self.stats.counts
+    }
+
+    /// Returns the counts for the current selection, if any.
+    pub fn selection_stats(&self) -> Option<DocumentStats> {
+        let (beg, end) = self.selection_range()?;
+        Some(DocumentStats::of_range(&self.buffer, beg.offset..end.offset))
+    }
+
+    pub(super) fn recount_document_stats(&mut self) {
+        self.stats.counts = DocumentStats::of_range(&self.buffer, 0..self.buffer.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::CursorMovement;
+    use crate::helpers::{CoordType, Point};
+
+    fn count(text: &str) -> DocumentStats {
+        DocumentStats::of_range(&text.as_bytes(), 0..text.len())
+    }
+
+    #[test]
+    fn test_count() {
+        assert_eq!(count(""), DocumentStats::default());
+        assert_eq!(
+            count("héllo  wörld\n foo"),
+            DocumentStats { newlines: 1, words: 3, chars: 17, bytes: 19 }
+        );
+    }
+
+    #[test]
+    fn test_copy_from_str() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.copy_from_str(&"foo bar\nbaz".to_string());
+        assert_eq!(
+            tb.document_stats(),
+            DocumentStats { newlines: 0, words: 2, chars: 7, bytes: 7 }
+        );
+    }
+
+    #[test]
+    fn test_apply_edit() {
+        // (text, edit range, inserted)
+        let cases = [
+            ("foo bar", 3..4, ""),   // joins two words
+            ("foobar", 3..3, " "),   // splits a word
+            ("foo bar", 0..7, ""),   // removes everything
+            ("", 0..0, "a b\nc"),    // inserts into nothing
+            ("ab cd", 1..4, "x\ny"), // replaces across words
+            ("a b", 1..2, "\t\t"),   // whitespace for whitespace
+            ("x", 1..1, "é"),        // appends to a word
+            (" x", 0..1, ""),        // makes a word the first
+        ];
+
+        for (text, range, inserted) in cases {
+            let mut stats = count(text);
+            let bytes = text.as_bytes();
+            let prev = bytes[..range.start].last().copied();
+            let next = bytes.get(range.end).copied();
+            stats.apply_edit(prev, &bytes[range.clone()], inserted.as_bytes(), next);
+
+            let mut expected = text.to_string();
+            expected.replace_range(range, inserted);
+            assert_eq!(stats, count(&expected), "{text:?} -> {expected:?}");
+        }
+    }
+
+    #[test]
+    fn test_random_edits() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_crlf(false);
+        let alphabet = [b"a".as_slice(), b"b", b" ", b"\n", "é".as_bytes(), b"\t"];
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let mut next = |max: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % max as u64) as usize
+        };
+
+        for _ in 0..500 {
+            let lines = tb.logical_line_count() as usize;
+            tb.cursor_move_to_logical(Point {
+                x: next(8) as CoordType,
+                y: next(lines) as CoordType,
+            });
+
+            match next(4) {
+                0 => tb.delete(CursorMovement::Grapheme, 1 + next(3) as CoordType),
+                1 => tb.delete(CursorMovement::Grapheme, -1 - next(3) as CoordType),
+                2 => tb.undo(),
+                _ => {
+                    let mut text = Vec::new();
+                    for _ in 0..1 + next(5) {
+                        text.extend_from_slice(alphabet[next(alphabet.len())]);
+                    }
+                    tb.write_raw(&text);
+                }
+            }
+
+            let expected = DocumentStats::of_range(&tb.buffer, 0..tb.buffer.len());
+            assert_eq!(tb.document_stats(), expected);
+            assert_eq!(expected.lines(), tb.logical_line_count() as usize);
+        }
+    }
+}
@@ -0,0 +1,661 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Line-wise transforms over the current selection, such as sorting lines.
+//!
+//! All of them work the same way: The selection is snapped to whole lines,
+//! the region is extracted once, the lines are rearranged or rewritten
+//! from slices into that copy, and the result is written back as a single
+//! undo step.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use super::{TextBuffer, TextBufferSelection};
+use crate::helpers::*;
+use crate::unicode::{Cursor, MeasurementConfig};
+
+/// Options for [`TextBuffer::sort_selected_lines`].
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub struct SortLinesOptions {
+    /// If true, ASCII letters are compared case-insensitively.
+    pub case_insensitive: bool,
+    /// If true, runs of digits are compared by their numeric value,
+    /// so that "file2" sorts before "file10".
+    pub numeric: bool,
+    /// If true, the sort order is reversed. Equal lines keep their relative order.
+    pub descending: bool,
+}
+
+/// See [`TextBuffer::dedup_selected_lines`].
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DedupLines {
+    /// Only remove lines that are equal to the line right above them.
+    Consecutive,
+    /// Remove all but the first occurrence of each line.
+    Global,
+}
+
+/// A line in an extracted region. The newline is stored separately
+/// from the text, so that each line can retain its own (CR)LF.
+#[derive(Clone, Copy)]
+struct RegionLine<'a> {
+    text: &'a [u8],
+    newline: &'a [u8],
+}
+
+impl TextBuffer {
+    /// Sorts the lines touched by the selection.
+    ///
+    /// A selection that doesn't start or end at a line boundary
+    /// is extended to cover the lines it touches.
+    pub fn sort_selected_lines(&mut self, options: SortLinesOptions) {
+        self.transform_selected_lines(|lines| {
+            // `sort_by` is stable, which means that lines with equal keys retain their order,
+            // even if we sort in descending order (because we reverse the comparison, not the result).
+            lines.sort_by(|a, b| {
+                let ord = compare_lines(a.text, b.text, options);
+                if options.descending { ord.reverse() } else { ord }
+            });
+        });
+    }
+
+    /// Removes duplicate lines among the lines touched by the selection.
+    pub fn dedup_selected_lines(&mut self, mode: DedupLines) {
+        self.transform_selected_lines(|lines| match mode {
+            DedupLines::Consecutive => lines.dedup_by(|a, b| a.text == b.text),
+            DedupLines::Global => {
+                let mut seen = HashSet::with_capacity(lines.len());
+                lines.retain(|l| seen.insert(l.text));
+            }
+        });
+    }
+
+    /// Reverses the order of the lines touched by the selection.
+    pub fn reverse_selected_lines(&mut self) {
+        self.transform_selected_lines(|lines| lines.reverse());
+    }
+
+    /// Joins the lines touched by the selection into one. Without a selection,
+    /// or if it only touches a single line, that line is joined with the next one.
+    ///
+    /// The leading whitespace of each joined line is removed and, if `separator`
+    /// is true, replaced with a single space (unless there's whitespace already).
+    pub fn join_selected_lines(&mut self, separator: bool) {
+        let (beg, mut end) = self.selected_lines_range();
+        if end.logical_pos.y - beg.logical_pos.y < 2 {
+            end =
+                self.cursor_move_to_logical_internal(end, Point { x: 0, y: beg.logical_pos.y + 2 });
+        }
+
+        self.rewrite_lines(beg, end, |lines, out| {
+            for (i, line) in lines.iter().enumerate() {
+                let mut text = line.text;
+                if i != 0 {
+                    text = trim_indentation(text);
+                    if separator
+                        && !text.is_empty()
+                        && !out.is_empty()
+                        && !out.ends_with(b" ")
+                        && !out.ends_with(b"\t")
+                    {
+                        out.push(b' ');
+                    }
+                }
+                out.extend_from_slice(text);
+            }
+
+            if let Some(last) = lines.last() {
+                out.extend_from_slice(last.newline);
+            }
+        });
+    }
+
+    /// Hard-wraps the lines touched by the selection, such that
+    /// no line is wider than `width` columns, if possible.
+    ///
+    /// Lines are wrapped at word boundaries, or at grapheme cluster boundaries if a word is
+    /// wider than `width`. The continuation lines get the same indentation as the original.
+    pub fn wrap_selected_lines(&mut self, width: CoordType) {
+        // The measurement code needs 2 columns, because otherwise wide glyphs wouldn't ever fit.
+        let width = width.max(2);
+        let tab_size = self.tab_size;
+        let default_newline = self.default_newline();
+        let (beg, end) = self.selected_lines_range();
+
+        self.rewrite_lines(beg, end, |lines, out| {
+            for line in lines {
+                wrap_line(out, line, width, tab_size, default_newline);
+            }
+        });
+    }
+
+    /// Comments or uncomments the lines touched by the selection, given a
+    /// line comment `prefix` such as `"// "` or `"# "`.
+    ///
+    /// If all non-blank lines already start with the prefix (after their indentation), the
+    /// first occurrence of it is removed, with or without its trailing space. Otherwise, the
+    /// prefix is inserted into all non-blank lines at their common indentation column.
+    pub fn toggle_line_comments(&mut self, prefix: &str) {
+        let prefix = prefix.as_bytes();
+        let prefix_trimmed = prefix.trim_ascii_end();
+        if prefix_trimmed.is_empty() {
+            return;
+        }
+
+        let tab_size = self.tab_size;
+        let had_selection = self.selection.is_some();
+        let cursor_before = self.cursor.logical_pos;
+        let (beg, end) = self.selected_lines_range();
+        let cursor_line = (cursor_before.y - beg.logical_pos.y) as usize;
+        // How far the cursor needs to move, if it's past the given column.
+        let mut cursor_adjust = (0, 0);
+
+        self.rewrite_lines(beg, end, |lines, out| {
+            let is_blank = |line: &RegionLine| trim_indentation(line.text).is_empty();
+            let uncomment = lines
+                .iter()
+                .filter(|l| !is_blank(l))
+                .all(|l| trim_indentation(l.text).starts_with(prefix_trimmed));
+
+            // The column at which the prefix is inserted is the smallest indentation among the lines.
+            let column = lines
+                .iter()
+                .filter(|l| !is_blank(l))
+                .map(|l| indentation_width(l.text, usize::MAX, tab_size).1)
+                .min()
+                .unwrap_or(0);
+
+            for (i, line) in lines.iter().enumerate() {
+                let text = line.text;
+
+                if is_blank(line) {
+                    out.extend_from_slice(text);
+                } else if uncomment {
+                    let indentation = text.len() - trim_indentation(text).len();
+                    let rest = &text[indentation..];
+                    let remove = if rest.starts_with(prefix) { prefix } else { prefix_trimmed };
+                    out.extend_from_slice(&text[..indentation]);
+                    out.extend_from_slice(&rest[remove.len()..]);
+
+                    if i == cursor_line {
+                        cursor_adjust = (indentation as CoordType, -(remove.len() as CoordType));
+                    }
+                } else {
+                    // The prefix is inserted at the last character that still fits into the column.
+                    // This ensures that we never insert it in front of a non-whitespace character.
+                    let (offset, _) = indentation_width(text, column, tab_size);
+                    out.extend_from_slice(&text[..offset]);
+                    out.extend_from_slice(prefix);
+                    out.extend_from_slice(&text[offset..]);
+
+                    if i == cursor_line {
+                        cursor_adjust = (offset as CoordType, prefix.len() as CoordType);
+                    }
+                }
+
+                out.extend_from_slice(line.newline);
+            }
+        });
+
+        // Keep the cursor on the same character, unless it's within the removed prefix.
+        let (column, delta) = cursor_adjust;
+        if !had_selection && delta != 0 && cursor_before.x >= column {
+            let x = (cursor_before.x + delta).max(column);
+            self.cursor_move_to_logical(Point { x, y: cursor_before.y });
+        }
+    }
+
+    /// Returns the start of the first and the end of the last line touched by the
+    /// selection. Without a selection, the line the cursor is on is returned.
+    ///
+    /// A selection ending at the very start of a line doesn't include that line,
+    /// because that's what it looks like to the user.
+    fn selected_lines_range(&self) -> (Cursor, Cursor) {
+        let [beg, end] = match self.selection {
+            Some(TextBufferSelection { beg, end }) => minmax(beg, end),
+            None => [self.cursor.logical_pos, self.cursor.logical_pos],
+        };
+        let end_y = if end.x == 0 && end.y > beg.y { end.y } else { end.y + 1 };
+
+        let beg = self.cursor_move_to_logical_internal(self.cursor, Point { x: 0, y: beg.y });
+        let end = self.cursor_move_to_logical_internal(beg, Point { x: 0, y: end_y });
+        (beg, end)
+    }
+
+    /// Extracts the lines touched by the selection, lets `func` rearrange
+    /// them, and writes the result back as a single undo step.
+    ///
+    /// Lines retain their original newline. The only exception is the last line of
+    /// the buffer, which may not have one: Whether the region ends in a newline is
+    /// preserved, so a line moving in or out of that position gains or loses one.
+    fn transform_selected_lines(&mut self, func: impl FnOnce(&mut Vec<RegionLine>)) {
+        let (beg, end) = self.selected_lines_range();
+        let default_newline = self.default_newline();
+
+        self.rewrite_lines(beg, end, |lines, out| {
+            let trailing_newline = lines.last().is_some_and(|l| !l.newline.is_empty());
+            let mut lines = lines.to_vec();
+            func(&mut lines);
+
+            for (i, line) in lines.iter().enumerate() {
+                let is_last = i + 1 == lines.len();
+                out.extend_from_slice(line.text);
+                if !is_last || trailing_newline {
+                    let newline =
+                        if line.newline.is_empty() { default_newline } else { line.newline };
+                    out.extend_from_slice(newline);
+                }
+            }
+        });
+    }
+
+    /// Extracts the lines between `beg` and `end`, lets `func` write their
+    /// replacement into the given `Vec`, and applies it as a single undo step.
+    ///
+    /// If there was a selection, it'll cover the rewritten lines afterwards.
+    /// Otherwise, the cursor stays at its logical position.
+    fn rewrite_lines(
+        &mut self,
+        beg: Cursor,
+        end: Cursor,
+        func: impl FnOnce(&[RegionLine], &mut Vec<u8>),
+    ) {
+        if beg.offset >= end.offset {
+            return;
+        }
+
+        let had_selection = self.selection.is_some();
+        let cursor_before = self.cursor.logical_pos;
+
+        let mut region = Vec::new();
+        self.buffer.extract_raw(beg.offset..end.offset, &mut region, 0);
+
+        let lines = split_region_lines(&region);
+        let mut replacement = Vec::with_capacity(region.len() + 2);
+        func(&lines, &mut replacement);
+
+        if replacement == region {
+            return;
+        }
+
+        self.edit_replace(beg, end, &replacement);
+
+        if had_selection {
+            let end = self.cursor;
+            let beg = self.cursor_move_to_logical_internal(end, beg.logical_pos);
+            self.set_cursor_internal(end);
+            self.set_selection(Some(TextBufferSelection {
+                beg: beg.logical_pos,
+                end: end.logical_pos,
+            }));
+        } else {
+            self.cursor_move_to_logical(cursor_before);
+        }
+    }
+
+    fn default_newline(&self) -> &'static [u8] {
+        if self.newlines_are_crlf { b"\r\n" } else { b"\n" }
+    }
+}
+
+/// Splits `region` into lines, each with its (CR)LF, if any.
+fn split_region_lines(region: &[u8]) -> Vec<RegionLine<'_>> {
+    let mut lines = Vec::new();
+    let mut beg = 0;
+
+    while beg < region.len() {
+        let (text_end, newline_end) = match region[beg..].iter().position(|&c| c == b'\n') {
+            Some(i) => {
+                let lf = beg + i;
+                let cr = lf > beg && region[lf - 1] == b'\r';
+                (lf - cr as usize, lf + 1)
+            }
+            None => (region.len(), region.len()),
+        };
+
+        lines.push(RegionLine {
+            text: &region[beg..text_end],
+            newline: &region[text_end..newline_end],
+        });
+        beg = newline_end;
+    }
+
+    lines
+}
+
+/// Writes `line` into `out`, hard-wrapped at `width` columns.
+/// See [`TextBuffer::wrap_selected_lines`].
+fn wrap_line(
+    out: &mut Vec<u8>,
+    line: &RegionLine,
+    width: CoordType,
+    tab_size: CoordType,
+    newline: &[u8],
+) {
+    let text = line.text;
+    let indentation = &text[..text.len() - trim_indentation(text).len()];
+    let indentation_columns =
+        MeasurementConfig::new(&text).with_tab_size(tab_size).goto_offset(indentation.len()).column;
+    let mut cursor = Cursor::default();
+
+    loop {
+        // Find the start of the next visual row, as if word wrap was enabled.
+        let mut next = MeasurementConfig::new(&text)
+            .with_tab_size(tab_size)
+            .with_word_wrap_column(width)
+            .with_cursor(cursor)
+            .goto_visual(Point { x: 0, y: 1 });
+
+        // The remainder fits.
+        if next.visual_pos.y == 0 {
+            out.extend_from_slice(&text[cursor.offset..]);
+            break;
+        }
+
+        // If there was no word boundary past the indentation, we have to wrap in the middle
+        // of the word instead. We need to make at least some progress, even if that means
+        // exceeding the `width` (e.g. if the indentation is wider than the `width` itself).
+        if trim_indentation(&text[cursor.offset..next.offset]).is_empty() {
+            let mut cfg = MeasurementConfig::new(&text).with_tab_size(tab_size).with_cursor(cursor);
+            next = cfg.goto_visual(Point { x: width, y: 0 });
+            if next.offset <= cursor.offset {
+                next = cfg.goto_logical(Point { x: cursor.logical_pos.x + 1, y: 0 });
+            }
+        }
+
+        let row = &text[cursor.offset..next.offset];
+        out.extend_from_slice(row.trim_ascii_end());
+        out.extend_from_slice(newline);
+
+        let rest = trim_indentation(&text[next.offset..]);
+        if rest.is_empty() {
+            break;
+        }
+
+        out.extend_from_slice(indentation);
+
+        // Continue measuring the rest of the line as if it was preceded by the indentation.
+        cursor = Cursor {
+            offset: text.len() - rest.len(),
+            visual_pos: Point { x: indentation_columns, y: 0 },
+            column: indentation_columns,
+            ..Default::default()
+        };
+    }
+
+    if !line.newline.is_empty() {
+        out.extend_from_slice(line.newline);
+    }
+}
+
+/// Strips leading spaces and tabs.
+fn trim_indentation(text: &[u8]) -> &[u8] {
+    let indentation = text.iter().take_while(|&&c| c == b' ' || c == b'\t').count();
+    &text[indentation..]
+}
+
+/// Measures the indentation of `text`, up to `max_columns` columns.
+/// Returns the length in bytes and its width in columns.
+fn indentation_width(text: &[u8], max_columns: usize, tab_size: CoordType) -> (usize, usize) {
+    let tab_size = tab_size as usize;
+    let mut columns = 0;
+    let mut offset = 0;
+
+    for &c in text {
+        let next = match c {
+            b' ' => columns + 1,
+            b'\t' => columns + tab_size - columns % tab_size,
+            _ => break,
+        };
+        if next > max_columns {
+            break;
+        }
+        columns = next;
+        offset += 1;
+    }
+
+    (offset, columns)
+}
+
+/// Compares two lines according to the given sort options.
+fn compare_lines(a: &[u8], b: &[u8], options: SortLinesOptions) -> Ordering {
+    let fold = |c: u8| if options.case_insensitive { c.to_ascii_lowercase() } else { c };
+    let mut i = 0;
+    let mut j = 0;
+
+    while i < a.len() && j < b.len() {
+        if options.numeric && a[i].is_ascii_digit() && b[j].is_ascii_digit() {
+            let a_end = i + a[i..].iter().take_while(|c| c.is_ascii_digit()).count();
+            let b_end = j + b[j..].iter().take_while(|c| c.is_ascii_digit()).count();
+            let a_num = trim_leading_zeros(&a[i..a_end]);
+            let b_num = trim_leading_zeros(&b[j..b_end]);
+
+            // Without leading zeros, the longer number is the larger one.
+            // For numbers of equal length, a plain byte comparison does the trick.
+            let ord = a_num.len().cmp(&b_num.len()).then_with(|| a_num.cmp(b_num));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+
+            i = a_end;
+            j = b_end;
+        } else {
+            let ord = fold(a[i]).cmp(&fold(b[j]));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+
+            i += 1;
+            j += 1;
+        }
+    }
+
+    (a.len() - i).cmp(&(b.len() - j))
+}
+
+fn trim_leading_zeros(digits: &[u8]) -> &[u8] {
+    let zeros = digits.iter().take_while(|&&c| c == b'0').count();
+    &digits[zeros..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{buffer_from, contents};
+
+    fn select_lines(tb: &mut TextBuffer, beg: Point, end: Point) {
+        tb.cursor_move_to_logical(beg);
+        tb.selection_update_logical(end);
+    }
+
+    #[test]
+    fn test_sort_numeric() {
+        let mut tb = buffer_from("file10\nfile2\nfile1\n");
+        tb.select_all();
+        tb.sort_selected_lines(SortLinesOptions::default());
+        assert_eq!(contents(&tb), "file1\nfile10\nfile2\n");
+
+        tb.select_all();
+        tb.sort_selected_lines(SortLinesOptions { numeric: true, ..Default::default() });
+        assert_eq!(contents(&tb), "file1\nfile2\nfile10\n");
+
+        tb.select_all();
+        tb.sort_selected_lines(SortLinesOptions {
+            numeric: true,
+            descending: true,
+            ..Default::default()
+        });
+        assert_eq!(contents(&tb), "file10\nfile2\nfile1\n");
+    }
+
+    #[test]
+    fn test_sort_stable() {
+        let options = SortLinesOptions { case_insensitive: true, ..Default::default() };
+        let mut tb = buffer_from("b\nB\na\nA\nb");
+        tb.select_all();
+        tb.sort_selected_lines(options);
+        assert_eq!(contents(&tb), "a\nA\nb\nB\nb");
+
+        // Equal keys keep their relative order in descending order, too.
+        tb.select_all();
+        tb.sort_selected_lines(SortLinesOptions { descending: true, ..options });
+        assert_eq!(contents(&tb), "b\nB\nb\na\nA");
+    }
+
+    #[test]
+    fn test_sort_snaps_to_lines() {
+        let mut tb = buffer_from("zeta\ngamma\nbeta\nalpha\n");
+        // From the middle of "gamma" to the middle of "beta".
+        select_lines(&mut tb, Point { x: 2, y: 1 }, Point { x: 2, y: 2 });
+        tb.sort_selected_lines(SortLinesOptions::default());
+        assert_eq!(contents(&tb), "zeta\nbeta\ngamma\nalpha\n");
+
+        // A selection ending at the start of a line doesn't include that line.
+        select_lines(&mut tb, Point { x: 0, y: 0 }, Point { x: 0, y: 2 });
+        tb.sort_selected_lines(SortLinesOptions::default());
+        assert_eq!(contents(&tb), "beta\nzeta\ngamma\nalpha\n");
+    }
+
+    #[test]
+    fn test_sort_single_undo() {
+        let mut tb = buffer_from("c\nb\na");
+        let undo_len = tb.undo_stack.len();
+        tb.select_all();
+        tb.sort_selected_lines(SortLinesOptions::default());
+        assert_eq!(contents(&tb), "a\nb\nc");
+        assert_eq!(tb.undo_stack.len(), undo_len + 1);
+
+        tb.undo();
+        assert_eq!(contents(&tb), "c\nb\na");
+    }
+
+    #[test]
+    fn test_crlf_preserved() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_crlf(true);
+        tb.write_raw(b"b\n");
+        tb.set_crlf(false);
+        tb.write_raw(b"c\na");
+        assert_eq!(contents(&tb), "b\r\nc\na");
+
+        // "a" has no newline, because it's the last line. It receives the
+        // buffer's default newline when it moves up, and "c" loses its own.
+        tb.select_all();
+        tb.sort_selected_lines(SortLinesOptions::default());
+        assert_eq!(contents(&tb), "a\nb\r\nc");
+
+        tb.select_all();
+        tb.reverse_selected_lines();
+        assert_eq!(contents(&tb), "c\nb\r\na");
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut tb = buffer_from("a\na\nb\na\nb\n");
+        tb.select_all();
+        tb.dedup_selected_lines(DedupLines::Consecutive);
+        assert_eq!(contents(&tb), "a\nb\na\nb\n");
+
+        tb.select_all();
+        tb.dedup_selected_lines(DedupLines::Global);
+        assert_eq!(contents(&tb), "a\nb\n");
+    }
+
+    #[test]
+    fn test_noop_records_no_undo() {
+        let mut tb = buffer_from("a\nb\n");
+        let undo_len = tb.undo_stack.len();
+        tb.select_all();
+        tb.sort_selected_lines(SortLinesOptions::default());
+        assert_eq!(tb.undo_stack.len(), undo_len);
+    }
+
+    #[test]
+    fn test_join() {
+        let mut tb = buffer_from("a\n    b\n\tc\nd\n");
+        tb.cursor_move_to_logical(Point { x: 0, y: 0 });
+        tb.join_selected_lines(true);
+        assert_eq!(contents(&tb), "a b\n\tc\nd\n");
+
+        tb.select_all();
+        tb.join_selected_lines(true);
+        assert_eq!(contents(&tb), "a b c d\n");
+        tb.undo();
+        assert_eq!(contents(&tb), "a b\n\tc\nd\n");
+
+        tb.select_all();
+        tb.join_selected_lines(false);
+        assert_eq!(contents(&tb), "a bcd\n");
+    }
+
+    #[test]
+    fn test_wrap() {
+        let mut tb = buffer_from("\tfoo bar baz qux\n  abcdefghijkl\nshort\n");
+        tb.set_tab_size(4);
+        tb.select_all();
+        tb.wrap_selected_lines(12);
+        assert_eq!(contents(&tb), "\tfoo bar\n\tbaz qux\n  abcdefghij\n  kl\nshort\n");
+
+        tb.undo();
+        tb.select_all();
+        tb.wrap_selected_lines(8);
+        assert_eq!(contents(&tb), "\tfoo\n\tbar\n\tbaz\n\tqux\n  abcdef\n  ghijkl\nshort\n");
+
+        // "\tfoo" is 7 columns wide, so even single words need to be split.
+        tb.undo();
+        tb.select_all();
+        tb.wrap_selected_lines(6);
+        assert_eq!(
+            contents(&tb),
+            "\tfo\n\to\n\tba\n\tr\n\tba\n\tz\n\tqu\n\tx\n  abcd\n  efgh\n  ijkl\nshort\n"
+        );
+    }
+
+    #[test]
+    fn test_toggle_line_comments() {
+        let original = "fn main() {\n    let a = 1;\n\n\tlet b = 2;\n  }\n";
+        let mut tb = buffer_from(original);
+        tb.set_tab_size(4);
+
+        // Blank lines are skipped and the prefix is inserted at the common indentation.
+        select_lines(&mut tb, Point { x: 0, y: 1 }, Point { x: 0, y: 4 });
+        tb.toggle_line_comments("// ");
+        assert_eq!(contents(&tb), "fn main() {\n    // let a = 1;\n\n\t// let b = 2;\n  }\n");
+
+        // Toggling again restores the original text.
+        tb.toggle_line_comments("// ");
+        assert_eq!(contents(&tb), original);
+
+        // Lines with less indentation than the others determine the column.
+        tb.select_all();
+        tb.toggle_line_comments("// ");
+        assert_eq!(contents(&tb), "// fn main() {\n//     let a = 1;\n\n// \tlet b = 2;\n//   }\n");
+        tb.select_all();
+        tb.toggle_line_comments("// ");
+        assert_eq!(contents(&tb), original);
+
+        // Only if all lines are commented, the comments are removed.
+        select_lines(&mut tb, Point { x: 0, y: 3 }, Point { x: 0, y: 5 });
+        tb.toggle_line_comments("# ");
+        assert_eq!(contents(&tb), "fn main() {\n    let a = 1;\n\n# \tlet b = 2;\n  # }\n");
+        select_lines(&mut tb, Point { x: 0, y: 1 }, Point { x: 0, y: 4 });
+        tb.toggle_line_comments("# ");
+        assert_eq!(contents(&tb), "fn main() {\n#     let a = 1;\n\n# # \tlet b = 2;\n  # }\n");
+    }
+
+    #[test]
+    fn test_uncomment_without_space() {
+        let mut tb = buffer_from("  //a\n  // b\n");
+        tb.select_all();
+        tb.toggle_line_comments("// ");
+        assert_eq!(contents(&tb), "  a\n  b\n");
+        assert_eq!(tb.undo_stack.len(), 2);
+
+        // Without a selection, the cursor line is toggled and the cursor stays on its character.
+        tb.cursor_move_to_logical(Point { x: 3, y: 1 });
+        tb.toggle_line_comments("// ");
+        assert_eq!(contents(&tb), "  a\n  // b\n");
+        assert_eq!(tb.cursor_logical_pos(), Point { x: 6, y: 1 });
+    }
+}
@@ -0,0 +1,308 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Caches the layout of the rows in view, so that scrolling doesn't lay out all of them again.
+//!
+//! Scrolling by a few rows damages the newly exposed ones and only those are laid out.
+//! After an edit, [`TextBuffer::render`] passes the damaged rows to [`ViewportCache::invalidate`]
+//! and only those are laid out again. The cached rows below them are shifted by however many
+//! bytes the edit inserted or removed, as long as the number of lines stayed the same.
+//! Otherwise, they're laid out again as well. If the text changed without a call to
+//! `invalidate`, for instance above the viewport, the rows are checked the same way.
+
+use super::TextBuffer;
+use crate::damage::Damage;
+use crate::helpers::*;
+use crate::unicode::Cursor;
+
+/// A laid out row: The text between `beg` and `end` is visible.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViewportRow {
+    pub beg: Cursor,
+    pub end: Cursor,
+}
+
+/// The layout of the rows in view. See the module documentation.
+#[derive(Default)]
+pub struct ViewportCache {
+    top: CoordType,
+    left: CoordType,
+    /// The text width, word wrap column and tab size the rows were laid out with.
+    layout: (CoordType, CoordType, CoordType),
+    /// The text length and visual line count the rows were laid out with.
+    text_length: usize,
+    visual_lines: CoordType,
+    rows: Vec<ViewportRow>,
+    valid: Vec<bool>,
+    computed: usize,
+}
+
+impl ViewportCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The rows in view, as of the last [`ViewportCache::update`].
+    pub fn rows(&self) -> &[ViewportRow] {
+        &self.rows
+    }
+
+    /// The number of rows laid out so far. Useful to check that the cache works.
+    pub fn computed(&self) -> usize {
+        self.computed
+    }
+
+    /// Marks the rows in `damage` as outdated. Its rows are visual rows in the document.
+    pub fn invalidate(&mut self, damage: &Damage) {
+        for (y, valid) in self.valid.iter_mut().enumerate() {
+            if damage.contains_row(self.top + y as CoordType) {
+                *valid = false;
+            }
+        }
+
+        // An edit above the viewport may have moved the rows in it.
+        // Laying out the first row again lets `fixup_after` check the others.
+        let above = damage.rows().first().is_some_and(|r| r.start < self.top)
+            || damage.rects().iter().any(|r| r.top < self.top);
+        if above && let Some(valid) = self.valid.first_mut() {
+            *valid = false;
+        }
+    }
+
+    /// Marks all rows as outdated.
+    pub fn clear(&mut self) {
+        self.valid.fill(false);
+    }
+
+    /// Lays out the rows of the given `size` starting at `origin`, reusing what's still valid.
+    pub fn update(&mut self, tb: &TextBuffer, origin: Point, size: Size) {
+        let height = size.height.max(0) as usize;
+        let text_width = size.width;
+        let layout = (text_width, tb.word_wrap_column, tb.tab_size);
+        // An edit that wasn't passed to `invalidate`, like one above the viewport,
+        // leaves all rows valid even though the text changed.
+        let unnoticed_edit = (tb.text_length() != self.text_length
+            || tb.visual_line_count() != self.visual_lines)
+            && !self.valid.contains(&false);
+
+        let delta = origin.y - self.top;
+        let shift = delta.unsigned_abs();
+        self.top = origin.y;
+
+        if layout != self.layout || origin.x != self.left {
+            self.clear();
+        } else {
+            // Scrolling damages the rows that come into view.
+            let mut exposed = Damage::new();
+            let len = self.rows.len() as CoordType;
+            if shift >= self.rows.len() {
+                exposed.mark_all();
+            } else if delta > 0 {
+                self.rows.rotate_left(shift);
+                self.valid.rotate_left(shift);
+                exposed.add_rows(origin.y + len - delta..origin.y + len);
+            } else if delta < 0 {
+                self.rows.rotate_right(shift);
+                self.valid.rotate_right(shift);
+                exposed.add_rows(origin.y..origin.y - delta);
+            }
+            self.invalidate(&exposed);
+        }
+
+        self.left = origin.x;
+        self.layout = layout;
+        self.rows.resize(height, ViewportRow::default());
+        self.valid.resize(height, false);
+
+        // Laying out the first row again lets `fixup_after` check the others.
+        if unnoticed_edit && let Some(valid) = self.valid.first_mut() {
+            *valid = false;
+        }
+
+        // Start from the cursor closer to the rows in view.
+        let mut hint = {
+            let a = tb.cursor;
+            let b = tb.cursor_for_rendering.unwrap_or_default();
+            let da = (a.visual_pos.y - origin.y).abs();
+            let db = (b.visual_pos.y - origin.y).abs();
+            if da < db { a } else { b }
+        };
+
+        for i in 0..height {
+            if self.valid[i] {
+                hint = self.rows[i].end;
+                continue;
+            }
+
+            let y = self.top + i as CoordType;
+            let beg = tb.cursor_move_to_visual_internal(hint, Point { x: self.left, y });
+            let end =
+                tb.cursor_move_to_visual_internal(beg, Point { x: self.left + text_width, y });
+            self.rows[i] = ViewportRow { beg, end };
+            self.valid[i] = true;
+            self.computed += 1;
+            hint = end;
+
+            if i + 1 < height && self.valid[i + 1] {
+                self.fixup_after(tb, i);
+            }
+        }
+
+        self.text_length = tb.text_length();
+        self.visual_lines = tb.visual_line_count();
+    }
+
+    /// Row `i` was laid out again, but the one after it wasn't.
+    /// If an edit moved the text after it, this adjusts the offsets of the cached rows,
+    /// or invalidates them if the edit changed how they're laid out.
+    fn fixup_after(&mut self, tb: &TextBuffer, i: usize) {
+        let y = self.top + i as CoordType + 1;
+        let expected =
+            tb.cursor_move_to_visual_internal(self.rows[i].end, Point { x: self.left, y });
+        let cached = self.rows[i + 1].beg;
+        let delta = expected.offset.wrapping_sub(cached.offset);
+
+        // The rows below only moved if the edits happened above them. In that case,
+        // they moved by as much as the text length changed and they start at the same position.
+        if tb.visual_line_count() != self.visual_lines
+            || delta != tb.text_length().wrapping_sub(self.text_length)
+            || expected.logical_pos != cached.logical_pos
+            || expected.visual_pos != cached.visual_pos
+        {
+            self.valid[i + 1..].fill(false);
+            return;
+        }
+
+        if delta != 0 {
+            for (row, _) in self.rows[i + 1..].iter_mut().zip(&self.valid[i + 1..]).filter(|r| *r.1)
+            {
+                row.beg.offset = row.beg.offset.wrapping_add(delta);
+                row.end.offset = row.end.offset.wrapping_add(delta);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::CursorMovement;
+
+    fn buffer(lines: usize) -> TextBuffer {
+        let mut tb = TextBuffer::new(false).unwrap();
+        tb.set_crlf(false);
+        tb.set_width(40);
+        let text: String = (0..lines).map(|i| format!("line {i}\n")).collect();
+        tb.write_raw(text.as_bytes());
+        tb
+    }
+
+    const VIEW: Size = Size { width: 40, height: 10 };
+
+    /// Checks the cached rows against freshly laid out ones.
+    fn check(cache: &ViewportCache, tb: &TextBuffer, origin: Point) {
+        let mut fresh = ViewportCache::new();
+        fresh.update(tb, origin, Size { width: 40, height: cache.rows().len() as CoordType });
+        assert_eq!(cache.rows(), fresh.rows());
+    }
+
+    #[test]
+    fn test_scroll() {
+        let tb = buffer(100);
+        let mut cache = ViewportCache::new();
+
+        cache.update(&tb, Point { x: 0, y: 0 }, VIEW);
+        assert_eq!(cache.computed(), 10);
+        assert_eq!(cache.rows()[3].beg.logical_pos, Point { x: 0, y: 3 });
+
+        // Only the newly exposed rows are laid out.
+        cache.update(&tb, Point { x: 0, y: 1 }, VIEW);
+        assert_eq!(cache.computed(), 11);
+        check(&cache, &tb, Point { x: 0, y: 1 });
+
+        cache.update(&tb, Point { x: 0, y: 0 }, VIEW);
+        assert_eq!(cache.computed(), 12);
+        check(&cache, &tb, Point { x: 0, y: 0 });
+
+        // Jumping far away lays out everything.
+        cache.update(&tb, Point { x: 0, y: 80 }, VIEW);
+        assert_eq!(cache.computed(), 22);
+        check(&cache, &tb, Point { x: 0, y: 80 });
+
+        // So does scrolling horizontally.
+        cache.update(&tb, Point { x: 2, y: 80 }, VIEW);
+        assert_eq!(cache.computed(), 32);
+        check(&cache, &tb, Point { x: 2, y: 80 });
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut tb = buffer(100);
+        let mut cache = ViewportCache::new();
+        let origin = Point { x: 0, y: 0 };
+        cache.update(&tb, origin, VIEW);
+
+        // An edit within a line only invalidates its row.
+        tb.cursor_move_to_logical(Point { x: 2, y: 3 });
+        tb.write_raw(b"xyz");
+        let mut damage = Damage::new();
+        damage.add_rows(3..4);
+        cache.invalidate(&damage);
+        cache.update(&tb, origin, VIEW);
+        assert_eq!(cache.computed(), 11);
+        check(&cache, &tb, origin);
+
+        // An edit that adds a line changes the rows below it as well.
+        tb.write_raw(b"\n");
+        let mut damage = Damage::new();
+        damage.add_rows(3..4);
+        cache.invalidate(&damage);
+        cache.update(&tb, origin, VIEW);
+        assert_eq!(cache.computed(), 11 + 7);
+        check(&cache, &tb, origin);
+
+        // So does moving a newline around, even if the line count stays the same.
+        tb.cursor_move_to_logical(Point { x: 0, y: 4 });
+        tb.delete(CursorMovement::Grapheme, -1);
+        tb.cursor_move_to_logical(Point { x: 1, y: 3 });
+        tb.write_raw(b"\n");
+        let mut damage = Damage::new();
+        damage.add_rows(3..4);
+        cache.invalidate(&damage);
+        cache.update(&tb, origin, VIEW);
+        assert_eq!(cache.computed(), 11 + 7 + 7);
+        check(&cache, &tb, origin);
+    }
+
+    #[test]
+    fn test_edit_above() {
+        let mut tb = buffer(100);
+        let mut cache = ViewportCache::new();
+        let origin = Point { x: 0, y: 50 };
+        cache.update(&tb, origin, VIEW);
+
+        // Without a line break, the rows only move.
+        tb.cursor_move_to_logical(Point { x: 2, y: 3 });
+        tb.write_raw(b"xyz");
+        cache.update(&tb, origin, VIEW);
+        assert_eq!(cache.computed(), 11);
+        check(&cache, &tb, origin);
+
+        tb.write_raw(b"\n");
+        cache.update(&tb, origin, VIEW);
+        assert_eq!(cache.computed(), 21);
+        check(&cache, &tb, origin);
+
+        // The rows above an edit in view are checked as well, if there was one above it.
+        tb.cursor_move_to_logical(Point { x: 2, y: 3 });
+        tb.write_raw(b"xyz");
+        tb.cursor_move_to_logical(Point { x: 2, y: 55 });
+        tb.write_raw(b"xyz");
+        let mut damage = Damage::new();
+        damage.add_rows(3..4);
+        damage.add_rows(55..56);
+        cache.invalidate(&damage);
+        cache.update(&tb, origin, VIEW);
+        check(&cache, &tb, origin);
+    }
+}
@@ -1,5 +1,12 @@
 //! Clipboard facilities for the editor.
 
+use std::collections::VecDeque;
+
+use crate::arena::ArenaString;
+use crate::base64;
+use crate::helpers::KIBI;
+use crate::mem_report::{MemoryReport, MemoryUsage};
+
 /// The builtin, internal clipboard of the editor.
 ///
 /// This is useful particularly when the terminal doesn't support
@@ -51,3 +58,318 @@ impl Clipboard {
         self.line_copy = line_copy;
     }
 }
+
+/// The most recent clipboard contents, newest first.
+///
+/// It allows pasting a previous entry by first pasting the newest one and then
+/// cycling through the older ones, each replacing the previously pasted text.
+/// See [`crate::buffer::TextBuffer::paste_from_ring`] and
+/// [`crate::buffer::TextBuffer::paste_cycle`].
+pub struct ClipboardRing {
+    entries: VecDeque<Vec<u8>>,
+    capacity: usize,
+    last_paste: Option<RingPaste>,
+    last_kill: Option<RingKill>,
+}
+
+/// Describes the text that was most recently pasted from a [`ClipboardRing`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RingPaste {
+    /// The index of the pasted entry in the ring.
+    pub index: usize,
+    /// The offsets of the pasted text in the document.
+    pub beg: usize,
+    pub end: usize,
+    /// The generation of the document right after the paste.
+    /// If it changed since, the paste can't be cycled anymore.
+    pub generation: u32,
+}
+
+/// Whether a kill deleted the text after or before the cursor.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KillDirection {
+    Forward,
+    Backward,
+}
+
+/// The state of the document right before or after a kill. See [`ClipboardRing::kill`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RingKill {
+    /// Identifies the document, so that kills in different ones are never collected together.
+    pub buffer: u32,
+    /// See [`crate::buffer::TextBuffer::generation`].
+    pub generation: u32,
+    /// See [`crate::buffer::TextBuffer::cursor_generation`].
+    pub cursor_generation: u32,
+}
+
+impl ClipboardRing {
+    /// Creates a ring holding up to `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+            last_paste: None,
+            last_kill: None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Adds a new entry at the front, evicting the oldest one if the ring is full.
+    /// Empty entries and entries identical to the newest one are ignored.
+    pub fn push(&mut self, entry: Vec<u8>) {
+        if entry.is_empty() || self.entries.front() == Some(&entry) {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_back();
+        }
+        self.entries.push_front(entry);
+        self.last_paste = None;
+        self.last_kill = None;
+    }
+
+    /// Adds the clipboard contents reported by an OSC 52 response. See [`osc52_parse_response`].
+    ///
+    /// Returns false if `data` isn't a valid response, in which case the ring is unchanged.
+    pub fn push_osc52_response(&mut self, data: &str) -> bool {
+        match osc52_parse_response(data) {
+            Some(entry) => {
+                self.push(entry);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds killed (= cut) text. `before` and `after` describe the document around the kill.
+    ///
+    /// If `before` matches the state after the previous kill, the cursor wasn't moved and
+    /// nothing was edited in between. The kills then form a sequence and share one entry:
+    /// Forward kills are appended to it and backward kills prepended.
+    pub fn kill(
+        &mut self,
+        text: Vec<u8>,
+        direction: KillDirection,
+        before: RingKill,
+        after: RingKill,
+    ) {
+        if text.is_empty() {
+            return;
+        }
+
+        match self.entries.front_mut() {
+            Some(entry) if self.last_kill == Some(before) => match direction {
+                KillDirection::Forward => entry.extend_from_slice(&text),
+                KillDirection::Backward => {
+                    entry.splice(0..0, text);
+                }
+            },
+            _ => self.push(text),
+        }
+
+        self.last_paste = None;
+        self.last_kill = Some(after);
+    }
+
+    /// Returns the `n`-th newest entry.
+    pub fn get(&self, n: usize) -> Option<&[u8]> {
+        self.entries.get(n).map(|e| &e[..])
+    }
+
+    /// Returns the last paste, if any.
+    pub fn last_paste(&self) -> Option<RingPaste> {
+        self.last_paste
+    }
+
+    /// Records where the entry at `paste.index` was pasted.
+    pub fn set_last_paste(&mut self, paste: Option<RingPaste>) {
+        self.last_paste = paste;
+    }
+}
+
+impl MemoryUsage for ClipboardRing {
+    fn memory_usage(&self) -> MemoryReport {
+        let mut children = vec![self.entries.memory_usage().named("entries")];
+        children.extend(self.entries.iter().map(|e| e.memory_usage().named("entry")));
+        MemoryReport::node("ClipboardRing", children)
+    }
+}
+
+/// The selection targeted by an OSC 52 sequence.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Osc52Selection {
+    /// The regular system clipboard.
+    Clipboard,
+    /// The X11 primary selection.
+    Primary,
+}
+
+/// Many terminals ignore OSC 52 sequences beyond about 100KB.
+pub const OSC52_DEFAULT_LIMIT: usize = 100 * KIBI;
+
+/// Appends an OSC 52 sequence to `dst`, which asks the terminal to put `payload` into the given `selection`.
+///
+/// If the sequence would be longer than `limit` bytes, the payload is truncated
+/// at a UTF-8 character boundary. Returns the number of payload bytes that were included.
+pub fn osc52_copy(
+    dst: &mut ArenaString,
+    payload: &[u8],
+    selection: Osc52Selection,
+    limit: usize,
+) -> usize {
+    const PREFIX_LEN: usize = "\x1b]52;c;".len();
+    const SUFFIX_LEN: usize = "\x1b\\".len();
+
+    let available = limit.saturating_sub(PREFIX_LEN + SUFFIX_LEN) / 4 * 3;
+    let mut len = payload.len().min(available);
+    if len < payload.len() {
+        // Don't cut a character in half, unless it's not UTF-8 to begin with.
+        let boundary = (len.saturating_sub(3)..=len)
+            .rev()
+            .find(|&i| payload.get(i).is_none_or(|&c| (c as i8) >= -0x40));
+        len = boundary.unwrap_or(len);
+    }
+
+    // Rust doubles the size of a string when it needs to grow it.
+    // If `payload` is *really* large, this may then double
+    // the size of `dst` from e.g. 100MB to 200MB. Not good.
+    // We can avoid that by reserving the needed size in advance.
+    dst.reserve_exact(base64::encode_len(len) + PREFIX_LEN + SUFFIX_LEN);
+    dst.push_str(match selection {
+        Osc52Selection::Clipboard => "\x1b]52;c;",
+        Osc52Selection::Primary => "\x1b]52;p;",
+    });
+    base64::encode(dst, &payload[..len]);
+    dst.push_str("\x1b\\");
+    len
+}
+
+/// Parses the data of an OSC 52 response, like `52;c;YWJj`, as reported by [`crate::vt::Token::Osc`].
+///
+/// Returns `None` if it's not an OSC 52 response or if it's malformed.
+pub fn osc52_parse_response(data: &str) -> Option<Vec<u8>> {
+    let rest = data.strip_prefix("52;")?;
+    let (selection, payload) = rest.split_once(';')?;
+    if !selection.bytes().all(|c| c.is_ascii_alphanumeric()) || payload == "?" {
+        return None;
+    }
+    base64::decode(payload.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::Arena;
+
+    #[test]
+    fn test_ring_bound_and_dedup() {
+        let mut ring = ClipboardRing::new(3);
+        for entry in ["a", "b", "b", "", "c", "b", "d"] {
+            ring.push(entry.as_bytes().to_vec());
+        }
+
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.get(0), Some(&b"d"[..]));
+        assert_eq!(ring.get(1), Some(&b"b"[..]));
+        assert_eq!(ring.get(2), Some(&b"c"[..]));
+        assert_eq!(ring.get(3), None);
+    }
+
+    #[test]
+    fn test_ring_osc52_response() {
+        let mut ring = ClipboardRing::new(3);
+        ring.push(b"a".to_vec());
+
+        assert!(ring.push_osc52_response("52;c;aGVsbG8="));
+        assert!(!ring.push_osc52_response("52;c;?"));
+        assert!(!ring.push_osc52_response("52;c;aGVs\x1b\\bG8="));
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.get(0), Some(&b"hello"[..]));
+        assert_eq!(ring.get(1), Some(&b"a"[..]));
+    }
+
+    #[test]
+    fn test_ring_kill_sequence() {
+        let state = |generation| RingKill { buffer: 0, generation, cursor_generation: 0 };
+        let mut ring = ClipboardRing::new(3);
+
+        ring.kill(b"b".to_vec(), KillDirection::Forward, state(1), state(2));
+        ring.kill(b"c".to_vec(), KillDirection::Forward, state(2), state(3));
+        ring.kill(b"a".to_vec(), KillDirection::Backward, state(3), state(4));
+        assert_eq!(ring.len(), 1);
+        assert_eq!(ring.get(0), Some(&b"abc"[..]));
+
+        // The document changed in between.
+        ring.kill(b"d".to_vec(), KillDirection::Forward, state(5), state(6));
+        assert_eq!(ring.len(), 2);
+        assert_eq!(ring.get(0), Some(&b"d"[..]));
+
+        // Copying ends the sequence, too.
+        ring.push(b"e".to_vec());
+        ring.kill(b"f".to_vec(), KillDirection::Forward, state(6), state(7));
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.get(0), Some(&b"f"[..]));
+    }
+
+    #[test]
+    fn test_osc52_copy() {
+        let arena = Arena::new(64 * 1024).unwrap();
+        let copy = |payload: &[u8], selection, limit| {
+            let mut dst = ArenaString::new_in(&arena);
+            let len = osc52_copy(&mut dst, payload, selection, limit);
+            (dst, len)
+        };
+
+        let (seq, len) = copy(b"hello", Osc52Selection::Clipboard, OSC52_DEFAULT_LIMIT);
+        assert_eq!(seq, "\x1b]52;c;aGVsbG8=\x1b\\");
+        assert_eq!(len, 5);
+
+        let (seq, _) = copy(b"", Osc52Selection::Primary, OSC52_DEFAULT_LIMIT);
+        assert_eq!(seq, "\x1b]52;p;\x1b\\");
+
+        // 9 bytes of overhead + 8 base64 characters = 6 payload bytes.
+        let (seq, len) = copy(b"abcdefghi", Osc52Selection::Clipboard, 20);
+        assert_eq!(seq, "\x1b]52;c;YWJjZGVm\x1b\\");
+        assert_eq!(len, 6);
+
+        // "ä" is 2 bytes long and mustn't be split.
+        let (seq, len) = copy("abcdeä".as_bytes(), Osc52Selection::Clipboard, 20);
+        assert_eq!(seq, "\x1b]52;c;YWJjZGU=\x1b\\");
+        assert_eq!(len, 5);
+    }
+
+    #[test]
+    fn test_osc52_parse_response() {
+        assert_eq!(osc52_parse_response("52;c;aGVsbG8="), Some(b"hello".to_vec()));
+        assert_eq!(osc52_parse_response("52;;aGVsbG8"), Some(b"hello".to_vec()));
+        assert_eq!(osc52_parse_response("52;c;?"), None);
+        assert_eq!(osc52_parse_response("52;c"), None);
+        assert_eq!(osc52_parse_response("11;rgb:0000/0000/0000"), None);
+        assert_eq!(osc52_parse_response("52;c;aGVs\x07bG8="), None);
+        assert_eq!(osc52_parse_response("52;c;aGVs\x1b\\bG8="), None);
+        assert_eq!(osc52_parse_response("52;c\x1b;aGVsbG8="), None);
+
+        // Whatever garbage we're given, we must not panic.
+        let mut state = 1442695040888963407u64;
+        let alphabet = b"52;cp?=+/aZ09\x07\x1b\\\n";
+        for _ in 0..10000 {
+            let mut data = String::from("52;");
+            for _ in 0..16 {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                data.push(alphabet[(state >> 33) as usize % alphabet.len()] as char);
+            }
+            if let Some(decoded) = osc52_parse_response(&data) {
+                assert!(decoded.len() <= data.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,256 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Aligns the columns of CSV, TSV and similar delimiter-separated data.
+//!
+//! [`align_lines`] pads each field with spaces up to the width of its column, measured in
+//! terminal cells, so that wide characters line up. The result can either be shown as is,
+//! or [`AlignColumns`] can be passed to [`TextBuffer::apply_transforms`] to rewrite the text.
+//!
+//! Fields in double quotes may contain the delimiter. Doubled quotes within them are escapes.
+//!
+//! [`TextBuffer::apply_transforms`]: crate::buffer::TextBuffer::apply_transforms
+
+use std::ops::Range;
+
+use crate::buffer::{BufferTransform, TextEdit};
+use crate::helpers::{CoordType, Point};
+use crate::simd::memchr2;
+use crate::unicode::MeasurementConfig;
+
+/// The delimiters recognized by [`detect_delimiter`], in order of preference.
+pub const DELIMITERS: [u8; 4] = *b",\t|;";
+
+/// Returns the byte ranges of the fields of `line`, which must not contain a newline.
+pub fn split_fields(line: &[u8], delimiter: u8) -> Vec<Range<usize>> {
+    let mut fields = Vec::new();
+    let mut beg = 0;
+    let mut quoted = false;
+
+    for (i, &b) in line.iter().enumerate() {
+        if b == b'"' {
+            // A doubled quote toggles twice, which leaves the state as it was.
+            quoted = !quoted;
+        } else if b == delimiter && !quoted {
+            fields.push(beg..i);
+            beg = i + 1;
+        }
+    }
+
+    fields.push(beg..line.len());
+    fields
+}
+
+/// Guesses the delimiter of `lines` by how consistently it splits them into the same number
+/// of fields. Returns `None` if none of the [`DELIMITERS`] splits most lines at all.
+pub fn detect_delimiter(lines: &[&[u8]]) -> Option<u8> {
+    let lines: Vec<_> = lines.iter().filter(|l| !l.trim_ascii().is_empty()).collect();
+    let mut best = None;
+    let mut best_score = 0;
+
+    for delimiter in DELIMITERS {
+        let mut counts: Vec<_> = lines.iter().map(|l| split_fields(l, delimiter).len()).collect();
+        counts.sort_unstable();
+
+        // The most common number of fields and how many lines have it.
+        let (fields, lines_with) = counts
+            .chunk_by(|a, b| a == b)
+            .map(|run| (run[0], run.len()))
+            .max_by_key(|&(fields, n)| (n, fields))
+            .unwrap_or((1, 0));
+
+        // More fields are better, but only if most lines agree.
+        if fields < 2 || lines_with * 2 <= lines.len() {
+            continue;
+        }
+        let score = lines_with * (fields - 1);
+        if score > best_score {
+            best = Some(delimiter);
+            best_score = score;
+        }
+    }
+
+    best
+}
+
+/// Pads the fields of `lines` so that their columns line up, and returns the new lines.
+///
+/// Trailing spaces of fields are replaced, so aligning twice yields the same result.
+/// The last field of each line isn't padded. Rows may have any number of fields.
+pub fn align_lines(lines: &[&[u8]], delimiter: u8, tab_size: CoordType) -> Vec<Vec<u8>> {
+    let rows: Vec<Vec<&[u8]>> = lines
+        .iter()
+        .map(|line| {
+            split_fields(line, delimiter).into_iter().map(|r| trim_spaces_end(&line[r])).collect()
+        })
+        .collect();
+
+    let mut widths: Vec<CoordType> = Vec::new();
+    for row in &rows {
+        // The last field doesn't need to be measured, since it isn't padded.
+        for (i, field) in row.iter().enumerate().take(row.len().saturating_sub(1)) {
+            let width = measure(field, tab_size);
+            match widths.get_mut(i) {
+                Some(w) => *w = (*w).max(width),
+                None => widths.push(width),
+            }
+        }
+    }
+
+    rows.iter()
+        .map(|row| {
+            let mut out = Vec::new();
+            for (i, field) in row.iter().enumerate() {
+                out.extend_from_slice(field);
+                if i + 1 < row.len() {
+                    let padding = widths[i] - measure(field, tab_size);
+                    out.extend(std::iter::repeat_n(b' ', padding as usize));
+                    out.push(delimiter);
+                }
+            }
+            out
+        })
+        .collect()
+}
+
+/// Aligns all lines of `text` for display. The newlines of the result are LF.
+pub fn render_aligned(text: &[u8], delimiter: u8, tab_size: CoordType) -> Vec<u8> {
+    let lines: Vec<_> = split_lines(text).into_iter().map(|(_, line)| line).collect();
+    let mut out = Vec::with_capacity(text.len());
+    for (i, line) in align_lines(&lines, delimiter, tab_size).iter().enumerate() {
+        if i > 0 {
+            out.push(b'\n');
+        }
+        out.extend_from_slice(line);
+    }
+    out
+}
+
+/// Rewrites the given lines of a document with their columns aligned.
+/// If `delimiter` is `None`, it's detected from those lines.
+pub struct AlignColumns {
+    /// 0-based line numbers.
+    pub lines: Range<usize>,
+    pub delimiter: Option<u8>,
+    pub tab_size: CoordType,
+}
+
+impl BufferTransform for AlignColumns {
+    fn name(&self) -> &str {
+        "align columns"
+    }
+
+    fn apply(&self, text: &[u8]) -> Vec<TextEdit> {
+        let (offsets, lines): (Vec<_>, Vec<_>) =
+            split_lines(text).into_iter().skip(self.lines.start).take(self.lines.len()).unzip();
+
+        let Some(delimiter) = self.delimiter.or_else(|| detect_delimiter(&lines)) else {
+            return Vec::new();
+        };
+
+        align_lines(&lines, delimiter, self.tab_size)
+            .into_iter()
+            .zip(offsets.into_iter().zip(lines))
+            .filter(|(aligned, (_, line))| aligned[..] != line[..])
+            .map(|(aligned, (off, line))| TextEdit { range: off..off + line.len(), text: aligned })
+            .collect()
+    }
+}
+
+/// Splits `text` into lines without their newlines, CR included, along with their offsets.
+fn split_lines(text: &[u8]) -> Vec<(usize, &[u8])> {
+    let mut lines = Vec::new();
+    let mut off = 0;
+    while off < text.len() {
+        let end = memchr2(b'\n', b'\n', text, off);
+        let line = &text[off..end];
+        lines.push((off, line.strip_suffix(b"\r").unwrap_or(line)));
+        off = end + 1;
+    }
+    lines
+}
+
+fn trim_spaces_end(field: &[u8]) -> &[u8] {
+    let len = field.iter().rposition(|&b| b != b' ').map_or(0, |i| i + 1);
+    &field[..len]
+}
+
+/// Returns the width of `text` in terminal cells.
+fn measure(text: &[u8], tab_size: CoordType) -> CoordType {
+    let mut cfg = MeasurementConfig::new(&text).with_tab_size(tab_size);
+    cfg.goto_logical(Point { x: CoordType::MAX, y: 0 }).visual_pos.x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::TextBuffer;
+
+    fn lines(text: &str) -> Vec<&[u8]> {
+        text.lines().map(str::as_bytes).collect()
+    }
+
+    fn align(text: &str, delimiter: u8) -> String {
+        String::from_utf8(render_aligned(text.as_bytes(), delimiter, 4)).unwrap()
+    }
+
+    #[test]
+    fn test_detect_delimiter() {
+        assert_eq!(detect_delimiter(&lines("a,b,c\n1,2,3\n4,5,6")), Some(b','));
+        assert_eq!(detect_delimiter(&lines("a\tb\n1\t2\n\n3\t4")), Some(b'\t'));
+
+        // Commas within the fields don't outweigh the consistent semicolons.
+        assert_eq!(detect_delimiter(&lines("a;b, c;d\n1;2;3\n4, 5;6;7")), Some(b';'));
+
+        // Only one line has pipes, so they aren't the delimiter. Neither is anything else.
+        assert_eq!(detect_delimiter(&lines("a|b|c|d|e\nfoo\nbar")), None);
+
+        // Quoted commas don't count.
+        assert_eq!(detect_delimiter(&lines("\"a,b,c\"|x\n\"d,e\"|y\n\"f,g,h,i\"|z")), Some(b'|'));
+    }
+
+    #[test]
+    fn test_quoted() {
+        assert_eq!(split_fields(b"a,\"b,c\",\"d\"\"e,f\",g", b','), [0..1, 2..7, 8..16, 17..18]);
+        assert_eq!(
+            align("name,comment\n\"Doe, J.\",ok\nX,\"a, b\"", b','),
+            "name     ,comment\n\"Doe, J.\",ok\nX        ,\"a, b\""
+        );
+    }
+
+    #[test]
+    fn test_ragged_rows() {
+        // "xxxx" and "ccc" are the last fields of their rows, so they don't widen their columns.
+        assert_eq!(align("a,bb,ccc\nxxxx\n1,2\n,,,,z", b','), "a,bb,ccc\nxxxx\n1,2\n ,  ,,,z");
+        // Aligning again doesn't change anything.
+        let aligned = align("a,bb,ccc\nxxxx\n1,2\n,,,,z", b',');
+        assert_eq!(align(&aligned, b','), aligned);
+    }
+
+    #[test]
+    fn test_wide_characters() {
+        assert_eq!(align("名前\tage\nbob\t42\n😀x\t1", b'\t'), "名前\tage\nbob \t42\n😀x \t1");
+        assert_eq!(measure("名前".as_bytes(), 4), 4);
+        assert_eq!(measure(b"a\tb", 4), 5);
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_crlf(false);
+        tb.write_raw(b"header\nid,name\n1,alice\n22,bob\nfooter,x\n");
+
+        let transform = AlignColumns { lines: 1..4, delimiter: None, tab_size: 4 };
+        let reports = tb.apply_transforms(&[&transform]).unwrap();
+        assert_eq!(reports[0].changes, 1);
+
+        let mut s = String::new();
+        tb.save_as_string(&mut s);
+        assert_eq!(s, "header\nid,name\n1 ,alice\n22,bob\nfooter,x\n");
+
+        tb.undo();
+        s.clear();
+        tb.save_as_string(&mut s);
+        assert_eq!(s, "header\nid,name\n1,alice\n22,bob\nfooter,x\n");
+    }
+}
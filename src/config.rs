@@ -0,0 +1,517 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The user's settings.
+//!
+//! They're written in the same INI-like format as the language definitions, see [`crate::ini`].
+//! Keys before the first section are global. `[lang.<name>]` sections override
+//! some of them for the language of that name.
+//!
+//! ```text
+//! tab_size = 4
+//! indent_with_tabs = false
+//! ; auto, lf or crlf
+//! newline = auto
+//! theme = default
+//! ; In seconds. 0 or off disables it.
+//! autosave = 30
+//! ; See the save_policy module.
+//! save = final_newline=true
+//! ; off, a suffix like ~ or .bak, or numbered followed by the number of backups to keep.
+//! backup = numbered 3
+//!
+//! [lang.makefile]
+//! indent_with_tabs = true
+//! save = eol=lf
+//! ```
+//!
+//! Unknown keys and sections are reported as warnings. Values of the wrong type are errors,
+//! and the setting keeps its previous value. Either way, the rest of the file still applies.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::backup::BackupMode;
+use crate::helpers::CoordType;
+use crate::save_policy::SavePolicy;
+use crate::{apperr, ini};
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The line was ignored, but that's probably harmless, e.g. a key from a newer version.
+    Warning,
+    /// The line couldn't be applied.
+    Error,
+}
+
+/// A problem found while parsing the settings.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The 1-based line number within the parsed text.
+    pub line: usize,
+    pub severity: Severity,
+    pub message: String,
+}
+
+/// Which newline sequence new files use.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NewlinePreference {
+    /// The platform's default.
+    #[default]
+    Auto,
+    Lf,
+    Crlf,
+}
+
+impl NewlinePreference {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Lf => "lf",
+            Self::Crlf => "crlf",
+        }
+    }
+}
+
+/// The settings that apply to a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub tab_size: CoordType,
+    pub indent_with_tabs: bool,
+    pub newline: NewlinePreference,
+    pub theme: String,
+    /// The delay after the last edit before documents are saved automatically, if enabled.
+    pub autosave: Option<Duration>,
+    /// How documents are saved. It's only one layer of [`crate::save_policy::resolve`].
+    pub save: SavePolicy,
+    /// How the previous version of a file is kept when saving over it, if at all.
+    pub backup: Option<BackupMode>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            tab_size: 4,
+            indent_with_tabs: false,
+            newline: NewlinePreference::Auto,
+            theme: "default".to_string(),
+            autosave: None,
+            save: SavePolicy::KEEP,
+            backup: None,
+        }
+    }
+}
+
+/// The settings a `[lang.<name>]` section overrides. `None` means it's not overridden.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LanguageOverrides {
+    pub tab_size: Option<CoordType>,
+    pub indent_with_tabs: Option<bool>,
+    pub newline: Option<NewlinePreference>,
+    pub autosave: Option<Option<Duration>>,
+    /// Merged into the global `save` setting field by field.
+    pub save: Option<SavePolicy>,
+}
+
+/// The parsed settings file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub global: Settings,
+    /// Per-language overrides, by language name, in the order they first appeared.
+    pub languages: Vec<(String, LanguageOverrides)>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Config {
+    /// Reads the settings from the file at `path`.
+    pub fn load(path: &Path) -> apperr::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parses the settings in `text`. Problems are collected in [`Config::diagnostics`].
+    pub fn parse(text: &str) -> Self {
+        let mut config = Self::default();
+        // The index into `config.languages` of the current section, if any.
+        let mut section: Option<usize> = None;
+        let mut skipping = false;
+
+        for (line_no, line, kind) in ini::lines(text) {
+            let (key, value) = match kind {
+                ini::Line::Pair(key, value) => (key, value),
+                ini::Line::Section(name) => {
+                    section = None;
+                    skipping = true;
+                    match name {
+                        Some(name)
+                            if let Some(lang) = name.strip_prefix("lang.")
+                                && !lang.is_empty()
+                                && !lang.contains(char::is_whitespace) =>
+                        {
+                            section = Some(config.language_index(lang));
+                            skipping = false;
+                        }
+                        Some(name) if !name.is_empty() => {
+                            config.warning(line_no, format!("unknown section: [{name}]"));
+                        }
+                        _ => config.error(line_no, format!("invalid section header: {line}")),
+                    }
+                    continue;
+                }
+                ini::Line::Invalid => {
+                    if !skipping {
+                        config.error(line_no, format!("expected key = value: {line}"));
+                    }
+                    continue;
+                }
+            };
+            if skipping {
+                continue;
+            }
+
+            match section {
+                None => config.set_global(line_no, key, value),
+                Some(idx) => config.set_override(line_no, idx, key, value),
+            }
+        }
+
+        config
+    }
+
+    /// The problems found by [`Config::parse`], in the order of their lines.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /// Returns the settings for documents in the given language,
+    /// which are the global ones with that language's overrides applied.
+    pub fn effective(&self, language: Option<&str>) -> Settings {
+        let mut settings = self.global.clone();
+        let overrides = language.and_then(|l| self.languages.iter().find(|(name, _)| name == l));
+
+        if let Some((_, o)) = overrides {
+            if let Some(tab_size) = o.tab_size {
+                settings.tab_size = tab_size;
+            }
+            if let Some(indent_with_tabs) = o.indent_with_tabs {
+                settings.indent_with_tabs = indent_with_tabs;
+            }
+            if let Some(newline) = o.newline {
+                settings.newline = newline;
+            }
+            if let Some(autosave) = o.autosave {
+                settings.autosave = autosave;
+            }
+            if let Some(save) = &o.save {
+                settings.save.merge(save);
+            }
+        }
+
+        settings
+    }
+
+    /// Turns the settings back into text that [`Config::parse`] accepts.
+    /// Comments and the original formatting are not preserved.
+    pub fn serialize(&self) -> String {
+        let g = &self.global;
+        let mut out = String::new();
+        _ = writeln!(out, "tab_size = {}", g.tab_size);
+        _ = writeln!(out, "indent_with_tabs = {}", g.indent_with_tabs);
+        _ = writeln!(out, "newline = {}", g.newline.as_str());
+        _ = writeln!(out, "theme = {}", g.theme);
+        _ = writeln!(out, "autosave = {}", autosave_to_str(g.autosave));
+        if g.save != SavePolicy::KEEP {
+            _ = writeln!(out, "save = {}", g.save);
+        }
+        _ = writeln!(out, "backup = {}", backup_to_str(g.backup.as_ref()));
+
+        for (name, o) in &self.languages {
+            _ = writeln!(out, "\n[lang.{name}]");
+            if let Some(tab_size) = o.tab_size {
+                _ = writeln!(out, "tab_size = {tab_size}");
+            }
+            if let Some(indent_with_tabs) = o.indent_with_tabs {
+                _ = writeln!(out, "indent_with_tabs = {indent_with_tabs}");
+            }
+            if let Some(newline) = o.newline {
+                _ = writeln!(out, "newline = {}", newline.as_str());
+            }
+            if let Some(autosave) = o.autosave {
+                _ = writeln!(out, "autosave = {}", autosave_to_str(autosave));
+            }
+            if let Some(save) = &o.save {
+                _ = writeln!(out, "save = {save}");
+            }
+        }
+
+        out
+    }
+
+    fn set_global(&mut self, line: usize, key: &str, value: &str) {
+        let result = match key {
+            "tab_size" => parse_tab_size(value).map(|v| self.global.tab_size = v),
+            "indent_with_tabs" => parse_bool(value).map(|v| self.global.indent_with_tabs = v),
+            "newline" => parse_newline(value).map(|v| self.global.newline = v),
+            "theme" => parse_theme(value).map(|v| self.global.theme = v),
+            "autosave" => parse_autosave(value).map(|v| self.global.autosave = v),
+            "backup" => parse_backup(value).map(|v| self.global.backup = v),
+            "save" => {
+                let save = SavePolicy::parse(value, line, &mut self.diagnostics);
+                return self.global.save.merge(&save);
+            }
+            _ => return self.warning(line, format!("unknown key: {key}")),
+        };
+        if let Err(message) = result {
+            self.error(line, format!("{key}: {message}"));
+        }
+    }
+
+    fn set_override(&mut self, line: usize, idx: usize, key: &str, value: &str) {
+        let o = &mut self.languages[idx].1;
+        let result = match key {
+            "tab_size" => parse_tab_size(value).map(|v| o.tab_size = Some(v)),
+            "indent_with_tabs" => parse_bool(value).map(|v| o.indent_with_tabs = Some(v)),
+            "newline" => parse_newline(value).map(|v| o.newline = Some(v)),
+            "autosave" => parse_autosave(value).map(|v| o.autosave = Some(v)),
+            "save" => {
+                let save = SavePolicy::parse(value, line, &mut self.diagnostics);
+                let o = &mut self.languages[idx].1;
+                return o.save.get_or_insert_default().merge(&save);
+            }
+            "theme" => return self.warning(line, "theme can't be set per language".to_string()),
+            "backup" => return self.warning(line, "backup can't be set per language".to_string()),
+            _ => return self.warning(line, format!("unknown key: {key}")),
+        };
+        if let Err(message) = result {
+            self.error(line, format!("{key}: {message}"));
+        }
+    }
+
+    fn language_index(&mut self, name: &str) -> usize {
+        match self.languages.iter().position(|(n, _)| n == name) {
+            Some(idx) => idx,
+            None => {
+                self.languages.push((name.to_string(), LanguageOverrides::default()));
+                self.languages.len() - 1
+            }
+        }
+    }
+
+    fn warning(&mut self, line: usize, message: String) {
+        self.diagnostics.push(Diagnostic { line, severity: Severity::Warning, message });
+    }
+
+    fn error(&mut self, line: usize, message: String) {
+        self.diagnostics.push(Diagnostic { line, severity: Severity::Error, message });
+    }
+}
+
+fn parse_tab_size(value: &str) -> Result<CoordType, String> {
+    match value.parse::<CoordType>() {
+        Ok(v) if (1..=16).contains(&v) => Ok(v),
+        _ => Err(format!("expected a number between 1 and 16: {value}")),
+    }
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("expected true or false: {value}")),
+    }
+}
+
+fn parse_newline(value: &str) -> Result<NewlinePreference, String> {
+    match value {
+        "auto" => Ok(NewlinePreference::Auto),
+        "lf" => Ok(NewlinePreference::Lf),
+        "crlf" => Ok(NewlinePreference::Crlf),
+        _ => Err(format!("expected auto, lf or crlf: {value}")),
+    }
+}
+
+fn parse_theme(value: &str) -> Result<String, String> {
+    if value.is_empty() { Err("expected a theme name".to_string()) } else { Ok(value.to_string()) }
+}
+
+fn parse_autosave(value: &str) -> Result<Option<Duration>, String> {
+    match value {
+        "off" | "0" => Ok(None),
+        _ => match value.parse::<u64>() {
+            Ok(secs) => Ok(Some(Duration::from_secs(secs))),
+            Err(_) => Err(format!("expected a number of seconds or off: {value}")),
+        },
+    }
+}
+
+fn autosave_to_str(autosave: Option<Duration>) -> String {
+    match autosave {
+        Some(delay) => delay.as_secs().to_string(),
+        None => "off".to_string(),
+    }
+}
+
+fn parse_backup(value: &str) -> Result<Option<BackupMode>, String> {
+    if value == "off" {
+        return Ok(None);
+    }
+    if let Some(limit) = value.strip_prefix("numbered") {
+        return match limit.trim().parse::<usize>() {
+            Ok(limit) if limit > 0 => Ok(Some(BackupMode::Numbered(limit))),
+            _ => Err(format!("expected numbered followed by a number: {value}")),
+        };
+    }
+    if value.is_empty() || value.contains(['/', '\\']) {
+        return Err(format!("expected off, numbered or a file name suffix: {value}"));
+    }
+    Ok(Some(BackupMode::Simple(value.to_string())))
+}
+
+fn backup_to_str(backup: Option<&BackupMode>) -> String {
+    match backup {
+        Some(BackupMode::Simple(suffix)) => suffix.clone(),
+        Some(BackupMode::Numbered(limit)) => format!("numbered {limit}"),
+        None => "off".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(line: usize, severity: Severity, message: &str) -> Diagnostic {
+        Diagnostic { line, severity, message: message.to_string() }
+    }
+
+    #[test]
+    fn test_layering() {
+        let config = Config::parse(
+            "tab_size = 2\n\
+             autosave = 30\n\
+             backup = .bak\n\
+             \n\
+             [lang.makefile]\n\
+             indent_with_tabs = true\n\
+             tab_size = 8\n\
+             \n\
+             [lang.rust]\n\
+             autosave = off\n\
+             \n\
+             ; A second section for the same language adds to the first one.\n\
+             [lang.makefile]\n\
+             newline = lf\n\
+             ; The later value wins.\n\
+             tab_size = 4\n",
+        );
+        assert_eq!(config.diagnostics(), []);
+
+        let global = config.effective(None);
+        assert_eq!(global.tab_size, 2);
+        assert!(!global.indent_with_tabs);
+        assert_eq!(global.autosave, Some(Duration::from_secs(30)));
+        assert_eq!(global.backup, Some(BackupMode::Simple(".bak".to_string())));
+
+        // Unknown languages get the global settings.
+        assert_eq!(config.effective(Some("python")), global);
+
+        let makefile = config.effective(Some("makefile"));
+        assert_eq!(makefile.tab_size, 4);
+        assert!(makefile.indent_with_tabs);
+        assert_eq!(makefile.newline, NewlinePreference::Lf);
+        assert_eq!(makefile.autosave, Some(Duration::from_secs(30)));
+
+        // An override can turn a setting off.
+        let rust = config.effective(Some("rust"));
+        assert_eq!(rust.autosave, None);
+        assert_eq!(rust.tab_size, 2);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let config = Config::parse(
+            "tab_size = 4\n\
+             tab_size = 40\n\
+             font = mono\n\
+             indent_with_tabs = yes\n\
+             newline = cr\n\
+             autosave = soon\n\
+             theme =\n\
+             backup = numbered\n\
+             no value here\n\
+             [lang.rust\n\
+             tab_size = 3\n\
+             [editor]\n\
+             tab_size = 3\n\
+             [lang.c]\n\
+             theme = dark\n\
+             backup = ~\n\
+             wrap = true\n\
+             tab_size = x\n",
+        );
+
+        assert_eq!(
+            config.diagnostics(),
+            [
+                diagnostic(2, Severity::Error, "tab_size: expected a number between 1 and 16: 40"),
+                diagnostic(3, Severity::Warning, "unknown key: font"),
+                diagnostic(4, Severity::Error, "indent_with_tabs: expected true or false: yes"),
+                diagnostic(5, Severity::Error, "newline: expected auto, lf or crlf: cr"),
+                diagnostic(
+                    6,
+                    Severity::Error,
+                    "autosave: expected a number of seconds or off: soon"
+                ),
+                diagnostic(7, Severity::Error, "theme: expected a theme name"),
+                diagnostic(
+                    8,
+                    Severity::Error,
+                    "backup: expected numbered followed by a number: numbered"
+                ),
+                diagnostic(9, Severity::Error, "expected key = value: no value here"),
+                diagnostic(10, Severity::Error, "invalid section header: [lang.rust"),
+                diagnostic(12, Severity::Warning, "unknown section: [editor]"),
+                diagnostic(15, Severity::Warning, "theme can't be set per language"),
+                diagnostic(16, Severity::Warning, "backup can't be set per language"),
+                diagnostic(17, Severity::Warning, "unknown key: wrap"),
+                diagnostic(18, Severity::Error, "tab_size: expected a number between 1 and 16: x"),
+            ]
+        );
+
+        // Invalid values and skipped sections leave the settings untouched.
+        assert_eq!(config.global, Settings::default());
+        assert_eq!(config.effective(Some("c")), Settings::default());
+        assert!(config.languages.iter().all(|(name, _)| name == "c"));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let config = Config::parse(
+            "newline = crlf\n\
+             theme = solarized dark\n\
+             save = final_newline=true\n\
+             backup = numbered 3\n\
+             [lang.makefile]\n\
+             indent_with_tabs = true\n\
+             autosave = 5\n\
+             save = eol=lf encoding=utf-8-bom\n\
+             [lang.rust]\n\
+             autosave = off\n",
+        );
+        assert_eq!(config.diagnostics(), []);
+
+        let text = config.serialize();
+        let parsed = Config::parse(&text);
+        assert_eq!(parsed.diagnostics(), []);
+        assert_eq!(parsed, config);
+
+        // The effective settings of a language can be saved on their own.
+        let makefile = Config { global: config.effective(Some("makefile")), ..Default::default() };
+        let parsed = Config::parse(&makefile.serialize());
+        assert_eq!(parsed.effective(None), config.effective(Some("makefile")));
+        assert_eq!(parsed.global.autosave, Some(Duration::from_secs(5)));
+        assert_eq!(parsed.global.backup, Some(BackupMode::Numbered(3)));
+        assert_eq!(parsed.global.save.to_string(), "eol=lf encoding=UTF-8-BOM final_newline=true");
+    }
+}
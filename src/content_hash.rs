@@ -0,0 +1,198 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Detects external changes to files by comparing the hashes of their contents.
+//!
+//! Modification times are unreliable on network file systems and have a limited resolution,
+//! which is why the contents are hashed in fixed-size chunks instead. When checking a file,
+//! its size is compared first and then its chunks, stopping at the first one that differs.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::apperr;
+use crate::document::ReadableDocument;
+use crate::hash::hash;
+use crate::helpers::KIBI;
+
+/// The size of the chunks that are hashed individually.
+pub const CHUNK_SIZE: usize = 64 * KIBI;
+
+/// The result of [`ContentHashes::check_file`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeStatus {
+    Unchanged,
+    Changed,
+    /// The file was deleted or can't be read anymore.
+    Unreadable,
+}
+
+/// The hashes of a file's contents, in chunks of [`CHUNK_SIZE`].
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ContentHashes {
+    len: u64,
+    chunks: Vec<u64>,
+}
+
+impl ContentHashes {
+    /// Hashes the given bytes.
+    pub fn of_bytes(data: &[u8]) -> Self {
+        let chunks = data.chunks(CHUNK_SIZE).map(|c| hash(0, c)).collect();
+        Self { len: data.len() as u64, chunks }
+    }
+
+    /// Hashes the contents of the given document.
+    ///
+    /// This only matches the file on disk if it's saved as UTF-8 without a BOM.
+    pub fn of_document(doc: &dyn ReadableDocument) -> Self {
+        let mut res = Self::default();
+        let mut chunk = Vec::with_capacity(CHUNK_SIZE);
+        let mut off = 0;
+
+        loop {
+            let data = doc.read_forward(off);
+            if data.is_empty() {
+                break;
+            }
+
+            let len = data.len().min(CHUNK_SIZE - chunk.len());
+            chunk.extend_from_slice(&data[..len]);
+            off += len;
+
+            if chunk.len() == CHUNK_SIZE {
+                res.chunks.push(hash(0, &chunk));
+                chunk.clear();
+            }
+        }
+
+        if !chunk.is_empty() {
+            res.chunks.push(hash(0, &chunk));
+        }
+        res.len = off as u64;
+        res
+    }
+
+    /// Hashes the contents of the file at `path`.
+    pub fn of_file(path: &Path) -> apperr::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut res = Self::default();
+        let mut buf = vec![0; CHUNK_SIZE];
+
+        loop {
+            let len = read_full(&mut file, &mut buf)?;
+            if len == 0 {
+                break;
+            }
+            res.chunks.push(hash(0, &buf[..len]));
+            res.len += len as u64;
+        }
+
+        Ok(res)
+    }
+
+    /// The length of the hashed contents in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Checks whether the file at `path` still has the hashed contents.
+    pub fn check_file(&self, path: &Path) -> ChangeStatus {
+        match self.check_file_impl(path) {
+            Ok(true) => ChangeStatus::Unchanged,
+            Ok(false) => ChangeStatus::Changed,
+            Err(_) => ChangeStatus::Unreadable,
+        }
+    }
+
+    fn check_file_impl(&self, path: &Path) -> io::Result<bool> {
+        let mut file = File::open(path)?;
+        if file.metadata()?.len() != self.len {
+            return Ok(false);
+        }
+
+        let mut buf = vec![0; CHUNK_SIZE];
+        for &expected in &self.chunks {
+            let len = read_full(&mut file, &mut buf)?;
+            if len == 0 || hash(0, &buf[..len]) != expected {
+                return Ok(false);
+            }
+        }
+
+        // The file may have grown since we checked its size.
+        Ok(read_full(&mut file, &mut buf[..1])? == 0)
+    }
+}
+
+/// Like [`Read::read_exact`], but allows the input to end early.
+fn read_full(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn test_data() -> Vec<u8> {
+        (0..3 * CHUNK_SIZE + 123).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn test_of_document() {
+        let data = test_data();
+        let hashes = ContentHashes::of_bytes(&data);
+        assert_eq!(hashes.chunks.len(), 4);
+        assert_eq!(ContentHashes::of_document(&data.as_slice()), hashes);
+
+        // A gap buffer hands out its contents in two parts, which mustn't matter.
+        let mut buffer = crate::buffer::GapBuffer::new(false).unwrap();
+        buffer.replace(0..0, &data[CHUNK_SIZE + 5..]);
+        buffer.replace(0..0, &data[..CHUNK_SIZE + 5]);
+        assert_eq!(ContentHashes::of_document(&buffer), hashes);
+    }
+
+    #[test]
+    fn test_check_file() {
+        let dir = TempDir::new("content-hash");
+        let data = test_data();
+        let path = dir.write("check", &data);
+
+        let hashes = ContentHashes::of_file(&path).unwrap();
+        assert_eq!(hashes, ContentHashes::of_bytes(&data));
+        assert_eq!(hashes.check_file(&path), ChangeStatus::Unchanged);
+
+        // A single byte in the middle.
+        let mut changed = data.clone();
+        changed[2 * CHUNK_SIZE + 17] ^= 1;
+        fs::write(&path, &changed).unwrap();
+        assert_eq!(hashes.check_file(&path), ChangeStatus::Changed);
+
+        // Truncated.
+        fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert_eq!(hashes.check_file(&path), ChangeStatus::Changed);
+
+        // Restored.
+        fs::write(&path, &data).unwrap();
+        assert_eq!(hashes.check_file(&path), ChangeStatus::Unchanged);
+
+        // Deleted.
+        fs::remove_file(&path).unwrap();
+        assert_eq!(hashes.check_file(&path), ChangeStatus::Unreadable);
+    }
+}
@@ -0,0 +1,244 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tracks which parts of the screen were drawn to.
+//!
+//! The renderer only needs to look at the rows that were damaged,
+//! instead of comparing the entire screen with the previous frame.
+
+use std::ops::Range;
+
+use crate::helpers::{CoordType, Rect};
+
+/// The damaged regions of a frame: row ranges and rectangles.
+///
+/// Adjacent and overlapping regions are coalesced as they're added, as long as
+/// their union is a row range or rectangle itself. This keeps the lists short
+/// even if the same area is drawn to many times.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Damage {
+    all: bool,
+    /// Sorted and non-overlapping, without adjacent ranges.
+    rows: Vec<Range<CoordType>>,
+    /// Not sorted. Two of them may overlap if their union isn't a rectangle.
+    rects: Vec<Rect>,
+}
+
+impl Damage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns whether nothing was damaged.
+    pub fn is_empty(&self) -> bool {
+        !self.all && self.rows.is_empty() && self.rects.is_empty()
+    }
+
+    /// Returns whether everything was damaged.
+    pub fn is_all(&self) -> bool {
+        self.all
+    }
+
+    /// Damages everything. Use this when there's no better way to tell what changed,
+    /// for instance after resizing or scrolling the entire screen.
+    pub fn mark_all(&mut self) {
+        self.all = true;
+        self.rows.clear();
+        self.rects.clear();
+    }
+
+    /// Damages the full width of the given `rows`.
+    pub fn add_rows(&mut self, rows: Range<CoordType>) {
+        if self.all || rows.is_empty() {
+            return;
+        }
+
+        let mut rows = rows;
+        // The ranges that overlap or touch `rows` are all merged into it.
+        let beg = self.rows.partition_point(|r| r.end < rows.start);
+        let end = self.rows.partition_point(|r| r.start <= rows.end);
+        if beg < end {
+            rows.start = rows.start.min(self.rows[beg].start);
+            rows.end = rows.end.max(self.rows[end - 1].end);
+        }
+        self.rows.splice(beg..end, [rows]);
+    }
+
+    /// Damages the given `rect`.
+    pub fn add_rect(&mut self, rect: Rect) {
+        if self.all || rect.is_empty() {
+            return;
+        }
+
+        let mut rect = rect;
+        let mut i = 0;
+        while i < self.rects.len() {
+            let r = self.rects[i];
+            if r.intersect(rect) == rect {
+                return;
+            }
+            if let Some(merged) = coalesce(r, rect) {
+                // The union may now touch rectangles that were already checked.
+                self.rects.swap_remove(i);
+                rect = merged;
+                i = 0;
+            } else {
+                i += 1;
+            }
+        }
+        self.rects.push(rect);
+    }
+
+    /// Adds all regions damaged in `other`.
+    pub fn merge(&mut self, other: &Damage) {
+        if other.all {
+            self.mark_all();
+            return;
+        }
+        for rows in &other.rows {
+            self.add_rows(rows.clone());
+        }
+        for &rect in &other.rects {
+            self.add_rect(rect);
+        }
+    }
+
+    /// Returns the damage and resets it, usually once per frame.
+    pub fn take(&mut self) -> Damage {
+        std::mem::take(self)
+    }
+
+    /// Returns whether any part of row `y` was damaged.
+    pub fn contains_row(&self, y: CoordType) -> bool {
+        self.all
+            || self.rows.binary_search_by(|r| cmp_range(r, y)).is_ok()
+            || self.rects.iter().any(|r| r.top <= y && y < r.bottom)
+    }
+
+    /// The damaged row ranges, sorted. Doesn't include the rectangles.
+    pub fn rows(&self) -> &[Range<CoordType>] {
+        &self.rows
+    }
+
+    /// The damaged rectangles, in no particular order. They may overlap.
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects
+    }
+}
+
+fn cmp_range(range: &Range<CoordType>, y: CoordType) -> std::cmp::Ordering {
+    if range.end <= y {
+        std::cmp::Ordering::Less
+    } else if range.start > y {
+        std::cmp::Ordering::Greater
+    } else {
+        std::cmp::Ordering::Equal
+    }
+}
+
+/// Returns the union of `a` and `b` if it's a rectangle itself, i.e. if one contains the other,
+/// or if they overlap or touch along an edge of the same length.
+fn coalesce(a: Rect, b: Rect) -> Option<Rect> {
+    let union = Rect {
+        left: a.left.min(b.left),
+        top: a.top.min(b.top),
+        right: a.right.max(b.right),
+        bottom: a.bottom.max(b.bottom),
+    };
+    let same_columns = a.left == b.left && a.right == b.right;
+    let same_rows = a.top == b.top && a.bottom == b.bottom;
+    let touch_vertically = a.top <= b.bottom && b.top <= a.bottom;
+    let touch_horizontally = a.left <= b.right && b.left <= a.right;
+
+    if a.intersect(b) == a
+        || a.intersect(b) == b
+        || (same_columns && touch_vertically)
+        || (same_rows && touch_horizontally)
+    {
+        Some(union)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(left: CoordType, top: CoordType, right: CoordType, bottom: CoordType) -> Rect {
+        Rect { left, top, right, bottom }
+    }
+
+    #[test]
+    fn test_rows() {
+        let mut d = Damage::new();
+        assert!(d.is_empty());
+
+        d.add_rows(10..13);
+        d.add_rows(0..2);
+        d.add_rows(5..5);
+        assert_eq!(d.rows(), [0..2, 10..13]);
+
+        // Adjacent ranges are joined...
+        d.add_rows(13..15);
+        assert_eq!(d.rows(), [0..2, 10..15]);
+
+        // ...and so are overlapping ones, even several at once.
+        d.add_rows(1..11);
+        assert_eq!(d.rows().len(), 1);
+        assert_eq!(d.rows()[0], 0..15);
+
+        assert!(d.contains_row(14));
+        assert!(!d.contains_row(15));
+    }
+
+    #[test]
+    fn test_rects() {
+        let mut d = Damage::new();
+        d.add_rect(rect(0, 0, 4, 2));
+        // Contained in the first one.
+        d.add_rect(rect(1, 1, 3, 2));
+        // Stacked below the first one with the same width.
+        d.add_rect(rect(0, 2, 4, 3));
+        assert_eq!(d.rects(), [rect(0, 0, 4, 3)]);
+
+        // Unrelated ones are kept separate, until a third one connects them.
+        d.add_rect(rect(8, 0, 10, 3));
+        assert_eq!(d.rects().len(), 2);
+        d.add_rect(rect(4, 0, 8, 3));
+        assert_eq!(d.rects(), [rect(0, 0, 10, 3)]);
+
+        assert!(d.contains_row(2));
+        assert!(!d.contains_row(3));
+
+        // Overlapping ones are kept separate if their union isn't a rectangle.
+        d.add_rect(rect(8, 2, 12, 5));
+        assert_eq!(d.rects(), [rect(0, 0, 10, 3), rect(8, 2, 12, 5)]);
+        assert!(d.contains_row(4));
+    }
+
+    #[test]
+    fn test_merge_and_take() {
+        let mut a = Damage::new();
+        a.add_rows(0..1);
+        let mut b = Damage::new();
+        b.add_rows(1..2);
+        b.add_rect(rect(0, 5, 1, 6));
+
+        a.merge(&b);
+        assert_eq!(a.rows().len(), 1);
+        assert_eq!(a.rows()[0], 0..2);
+        assert_eq!(a.rects(), [rect(0, 5, 1, 6)]);
+
+        let taken = a.take();
+        assert!(a.is_empty());
+        assert!(!taken.is_empty());
+
+        b.mark_all();
+        b.add_rows(7..8);
+        a.merge(&b);
+        assert!(a.is_all());
+        assert!(a.contains_row(100));
+        assert!(a.rows().is_empty());
+    }
+}
@@ -0,0 +1,294 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Computes the difference between two sequences, using the
+//! O(ND) algorithm from Eugene W. Myers' paper "An O(ND) Difference Algorithm and Its Variations".
+//!
+//! The cost of the algorithm grows with the number of differences.
+//! To keep pathological inputs from stalling the editor, the caller passes
+//! a maximum number of differences, beyond which the diff is abandoned.
+
+use std::ops::Range;
+
+/// The maximum cost the editor's line diffs are attempted with, see [`diff`].
+/// Beyond it, the edited region is treated as replaced as a whole.
+pub const MAX_DIFF_COST: usize = 4096;
+
+/// A region where the two sequences differ:
+/// `old[self.old]` was replaced with `new[self.new]`.
+/// Either of the two ranges may be empty, but never both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Returns the hunks that turn `old` into `new`, sorted by position.
+///
+/// Returns `None` if the sequences differ by more than `max_cost` inserted or removed elements.
+pub fn diff<T: PartialEq>(old: &[T], new: &[T], max_cost: usize) -> Option<Vec<Hunk>> {
+    // Most changes are small and local, so it's worth skipping the common prefix and suffix.
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+    let mut hunks = Vec::new();
+
+    if a.is_empty() && b.is_empty() {
+        return Some(hunks);
+    }
+    if a.is_empty() || b.is_empty() {
+        if a.len() + b.len() > max_cost {
+            return None;
+        }
+        hunks.push(Hunk { old: prefix..prefix + a.len(), new: prefix..prefix + b.len() });
+        return Some(hunks);
+    }
+
+    for (x, y, insert) in shortest_edit(a, b, max_cost)? {
+        let (old, new) = if insert {
+            (prefix + x..prefix + x, prefix + y..prefix + y + 1)
+        } else {
+            (prefix + x..prefix + x + 1, prefix + y..prefix + y)
+        };
+
+        // Join adjacent edits into a single hunk.
+        if let Some(last) = hunks.last_mut()
+            && last.old.end == old.start
+            && last.new.end == new.start
+        {
+            last.old.end = old.end;
+            last.new.end = new.end;
+        } else {
+            hunks.push(Hunk { old, new });
+        }
+    }
+
+    Some(hunks)
+}
+
+/// Returns the single-element edits of the shortest edit script, sorted by position:
+/// `(x, y, true)` inserts `b[y]` before `a[x]` and `(x, y, false)` removes `a[x]`.
+///
+/// Uses the linear space refinement from section 4b of the paper: the "middle snake"
+/// of the edit script is found by searching from both ends at once, and the parts
+/// before and after it are solved recursively. Only two vectors of diagonals are needed
+/// instead of a copy of them per step, which would be quadratic in the number of edits.
+fn shortest_edit<T: PartialEq>(
+    a: &[T],
+    b: &[T],
+    max_cost: usize,
+) -> Option<Vec<(usize, usize, bool)>> {
+    let max_d = max_cost.min(a.len() + b.len()).div_ceil(2);
+    let mut ctx = Context {
+        a,
+        b,
+        vf: vec![0; 2 * max_d + 3],
+        vb: vec![0; 2 * max_d + 3],
+        off: max_d as isize + 1,
+        edits: Vec::new(),
+    };
+
+    // The recursion below can't be aborted half-way, so check the cost up front.
+    // The top-level middle snake is needed anyway and gets computed twice, which is cheap
+    // compared to the rest: the subproblems together are about as expensive again.
+    let (d, _) = ctx.middle_snake(0..a.len(), 0..b.len(), max_d)?;
+    if d > max_cost {
+        return None;
+    }
+
+    ctx.solve(0..a.len(), 0..b.len());
+    Some(ctx.edits)
+}
+
+struct Context<'a, T> {
+    a: &'a [T],
+    b: &'a [T],
+    /// `vf[off + k]` is the furthest x reached on diagonal k = x - y from the start.
+    vf: Vec<isize>,
+    /// `vb[off + k]` is the furthest distance from the end reached on diagonal k
+    /// of the reversed sequences, i.e. on diagonal `delta - k` of the original ones.
+    vb: Vec<isize>,
+    off: isize,
+    edits: Vec<(usize, usize, bool)>,
+}
+
+impl<T: PartialEq> Context<'_, T> {
+    fn solve(&mut self, mut a: Range<usize>, mut b: Range<usize>) {
+        while !a.is_empty() && !b.is_empty() && self.a[a.start] == self.b[b.start] {
+            a.start += 1;
+            b.start += 1;
+        }
+        while !a.is_empty() && !b.is_empty() && self.a[a.end - 1] == self.b[b.end - 1] {
+            a.end -= 1;
+            b.end -= 1;
+        }
+
+        // Without a common prefix and suffix, a single edit leaves one of the two empty.
+        // Otherwise the middle snake splits the problem into two smaller ones.
+        if a.is_empty() {
+            self.edits.extend(b.map(|y| (a.start, y, true)));
+        } else if b.is_empty() {
+            self.edits.extend(a.map(|x| (x, b.start, false)));
+        } else {
+            // The cost of a part is at most that of the whole, which fits into the diagonals.
+            let max_d = self.off as usize - 1;
+            let (_, (x, y, u, v)) = self.middle_snake(a.clone(), b.clone(), max_d).unwrap();
+            self.solve(a.start..x, b.start..y);
+            self.solve(u..a.end, v..b.end);
+        }
+    }
+
+    /// Returns the length of the shortest edit script for `a[ra]` and `b[rb]`
+    /// and the start and end of its middle snake, or `None` if the length exceeds `2 * max_d`.
+    fn middle_snake(
+        &mut self,
+        ra: Range<usize>,
+        rb: Range<usize>,
+        max_d: usize,
+    ) -> Option<(usize, (usize, usize, usize, usize))> {
+        let (a, b) = (&self.a[ra.clone()], &self.b[rb.clone()]);
+        let n = a.len() as isize;
+        let m = b.len() as isize;
+        let delta = n - m;
+        let odd = delta & 1 != 0;
+        let off = self.off;
+        let (vf, vb) = (&mut self.vf, &mut self.vb);
+        let at = |x: isize, y: isize| (ra.start + x as usize, rb.start + y as usize);
+
+        vf[(off + 1) as usize] = 0;
+        vb[(off + 1) as usize] = 0;
+
+        for d in 0..=max_d as isize {
+            for k in (-d..=d).step_by(2) {
+                let i = (off + k) as usize;
+                let mut x = if k == -d || (k != d && vf[i - 1] < vf[i + 1]) {
+                    vf[i + 1]
+                } else {
+                    vf[i - 1] + 1
+                };
+                let mut y = x - k;
+                let (x0, y0) = (x, y);
+
+                while x < n && y < m && a[x as usize] == b[y as usize] {
+                    x += 1;
+                    y += 1;
+                }
+
+                vf[i] = x;
+                // The backward search has done d - 1 steps and reached diagonals -(d-1)..=(d-1).
+                let kb = delta - k;
+                if odd && kb.abs() < d && x + vb[(off + kb) as usize] >= n {
+                    let (sx, sy) = at(x0, y0);
+                    let (ex, ey) = at(x, y);
+                    return Some((2 * d as usize - 1, (sx, sy, ex, ey)));
+                }
+            }
+
+            for k in (-d..=d).step_by(2) {
+                let i = (off + k) as usize;
+                let mut x = if k == -d || (k != d && vb[i - 1] < vb[i + 1]) {
+                    vb[i + 1]
+                } else {
+                    vb[i - 1] + 1
+                };
+                let mut y = x - k;
+                let (x0, y0) = (x, y);
+
+                while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                    x += 1;
+                    y += 1;
+                }
+
+                vb[i] = x;
+                let kf = delta - k;
+                if !odd && kf.abs() <= d && x + vf[(off + kf) as usize] >= n {
+                    let (sx, sy) = at(n - x, m - y);
+                    let (ex, ey) = at(n - x0, m - y0);
+                    return Some((2 * d as usize, (sx, sy, ex, ey)));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies the hunks to `old` and checks that the result is `new`.
+    fn check(old: &str, new: &str) -> Vec<Hunk> {
+        let (a, b) = (old.as_bytes(), new.as_bytes());
+        let hunks = diff(a, b, usize::MAX).unwrap();
+
+        let mut res = Vec::new();
+        let mut pos = 0;
+        for h in &hunks {
+            assert!(!h.old.is_empty() || !h.new.is_empty());
+            assert!(h.old.start >= pos);
+            res.extend_from_slice(&a[pos..h.old.start]);
+            res.extend_from_slice(&b[h.new.clone()]);
+            pos = h.old.end;
+        }
+        res.extend_from_slice(&a[pos..]);
+        assert_eq!(res, b);
+
+        hunks
+    }
+
+    #[test]
+    fn test_diff() {
+        assert!(check("", "").is_empty());
+        assert!(check("abc", "abc").is_empty());
+        assert_eq!(check("abc", "axc"), [Hunk { old: 1..2, new: 1..2 }]);
+        assert_eq!(check("abc", "ac"), [Hunk { old: 1..2, new: 1..1 }]);
+        assert_eq!(check("", "abc"), [Hunk { old: 0..0, new: 0..3 }]);
+        check("abcabba", "cbabac");
+        check("the quick brown fox", "a quick brown dog jumps");
+        check("xxxxxxxx", "yyyy");
+    }
+
+    #[test]
+    fn test_shortest() {
+        // Compare the cost against the longest common subsequence, computed the slow way.
+        let mut state = 1u64;
+        let mut rng = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 33) as usize
+        };
+        for _ in 0..200 {
+            let mut text = || (0..rng() % 40).map(|_| b'a' + (rng() % 3) as u8).collect::<Vec<_>>();
+            let a = text();
+            let b = text();
+
+            let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+            for i in 0..a.len() {
+                for j in 0..b.len() {
+                    lcs[i + 1][j + 1] =
+                        if a[i] == b[j] { lcs[i][j] + 1 } else { lcs[i][j + 1].max(lcs[i + 1][j]) };
+                }
+            }
+
+            let hunks = check(str::from_utf8(&a).unwrap(), str::from_utf8(&b).unwrap());
+            let cost: usize = hunks.iter().map(|h| h.old.len() + h.new.len()).sum();
+            assert_eq!(cost, a.len() + b.len() - 2 * lcs[a.len()][b.len()]);
+        }
+    }
+
+    #[test]
+    fn test_max_cost() {
+        let a = b"abcdefgh";
+        let b = b"abXdefYh";
+        assert!(diff(a, b, 3).is_none());
+        assert_eq!(diff(a, b, 4).unwrap().len(), 2);
+        assert!(diff(b"", b"abc", 2).is_none());
+    }
+}
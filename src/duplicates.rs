@@ -0,0 +1,386 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Finds duplicate lines and repeated blocks of lines, e.g. for cleaning up config files.
+//!
+//! Lines are compared by their hash first. Each distinct line gets an id, and repeated
+//! blocks are then found with a rolling hash over those ids. To stay usable on huge files,
+//! only a limited number of line numbers is kept per group.
+
+use std::collections::HashMap;
+
+use crate::hash::hash;
+
+/// How lines are compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DuplicateOptions {
+    /// Ignore leading and trailing whitespace.
+    pub trim: bool,
+    pub ignore_case: bool,
+    /// Blank lines are never reported as duplicates, and blocks can't consist of them only.
+    pub skip_blank: bool,
+    /// The maximum number of line numbers kept per group.
+    pub max_lines: usize,
+}
+
+impl Default for DuplicateOptions {
+    fn default() -> Self {
+        Self { trim: false, ignore_case: false, skip_blank: true, max_lines: 1000 }
+    }
+}
+
+/// A line that occurs more than once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    /// The first occurrence, as written in the document.
+    pub text: String,
+    pub count: usize,
+    /// The 0-based line numbers of the occurrences.
+    pub lines: Vec<usize>,
+    /// Whether `lines` was cut off at [`DuplicateOptions::max_lines`].
+    pub truncated: bool,
+}
+
+/// A block of lines that occurs more than once, without overlapping itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepeatedBlock {
+    /// The number of lines in the block.
+    pub len: usize,
+    pub count: usize,
+    /// The 0-based line numbers at which the occurrences start.
+    pub starts: Vec<usize>,
+    /// Whether `starts` was cut off at [`DuplicateOptions::max_lines`].
+    pub truncated: bool,
+}
+
+/// The lines of a text, each assigned an id that's shared by all lines that compare equal.
+struct LineIds<'a> {
+    lines: Vec<&'a [u8]>,
+    ids: Vec<u32>,
+    /// The number of lines with each id.
+    counts: Vec<u32>,
+    blank: Vec<bool>,
+}
+
+impl<'a> LineIds<'a> {
+    fn new(text: &'a [u8], options: &DuplicateOptions) -> Self {
+        let mut lines: Vec<_> = text.split(|&b| b == b'\n').collect();
+        if text.is_empty() || text.ends_with(b"\n") {
+            lines.pop();
+        }
+        for line in &mut lines {
+            *line = line.strip_suffix(b"\r").unwrap_or(line);
+        }
+
+        let mut ids = Vec::with_capacity(lines.len());
+        let mut counts: Vec<u32> = Vec::new();
+        let mut blank = Vec::new();
+        // The first line with each id, to compare against on hash collisions.
+        let mut firsts: Vec<usize> = Vec::new();
+        let mut by_hash: HashMap<u64, Vec<u32>> = HashMap::new();
+        let mut buf = Vec::new();
+        let mut other = Vec::new();
+
+        for (i, line) in lines.iter().enumerate() {
+            let norm = normalize(line, options, &mut buf);
+            let candidates = by_hash.entry(hash(0, norm)).or_default();
+            let found = candidates
+                .iter()
+                .copied()
+                .find(|&id| normalize(lines[firsts[id as usize]], options, &mut other) == norm);
+
+            let id = found.unwrap_or_else(|| {
+                let id = counts.len() as u32;
+                counts.push(0);
+                blank.push(norm.iter().all(u8::is_ascii_whitespace));
+                firsts.push(i);
+                candidates.push(id);
+                id
+            });
+            counts[id as usize] += 1;
+            ids.push(id);
+        }
+
+        Self { lines, ids, counts, blank }
+    }
+}
+
+fn normalize<'b>(line: &[u8], options: &DuplicateOptions, buf: &'b mut Vec<u8>) -> &'b [u8] {
+    let line = if options.trim { line.trim_ascii() } else { line };
+    buf.clear();
+    if options.ignore_case {
+        match str::from_utf8(line) {
+            Ok(s) => buf.extend_from_slice(s.to_lowercase().as_bytes()),
+            Err(_) => buf.extend(line.iter().map(u8::to_ascii_lowercase)),
+        }
+    } else {
+        buf.extend_from_slice(line);
+    }
+    buf
+}
+
+/// Returns the lines of `text` that occur more than once,
+/// the most frequent first, and otherwise in the order of their first occurrence.
+pub fn duplicate_lines(text: &[u8], options: &DuplicateOptions) -> Vec<DuplicateGroup> {
+    let ids = LineIds::new(text, options);
+    // Maps ids to indices into `groups`.
+    let mut group_of = vec![usize::MAX; ids.counts.len()];
+    let mut groups = Vec::new();
+
+    for (i, &id) in ids.ids.iter().enumerate() {
+        let id = id as usize;
+        if ids.counts[id] < 2 || (options.skip_blank && ids.blank[id]) {
+            continue;
+        }
+        if group_of[id] == usize::MAX {
+            group_of[id] = groups.len();
+            groups.push(DuplicateGroup {
+                text: String::from_utf8_lossy(ids.lines[i]).into_owned(),
+                count: ids.counts[id] as usize,
+                lines: Vec::new(),
+                truncated: false,
+            });
+        }
+        let group = &mut groups[group_of[id]];
+        if group.lines.len() < options.max_lines {
+            group.lines.push(i);
+        } else {
+            group.truncated = true;
+        }
+    }
+
+    // A stable sort keeps equally frequent lines in document order.
+    groups.sort_by_key(|g| std::cmp::Reverse(g.count));
+    groups
+}
+
+/// Returns the blocks of at least `min_lines` lines that occur more than once.
+/// Blocks are extended as far as all of their occurrences continue to match.
+/// The most frequent come first, then the longest ones.
+pub fn repeated_blocks(
+    text: &[u8],
+    min_lines: usize,
+    options: &DuplicateOptions,
+) -> Vec<RepeatedBlock> {
+    const BASE: u64 = 0x100000001b3;
+
+    let ids = LineIds::new(text, options);
+    let k = min_lines.max(2);
+    if ids.ids.len() < k * 2 {
+        return Vec::new();
+    }
+
+    // The rolling hash of each window of `k` ids, along with the window's start.
+    let pow = (1..k).fold(1u64, |p, _| p.wrapping_mul(BASE));
+    let mut windows = Vec::with_capacity(ids.ids.len() - k + 1);
+    let mut h = 0u64;
+    for (i, &id) in ids.ids.iter().enumerate() {
+        if i >= k {
+            let out = ids.ids[i - k] as u64 + 1;
+            h = h.wrapping_sub(out.wrapping_mul(pow));
+        }
+        h = h.wrapping_mul(BASE).wrapping_add(id as u64 + 1);
+        if i + 1 >= k {
+            windows.push((h, i + 1 - k));
+        }
+    }
+    windows.sort_unstable();
+
+    // Group the windows with equal contents. Each group is a list of non-overlapping starts.
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for run in windows.chunk_by(|a, b| a.0 == b.0) {
+        if run.len() < 2 {
+            continue;
+        }
+        let mut starts: Vec<_> = run.iter().map(|&(_, s)| s).collect();
+        starts.sort_unstable();
+
+        // Hash collisions are split into separate groups by comparing the ids.
+        while let Some(&first) = starts.first() {
+            let window = &ids.ids[first..first + k];
+            let (same, rest): (Vec<_>, Vec<_>) =
+                starts.iter().partition(|&&s| &ids.ids[s..s + k] == window);
+            starts = rest;
+
+            if options.skip_blank && window.iter().all(|&id| ids.blank[id as usize]) {
+                continue;
+            }
+            let mut group: Vec<usize> = Vec::with_capacity(same.len());
+            for s in same {
+                if group.last().is_none_or(|&prev| s >= prev + k) {
+                    group.push(s);
+                }
+            }
+            if group.len() >= 2 {
+                groups.push(group);
+            }
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for starts in groups {
+        // If all occurrences are preceded by the same line, the block is part of a longer one.
+        if is_left_maximal(&ids.ids, &starts) {
+            extend_block(&ids, starts, k, options, &mut blocks);
+        }
+    }
+    blocks.sort_by_key(|b| (std::cmp::Reverse(b.count), std::cmp::Reverse(b.len), b.starts[0]));
+    blocks
+}
+
+fn is_left_maximal(ids: &[u32], starts: &[usize]) -> bool {
+    starts[0] == 0 || starts.iter().any(|&s| ids[s - 1] != ids[starts[0] - 1])
+}
+
+/// Extends the block of `len` lines at `starts` as far as all occurrences match
+/// and don't overlap. Then does the same for the subsets of occurrences that continue
+/// to match beyond that, since those are longer blocks that occur fewer times.
+fn extend_block(
+    ids: &LineIds,
+    starts: Vec<usize>,
+    mut len: usize,
+    options: &DuplicateOptions,
+    out: &mut Vec<RepeatedBlock>,
+) {
+    let n = ids.ids.len();
+    let fits = |starts: &[usize], len: usize| {
+        starts.last().is_some_and(|&s| s + len <= n)
+            && starts.windows(2).all(|w| w[0] + len <= w[1])
+    };
+
+    while fits(&starts, len + 1)
+        && starts.iter().all(|&s| ids.ids[s + len] == ids.ids[starts[0] + len])
+    {
+        len += 1;
+    }
+
+    let all_blank = (starts[0]..starts[0] + len).all(|i| ids.blank[ids.ids[i] as usize]);
+    if !(options.skip_blank && all_blank) {
+        let count = starts.len();
+        let mut kept = starts.clone();
+        kept.truncate(options.max_lines);
+        out.push(RepeatedBlock { len, count, starts: kept, truncated: count > options.max_lines });
+    }
+
+    if starts.len() > 2 {
+        let mut rest: Vec<_> = starts.into_iter().filter(|&s| s + len < n).collect();
+        while let Some(&first) = rest.first() {
+            let next = ids.ids[first + len];
+            let (part, others): (Vec<_>, Vec<_>) =
+                rest.iter().partition(|&&s| ids.ids[s + len] == next);
+            rest = others;
+            if part.len() >= 2 && fits(&part, len + 1) {
+                extend_block(ids, part, len + 1, options, out);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn group(text: &str, lines: &[usize]) -> DuplicateGroup {
+        DuplicateGroup {
+            text: text.to_string(),
+            count: lines.len(),
+            lines: lines.to_vec(),
+            truncated: false,
+        }
+    }
+
+    #[test]
+    fn test_duplicate_lines() {
+        let text = b"a = 1\r\nb = 2\r\na = 1\r\n\r\nc = 3\r\n\r\nb = 2\r\na = 1\r\nd = 4";
+        let groups = duplicate_lines(text, &DuplicateOptions::default());
+        assert_eq!(groups, [group("a = 1", &[0, 2, 7]), group("b = 2", &[1, 6])]);
+
+        // Blank lines can be included, and a final newline doesn't add an empty line.
+        let options = DuplicateOptions { skip_blank: false, ..Default::default() };
+        let groups = duplicate_lines(b"x\n\ny\n\n", &options);
+        assert_eq!(groups, [group("", &[1, 3])]);
+        assert!(duplicate_lines(b"", &options).is_empty());
+    }
+
+    #[test]
+    fn test_normalization() {
+        let text = "Key = 1\n  key = 1\nKEY = 1  \nÄrger\närger\n".as_bytes();
+        assert!(duplicate_lines(text, &DuplicateOptions::default()).is_empty());
+
+        let options = DuplicateOptions { trim: true, ..Default::default() };
+        assert!(duplicate_lines(text, &options).is_empty());
+
+        let options = DuplicateOptions { ignore_case: true, ..Default::default() };
+        assert_eq!(duplicate_lines(text, &options), [group("Ärger", &[3, 4])]);
+
+        let options = DuplicateOptions { trim: true, ignore_case: true, ..Default::default() };
+        assert_eq!(
+            duplicate_lines(text, &options),
+            [group("Key = 1", &[0, 1, 2]), group("Ärger", &[3, 4])]
+        );
+    }
+
+    #[test]
+    fn test_truncation() {
+        let text = "same\n".repeat(10);
+        let options = DuplicateOptions { max_lines: 3, ..Default::default() };
+        let groups = duplicate_lines(text.as_bytes(), &options);
+        assert_eq!(
+            groups,
+            [DuplicateGroup {
+                text: "same".to_string(),
+                count: 10,
+                lines: vec![0, 1, 2],
+                truncated: true
+            }]
+        );
+
+        let text = "a\nb\n".repeat(5);
+        let blocks = repeated_blocks(text.as_bytes(), 2, &options);
+        assert_eq!(blocks[0].count, 5);
+        assert_eq!(blocks[0].starts, [0, 2, 4]);
+        assert!(blocks[0].truncated);
+    }
+
+    #[test]
+    fn test_repeated_blocks() {
+        let text = "\
+fn a() {
+    let x = 1;
+    let y = 2;
+    call(x, y);
+}
+
+fn b() {
+    let x = 1;
+    let y = 2;
+    call(x, y);
+}
+
+let x = 1;
+let y = 2;
+";
+        let blocks = repeated_blocks(text.as_bytes(), 2, &DuplicateOptions::default());
+        // The body of the functions and the closing brace, including the blank line after it.
+        assert_eq!(
+            blocks,
+            [RepeatedBlock { len: 5, count: 2, starts: vec![1, 7], truncated: false }]
+        );
+
+        // With trimming, the indented lines match the last two as well.
+        let options = DuplicateOptions { trim: true, ..Default::default() };
+        let blocks = repeated_blocks(text.as_bytes(), 2, &options);
+        assert_eq!(
+            blocks,
+            [
+                RepeatedBlock { len: 2, count: 3, starts: vec![1, 7, 12], truncated: false },
+                RepeatedBlock { len: 5, count: 2, starts: vec![1, 7], truncated: false },
+            ]
+        );
+
+        // Blocks must be at least `min_lines` long, and can't overlap themselves.
+        assert!(repeated_blocks(text.as_bytes(), 6, &options).is_empty());
+        let blocks = repeated_blocks(b"a\na\na\na\na\n", 2, &options);
+        assert_eq!(blocks[0].starts, [0, 2]);
+    }
+}
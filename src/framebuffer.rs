@@ -5,11 +5,13 @@
 
 use std::cell::Cell;
 use std::fmt::Write;
-use std::ops::{BitOr, BitXor};
-use std::ptr;
+use std::ops::{BitOr, BitXor, Range};
 use std::slice::ChunksExact;
+use std::{mem, ptr, slice};
 
 use crate::arena::{Arena, ArenaString};
+use crate::damage::Damage;
+use crate::hash::hash;
 use crate::helpers::{CoordType, Point, Rect, Size};
 use crate::oklab::{oklab_blend, srgb_to_oklab};
 use crate::simd::{MemsetSafe, memset};
@@ -132,6 +134,10 @@ impl Framebuffer {
         self.indexed_colors = colors;
         self.background_fill = 0;
         self.foreground_fill = 0;
+        // The fill colors changed, which affects even the parts nothing gets drawn to.
+        for buffer in &mut self.buffers {
+            buffer.damage.mark_all();
+        }
 
         self.auto_colors = [
             self.indexed_colors[IndexedColor::Black as usize],
@@ -155,6 +161,8 @@ impl Framebuffer {
             let front = &mut self.buffers[self.frame_counter & 1];
             // Trigger a full redraw. (Yes, it's a hack.)
             front.fg_bitmap.fill(1);
+            front.hash_rows();
+            front.damage.mark_all();
             // Trigger a cursor update as well, just to be sure.
             front.cursor = Cursor::new_invalid();
         }
@@ -168,6 +176,7 @@ impl Framebuffer {
         back.fg_bitmap.fill(self.foreground_fill);
         back.attributes.reset();
         back.cursor = Cursor::new_disabled();
+        back.damage.take();
     }
 
     /// Replaces text contents in a single line of the framebuffer.
@@ -181,6 +190,7 @@ impl Framebuffer {
         text: &str,
     ) {
         let back = &mut self.buffers[self.frame_counter & 1];
+        back.damage.add_rect(Rect { left: origin_x, top: y, right: clip_right, bottom: y + 1 });
         back.text.replace_text(y, origin_x, clip_right, text)
     }
 
@@ -351,6 +361,7 @@ impl Framebuffer {
     /// but ideally `blend_bg` with semi-transparent dark should also darken text below it.
     pub fn blend_bg(&mut self, target: Rect, bg: u32) {
         let back = &mut self.buffers[self.frame_counter & 1];
+        back.damage.add_rect(target);
         back.bg_bitmap.blend(target, bg);
     }
 
@@ -360,6 +371,7 @@ impl Framebuffer {
     /// but ideally `blend_fg` should blend with the background color below it.
     pub fn blend_fg(&mut self, target: Rect, fg: u32) {
         let back = &mut self.buffers[self.frame_counter & 1];
+        back.damage.add_rect(target);
         back.fg_bitmap.blend(target, fg);
     }
 
@@ -371,6 +383,7 @@ impl Framebuffer {
         if target.is_empty() {
             return;
         }
+        back.damage.add_rect(target);
 
         let top = target.top as usize;
         let bottom = target.bottom as usize;
@@ -390,6 +403,7 @@ impl Framebuffer {
     /// Replaces VT attributes in the given rectangle.
     pub fn replace_attr(&mut self, target: Rect, mask: Attributes, attr: Attributes) {
         let back = &mut self.buffers[self.frame_counter & 1];
+        back.damage.add_rect(target);
         back.attributes.replace(target, mask, attr);
     }
 
@@ -402,91 +416,44 @@ impl Framebuffer {
         back.cursor.overtype = overtype;
     }
 
+    /// Returns the operations that turn the last rendered frame into the current one.
+    pub fn draw_ops(&mut self) -> Vec<DrawOp<'_>> {
+        let idx = self.frame_counter & 1;
+        self.buffers[idx].hash_rows();
+        diff_frames(&self.buffers[1 - idx], &self.buffers[idx])
+    }
+
     /// Renders the framebuffer contents accumulated since the
     /// last call to `flip()` and returns them serialized as VT.
     pub fn render<'a>(&mut self, arena: &'a Arena) -> ArenaString<'a> {
         let idx = self.frame_counter & 1;
-        // Borrows the front/back buffers without letting Rust know that we have a reference to self.
-        // SAFETY: Well this is certainly correct, but whether Rust and its strict rules likes it is another question.
-        let (back, front) = unsafe {
-            let ptr = self.buffers.as_mut_ptr();
-            let back = &mut *ptr.add(idx);
-            let front = &*ptr.add(1 - idx);
-            (back, front)
-        };
-
-        let mut front_lines = front.text.lines.iter(); // hahaha
-        let mut front_bgs = front.bg_bitmap.iter();
-        let mut front_fgs = front.fg_bitmap.iter();
-        let mut front_attrs = front.attributes.iter();
-
-        let mut back_lines = back.text.lines.iter();
-        let mut back_bgs = back.bg_bitmap.iter();
-        let mut back_fgs = back.fg_bitmap.iter();
-        let mut back_attrs = back.attributes.iter();
+        self.buffers[idx].hash_rows();
 
+        let back = &self.buffers[idx];
+        let front = &self.buffers[1 - idx];
         let mut result = ArenaString::new_in(arena);
-        let mut last_bg = u64::MAX;
-        let mut last_fg = u64::MAX;
+        let mut last_bg = None;
+        let mut last_fg = None;
         let mut last_attr = Attributes::None;
 
-        for y in 0..front.text.size.height {
-            // SAFETY: The only thing that changes the size of these containers,
-            // is the reset() method and it always resets front/back to the same size.
-            let front_line = unsafe { front_lines.next().unwrap_unchecked() };
-            let front_bg = unsafe { front_bgs.next().unwrap_unchecked() };
-            let front_fg = unsafe { front_fgs.next().unwrap_unchecked() };
-            let front_attr = unsafe { front_attrs.next().unwrap_unchecked() };
-
-            let back_line = unsafe { back_lines.next().unwrap_unchecked() };
-            let back_bg = unsafe { back_bgs.next().unwrap_unchecked() };
-            let back_fg = unsafe { back_fgs.next().unwrap_unchecked() };
-            let back_attr = unsafe { back_attrs.next().unwrap_unchecked() };
-
-            // TODO: Ideally, we should properly diff the contents and so if
-            // only parts of a line change, we should only update those parts.
-            if front_line == back_line
-                && front_bg == back_bg
-                && front_fg == back_fg
-                && front_attr == back_attr
-            {
-                continue;
-            }
-
-            let line_bytes = back_line.as_bytes();
-            let mut cfg = MeasurementConfig::new(&line_bytes);
-            let mut chunk_end = 0;
-
-            if result.is_empty() {
-                result.push_str("\x1b[m");
-            }
-            _ = write!(result, "\x1b[{};1H", y + 1);
-
-            while {
-                let bg = back_bg[chunk_end];
-                let fg = back_fg[chunk_end];
-                let attr = back_attr[chunk_end];
-
-                // Chunk into runs of the same color.
-                while {
-                    chunk_end += 1;
-                    chunk_end < back_bg.len()
-                        && back_bg[chunk_end] == bg
-                        && back_fg[chunk_end] == fg
-                        && back_attr[chunk_end] == attr
-                } {}
-
-                if last_bg != bg as u64 {
-                    last_bg = bg as u64;
-                    self.format_color(&mut result, false, bg);
-                }
-
-                if last_fg != fg as u64 {
-                    last_fg = fg as u64;
-                    self.format_color(&mut result, true, fg);
+        for op in diff_frames(front, back) {
+            match op {
+                DrawOp::MoveTo(pos) => {
+                    if result.is_empty() {
+                        result.push_str("\x1b[m");
+                    }
+                    _ = write!(result, "\x1b[{};{}H", pos.y + 1, pos.x + 1);
                 }
+                DrawOp::Style { bg, fg, attr } => {
+                    if last_bg != Some(bg) {
+                        last_bg = Some(bg);
+                        self.format_color(&mut result, false, bg);
+                    }
+                    if last_fg != Some(fg) {
+                        last_fg = Some(fg);
+                        self.format_color(&mut result, true, fg);
+                    }
 
-                if last_attr != attr {
                     let diff = last_attr ^ attr;
                     if diff.is(Attributes::Italic) {
                         if attr.is(Attributes::Italic) {
@@ -504,13 +471,8 @@ impl Framebuffer {
                     }
                     last_attr = attr;
                 }
-
-                let beg = cfg.cursor().offset;
-                let end = cfg.goto_visual(Point { x: chunk_end as CoordType, y: 0 }).offset;
-                result.push_str(&back_line[beg..end]);
-
-                chunk_end < back_bg.len()
-            } {}
+                DrawOp::Text(text) => result.push_str(text),
+            }
         }
 
         // If the cursor has changed since the last frame we naturally need to update it,
@@ -537,6 +499,32 @@ impl Framebuffer {
         result
     }
 
+    /// Returns the rows that differ between the last rendered frame and the current one,
+    /// including the rows the cursor moved from and to.
+    pub fn changed_rows(&mut self) -> Vec<CoordType> {
+        let idx = self.frame_counter & 1;
+        self.buffers[idx].hash_rows();
+
+        let back = &self.buffers[idx];
+        let front = &self.buffers[1 - idx];
+        let mut rows: Vec<_> =
+            changed_rows(&front.row_hashes, &back.row_hashes, |y| front.row_matches(back, y))
+                .map(|y| y as CoordType)
+                .filter(|&y| front.damage.contains_row(y) || back.damage.contains_row(y))
+                .collect();
+
+        if back.cursor != front.cursor {
+            for y in [front.cursor.pos.y, back.cursor.pos.y] {
+                if y >= 0 && y < back.text.size.height && !rows.contains(&y) {
+                    rows.push(y);
+                }
+            }
+            rows.sort_unstable();
+        }
+
+        rows
+    }
+
     fn format_color(&self, dst: &mut ArenaString, fg: bool, mut color: u32) {
         let typ = if fg { '3' } else { '4' };
 
@@ -571,6 +559,176 @@ impl Framebuffer {
     }
 }
 
+/// Returns the indices of the rows whose hashes differ.
+/// If the number of rows differs, all rows of `current` are returned.
+///
+/// Since hashes can collide, rows with equal hashes are passed to `same_row`,
+/// which must compare their contents and return whether they're actually equal.
+pub fn changed_rows<'a>(
+    prev: &'a [u64],
+    current: &'a [u64],
+    same_row: impl Fn(usize) -> bool + 'a,
+) -> impl Iterator<Item = usize> + 'a {
+    let resized = prev.len() != current.len();
+    (0..current.len()).filter(move |&y| resized || prev[y] != current[y] || !same_row(y))
+}
+
+/// A step in updating the terminal from one frame to the next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawOp<'a> {
+    /// Moves the cursor to the given cell.
+    MoveTo(Point),
+    /// Sets the style for the following text.
+    Style { bg: u32, fg: u32, attr: Attributes },
+    /// Writes text at the cursor, which advances it.
+    Text(&'a str),
+}
+
+/// Unchanged runs of cells shorter than this are rewritten anyway,
+/// because moving the cursor past them costs about as much.
+const MIN_SKIP_CELLS: usize = 4;
+
+/// Computes the [`DrawOp`]s that turn `front` into `back`.
+///
+/// Only the changed cells of changed rows are written. Wide characters are always written
+/// as a whole, and so are the ones in `front` that would otherwise be half-overwritten.
+fn diff_frames<'a>(front: &Buffer, back: &'a Buffer) -> Vec<DrawOp<'a>> {
+    let mut ops = Vec::new();
+    let mut last_style = None;
+    let width = back.text.size.width as usize;
+    let resized = front.text.size != back.text.size;
+
+    let mut front_cells = Vec::new();
+    let mut back_cells = Vec::new();
+    let mut spans: Vec<Range<usize>> = Vec::new();
+
+    for y in 0..back.text.size.height as usize {
+        // Since every frame starts out blank, only rows drawn to in either frame can differ.
+        let damaged =
+            front.damage.contains_row(y as CoordType) || back.damage.contains_row(y as CoordType);
+        if !resized && (!damaged || front.row_matches(back, y)) {
+            continue;
+        }
+
+        let back_line = &back.text.lines[y];
+        let back_style = back.row_style(y);
+        row_cells(back_line, width, &mut back_cells);
+
+        spans.clear();
+        if resized {
+            spans.push(0..width);
+        } else {
+            let front_line = &front.text.lines[y];
+            let front_style = front.row_style(y);
+            row_cells(front_line, width, &mut front_cells);
+
+            let differs = |x: usize| {
+                front_line[front_cells[x].clone()] != back_line[back_cells[x].clone()]
+                    || is_cell_start(&front_cells, x) != is_cell_start(&back_cells, x)
+                    || front_style(x) != back_style(x)
+            };
+
+            for x in 0..width {
+                if !differs(x) {
+                    continue;
+                }
+                match spans.last_mut() {
+                    Some(last) if x - last.end < MIN_SKIP_CELLS => last.end = x + 1,
+                    _ => spans.push(x..x + 1),
+                }
+            }
+
+            // Widen the spans to whole characters, in both frames.
+            let is_boundary = |x: usize| {
+                x == 0
+                    || x >= width
+                    || (is_cell_start(&front_cells, x) && is_cell_start(&back_cells, x))
+            };
+            for span in &mut spans {
+                while !is_boundary(span.start) {
+                    span.start -= 1;
+                }
+                while !is_boundary(span.end) {
+                    span.end += 1;
+                }
+            }
+            spans.dedup_by(|next, prev| {
+                let overlaps = next.start <= prev.end;
+                if overlaps {
+                    prev.end = prev.end.max(next.end);
+                }
+                overlaps
+            });
+        }
+
+        for span in &spans {
+            ops.push(DrawOp::MoveTo(Point { x: span.start as CoordType, y: y as CoordType }));
+
+            let mut x = span.start;
+            while x < span.end {
+                let style = back_style(x);
+                let beg = x;
+
+                // Chunk into runs of the same style, without splitting wide characters.
+                while {
+                    x += 1;
+                    x < span.end && (back_style(x) == style || !is_cell_start(&back_cells, x))
+                } {}
+
+                if last_style != Some(style) {
+                    last_style = Some(style);
+                    let (bg, fg, attr) = style;
+                    ops.push(DrawOp::Style { bg, fg, attr });
+                }
+                let text = back_cells[beg].start..back_cells[x - 1].end;
+                ops.push(DrawOp::Text(&back_line[text]));
+            }
+        }
+    }
+
+    ops
+}
+
+/// Stores the byte range of the character that covers each column of `line` in `cells`.
+/// Wide characters cover two columns.
+fn row_cells(line: &str, width: usize, cells: &mut Vec<Range<usize>>) {
+    let bytes = line.as_bytes();
+    let mut cfg = MeasurementConfig::new(&bytes);
+    cells.clear();
+
+    while cells.len() < width {
+        let beg = cfg.cursor();
+        let end = cfg.goto_logical(Point { x: beg.logical_pos.x + 1, y: 0 });
+        if end.offset == beg.offset {
+            break;
+        }
+
+        let columns = (end.visual_pos.x - beg.visual_pos.x) as usize;
+        if columns == 0
+            && let Some(last) = cells.last()
+        {
+            // Zero-width characters are attached to the preceding cell.
+            let start = last.start;
+            for cell in cells.iter_mut().rev().take_while(|c| c.start == start) {
+                cell.end = end.offset;
+            }
+            continue;
+        }
+
+        for _ in 0..columns.max(1) {
+            cells.push(beg.offset..end.offset);
+        }
+    }
+
+    cells.truncate(width);
+    cells.resize(width, bytes.len()..bytes.len());
+}
+
+/// Returns whether column `x` is the first one of its character.
+fn is_cell_start(cells: &[Range<usize>], x: usize) -> bool {
+    x == 0 || cells[x].is_empty() || cells[x - 1] != cells[x]
+}
+
 #[derive(Default)]
 struct Buffer {
     text: LineBuffer,
@@ -578,6 +736,78 @@ struct Buffer {
     fg_bitmap: Bitmap,
     attributes: AttributeBuffer,
     cursor: Cursor,
+    /// A hash of the contents of each row, computed by `hash_rows`.
+    /// Comparing them is a lot cheaper than comparing the rows.
+    row_hashes: Vec<u64>,
+    /// The parts that were drawn to since the last `flip`.
+    damage: Damage,
+}
+
+impl Buffer {
+    /// Returns a function that returns the style of a cell in row `y`.
+    fn row_style(&self, y: usize) -> impl Fn(usize) -> (u32, u32, Attributes) + '_ {
+        let width = self.text.size.width as usize;
+        let row = y * width;
+        move |x| {
+            let i = row + x;
+            (self.bg_bitmap.data[i], self.fg_bitmap.data[i], self.attributes.data[i])
+        }
+    }
+
+    fn hash_rows(&mut self) {
+        self.row_hashes.clear();
+
+        for (((line, bg), fg), attr) in self
+            .text
+            .lines
+            .iter()
+            .zip(self.bg_bitmap.iter())
+            .zip(self.fg_bitmap.iter())
+            .zip(self.attributes.iter())
+        {
+            let mut h = hash(0, line.as_bytes());
+            h = hash(h, slice_as_bytes(bg));
+            h = hash(h, slice_as_bytes(fg));
+            h = hash(h, slice_as_bytes(attr));
+            self.row_hashes.push(h);
+        }
+    }
+
+    /// Returns whether row `y` is the same in both buffers.
+    ///
+    /// The hashes computed by `hash_rows` rule out most changed rows cheaply,
+    /// but a match is confirmed by comparing the contents, since they can collide.
+    fn row_matches(&self, other: &Buffer, y: usize) -> bool {
+        if self.text.size != other.text.size || self.row_hashes.get(y) != other.row_hashes.get(y) {
+            return false;
+        }
+
+        let width = self.text.size.width as usize;
+        let cells = y * width..(y + 1) * width;
+        self.text.lines[y] == other.text.lines[y]
+            && self.bg_bitmap.data[cells.clone()] == other.bg_bitmap.data[cells.clone()]
+            && self.fg_bitmap.data[cells.clone()] == other.fg_bitmap.data[cells.clone()]
+            && self.attributes.data[cells.clone()] == other.attributes.data[cells]
+    }
+}
+
+/// Types whose values can be viewed as plain bytes.
+///
+/// # Safety
+///
+/// Implementors must have no padding and every bit of them must be initialized,
+/// which holds for integers and `repr(transparent)` wrappers of them.
+unsafe trait AsBytes: Copy {}
+
+unsafe impl AsBytes for u32 {}
+// `Attributes` is a `repr(transparent)` wrapper of an `u8`.
+unsafe impl AsBytes for Attributes {}
+
+fn slice_as_bytes<T: AsBytes>(slice: &[T]) -> &[u8] {
+    // SAFETY: `AsBytes` guarantees that `T` has no padding or uninitialized bytes,
+    // so all `size_of_val(slice)` bytes behind the pointer are valid `u8`s.
+    // The alignment of `u8` is 1 and the lifetime is tied to `slice`.
+    unsafe { slice::from_raw_parts(slice.as_ptr() as *const u8, mem::size_of_val(slice)) }
 }
 
 /// A buffer for the text contents of the framebuffer.
@@ -651,11 +881,20 @@ impl LineBuffer {
         }
 
         // Measure the width of the new text (= `res_new.visual_target.x`).
-        let beg_off = cfg.cursor().offset;
-        let end = cfg.goto_visual(Point { x: layout_width, y: 0 });
+        let beg = cfg.cursor();
+        let beg_off = beg.offset;
+        let end = cfg.goto_visual(Point { x: beg.visual_pos.x + clip_right - left, y: 0 });
+
+        // A wide glyph that doesn't fit in front of `clip_right` isn't drawn,
+        // but the cell it would've started in gets blanked with a space.
+        let mut right = left + end.visual_pos.x - beg.visual_pos.x;
+        let mut clip_pad = 0;
+        if end.offset < bytes.len() && right < clip_right {
+            clip_pad = (clip_right - right) as usize;
+            right = clip_right;
+        }
 
         // Figure out at which byte offset the new text gets inserted.
-        let right = left + end.visual_pos.x;
         let line_bytes = line.as_bytes();
         let mut cfg_old = MeasurementConfig::new(&line_bytes);
         let res_old_beg = cfg_old.goto_visual(Point { x: left, y: 0 });
@@ -671,7 +910,7 @@ impl LineBuffer {
         let src = &text[beg_off..end.offset];
         let overlap_beg = (left - res_old_beg.visual_pos.x).max(0) as usize;
         let overlap_end = (res_old_end.visual_pos.x - right).max(0) as usize;
-        let total_add = src.len() + overlap_beg + overlap_end;
+        let total_add = src.len() + clip_pad + overlap_beg + overlap_end;
         let total_del = res_old_end.offset - res_old_beg.offset;
 
         // This is basically a hand-written version of `Vec::splice()`,
@@ -716,7 +955,7 @@ impl LineBuffer {
             ptr = ptr.add(src_len);
 
             // Pad right.
-            for _ in 0..overlap_end {
+            for _ in 0..clip_pad + overlap_end {
                 ptr.write(b' ');
                 ptr = ptr.add(1);
             }
@@ -804,7 +1043,7 @@ impl Bitmap {
 ///
 /// It being a bitfield allows for simple diffing.
 #[repr(transparent)]
-#[derive(Default, Clone, Copy, PartialEq, Eq)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Attributes(u8);
 
 #[allow(non_upper_case_globals)]
@@ -902,3 +1141,282 @@ impl Cursor {
         Self { pos: Point { x: -1, y: -1 }, overtype: false }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::scratch_arena;
+
+    const SIZE: Size = Size { width: 10, height: 5 };
+
+    /// Renders a frame with the given rows and returns the rows that changed since the last one.
+    fn frame(fb: &mut Framebuffer, rows: &[&str], cursor: Option<Point>) -> Vec<CoordType> {
+        fb.flip(SIZE);
+        for (y, row) in rows.iter().enumerate() {
+            fb.replace_text(y as CoordType, 0, SIZE.width, row);
+        }
+        if let Some(pos) = cursor {
+            fb.set_cursor(pos, false);
+        }
+
+        let changed = fb.changed_rows();
+        let scratch = scratch_arena(None);
+        fb.render(&scratch);
+        changed
+    }
+
+    #[test]
+    fn test_changed_rows() {
+        let mut fb = Framebuffer::new();
+        let rows = ["one", "two", "three", "four", "five"];
+        let cursor = Some(Point { x: 1, y: 2 });
+
+        // The first frame draws everything.
+        assert_eq!(frame(&mut fb, &rows, cursor), [0, 1, 2, 3, 4]);
+        assert_eq!(frame(&mut fb, &rows, cursor), []);
+
+        // An edit that wraps across two rows.
+        assert_eq!(frame(&mut fb, &["one", "two!", "three!", "four", "five"], cursor), [1, 2]);
+        assert_eq!(frame(&mut fb, &["one", "two", "three", "four", "five"], cursor), [1, 2]);
+
+        // Scrolling by one changes every row.
+        assert_eq!(
+            frame(&mut fb, &["two", "three", "four", "five", "six"], cursor),
+            [0, 1, 2, 3, 4]
+        );
+
+        // Hiding the cursor, like when it blinks, only affects its row.
+        assert_eq!(frame(&mut fb, &["two", "three", "four", "five", "six"], None), [2]);
+
+        // A selection only changes the rows it covers.
+        fb.flip(SIZE);
+        for (y, row) in ["two", "three", "four", "five", "six"].iter().enumerate() {
+            fb.replace_text(y as CoordType, 0, SIZE.width, row);
+        }
+        fb.reverse(Rect { left: 0, top: 3, right: 2, bottom: 4 });
+        assert_eq!(fb.changed_rows(), [3]);
+    }
+
+    /// What a terminal shows in a cell: The text, or `None` for the second half of a
+    /// wide character, and the style.
+    type Screen = Vec<Vec<(Option<String>, Option<(u32, u32, Attributes)>)>>;
+
+    fn screen_of(buf: &Buffer) -> Screen {
+        let width = buf.text.size.width as usize;
+        let mut cells = Vec::new();
+        (0..buf.text.size.height as usize)
+            .map(|y| {
+                let line = &buf.text.lines[y];
+                let style = buf.row_style(y);
+                row_cells(line, width, &mut cells);
+                (0..width)
+                    .map(|x| match is_cell_start(&cells, x) {
+                        true => (Some(line[cells[x].clone()].to_string()), Some(style(x))),
+                        false => (None, None),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Applies the `ops` to the `screen` like a terminal would.
+    fn apply(screen: &mut Screen, ops: &[DrawOp]) {
+        let mut pos = Point::default();
+        let mut style = None;
+        let mut cells = Vec::new();
+
+        for op in ops {
+            match *op {
+                DrawOp::MoveTo(p) => pos = p,
+                DrawOp::Style { bg, fg, attr } => style = Some((bg, fg, attr)),
+                DrawOp::Text(text) => {
+                    row_cells(text, text.len() * 2, &mut cells);
+                    let row = &mut screen[pos.y as usize];
+                    for i in 0..cells.len() {
+                        if cells[i].is_empty() {
+                            break;
+                        }
+                        if !is_cell_start(&cells, i) {
+                            continue;
+                        }
+                        let grapheme = &text[cells[i].clone()];
+                        let columns = cells[i..].iter().take_while(|c| **c == cells[i]).count();
+                        let x = pos.x as usize;
+
+                        // Overwriting half of a wide character erases the other half.
+                        if row[x].0.is_none() {
+                            row[x - 1] = (Some(" ".into()), row[x - 1].1);
+                        }
+                        if let Some(next) = row.get_mut(x + columns)
+                            && next.0.is_none()
+                        {
+                            *next = (Some(" ".into()), style);
+                        }
+
+                        row[x] = (Some(grapheme.into()), style);
+                        for cell in &mut row[x + 1..x + columns] {
+                            *cell = (None, None);
+                        }
+                        pos.x += columns as CoordType;
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_draw_ops() {
+        let mut fb = Framebuffer::new();
+        let scratch = scratch_arena(None);
+        let draw = |fb: &mut Framebuffer, rows: &[&str], highlight: Option<Rect>| {
+            fb.flip(SIZE);
+            for (y, row) in rows.iter().enumerate() {
+                fb.replace_text(y as CoordType, 0, SIZE.width, row);
+            }
+            if let Some(rect) = highlight {
+                fb.blend_bg(rect, 0xff0000ff);
+            }
+        };
+
+        draw(&mut fb, &["abc", "a中b", "", "", ""], None);
+        fb.render(&scratch);
+
+        // Nothing changed, nothing to do.
+        draw(&mut fb, &["abc", "a中b", "", "", ""], None);
+        assert_eq!(fb.draw_ops(), []);
+        fb.render(&scratch);
+
+        // A single changed cell.
+        draw(&mut fb, &["aXc", "a中b", "", "", ""], None);
+        let ops = fb.draw_ops();
+        assert_eq!(ops.len(), 3);
+        assert_eq!(ops[0], DrawOp::MoveTo(Point { x: 1, y: 0 }));
+        assert_eq!(ops[2], DrawOp::Text("X"));
+        fb.render(&scratch);
+
+        // Changing the second half of a wide character redraws all of it.
+        draw(
+            &mut fb,
+            &["aXc", "a中b", "", "", ""],
+            Some(Rect { left: 2, top: 1, right: 3, bottom: 2 }),
+        );
+        let ops = fb.draw_ops();
+        assert_eq!(ops[0], DrawOp::MoveTo(Point { x: 1, y: 1 }));
+        assert_eq!(ops.last(), Some(&DrawOp::Text("中")));
+        fb.render(&scratch);
+    }
+
+    #[test]
+    fn test_draw_ops_reproduce_frame() {
+        let alphabet = ["a", "b", " ", "中", "字", "e\u{301}"];
+        let mut state = 0x9E3779B97F4A7C15u64;
+        let mut next = |max: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % max as u64) as usize
+        };
+
+        let mut fb = Framebuffer::new();
+        let scratch = scratch_arena(None);
+        let mut screen = Screen::new();
+
+        for frame in 0..200 {
+            fb.flip(SIZE);
+            for y in 0..SIZE.height {
+                let row: String = (0..next(8)).map(|_| alphabet[next(alphabet.len())]).collect();
+                fb.replace_text(y, next(3) as CoordType, SIZE.width, &row);
+            }
+            let x = next(SIZE.width as usize) as CoordType;
+            let y = next(SIZE.height as usize) as CoordType;
+            fb.blend_bg(Rect { left: x, top: y, right: x + 2, bottom: y + 1 }, 0xff00ff00);
+
+            let expected = screen_of(&fb.buffers[fb.frame_counter & 1]);
+            if frame == 0 {
+                screen = expected.clone();
+            }
+            let ops = fb.draw_ops();
+            apply(&mut screen, &ops);
+            assert_eq!(screen, expected, "frame {frame}");
+            fb.render(&scratch);
+        }
+    }
+
+    #[test]
+    fn test_replace_text_clipping() {
+        let family = "👩\u{200d}👩\u{200d}👧";
+        let mut lb = LineBuffer::new(Size { width: 6, height: 1 });
+        lb.fill_whitespace();
+
+        // Text is clipped at the right edge.
+        lb.replace_text(0, 3, 6, "abcdef");
+        assert_eq!(lb.lines[0], "   abc");
+
+        // A wide character that doesn't fit into the last column isn't drawn,
+        // but the column is still blanked, since the text covers it.
+        lb.replace_text(0, 4, 6, "x中");
+        assert_eq!(lb.lines[0], "   ax ");
+
+        // Multi-codepoint graphemes occupy their cells like any other character.
+        lb.replace_text(0, 0, 6, family);
+        assert_eq!(lb.lines[0], format!("{family} ax "));
+
+        // Overwriting half of one blanks the other half.
+        lb.replace_text(0, 1, 6, "y");
+        assert_eq!(lb.lines[0], " y ax ");
+        lb.replace_text(0, 0, 6, family);
+        lb.replace_text(0, -1, 6, "z中");
+        assert_eq!(lb.lines[0], "中 ax ");
+    }
+
+    #[test]
+    fn test_draw_ops_damage() {
+        let mut fb = Framebuffer::new();
+        let scratch = scratch_arena(None);
+        fb.flip(SIZE);
+        fb.render(&scratch);
+
+        // Nothing was drawn, so nothing is examined, even if the contents changed somehow.
+        fb.flip(SIZE);
+        let back = &mut fb.buffers[fb.frame_counter & 1];
+        back.text.lines[2].replace_range(0..1, "x");
+        assert!(back.damage.is_empty());
+        assert_eq!(fb.draw_ops(), []);
+        fb.render(&scratch);
+
+        fb.flip(SIZE);
+        fb.replace_text(1, 0, SIZE.width, "abc");
+        assert_eq!(fb.draw_ops()[0], DrawOp::MoveTo(Point { x: 0, y: 1 }));
+        fb.render(&scratch);
+
+        // Rows drawn to in the last frame but not in this one must be cleared.
+        fb.flip(SIZE);
+        let ops = fb.draw_ops();
+        assert_eq!(ops[0], DrawOp::MoveTo(Point { x: 0, y: 1 }));
+        assert_eq!(ops.last(), Some(&DrawOp::Text("   ")));
+    }
+
+    #[test]
+    fn test_draw_ops_resized() {
+        let mut fb = Framebuffer::new();
+        let scratch = scratch_arena(None);
+        fb.flip(SIZE);
+        fb.render(&scratch);
+
+        fb.flip(Size { width: 4, height: 2 });
+        let ops = fb.draw_ops();
+        assert_eq!(ops.iter().filter(|op| matches!(op, DrawOp::MoveTo(_))).count(), 2);
+        assert_eq!(ops.last(), Some(&DrawOp::Text("    ")));
+    }
+
+    #[test]
+    fn test_changed_rows_resized() {
+        let same = |_| true;
+        assert_eq!(changed_rows(&[1, 2, 3], &[1, 5, 3], same).collect::<Vec<_>>(), [1]);
+        assert_eq!(changed_rows(&[1, 2], &[1, 2, 3], same).collect::<Vec<_>>(), [0, 1, 2]);
+
+        // Rows whose hashes collide are still reported if their contents differ.
+        let collided = |y| y != 2;
+        assert_eq!(changed_rows(&[1, 2, 3], &[1, 5, 3], collided).collect::<Vec<_>>(), [1, 2]);
+    }
+}
@@ -5,6 +5,8 @@
 //! Other algorithms exist, such as Sublime Text's, or the one used in `fzf`,
 //! but I figured that this one is what lots of people may be familiar with.
 
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::vec;
 
 use crate::arena::{Arena, scratch_arena};
@@ -12,11 +14,95 @@ use crate::icu;
 
 const NO_MATCH: i32 = 0;
 
+/// The result of [`fuzzy_match`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    pub score: i32,
+    /// The byte offsets of the matched characters in the haystack, for highlighting.
+    pub positions: Vec<u32>,
+}
+
+/// Matches `needle` as a subsequence of `haystack`.
+///
+/// Matching is "smart case": A needle without uppercase letters matches case-insensitively.
+/// Otherwise, the case must match exactly.
+pub fn fuzzy_match(needle: &str, haystack: &str) -> Option<FuzzyMatch> {
+    let case_sensitive = needle.chars().any(char::is_uppercase);
+    if !is_subsequence(needle, haystack, case_sensitive) {
+        return None;
+    }
+
+    let scratch = scratch_arena(None);
+    let (score, chars) = score_fuzzy_impl(&scratch, haystack, needle, true, case_sensitive);
+    if score == NO_MATCH {
+        return None;
+    }
+
+    let mut positions = Vec::with_capacity(chars.len());
+    let mut chars = chars.iter().peekable();
+    for (i, (off, _)) in haystack.char_indices().enumerate() {
+        if chars.peek().is_none() {
+            break;
+        }
+        if chars.next_if(|&&c| c == i).is_some() {
+            positions.push(off as u32);
+        }
+    }
+
+    Some(FuzzyMatch { score, positions })
+}
+
+/// Returns the indices and scores of the `limit` best matches of `needle` among the `candidates`,
+/// best first. Among equal scores, shorter candidates win, and then the ones that come first.
+pub fn rank<'a>(
+    needle: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+    limit: usize,
+) -> Vec<(usize, i32)> {
+    // A min-heap of the best matches so far, so that the worst one is at the top.
+    let mut heap = BinaryHeap::with_capacity(limit + 1);
+
+    for (index, candidate) in candidates.into_iter().enumerate() {
+        let Some(m) = fuzzy_match(needle, candidate) else {
+            continue;
+        };
+        heap.push(Reverse((m.score, Reverse(candidate.len()), Reverse(index))));
+        if heap.len() > limit {
+            heap.pop();
+        }
+    }
+
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((score, _, Reverse(index)))| (index, score))
+        .collect()
+}
+
+/// A cheap check that rules out most non-matches before running the scorer.
+/// Only compares ASCII characters, since case folding of others may change their length.
+fn is_subsequence(needle: &str, haystack: &str, case_sensitive: bool) -> bool {
+    let mut haystack = haystack.bytes();
+    needle
+        .bytes()
+        .filter(u8::is_ascii)
+        .all(|n| haystack.any(|h| if case_sensitive { h == n } else { h.eq_ignore_ascii_case(&n) }))
+}
+
 pub fn score_fuzzy<'a>(
     arena: &'a Arena,
     haystack: &str,
     needle: &str,
     allow_non_contiguous_matches: bool,
+) -> (i32, Vec<usize, &'a Arena>) {
+    score_fuzzy_impl(arena, haystack, needle, allow_non_contiguous_matches, false)
+}
+
+fn score_fuzzy_impl<'a>(
+    arena: &'a Arena,
+    haystack: &str,
+    needle: &str,
+    allow_non_contiguous_matches: bool,
+    case_sensitive: bool,
 ) -> (i32, Vec<usize, &'a Arena>) {
     if haystack.is_empty() || needle.is_empty() {
         // return early if target or query are empty
@@ -32,10 +118,13 @@ pub fn score_fuzzy<'a>(
         return (NO_MATCH, Vec::new_in(arena));
     }
 
-    let target_lower = icu::fold_case(&scratch, haystack);
-    let query_lower = icu::fold_case(&scratch, needle);
-    let target_lower = map_chars(&scratch, &target_lower);
-    let query_lower = map_chars(&scratch, &query_lower);
+    let (target_lower, query_lower) = if case_sensitive {
+        (target.clone(), query.clone())
+    } else {
+        let target_lower = icu::fold_case(&scratch, haystack);
+        let query_lower = icu::fold_case(&scratch, needle);
+        (map_chars(&scratch, &target_lower), map_chars(&scratch, &query_lower))
+    };
 
     let area = query.len() * target.len();
     let mut scores = vec::from_elem_in(0, area, &*scratch);
@@ -219,3 +308,46 @@ fn map_chars<'a>(arena: &'a Arena, s: &str) -> Vec<char, &'a Arena> {
     chars.shrink_to_fit();
     chars
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ranking() {
+        let candidates = ["fileblob", "foo_bar_lib", "src/flb.rs", "flb", "frob", "FooBarLib"];
+        let ranked: Vec<_> =
+            rank("flb", candidates, 10).into_iter().map(|(i, _)| candidates[i]).collect();
+        // Consecutive matches beat matches after separators, which beat matches mid-word.
+        // "fileblob" and "FooBarLib" tie, so the shorter candidate wins.
+        assert_eq!(ranked, ["flb", "src/flb.rs", "foo_bar_lib", "fileblob", "FooBarLib"]);
+
+        // Only the top K are kept.
+        let top: Vec<_> = rank("flb", candidates, 2).into_iter().map(|(i, _)| i).collect();
+        assert_eq!(top, [3, 2]);
+    }
+
+    #[test]
+    fn test_positions() {
+        let m = fuzzy_match("flb", "foo_bar_lib").unwrap();
+        assert_eq!(m.positions, [0, 8, 10]);
+
+        // Positions are byte offsets.
+        let m = fuzzy_match("fb", "für_bar").unwrap();
+        assert_eq!(m.positions, [0, 5]);
+
+        assert_eq!(fuzzy_match("xyz", "foo_bar_lib"), None);
+        assert_eq!(fuzzy_match("", "foo"), None);
+    }
+
+    #[test]
+    fn test_smart_case() {
+        assert!(fuzzy_match("fbl", "FooBarLib").is_some());
+        assert!(fuzzy_match("FBL", "FooBarLib").is_some());
+        assert!(fuzzy_match("FBL", "foo_bar_lib").is_none());
+
+        // An uppercase needle picks the positions with the matching case.
+        let m = fuzzy_match("B", "abcB").unwrap();
+        assert_eq!(m.positions, [3]);
+    }
+}
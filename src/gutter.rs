@@ -0,0 +1,362 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tracks which lines changed since the document was last saved, for marks in the gutter.
+//!
+//! Only a hash of each line is kept, both for the saved state (the baseline) and the current one.
+//! The hunks between the two are kept up to date incrementally: After an edit, only the edited
+//! lines and the hunks that touch them are diffed again, not the whole document.
+
+use std::ops::Range;
+
+use crate::diff::{Hunk, MAX_DIFF_COST, diff};
+use crate::hash::hash;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GutterMark {
+    Unchanged,
+    /// The line was inserted.
+    Added,
+    /// The line replaced one or more saved lines.
+    Modified,
+    /// Lines were removed right before this one.
+    /// If they were removed at the end of the document, it's the last line instead.
+    Deleted,
+}
+
+/// See the module documentation.
+///
+/// Like the [`crate::word_index::WordIndex`], the owner is expected to report all edits via
+/// [`ChangeTracker::line_changed`], [`ChangeTracker::lines_inserted`] and
+/// [`ChangeTracker::lines_removed`], and to call [`ChangeTracker::update`]
+/// before calling [`ChangeTracker::gutter_marks`].
+#[derive(Debug, Default, Clone)]
+pub struct ChangeTracker {
+    baseline: Vec<u64>,
+    /// The hashes of the current lines. `None` if the line needs to be hashed again.
+    lines: Vec<Option<u64>>,
+    /// The differences between `baseline` and `lines`, sorted by position.
+    /// Outside of `dirty`, they're accurate.
+    hunks: Vec<Hunk>,
+    /// The lines that were edited since the last [`ChangeTracker::update`].
+    dirty: Option<Range<usize>>,
+}
+
+impl ChangeTracker {
+    /// Creates a tracker for a document with the given number of lines, which are the baseline.
+    /// `text` is called like in [`ChangeTracker::update`].
+    pub fn new(line_count: usize, text: impl FnMut(usize, &mut Vec<u8>)) -> Self {
+        let mut tracker = Self::default();
+        tracker.lines_inserted(0, line_count);
+        tracker.reset_baseline(text);
+        tracker
+    }
+
+    /// Makes the current state the new baseline, for instance after saving.
+    pub fn reset_baseline(&mut self, text: impl FnMut(usize, &mut Vec<u8>)) {
+        self.update(text);
+        self.baseline = self.lines.iter().map(|h| h.unwrap_or_default()).collect();
+        self.hunks.clear();
+    }
+
+    /// Whether the document matches the baseline.
+    pub fn is_clean(&self) -> bool {
+        self.dirty.is_none() && self.hunks.is_empty()
+    }
+
+    /// Marks the given line as needing to be hashed again.
+    pub fn line_changed(&mut self, line: usize) {
+        if let Some(hash) = self.lines.get_mut(line) {
+            *hash = None;
+            self.mark_dirty(line..line + 1);
+        }
+    }
+
+    /// Inserts `count` new lines before the line `at`.
+    pub fn lines_inserted(&mut self, at: usize, count: usize) {
+        let at = at.min(self.lines.len());
+        self.lines.splice(at..at, std::iter::repeat_n(None, count));
+        self.map_positions(|p| if p < at { p } else { p + count });
+        self.mark_dirty(at..at + count);
+    }
+
+    /// Removes the lines `at..at + count`.
+    pub fn lines_removed(&mut self, at: usize, count: usize) {
+        let at = at.min(self.lines.len());
+        let end = (at + count).min(self.lines.len());
+        self.lines.drain(at..end);
+        self.map_positions(|p| {
+            if p < at {
+                p
+            } else if p < end {
+                at
+            } else {
+                p - (end - at)
+            }
+        });
+        self.mark_dirty(at..at);
+    }
+
+    /// Hashes the edited lines and diffs them against the baseline. `text` is called with
+    /// a line index and needs to append the contents of that line to the given `Vec`.
+    pub fn update(&mut self, mut text: impl FnMut(usize, &mut Vec<u8>)) {
+        let Some(dirty) = self.dirty.take() else {
+            return;
+        };
+
+        let mut buf = Vec::new();
+        for (i, line) in self.lines.iter_mut().enumerate().take(dirty.end).skip(dirty.start) {
+            if line.is_none() {
+                buf.clear();
+                text(i, &mut buf);
+                *line = Some(hash(0, &buf));
+            }
+        }
+
+        // Widen the dirty range to the hunks that overlap or touch it, since they may
+        // need to be merged or split. Outside of it, the lines match the baseline one by one.
+        let first = self.hunks.partition_point(|h| h.new.end < dirty.start);
+        let last = self.hunks.partition_point(|h| h.new.start <= dirty.end);
+        let mut new = dirty;
+        if first < last {
+            new.start = new.start.min(self.hunks[first].new.start);
+            new.end = new.end.max(self.hunks[last - 1].new.end);
+        }
+
+        // The number of lines that the hunks before and after the range added.
+        let growth = |hunks: &[Hunk]| -> isize {
+            hunks.iter().map(|h| h.new.len() as isize - h.old.len() as isize).sum()
+        };
+        let old_start = (new.start as isize - growth(&self.hunks[..first])) as usize;
+        let old_end = (self.baseline.len() as isize + growth(&self.hunks[last..])
+            - (self.lines.len() - new.end) as isize) as usize;
+        let old = old_start..old_end;
+
+        let current: Vec<u64> = self.lines[new.clone()].iter().map(|h| h.unwrap()).collect();
+        let hunks = match diff(&self.baseline[old.clone()], &current, MAX_DIFF_COST) {
+            Some(hunks) => hunks
+                .into_iter()
+                .map(|h| Hunk {
+                    old: old.start + h.old.start..old.start + h.old.end,
+                    new: new.start + h.new.start..new.start + h.new.end,
+                })
+                .collect(),
+            None => vec![Hunk { old, new }],
+        };
+        self.hunks.splice(first..last, hunks);
+    }
+
+    /// Returns the mark of each of the given lines.
+    pub fn gutter_marks(&self, lines: Range<usize>) -> Vec<GutterMark> {
+        let end = lines.end.min(self.lines.len());
+        let beg = lines.start.min(end);
+        let mut marks = vec![GutterMark::Unchanged; end - beg];
+        let first = self.hunks.partition_point(|h| h.new.end < beg);
+
+        for h in &self.hunks[first..] {
+            if h.new.start > end {
+                break;
+            }
+            if h.new.is_empty() {
+                let line = h.new.start.min(self.lines.len().saturating_sub(1));
+                // Added and modified lines take precedence over a deletion at the end.
+                if (beg..end).contains(&line) && marks[line - beg] == GutterMark::Unchanged {
+                    marks[line - beg] = GutterMark::Deleted;
+                }
+            } else {
+                let mark = if h.old.is_empty() { GutterMark::Added } else { GutterMark::Modified };
+                for line in h.new.start.max(beg)..h.new.end.min(end) {
+                    marks[line - beg] = mark;
+                }
+            }
+        }
+
+        marks
+    }
+
+    fn mark_dirty(&mut self, range: Range<usize>) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(d) => d.start.min(range.start)..d.end.max(range.end),
+            None => range,
+        });
+    }
+
+    /// Moves the current line numbers of the hunks and the dirty range after an edit.
+    fn map_positions(&mut self, map: impl Fn(usize) -> usize) {
+        for h in &mut self.hunks {
+            h.new = map(h.new.start)..map(h.new.end);
+        }
+        if let Some(d) = &mut self.dirty {
+            *d = map(d.start)..map(d.end);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A document as a list of lines, which keeps a [`ChangeTracker`] up to date.
+    struct Doc {
+        lines: Vec<String>,
+        tracker: ChangeTracker,
+    }
+
+    impl Doc {
+        fn new(lines: &[&str]) -> Self {
+            let lines: Vec<String> = lines.iter().map(|s| s.to_string()).collect();
+            let tracker = ChangeTracker::new(lines.len(), |i, buf| {
+                buf.extend_from_slice(lines[i].as_bytes())
+            });
+            Self { lines, tracker }
+        }
+
+        fn update(&mut self) {
+            let lines = &self.lines;
+            self.tracker.update(|i, buf| buf.extend_from_slice(lines[i].as_bytes()));
+        }
+
+        fn set_line(&mut self, line: usize, text: &str) {
+            self.lines[line] = text.to_string();
+            self.tracker.line_changed(line);
+        }
+
+        fn insert_lines(&mut self, at: usize, lines: &[&str]) {
+            self.lines.splice(at..at, lines.iter().map(|s| s.to_string()));
+            self.tracker.lines_inserted(at, lines.len());
+        }
+
+        fn remove_lines(&mut self, at: usize, count: usize) {
+            self.lines.drain(at..at + count);
+            self.tracker.lines_removed(at, count);
+        }
+
+        /// The marks of all lines, as one character each.
+        fn marks(&mut self) -> String {
+            self.update();
+            marks_to_string(&self.tracker.gutter_marks(0..self.lines.len()))
+        }
+    }
+
+    fn marks_to_string(marks: &[GutterMark]) -> String {
+        marks
+            .iter()
+            .map(|m| match m {
+                GutterMark::Unchanged => '.',
+                GutterMark::Added => '+',
+                GutterMark::Modified => '~',
+                GutterMark::Deleted => '-',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_edits() {
+        let mut doc = Doc::new(&["a", "b", "c", "d", "e"]);
+        assert_eq!(doc.marks(), ".....");
+
+        doc.set_line(1, "B");
+        assert_eq!(doc.marks(), ".~...");
+
+        doc.insert_lines(3, &["x", "y"]);
+        assert_eq!(doc.marks(), ".~.++..");
+
+        doc.remove_lines(6, 1);
+        assert_eq!(doc.marks(), ".~.++-");
+
+        // Removing the line before a modified one merges the two into one modification.
+        doc.remove_lines(0, 1);
+        assert_eq!(doc.marks(), "~.++-");
+        assert_eq!(doc.tracker.gutter_marks(2..4), [GutterMark::Added, GutterMark::Added]);
+    }
+
+    #[test]
+    fn test_deletions() {
+        let mut doc = Doc::new(&["a", "b", "c", "d"]);
+        doc.remove_lines(1, 2);
+        assert_eq!(doc.marks(), ".-");
+
+        // A deletion at the end marks the last line, unless it's already marked otherwise.
+        let mut doc = Doc::new(&["a", "b", "c"]);
+        doc.remove_lines(2, 1);
+        assert_eq!(doc.marks(), ".-");
+        doc.set_line(1, "B");
+        assert_eq!(doc.marks(), ".~");
+
+        doc.remove_lines(0, 2);
+        assert_eq!(doc.marks(), "");
+        assert!(!doc.tracker.is_clean());
+    }
+
+    #[test]
+    fn test_back_to_clean() {
+        let mut doc = Doc::new(&["a", "b", "c"]);
+        doc.set_line(1, "changed");
+        doc.insert_lines(0, &["new"]);
+        doc.remove_lines(3, 1);
+        // The deletion at the end is hidden by the modification of the last line.
+        assert_eq!(doc.marks(), "+.~");
+
+        // Reverting the edits in a different order restores the clean state.
+        doc.remove_lines(0, 1);
+        doc.insert_lines(2, &["c"]);
+        assert_eq!(doc.marks(), ".~.");
+        doc.set_line(1, "b");
+        assert_eq!(doc.marks(), "...");
+        assert!(doc.tracker.is_clean());
+
+        // After saving, the current state is the baseline.
+        doc.set_line(0, "saved");
+        let lines = &doc.lines;
+        doc.tracker.reset_baseline(|i, buf| buf.extend_from_slice(lines[i].as_bytes()));
+        assert!(doc.tracker.is_clean());
+        doc.set_line(0, "a");
+        assert_eq!(doc.marks(), "~..");
+    }
+
+    #[test]
+    fn test_random_edits() {
+        let mut state = 0x2545F4914F6CDD1Du64;
+        let mut next = |max: usize| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % max as u64) as usize
+        };
+
+        let baseline: Vec<String> = (0..40).map(|i| format!("line {i}")).collect();
+        let baseline: Vec<&str> = baseline.iter().map(|s| s.as_str()).collect();
+        let mut doc = Doc::new(&baseline);
+        // Every new line is unique, which makes the diff unambiguous.
+        let mut counter = 0;
+
+        for _ in 0..300 {
+            // Sometimes, several edits happen between updates.
+            for _ in 0..1 + next(3) {
+                let len = doc.lines.len();
+                counter += 1;
+                let text = format!("new {counter}");
+                match next(3) {
+                    0 if len > 0 => doc.set_line(next(len), &text),
+                    1 if len > 0 => {
+                        let at = next(len);
+                        doc.remove_lines(at, 1 + next((len - at).min(3)));
+                    }
+                    _ => {
+                        let second = format!("{text}b");
+                        let lines = [text.as_str(), second.as_str()];
+                        doc.insert_lines(next(len + 1), &lines[..1 + next(2)]);
+                    }
+                }
+            }
+
+            // Diffing everything at once must yield the same result.
+            let mut full = Doc::new(&baseline);
+            full.remove_lines(0, baseline.len());
+            let lines: Vec<&str> = doc.lines.iter().map(|s| s.as_str()).collect();
+            full.insert_lines(0, &lines);
+            assert_eq!(doc.marks(), full.marks());
+            assert_eq!(doc.tracker.hunks, full.tracker.hunks);
+        }
+    }
+}
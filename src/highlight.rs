@@ -0,0 +1,579 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Line-based syntax highlighting with an incremental cache.
+//!
+//! Each line is tokenized on its own by a [`Tokenizer`], given the state at the end of the
+//! previous line. After an edit, [`HighlightCache::update`] only needs to re-highlight lines
+//! until the state at the end of a line matches the one it had before the edit.
+//!
+//! Languages are described by a [`Language`], which is plain data.
+
+use std::mem;
+use std::ops::Range;
+
+use crate::mem_report::{MemoryReport, MemoryUsage};
+
+/// The kind of highlighting applied to a [`Span`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum StyleId {
+    Keyword,
+    String,
+    Comment,
+    Number,
+}
+
+/// A highlighted byte range within a line.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Span {
+    pub range: Range<u32>,
+    pub style: StyleId,
+}
+
+/// The state of a [`Tokenizer`] at the end of a line.
+///
+/// Its meaning is private to the tokenizer that produced it,
+/// but it can be stored and restored via its raw value.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TokenizerState(u32);
+
+impl TokenizerState {
+    pub const fn from_raw(raw: u32) -> Self {
+        Self(raw)
+    }
+
+    pub const fn into_raw(self) -> u32 {
+        self.0
+    }
+}
+
+/// Splits lines of text into highlighted spans.
+pub trait Tokenizer {
+    /// Tokenizes a single line of `text` (without its newline),
+    /// given the `enter` state at the end of the previous line.
+    /// Returns the spans and the state at the end of this line.
+    fn line(&self, text: &[u8], enter: TokenizerState) -> (Vec<Span>, TokenizerState);
+}
+
+/// A tokenizer that doesn't highlight anything.
+pub struct PlainTokenizer;
+
+impl Tokenizer for PlainTokenizer {
+    fn line(&self, _text: &[u8], enter: TokenizerState) -> (Vec<Span>, TokenizerState) {
+        (Vec::new(), enter)
+    }
+}
+
+/// The state of the [`Language`] tokenizer.
+#[derive(Default, Clone, Copy, PartialEq, Eq, Debug)]
+enum LexState {
+    #[default]
+    Normal,
+    /// Inside a string literal that was started with the given quote.
+    InString(u8),
+    InBlockComment,
+}
+
+impl From<TokenizerState> for LexState {
+    fn from(state: TokenizerState) -> Self {
+        match state.0 {
+            1 => Self::InBlockComment,
+            s if s & 0x100 != 0 => Self::InString(s as u8),
+            _ => Self::Normal,
+        }
+    }
+}
+
+impl From<LexState> for TokenizerState {
+    fn from(state: LexState) -> Self {
+        match state {
+            LexState::Normal => Self(0),
+            LexState::InBlockComment => Self(1),
+            LexState::InString(quote) => Self(0x100 | quote as u32),
+        }
+    }
+}
+
+/// A description of a language, as needed to highlight it.
+/// It's a [`Tokenizer`] in its own right.
+#[derive(Default, Clone, Copy)]
+pub struct Language<'a> {
+    pub keywords: &'a [&'a str],
+    /// The prefix that starts a line comment, e.g. `"//"`. Empty if there's none.
+    pub line_comment: &'a str,
+    /// The delimiters of block comments, e.g. `("/*", "*/")`.
+    pub block_comment: Option<(&'a str, &'a str)>,
+    /// The ASCII characters that start and end a string literal, e.g. `"\"'"`.
+    pub quotes: &'a str,
+    /// The character that escapes the next one within a string, e.g. `b'\\'`.
+    pub escape: Option<u8>,
+    /// Whether string literals may continue on the next line.
+    pub multiline_strings: bool,
+    /// Numbers start with a digit and continue over alphanumerics, `_` and these characters, e.g. `"."`.
+    pub number_chars: &'a str,
+}
+
+impl Tokenizer for Language<'_> {
+    fn line(&self, text: &[u8], enter: TokenizerState) -> (Vec<Span>, TokenizerState) {
+        self.line_with_keywords(text, enter, &|word| {
+            self.keywords.iter().any(|kw| kw.as_bytes() == word)
+        })
+    }
+}
+
+impl Language<'_> {
+    /// Like [`Tokenizer::line`], but `is_keyword` decides what's a keyword instead of `self.keywords`.
+    /// This allows owners of larger keyword lists to use a faster lookup.
+    pub(crate) fn line_with_keywords(
+        &self,
+        text: &[u8],
+        enter: TokenizerState,
+        is_keyword: &dyn Fn(&[u8]) -> bool,
+    ) -> (Vec<Span>, TokenizerState) {
+        let (spans, exit) = self.lex_line(text, enter.into(), is_keyword);
+        (spans, exit.into())
+    }
+
+    fn lex_line(
+        &self,
+        text: &[u8],
+        state: LexState,
+        is_keyword: &dyn Fn(&[u8]) -> bool,
+    ) -> (Vec<Span>, LexState) {
+        let mut spans = Vec::new();
+        let mut state = state;
+        let mut i = 0;
+
+        while i < text.len() {
+            let beg = i;
+
+            match state {
+                LexState::InBlockComment => {
+                    let end = self.block_comment.map_or("", |(_, end)| end).as_bytes();
+                    match find(text, i, end) {
+                        Some(off) => {
+                            i = off + end.len();
+                            state = LexState::Normal;
+                        }
+                        None => i = text.len(),
+                    }
+                    push_span(&mut spans, beg..i, StyleId::Comment);
+                    continue;
+                }
+                LexState::InString(quote) => {
+                    while i < text.len() {
+                        let c = text[i];
+                        i += 1;
+                        if Some(c) == self.escape {
+                            i += 1;
+                        } else if c == quote {
+                            state = LexState::Normal;
+                            break;
+                        }
+                    }
+                    i = i.min(text.len());
+                    push_span(&mut spans, beg..i, StyleId::String);
+                    continue;
+                }
+                LexState::Normal => {}
+            }
+
+            let c = text[i];
+            let rest = &text[i..];
+
+            if !self.line_comment.is_empty() && rest.starts_with(self.line_comment.as_bytes()) {
+                push_span(&mut spans, i..text.len(), StyleId::Comment);
+                break;
+            }
+
+            if let Some((open, _)) = self.block_comment
+                && rest.starts_with(open.as_bytes())
+            {
+                // Skip the opening delimiter, so that `/*/` isn't treated as closed.
+                i += open.len();
+                state = LexState::InBlockComment;
+                push_span(&mut spans, beg..i, StyleId::Comment);
+                continue;
+            }
+
+            if self.quotes.as_bytes().contains(&c) {
+                i += 1;
+                state = LexState::InString(c);
+                push_span(&mut spans, beg..i, StyleId::String);
+                continue;
+            }
+
+            if c.is_ascii_digit() {
+                let number_chars = self.number_chars.as_bytes();
+                i += rest
+                    .iter()
+                    .position(|c| !is_word_char(*c) && !number_chars.contains(c))
+                    .unwrap_or(rest.len());
+                push_span(&mut spans, beg..i, StyleId::Number);
+                continue;
+            }
+
+            if is_word_char(c) {
+                i += rest.iter().position(|&c| !is_word_char(c)).unwrap_or(rest.len());
+                let word = &text[beg..i];
+                if is_keyword(word) {
+                    push_span(&mut spans, beg..i, StyleId::Keyword);
+                }
+                continue;
+            }
+
+            i += 1;
+        }
+
+        if let LexState::InString(_) = state
+            && !self.multiline_strings
+        {
+            state = LexState::Normal;
+        }
+
+        (spans, state)
+    }
+}
+
+/// Adds a span, merging it with the previous one if they're adjacent and of the same style.
+fn push_span(spans: &mut Vec<Span>, range: Range<usize>, style: StyleId) {
+    if let Some(last) = spans.last_mut()
+        && last.style == style
+        && last.range.end as usize == range.start
+    {
+        last.range.end = range.end as u32;
+    } else {
+        spans.push(Span { range: range.start as u32..range.end as u32, style });
+    }
+}
+
+fn find(haystack: &[u8], beg: usize, needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+    haystack[beg..].windows(needle.len()).position(|w| w == needle).map(|i| beg + i)
+}
+
+fn is_word_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+#[derive(Default, Clone)]
+struct CachedLine {
+    spans: Vec<Span>,
+    /// The state at the end of the previous line, when this line was highlighted.
+    enter: TokenizerState,
+    /// The state at the end of this line.
+    exit: TokenizerState,
+    dirty: bool,
+}
+
+/// Caches the highlighting of each line of a document.
+///
+/// The owner is expected to report all edits via [`HighlightCache::line_changed`],
+/// [`HighlightCache::lines_inserted`] and [`HighlightCache::lines_removed`],
+/// and to call [`HighlightCache::update`] before reading the spans.
+#[derive(Default)]
+pub struct HighlightCache {
+    lines: Vec<CachedLine>,
+    /// All lines before this one are known to be clean.
+    first_dirty: usize,
+    dirty_count: usize,
+}
+
+impl HighlightCache {
+    /// Creates a cache for a document with the given number of lines.
+    pub fn new(line_count: usize) -> Self {
+        let mut cache = Self::default();
+        cache.lines_inserted(0, line_count);
+        cache
+    }
+
+    /// The number of lines in the cache.
+    pub fn line_count(&self) -> usize {
+        self.lines.len()
+    }
+
+    /// Returns the spans of the given line, as of the last [`HighlightCache::update`].
+    pub fn spans_for_line(&self, line: usize) -> &[Span] {
+        self.lines.get(line).map_or(&[], |l| &l.spans[..])
+    }
+
+    /// Marks the given line as needing to be re-highlighted.
+    pub fn line_changed(&mut self, line: usize) {
+        if let Some(l) = self.lines.get_mut(line)
+            && !l.dirty
+        {
+            l.dirty = true;
+            self.dirty_count += 1;
+            self.first_dirty = self.first_dirty.min(line);
+        }
+    }
+
+    /// Inserts `count` new lines before the line `at`.
+    pub fn lines_inserted(&mut self, at: usize, count: usize) {
+        let at = at.min(self.lines.len());
+        let line = CachedLine { dirty: true, ..Default::default() };
+        self.lines.splice(at..at, std::iter::repeat_n(line, count));
+        self.dirty_count += count;
+        self.first_dirty = self.first_dirty.min(at);
+    }
+
+    /// Removes the lines `at..at + count`.
+    pub fn lines_removed(&mut self, at: usize, count: usize) {
+        let at = at.min(self.lines.len());
+        let end = (at + count).min(self.lines.len());
+        self.dirty_count -= self.lines[at..end].iter().filter(|l| l.dirty).count();
+        self.lines.drain(at..end);
+        // The line that now follows the removed ones has a different predecessor.
+        // It must be dirty, or `update` may stop before reaching it.
+        self.first_dirty = self.first_dirty.min(at);
+        self.line_changed(at);
+    }
+
+    /// Re-highlights all lines that need it. `text` is called with a line index
+    /// and needs to append the contents of that line (without newline) to the given `Vec`.
+    ///
+    /// Returns the number of lines that were highlighted.
+    pub fn update<T: Tokenizer + ?Sized>(
+        &mut self,
+        tokenizer: &T,
+        mut text: impl FnMut(usize, &mut Vec<u8>),
+    ) -> usize {
+        let mut buf = Vec::new();
+        let mut highlighted = 0;
+        let mut line = self.first_dirty;
+
+        while line < self.lines.len() {
+            let enter =
+                if line == 0 { TokenizerState::default() } else { self.lines[line - 1].exit };
+            let cached = &self.lines[line];
+
+            if !cached.dirty && cached.enter == enter {
+                if self.dirty_count == 0 {
+                    break;
+                }
+                line += 1;
+                continue;
+            }
+
+            buf.clear();
+            text(line, &mut buf);
+            let (spans, exit) = tokenizer.line(&buf, enter);
+
+            let cached = &mut self.lines[line];
+            if cached.dirty {
+                self.dirty_count -= 1;
+            }
+            *cached = CachedLine { spans, enter, exit, dirty: false };
+            highlighted += 1;
+            line += 1;
+        }
+
+        self.first_dirty = self.lines.len();
+        highlighted
+    }
+}
+
+impl MemoryUsage for HighlightCache {
+    fn memory_usage(&self) -> MemoryReport {
+        let (used, spare) = self.lines.iter().fold((0, 0), |(used, spare), l| {
+            let size = mem::size_of::<Span>();
+            (used + l.spans.len() * size, spare + (l.spans.capacity() - l.spans.len()) * size)
+        });
+        MemoryReport::node(
+            "HighlightCache",
+            vec![
+                self.lines.memory_usage().named("lines"),
+                MemoryReport::leaf("spans", used, spare),
+            ],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const C_LIKE: Language = Language {
+        keywords: &["if", "else", "return"],
+        line_comment: "//",
+        block_comment: Some(("/*", "*/")),
+        quotes: "\"'",
+        escape: Some(b'\\'),
+        multiline_strings: false,
+        number_chars: ".",
+    };
+    const TOML_LIKE: Language = Language {
+        keywords: &["true", "false"],
+        line_comment: "#",
+        block_comment: None,
+        quotes: "\"'",
+        escape: Some(b'\\'),
+        multiline_strings: true,
+        number_chars: ".:+-",
+    };
+
+    fn lex(lang: &Language, text: &[u8], state: LexState) -> (Vec<Span>, LexState) {
+        lang.lex_line(text, state, &|word| lang.keywords.iter().any(|kw| kw.as_bytes() == word))
+    }
+
+    fn span(range: Range<u32>, style: StyleId) -> Span {
+        Span { range, style }
+    }
+
+    #[test]
+    fn test_highlight_line() {
+        let (spans, state) =
+            lex(&C_LIKE, br#"if x { return "a\"b" + 12; } // c"#, LexState::Normal);
+        assert_eq!(
+            spans,
+            [
+                span(0..2, StyleId::Keyword),
+                span(7..13, StyleId::Keyword),
+                span(14..20, StyleId::String),
+                span(23..25, StyleId::Number),
+                span(29..33, StyleId::Comment),
+            ]
+        );
+        assert_eq!(state, LexState::Normal);
+
+        let (spans, state) = lex(&C_LIKE, b"a /*/ b", LexState::Normal);
+        assert_eq!(spans, [span(2..7, StyleId::Comment)]);
+        assert_eq!(state, LexState::InBlockComment);
+
+        let (spans, state) = lex(&C_LIKE, b"b */ if", LexState::InBlockComment);
+        assert_eq!(spans, [span(0..4, StyleId::Comment), span(5..7, StyleId::Keyword)]);
+        assert_eq!(state, LexState::Normal);
+
+        // Unterminated strings end with the line...
+        let (_, state) = lex(&C_LIKE, b"\"abc", LexState::Normal);
+        assert_eq!(state, LexState::Normal);
+        // ...unless the language allows otherwise.
+        let lang = Language { multiline_strings: true, ..C_LIKE };
+        let (_, state) = lex(&lang, b"\"abc", LexState::Normal);
+        assert_eq!(state, LexState::InString(b'"'));
+    }
+
+    #[test]
+    fn test_block_comment_edit() {
+        let mut lines: Vec<&[u8]> = vec![b"a", b"/* x", b"if", b"if", b"*/ if", b"if"];
+        let mut cache = HighlightCache::new(lines.len());
+        assert_eq!(cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(lines[i])), 6);
+        assert_eq!(cache.spans_for_line(2), [span(0..2, StyleId::Comment)]);
+        assert_eq!(cache.spans_for_line(5), [span(0..2, StyleId::Keyword)]);
+
+        // Removing the comment start re-highlights the lines up to the end of the comment.
+        lines[1] = b"x";
+        cache.line_changed(1);
+        assert_eq!(cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(lines[i])), 4);
+        assert_eq!(cache.spans_for_line(2), [span(0..2, StyleId::Keyword)]);
+        assert_eq!(cache.spans_for_line(5), [span(0..2, StyleId::Keyword)]);
+
+        // Nothing changed, nothing to do.
+        assert_eq!(cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(lines[i])), 0);
+    }
+
+    #[test]
+    fn test_bounded_recomputation() {
+        let mut lines: Vec<Vec<u8>> =
+            (0..10000).map(|i| format!("if {i} // x").into_bytes()).collect();
+        let mut cache = HighlightCache::new(lines.len());
+        assert_eq!(cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(&lines[i])), 10000);
+
+        lines[5000] = b"return".to_vec();
+        cache.line_changed(5000);
+        assert_eq!(cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(&lines[i])), 1);
+        assert_eq!(cache.spans_for_line(5000), [span(0..6, StyleId::Keyword)]);
+
+        // An unclosed comment affects all following lines.
+        lines[5000] = b"/*".to_vec();
+        cache.line_changed(5000);
+        assert_eq!(cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(&lines[i])), 5000);
+    }
+
+    #[test]
+    fn test_insert_and_remove_lines() {
+        let mut lines: Vec<&[u8]> = vec![b"if", b"/*", b"x", b"*/", b"if"];
+        let mut cache = HighlightCache::new(lines.len());
+        cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(lines[i]));
+
+        // Inserting a line within the comment only highlights the new line.
+        lines.insert(2, b"if");
+        cache.lines_inserted(2, 1);
+        assert_eq!(cache.line_count(), 6);
+        assert_eq!(cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(lines[i])), 1);
+        assert_eq!(cache.spans_for_line(2), [span(0..2, StyleId::Comment)]);
+        assert_eq!(cache.spans_for_line(5), [span(0..2, StyleId::Keyword)]);
+
+        // Removing the comment start shifts the following lines up and re-highlights them.
+        lines.remove(1);
+        cache.lines_removed(1, 1);
+        assert_eq!(cache.line_count(), 5);
+        assert_eq!(cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(lines[i])), 3);
+        assert_eq!(cache.spans_for_line(1), [span(0..2, StyleId::Keyword)]);
+        assert!(cache.spans_for_line(3).is_empty());
+        assert_eq!(cache.spans_for_line(4), [span(0..2, StyleId::Keyword)]);
+    }
+
+    #[test]
+    fn test_remove_lines_twice() {
+        let mut lines: Vec<&[u8]> = vec![b"a", b"/*", b"if", b"*/"];
+        lines.extend(std::iter::repeat_n(&b"x"[..], 16));
+        lines.extend([&b"/*"[..], b"if", b"*/", b"if"]);
+        let mut cache = HighlightCache::new(lines.len());
+        cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(lines[i]));
+        assert_eq!(cache.spans_for_line(21), [span(0..2, StyleId::Comment)]);
+
+        // Both comment starts are removed before the next update. The update must not stop
+        // after the first one settled, since the lines after the second one changed too.
+        lines.remove(20);
+        cache.lines_removed(20, 1);
+        lines.remove(1);
+        cache.lines_removed(1, 1);
+        assert_eq!(cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(lines[i])), 4);
+        assert_eq!(cache.spans_for_line(1), [span(0..2, StyleId::Keyword)]);
+        assert_eq!(cache.spans_for_line(19), [span(0..2, StyleId::Keyword)]);
+    }
+
+    #[test]
+    fn test_toml_like() {
+        let (spans, state) =
+            TOML_LIKE.line(b"date = 1979-05-27T07:32:00 # x", TokenizerState::default());
+        assert_eq!(spans, [span(7..26, StyleId::Number), span(27..30, StyleId::Comment)]);
+        assert_eq!(state, TokenizerState::default());
+
+        // Strings continue across lines, and the state says so.
+        let lines: Vec<&[u8]> = vec![b"a = \"x # y", b"true\" # z", b"b = true"];
+        let mut cache = HighlightCache::new(lines.len());
+        assert_eq!(cache.update(&TOML_LIKE, |i, buf| buf.extend_from_slice(lines[i])), 3);
+        assert_eq!(cache.spans_for_line(0), [span(4..10, StyleId::String)]);
+        assert_eq!(
+            cache.spans_for_line(1),
+            [span(0..5, StyleId::String), span(6..9, StyleId::Comment)]
+        );
+        assert_eq!(cache.spans_for_line(2), [span(4..8, StyleId::Keyword)]);
+
+        // The same lines mean something else in another language.
+        let mut cache = HighlightCache::new(lines.len());
+        cache.update(&C_LIKE, |i, buf| buf.extend_from_slice(lines[i]));
+        assert_eq!(cache.spans_for_line(1), [span(4..9, StyleId::String)]);
+    }
+
+    #[test]
+    fn test_plain_tokenizer() {
+        let lines: Vec<&[u8]> = vec![b"/* if", b"if"];
+        let mut cache = HighlightCache::new(lines.len());
+        assert_eq!(cache.update(&PlainTokenizer, |i, buf| buf.extend_from_slice(lines[i])), 2);
+        assert!(cache.spans_for_line(0).is_empty());
+        assert!(cache.spans_for_line(1).is_empty());
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        for state in [LexState::Normal, LexState::InBlockComment, LexState::InString(b'\'')] {
+            let raw = TokenizerState::from(state).into_raw();
+            assert_eq!(LexState::from(TokenizerState::from_raw(raw)), state);
+        }
+    }
+}
@@ -0,0 +1,355 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Remembers what was entered into the prompts (search, goto, ...) across sessions.
+//!
+//! Each prompt kind has its own [`PromptHistory`]. Up and Down recall older and newer entries
+//! that start with what the user typed. The history file is a line-based text file:
+//! ```text
+//! edit-history 1
+//! [search]
+//! 1700000000000000000 foo
+//! [goto]
+//! 1700000000000000000 42:7
+//! ```
+//! Each entry is prefixed with the time it was last used. Several instances of the editor
+//! may write the file concurrently, so saving merges with what's on disk,
+//! with the more recent use of an entry winning.
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{apperr, state_file};
+
+const HEADER: &str = "edit-history";
+const VERSION: u32 = 1;
+
+/// A previously entered text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub text: String,
+    /// When it was last entered, in nanoseconds since the UNIX epoch.
+    pub timestamp: u64,
+}
+
+/// The history of a single prompt kind, without duplicates and bounded in size.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptHistory {
+    /// Sorted oldest first.
+    entries: VecDeque<HistoryEntry>,
+    capacity: usize,
+    recall: Option<Recall>,
+}
+
+/// An ongoing Up/Down recall.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Recall {
+    /// What the user typed before the recall started.
+    prefix: String,
+    /// The entry that was recalled last.
+    index: usize,
+}
+
+impl PromptHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self { entries: VecDeque::new(), capacity: capacity.max(1), recall: None }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entries, oldest first.
+    pub fn entries(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> {
+        self.entries.iter()
+    }
+
+    /// Remembers `text` as the most recent entry.
+    pub fn push(&mut self, text: &str) {
+        self.push_at(text, now());
+    }
+
+    /// Remembers `text` as entered at `timestamp`, replacing an existing equal entry.
+    pub fn push_at(&mut self, text: &str, timestamp: u64) {
+        self.recall = None;
+        if text.is_empty() {
+            return;
+        }
+
+        let entry = match self.entries.iter().position(|e| e.text == text) {
+            Some(i) => {
+                let mut entry = self.entries.remove(i).unwrap();
+                entry.timestamp = entry.timestamp.max(timestamp);
+                entry
+            }
+            None => HistoryEntry { text: text.to_string(), timestamp },
+        };
+
+        // Keep the entries sorted, even if the clock went backwards.
+        let i = self.entries.partition_point(|e| e.timestamp <= entry.timestamp);
+        self.entries.insert(i, entry);
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Returns the next older entry that starts with what the user typed, for the Up key.
+    ///
+    /// `text` is the current contents of the prompt. If it differs from the entry
+    /// that was recalled last, the user edited it and the recall starts over with it as the prefix.
+    pub fn recall_prev(&mut self, text: &str) -> Option<&str> {
+        let r = self
+            .current_recall(text)
+            .unwrap_or_else(|| Recall { prefix: text.to_string(), index: self.entries.len() });
+
+        match self.entries.range(..r.index).rposition(|e| e.text.starts_with(&r.prefix)) {
+            Some(index) => {
+                self.recall = Some(Recall { prefix: r.prefix, index });
+                Some(&self.entries[index].text)
+            }
+            None => {
+                self.recall = Some(r);
+                None
+            }
+        }
+    }
+
+    /// Returns the next newer entry that starts with what the user typed, for the Down key.
+    ///
+    /// Past the newest one, this returns what the user originally typed.
+    /// Returns `None` if there's nothing newer to go to.
+    pub fn recall_next(&mut self, text: &str) -> Option<&str> {
+        let r = self.current_recall(text)?;
+        if r.index >= self.entries.len() {
+            self.recall = Some(r);
+            return None;
+        }
+
+        let index = self
+            .entries
+            .range(r.index + 1..)
+            .position(|e| e.text.starts_with(&r.prefix))
+            .map_or(self.entries.len(), |i| r.index + 1 + i);
+        let r = self.recall.insert(Recall { prefix: r.prefix, index });
+        Some(match self.entries.get(r.index) {
+            Some(e) => &e.text,
+            None => &r.prefix,
+        })
+    }
+
+    /// Returns the ongoing recall, unless the user edited the recalled text since.
+    fn current_recall(&mut self, text: &str) -> Option<Recall> {
+        self.recall.take().filter(|r| match self.entries.get(r.index) {
+            Some(e) => e.text == text,
+            None => r.prefix == text,
+        })
+    }
+
+    /// Ends the recall, for instance when the prompt is closed.
+    pub fn reset_recall(&mut self) {
+        self.recall = None;
+    }
+
+    /// Merges the entries of `other` into this one. For entries in both,
+    /// the more recent timestamp wins. The oldest entries are dropped if there are too many.
+    pub fn merge(&mut self, other: &PromptHistory) {
+        for e in &other.entries {
+            self.push_at(&e.text, e.timestamp);
+        }
+    }
+}
+
+/// The histories of all prompt kinds.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptHistories {
+    kinds: Vec<(String, PromptHistory)>,
+    capacity: usize,
+}
+
+impl PromptHistories {
+    /// Creates an empty set of histories, each holding up to `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        Self { kinds: Vec::new(), capacity }
+    }
+
+    pub fn get(&self, kind: &str) -> Option<&PromptHistory> {
+        self.kinds.iter().find(|(k, _)| k == kind).map(|(_, h)| h)
+    }
+
+    /// Returns the history for `kind`, creating it if needed.
+    pub fn get_mut(&mut self, kind: &str) -> &mut PromptHistory {
+        let i = match self.kinds.iter().position(|(k, _)| k == kind) {
+            Some(i) => i,
+            None => {
+                self.kinds.push((kind.to_string(), PromptHistory::new(self.capacity)));
+                self.kinds.len() - 1
+            }
+        };
+        &mut self.kinds[i].1
+    }
+
+    /// Merges all histories of `other` into these. See [`PromptHistory::merge`].
+    pub fn merge(&mut self, other: &PromptHistories) {
+        for (kind, history) in &other.kinds {
+            self.get_mut(kind).merge(history);
+        }
+    }
+
+    /// Reads the histories from the file at `path`.
+    ///
+    /// Malformed lines are skipped. Fails with [`apperr::APP_HISTORY_INVALID`]
+    /// if the file isn't a history file at all.
+    pub fn load(path: &Path, capacity: usize) -> apperr::Result<Self> {
+        let text = state_file::read(path, HEADER, apperr::APP_HISTORY_INVALID)?;
+        let mut histories = Self::new(capacity);
+        let mut kind = None;
+
+        for line in state_file::lines(&text) {
+            if let Some(k) = line.strip_prefix(b"[").and_then(|l| l.strip_suffix(b"]")) {
+                kind = Some(state_file::unescape_str(k));
+                continue;
+            }
+
+            let Some(kind) = &kind else {
+                continue;
+            };
+            let Some(i) = line.iter().position(|&c| c == b' ') else {
+                continue;
+            };
+            let Some(timestamp) = state_file::parse(&line[..i]) else {
+                continue;
+            };
+            histories.get_mut(kind).push_at(&state_file::unescape_str(&line[i + 1..]), timestamp);
+        }
+
+        Ok(histories)
+    }
+
+    /// Writes the histories to the file at `path`, merged with what another instance
+    /// of the editor may have written there in the meantime. The merge result is kept.
+    pub fn save(&mut self, path: &Path) -> apperr::Result<()> {
+        if let Ok(on_disk) = Self::load(path, self.capacity) {
+            self.merge(&on_disk);
+        }
+
+        let mut out = Vec::new();
+        for (kind, history) in &self.kinds {
+            out.push(b'[');
+            state_file::escape(&mut out, kind.as_bytes());
+            out.extend_from_slice(b"]\n");
+            for e in &history.entries {
+                out.extend_from_slice(format!("{} ", e.timestamp).as_bytes());
+                state_file::escape(&mut out, e.text.as_bytes());
+                out.push(b'\n');
+            }
+        }
+
+        state_file::write(path, HEADER, VERSION, &out)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn history(entries: &[&str]) -> PromptHistory {
+        let mut h = PromptHistory::new(10);
+        for (i, e) in entries.iter().enumerate() {
+            h.push_at(e, i as u64);
+        }
+        h
+    }
+
+    fn texts(h: &PromptHistory) -> Vec<&str> {
+        h.entries().map(|e| e.text.as_str()).collect()
+    }
+
+    #[test]
+    fn test_recall_prefix() {
+        let mut h = history(&["foo", "bar", "food", "baz", "fox"]);
+
+        assert_eq!(h.recall_prev("fo"), Some("fox"));
+        assert_eq!(h.recall_prev("fox"), Some("food"));
+        assert_eq!(h.recall_prev("food"), Some("foo"));
+        // There's nothing older, so it stays put.
+        assert_eq!(h.recall_prev("foo"), None);
+        assert_eq!(h.recall_prev("foo"), None);
+
+        assert_eq!(h.recall_next("foo"), Some("food"));
+        assert_eq!(h.recall_next("food"), Some("fox"));
+        // Past the newest entry we're back at what was typed.
+        assert_eq!(h.recall_next("fox"), Some("fo"));
+        assert_eq!(h.recall_next("fo"), None);
+
+        // An empty prefix matches everything.
+        assert_eq!(h.recall_prev(""), Some("fox"));
+        assert_eq!(h.recall_prev("fox"), Some("baz"));
+    }
+
+    #[test]
+    fn test_recall_reset_on_edit() {
+        let mut h = history(&["bar", "foo", "baz"]);
+
+        assert_eq!(h.recall_prev("b"), Some("baz"));
+        // The user edited the recalled text, so "ba" is the new prefix.
+        assert_eq!(h.recall_prev("ba"), Some("baz"));
+        assert_eq!(h.recall_prev("baz"), Some("bar"));
+
+        // Entering something ends the recall.
+        h.push_at("qux", 10);
+        assert_eq!(h.recall_next("bar"), None);
+        assert_eq!(h.recall_prev("bar"), Some("bar"));
+    }
+
+    #[test]
+    fn test_bound_and_dedup() {
+        let mut h = PromptHistory::new(3);
+        for (i, e) in ["a", "b", "a", "c", "d"].iter().enumerate() {
+            h.push_at(e, i as u64);
+        }
+        assert_eq!(texts(&h), ["a", "c", "d"]);
+
+        h.push_at("", 10);
+        assert_eq!(h.len(), 3);
+    }
+
+    #[test]
+    fn test_persist_merge() {
+        let dir = TempDir::new("history");
+        let path = dir.join("merge");
+
+        // Two instances, started with the same history, each add their own entries.
+        let mut first = PromptHistories::new(10);
+        first.get_mut("search").push_at("shared", 1);
+        first.get_mut("search").push_at("first", 3);
+        first.get_mut("goto").push_at("12:3\nx", 4);
+
+        let mut second = PromptHistories::new(10);
+        second.get_mut("search").push_at("second", 2);
+        second.get_mut("search").push_at("shared", 5);
+
+        first.save(&path).unwrap();
+        second.save(&path).unwrap();
+
+        let loaded = PromptHistories::load(&path, 10).unwrap();
+        assert_eq!(loaded, second);
+        // "shared" was used last by the second instance, so it's the newest entry.
+        assert_eq!(texts(loaded.get("search").unwrap()), ["second", "first", "shared"]);
+        assert_eq!(texts(loaded.get("goto").unwrap()), ["12:3\nx"]);
+
+        fs::write(&path, "something else\n").unwrap();
+        assert_eq!(PromptHistories::load(&path, 10), Err(apperr::APP_HISTORY_INVALID));
+    }
+}
@@ -0,0 +1,76 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The line syntax shared by the settings and the language definitions.
+//!
+//! ```text
+//! ; A comment.
+//! [section]
+//! key = value
+//! ```
+//!
+//! A `;` only starts a comment at the beginning of a line, so that values may contain it,
+//! e.g. `line_comment = ;`. Leading and trailing whitespace is ignored everywhere.
+//! What sections and keys mean is up to the caller.
+
+/// A line of the text given to [`lines`], without blank lines and comments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line<'a> {
+    /// `[name]`. The name is trimmed and may be empty.
+    /// `None` if the closing bracket is missing.
+    Section(Option<&'a str>),
+    /// `key = value`, both trimmed. The value is everything after the first `=`.
+    Pair(&'a str, &'a str),
+    /// Anything else.
+    Invalid,
+}
+
+/// Splits `text` into its meaningful lines.
+/// Yields their 1-based line number, their trimmed text, and what they are.
+pub fn lines(text: &str) -> impl Iterator<Item = (usize, &str, Line<'_>)> {
+    text.lines().enumerate().filter_map(|(i, line)| {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            return None;
+        }
+
+        let kind = if let Some(header) = line.strip_prefix('[') {
+            Line::Section(header.strip_suffix(']').map(str::trim))
+        } else if let Some((key, value)) = line.split_once('=') {
+            Line::Pair(key.trim(), value.trim())
+        } else {
+            Line::Invalid
+        };
+        Some((i + 1, line, kind))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let text = "; comment\n\
+                    \n  \
+                    [ lang.c ]\n\
+                    [broken\n\
+                    line_comment = ;\n\
+                    keywords = a b ; c\n\
+                    \t; indented comment\n\
+                    no value\n\
+                    key = a = b\n";
+        let lines: Vec<_> = lines(text).map(|(line, _, kind)| (line, kind)).collect();
+        assert_eq!(
+            lines,
+            [
+                (3, Line::Section(Some("lang.c"))),
+                (4, Line::Section(None)),
+                (5, Line::Pair("line_comment", ";")),
+                (6, Line::Pair("keywords", "a b ; c")),
+                (8, Line::Invalid),
+                (9, Line::Pair("key", "a = b")),
+            ]
+        );
+    }
+}
@@ -584,3 +584,139 @@ impl<'input> Stream<'_, '_, 'input> {
         modifiers
     }
 }
+
+/// Prepares pasted text for insertion into a document.
+///
+/// Text copied from a terminal's scrollback may contain escape sequences, which would
+/// wreck our rendering, or worse, be used to hide parts of the pasted text from the user.
+/// This removes all escape sequences (including truncated ones at the end, and those
+/// introduced by 8-bit C1 controls like U+009B), all C0 and C1 control characters except
+/// for tabs and newlines, and normalizes CR and CRLF to LF. Invalid UTF-8 is kept as is.
+pub fn sanitize_paste(text: &[u8]) -> Vec<u8> {
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum State {
+        Ground,
+        Esc,
+        /// After intermediate bytes of an escape sequence, e.g. the `(` of `ESC ( B`.
+        EscIntermediate,
+        Csi,
+        /// OSC, DCS, SOS, PM and APC, which end at ST (or BEL, for OSC).
+        String,
+        StringEsc,
+    }
+
+    let mut res = Vec::with_capacity(text.len());
+    let mut state = State::Ground;
+    let mut prev_cr = false;
+    let mut i = 0;
+
+    while i < text.len() {
+        let mut b = text[i];
+        i += 1;
+
+        // C1 controls are encoded as U+0080 to U+009F, which is C2 80 to C2 9F in UTF-8.
+        // Treat them as their 7-bit equivalent, e.g. U+009B as `ESC [`.
+        if b == 0xC2 && i < text.len() && (0x80..=0x9F).contains(&text[i]) {
+            b = text[i] - 0x40;
+            i += 1;
+            state = match state {
+                State::String | State::StringEsc => State::StringEsc,
+                _ => State::Esc,
+            };
+        }
+
+        let cr = state == State::Ground && b == b'\r';
+        state = match state {
+            State::Ground => {
+                match b {
+                    0x1B => {}
+                    b'\t' => res.push(b'\t'),
+                    b'\r' => res.push(b'\n'),
+                    // The \n of a \r\n was already written when we saw the \r.
+                    b'\n' if !prev_cr => res.push(b'\n'),
+                    0x00..0x20 | 0x7F => {}
+                    _ => res.push(b),
+                }
+                if b == 0x1B { State::Esc } else { State::Ground }
+            }
+            State::Esc => match b {
+                b'[' => State::Csi,
+                b']' | b'P' | b'X' | b'^' | b'_' => State::String,
+                0x20..0x30 => State::EscIntermediate,
+                0x1B => State::Esc,
+                _ => State::Ground,
+            },
+            State::EscIntermediate => match b {
+                0x20..0x30 => State::EscIntermediate,
+                _ => State::Ground,
+            },
+            State::Csi => match b {
+                0x40..0x7F => State::Ground,
+                0x1B => State::Esc,
+                _ => State::Csi,
+            },
+            State::String => match b {
+                0x07 => State::Ground,
+                0x1B => State::StringEsc,
+                _ => State::String,
+            },
+            // ST is `ESC \`. Anything else aborts the string and starts a new sequence.
+            State::StringEsc => match b {
+                b'\\' => State::Ground,
+                b'[' => State::Csi,
+                b']' | b'P' | b'X' | b'^' | b'_' => State::String,
+                _ => State::Ground,
+            },
+        };
+
+        prev_cr = cr;
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sanitize(text: &[u8]) -> String {
+        String::from_utf8(sanitize_paste(text)).unwrap()
+    }
+
+    #[test]
+    fn test_sanitize_clean() {
+        let text = "fn main() {\n\tprintln!(\"Grüße, 世界\");\n}\n";
+        assert_eq!(sanitize(text.as_bytes()), text);
+        assert_eq!(sanitize(b"a\r\nb\rc"), "a\nb\nc");
+    }
+
+    #[test]
+    fn test_sanitize_sequences() {
+        // CSI (colors, cursor movement), OSC (title, hyperlinks) with BEL and ST terminators.
+        let text = b"\x1b[1;31mred\x1b[0m \x1b[2Ahidden\x1b]0;title\x07 \x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\";
+        assert_eq!(sanitize(text), "red hidden link");
+        // Character set designation.
+        assert_eq!(sanitize(b"a\x1b(Bb"), "ab");
+        // C1 controls, including the 8-bit CSI and OSC with its parameters.
+        assert_eq!(sanitize("a\u{9b}31mb\u{85}c".as_bytes()), "abc");
+        assert_eq!(sanitize("a\u{9d}0;title\u{9c}b".as_bytes()), "ab");
+    }
+
+    #[test]
+    fn test_sanitize_controls() {
+        assert_eq!(sanitize(b"a\0b\x08c\x7fd\x07"), "abcd");
+    }
+
+    #[test]
+    fn test_sanitize_invalid_utf8() {
+        assert_eq!(sanitize_paste(b"a\xff\x1b[31mb\xc2"), b"a\xffb\xc2");
+    }
+
+    #[test]
+    fn test_sanitize_truncated() {
+        assert_eq!(sanitize(b"abc\x1b"), "abc");
+        assert_eq!(sanitize(b"abc\x1b[31"), "abc");
+        assert_eq!(sanitize(b"abc\x1b]0;tit"), "abc");
+        assert_eq!(sanitize(b"abc\x1b]0;title\x1b"), "abc");
+    }
+}
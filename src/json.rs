@@ -0,0 +1,539 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Pretty-printing and minifying JSON.
+//!
+//! The formatter only tokenizes and validates the input. It doesn't build a tree or convert
+//! numbers and strings, so their contents are copied byte for byte.
+//! [`FormatJson`] runs it over a document as a [`BufferTransform`].
+
+use std::cell::Cell;
+use std::fmt;
+use std::ops::Range;
+
+use crate::buffer::{BufferTransform, TextEdit};
+
+/// Malformed JSON. The position is that of the offending byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JsonError {
+    pub message: &'static str,
+    /// Byte offset into the text that was passed in.
+    pub offset: usize,
+    /// 1-based line number.
+    pub line: usize,
+    /// 1-based column in characters.
+    pub column: usize,
+}
+
+impl JsonError {
+    fn new(text: &[u8], offset: usize, message: &'static str) -> Self {
+        let line_start = text[..offset].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let line = text[..line_start].iter().filter(|&&b| b == b'\n').count() + 1;
+        // Count the bytes that aren't UTF-8 continuation bytes.
+        let column = text[line_start..offset].iter().filter(|&&b| (b & 0xc0) != 0x80).count() + 1;
+        Self { message, offset, line, column }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at line {}, column {}", self.message, self.line, self.column)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    BeginObject,
+    EndObject,
+    BeginArray,
+    EndArray,
+    Colon,
+    Comma,
+    /// Includes the quotes.
+    String,
+    Number,
+    True,
+    False,
+    Null,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub range: Range<usize>,
+}
+
+/// Splits JSON into [`Token`]s, skipping whitespace.
+/// After the first error, the iterator returns `None`.
+pub struct Tokenizer<'a> {
+    text: &'a [u8],
+    off: usize,
+    end: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn new(text: &'a [u8]) -> Self {
+        Self::with_range(text, 0..text.len())
+    }
+
+    /// Tokenizes only `range` of `text`. Error positions still refer to all of `text`.
+    pub fn with_range(text: &'a [u8], range: Range<usize>) -> Self {
+        Self { text, off: range.start, end: range.end.min(text.len()) }
+    }
+
+    fn error(&mut self, offset: usize, message: &'static str) -> Option<Result<Token, JsonError>> {
+        self.off = self.end;
+        Some(Err(JsonError::new(self.text, offset, message)))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        if self.off < self.end { Some(self.text[self.off]) } else { None }
+    }
+
+    /// Skips over a run of at least one digit. Returns `false` if there's none.
+    fn digits(&mut self) -> bool {
+        let beg = self.off;
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.off += 1;
+        }
+        self.off > beg
+    }
+
+    fn string(&mut self, beg: usize) -> Option<Result<Token, JsonError>> {
+        loop {
+            let Some(b) = self.peek() else {
+                return self.error(beg, "unterminated string");
+            };
+            self.off += 1;
+            match b {
+                b'"' => return Some(Ok(Token { kind: TokenKind::String, range: beg..self.off })),
+                b'\\' => match self.peek() {
+                    Some(b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't') => self.off += 1,
+                    Some(b'u')
+                        if self.off + 5 <= self.end
+                            && self.text[self.off + 1..self.off + 5]
+                                .iter()
+                                .all(u8::is_ascii_hexdigit) =>
+                    {
+                        self.off += 5
+                    }
+                    _ => return self.error(self.off - 1, "invalid escape sequence"),
+                },
+                0..0x20 => return self.error(self.off - 1, "control character in string"),
+                _ => {}
+            }
+        }
+    }
+
+    fn number(&mut self, beg: usize) -> Option<Result<Token, JsonError>> {
+        if self.peek() == Some(b'-') {
+            self.off += 1;
+        }
+        // Leading zeros aren't allowed.
+        if self.peek() == Some(b'0') {
+            self.off += 1;
+        } else if !self.digits() {
+            return self.error(self.off, "invalid number");
+        }
+        if self.peek() == Some(b'.') {
+            self.off += 1;
+            if !self.digits() {
+                return self.error(self.off, "invalid number");
+            }
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.off += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.off += 1;
+            }
+            if !self.digits() {
+                return self.error(self.off, "invalid number");
+            }
+        }
+        if self.peek().is_some_and(|b| b.is_ascii_alphanumeric() || b == b'.') {
+            return self.error(self.off, "invalid number");
+        }
+        Some(Ok(Token { kind: TokenKind::Number, range: beg..self.off }))
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Result<Token, JsonError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.peek().is_some_and(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r')) {
+            self.off += 1;
+        }
+
+        let beg = self.off;
+        let kind = match self.peek()? {
+            b'{' => TokenKind::BeginObject,
+            b'}' => TokenKind::EndObject,
+            b'[' => TokenKind::BeginArray,
+            b']' => TokenKind::EndArray,
+            b':' => TokenKind::Colon,
+            b',' => TokenKind::Comma,
+            b'"' => {
+                self.off += 1;
+                return self.string(beg);
+            }
+            b'-' | b'0'..=b'9' => return self.number(beg),
+            b'a'..=b'z' => {
+                while self.peek().is_some_and(|b| b.is_ascii_alphanumeric()) {
+                    self.off += 1;
+                }
+                return match &self.text[beg..self.off] {
+                    b"true" => Some(Ok(Token { kind: TokenKind::True, range: beg..self.off })),
+                    b"false" => Some(Ok(Token { kind: TokenKind::False, range: beg..self.off })),
+                    b"null" => Some(Ok(Token { kind: TokenKind::Null, range: beg..self.off })),
+                    _ => self.error(beg, "invalid literal"),
+                };
+            }
+            _ => return self.error(beg, "unexpected character"),
+        };
+        self.off += 1;
+        Some(Ok(Token { kind, range: beg..self.off }))
+    }
+}
+
+/// How [`format`] lays out its output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonStyle {
+    /// No whitespace at all.
+    Minified,
+    /// One value per line, indented by `indent` spaces, or by tabs if `tabs` is set.
+    /// Empty objects and arrays are kept on one line.
+    Pretty { indent: usize, tabs: bool },
+}
+
+/// Pretty-prints or minifies `text`, which must be a single JSON value.
+/// The output uses LF newlines and has no trailing newline.
+pub fn format(text: &[u8], style: JsonStyle) -> Result<Vec<u8>, JsonError> {
+    let mut out = Vec::with_capacity(text.len());
+    format_into(&mut out, text, 0..text.len(), style, b"\n", b"")?;
+    Ok(out)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Expect {
+    /// At the start of the input, or after a colon or a comma in an array.
+    Value,
+    /// After a `[`.
+    ValueOrEnd,
+    /// After a comma in an object.
+    Key,
+    /// After a `{`.
+    KeyOrEnd,
+    Colon,
+    CommaOrEnd,
+    Done,
+}
+
+/// Formats `range` of `text` into `out`. Each new line starts with `newline` and `base_indent`.
+fn format_into(
+    out: &mut Vec<u8>,
+    text: &[u8],
+    range: Range<usize>,
+    style: JsonStyle,
+    newline: &[u8],
+    base_indent: &[u8],
+) -> Result<(), JsonError> {
+    let end = range.end;
+    // The open objects (`{`) and arrays (`[`).
+    let mut stack = Vec::new();
+    let mut expect = Expect::Value;
+
+    let new_line = |out: &mut Vec<u8>, depth: usize| {
+        if let JsonStyle::Pretty { indent, tabs } = style {
+            out.extend_from_slice(newline);
+            out.extend_from_slice(base_indent);
+            if tabs {
+                out.extend(std::iter::repeat_n(b'\t', depth));
+            } else {
+                out.extend(std::iter::repeat_n(b' ', depth * indent));
+            }
+        }
+    };
+
+    for token in Tokenizer::with_range(text, range) {
+        let token = token?;
+        let closing = match stack.last() {
+            Some(b'{') => TokenKind::EndObject,
+            _ => TokenKind::EndArray,
+        };
+
+        // Empty objects and arrays.
+        if (expect == Expect::ValueOrEnd || expect == Expect::KeyOrEnd) && token.kind == closing {
+            stack.pop();
+            out.extend_from_slice(&text[token.range]);
+            expect = if stack.is_empty() { Expect::Done } else { Expect::CommaOrEnd };
+            continue;
+        }
+        if expect == Expect::ValueOrEnd || expect == Expect::KeyOrEnd {
+            new_line(out, stack.len());
+        }
+
+        expect = match (expect, token.kind) {
+            (Expect::Value | Expect::ValueOrEnd, TokenKind::BeginObject) => {
+                stack.push(b'{');
+                Expect::KeyOrEnd
+            }
+            (Expect::Value | Expect::ValueOrEnd, TokenKind::BeginArray) => {
+                stack.push(b'[');
+                Expect::ValueOrEnd
+            }
+            (
+                Expect::Value | Expect::ValueOrEnd,
+                TokenKind::String
+                | TokenKind::Number
+                | TokenKind::True
+                | TokenKind::False
+                | TokenKind::Null,
+            ) => {
+                if stack.is_empty() {
+                    Expect::Done
+                } else {
+                    Expect::CommaOrEnd
+                }
+            }
+            (Expect::Key | Expect::KeyOrEnd, TokenKind::String) => Expect::Colon,
+            (Expect::Colon, TokenKind::Colon) => {
+                out.push(b':');
+                if matches!(style, JsonStyle::Pretty { .. }) {
+                    out.push(b' ');
+                }
+                Expect::Value
+            }
+            (Expect::CommaOrEnd, TokenKind::Comma) => {
+                out.push(b',');
+                new_line(out, stack.len());
+                if closing == TokenKind::EndObject { Expect::Key } else { Expect::Value }
+            }
+            (Expect::CommaOrEnd, kind) if kind == closing => {
+                stack.pop();
+                new_line(out, stack.len());
+                if stack.is_empty() { Expect::Done } else { Expect::CommaOrEnd }
+            }
+            (expect, _) => {
+                let message = match expect {
+                    Expect::Value | Expect::ValueOrEnd => "expected a value",
+                    Expect::Key | Expect::KeyOrEnd => "expected a string",
+                    Expect::Colon => "expected ':'",
+                    Expect::CommaOrEnd if closing == TokenKind::EndObject => "expected ',' or '}'",
+                    Expect::CommaOrEnd => "expected ',' or ']'",
+                    Expect::Done => "unexpected data after the value",
+                };
+                return Err(JsonError::new(text, token.range.start, message));
+            }
+        };
+
+        // Colons and commas were already written above, along with their whitespace.
+        if !matches!(token.kind, TokenKind::Colon | TokenKind::Comma) {
+            out.extend_from_slice(&text[token.range]);
+        }
+    }
+
+    match expect {
+        Expect::Done => Ok(()),
+        Expect::Value if stack.is_empty() => Err(JsonError::new(text, end, "expected a value")),
+        _ => Err(JsonError::new(text, end, "unexpected end of input")),
+    }
+}
+
+/// Pretty-prints or minifies the JSON value in `range` of a document, or in all of it.
+///
+/// Whitespace around the value is kept. When pretty-printing, new lines are indented like the
+/// line the value starts on. If the value is malformed, no edits are made and the error can
+/// be retrieved with [`FormatJson::error`]. Its position refers to the whole document.
+pub struct FormatJson {
+    pub range: Option<Range<usize>>,
+    pub style: JsonStyle,
+    pub crlf: bool,
+    error: Cell<Option<JsonError>>,
+}
+
+impl FormatJson {
+    pub fn new(range: Option<Range<usize>>, style: JsonStyle, crlf: bool) -> Self {
+        Self { range, style, crlf, error: Cell::new(None) }
+    }
+
+    /// The error of the last [`BufferTransform::apply`], if the input was malformed.
+    pub fn error(&self) -> Option<JsonError> {
+        self.error.get()
+    }
+}
+
+impl BufferTransform for FormatJson {
+    fn name(&self) -> &str {
+        match self.style {
+            JsonStyle::Minified => "minify JSON",
+            JsonStyle::Pretty { .. } => "pretty-print JSON",
+        }
+    }
+
+    fn apply(&self, text: &[u8]) -> Vec<TextEdit> {
+        let range = self.range.clone().unwrap_or(0..text.len());
+        let end = range.end.min(text.len());
+        let beg = range.start.min(end);
+
+        let is_space = |b: &u8| matches!(b, b' ' | b'\t' | b'\n' | b'\r');
+        let beg = text[beg..end].iter().position(|b| !is_space(b)).map_or(end, |i| beg + i);
+        let end = text[beg..end].iter().rposition(|b| !is_space(b)).map_or(beg, |i| beg + i + 1);
+
+        let line_start = text[..beg].iter().rposition(|&b| b == b'\n').map_or(0, |i| i + 1);
+        let indent_len =
+            text[line_start..beg].iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+        let base_indent = &text[line_start..line_start + indent_len];
+        let newline: &[u8] = if self.crlf { b"\r\n" } else { b"\n" };
+
+        let mut out = Vec::with_capacity(end - beg);
+        match format_into(&mut out, text, beg..end, self.style, newline, base_indent) {
+            Ok(()) => {
+                self.error.set(None);
+                vec![TextEdit { range: beg..end, text: out }]
+            }
+            Err(err) => {
+                self.error.set(Some(err));
+                Vec::new()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::TextBuffer;
+
+    const PRETTY: JsonStyle = JsonStyle::Pretty { indent: 2, tabs: false };
+
+    fn pretty(text: &str) -> String {
+        String::from_utf8(format(text.as_bytes(), PRETTY).unwrap()).unwrap()
+    }
+
+    fn minify(text: &str) -> String {
+        String::from_utf8(format(text.as_bytes(), JsonStyle::Minified).unwrap()).unwrap()
+    }
+
+    fn error(text: &str) -> (&'static str, usize, usize, usize) {
+        let err = format(text.as_bytes(), PRETTY).unwrap_err();
+        (err.message, err.offset, err.line, err.column)
+    }
+
+    #[test]
+    fn test_nested() {
+        let input = r#"{"a":[1,2,{"b":null}],"c":{},"d":[],"e":{"f":[[true,false]]}}"#;
+        let expected = r#"{
+  "a": [
+    1,
+    2,
+    {
+      "b": null
+    }
+  ],
+  "c": {},
+  "d": [],
+  "e": {
+    "f": [
+      [
+        true,
+        false
+      ]
+    ]
+  }
+}"#;
+        assert_eq!(pretty(input), expected);
+        assert_eq!(minify(expected), input);
+
+        let tabs = format(b"[1,[2]]", JsonStyle::Pretty { indent: 2, tabs: true }).unwrap();
+        assert_eq!(tabs, b"[\n\t1,\n\t[\n\t\t2\n\t]\n]");
+
+        assert_eq!(pretty(" 42 "), "42");
+        assert_eq!(pretty("\"x\""), "\"x\"");
+    }
+
+    #[test]
+    fn test_contents_preserved() {
+        // Escapes, non-ASCII text and numbers are copied as they are.
+        let input = r#"["\u00e9\ud83d\ude00", "é 😀", "a\"b\\c\/\n", 1.50, -0, 1e+400, 0.1E-7]"#;
+        assert_eq!(
+            minify(input),
+            r#"["\u00e9\ud83d\ude00","é 😀","a\"b\\c\/\n",1.50,-0,1e+400,0.1E-7]"#
+        );
+        // Whitespace within strings isn't touched.
+        assert_eq!(minify(r#"{ "a b" : " c " }"#), r#"{"a b":" c "}"#);
+    }
+
+    #[test]
+    fn test_idempotent() {
+        let input = r#"{"x":[{"y":"[1, 2]"},{}],"z":{"w":[null]}}"#;
+        let once = pretty(input);
+        assert_eq!(pretty(&once), once);
+        assert_eq!(minify(&minify(input)), minify(input));
+        assert_eq!(minify(&once), input);
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert_eq!(error(""), ("expected a value", 0, 1, 1));
+        assert_eq!(error("[1, 2"), ("unexpected end of input", 5, 1, 6));
+        assert_eq!(error("{\n  \"a\": 1,\n  \"b\" 2\n}"), ("expected ':'", 18, 3, 7));
+        assert_eq!(error("[1, 2,]"), ("expected a value", 6, 1, 7));
+        assert_eq!(error("{\"a\": 1]"), ("expected ',' or '}'", 7, 1, 8));
+        assert_eq!(error("{1: 2}"), ("expected a string", 1, 1, 2));
+        assert_eq!(error("[01]"), ("invalid number", 2, 1, 3));
+        assert_eq!(error("[1.]"), ("invalid number", 3, 1, 4));
+        assert_eq!(error("[tru]"), ("invalid literal", 1, 1, 2));
+        assert_eq!(error("[\"é\\x\"]"), ("invalid escape sequence", 4, 1, 4));
+        assert_eq!(error("[\"\\u12\"]"), ("invalid escape sequence", 2, 1, 3));
+        assert_eq!(error("[\"a\nb\"]"), ("control character in string", 3, 1, 4));
+        assert_eq!(error("\"abc"), ("unterminated string", 0, 1, 1));
+        assert_eq!(error("{} []"), ("unexpected data after the value", 3, 1, 4));
+        assert_eq!(error("[1 # 2]"), ("unexpected character", 3, 1, 4));
+
+        let err = format("[\n\"ü\", x]".as_bytes(), PRETTY).unwrap_err();
+        assert_eq!(err.to_string(), "invalid literal at line 2, column 6");
+    }
+
+    #[test]
+    fn test_transform() {
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_crlf(false);
+        tb.write_raw(b"let x = {\"a\": [1, 2]};\n  y = [ ] ;\n");
+
+        // The range includes whitespace, which is kept as is.
+        let transform = FormatJson::new(Some(7..21), PRETTY, false);
+        tb.apply_transforms(&[&transform]).unwrap();
+        assert_eq!(transform.error(), None);
+
+        let mut s = String::new();
+        tb.save_as_string(&mut s);
+        assert_eq!(s, "let x = {\n  \"a\": [\n    1,\n    2\n  ]\n};\n  y = [ ] ;\n");
+
+        // A malformed value isn't touched, and the error refers to the whole document.
+        let transform = FormatJson::new(Some(7..18), PRETTY, false);
+        tb.apply_transforms(&[&transform]).unwrap();
+        let err = transform.error().unwrap();
+        assert_eq!(
+            (err.message, err.offset, err.line, err.column),
+            ("unexpected end of input", 18, 2, 9)
+        );
+
+        tb.undo();
+        s.clear();
+        tb.save_as_string(&mut s);
+        assert_eq!(s, "let x = {\"a\": [1, 2]};\n  y = [ ] ;\n");
+
+        // New lines are indented like the first one.
+        let mut tb = TextBuffer::new(true).unwrap();
+        tb.set_crlf(false);
+        tb.write_raw(b"  {\"a\":1}\n");
+        let transform = FormatJson::new(None, PRETTY, true);
+        tb.apply_transforms(&[&transform]).unwrap();
+        s.clear();
+        tb.save_as_string(&mut s);
+        assert_eq!(s, "  {\r\n    \"a\": 1\r\n  }\n");
+    }
+}
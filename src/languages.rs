@@ -0,0 +1,501 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Language definitions and the mapping from files to them.
+//!
+//! Definitions are written in a small INI-like format, see [`crate::ini`]. A section starts a new language
+//! and each line within it sets a key. Values that are lists are separated by whitespace.
+//!
+//! ```text
+//! ; A comment.
+//! [rust]
+//! extensions = rs
+//! keywords = fn let mut
+//! ; Lists accumulate over multiple lines.
+//! keywords = if else
+//! line_comment = //
+//! block_comment = /* */
+//! quotes = "
+//! escape = \
+//! indent_increase = {[(
+//! indent_decrease = }])
+//! ```
+//!
+//! The built-in definitions are loaded first. User definitions loaded afterwards
+//! replace built-in ones of the same name and take precedence in the file mapping.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::buffer::{AutoIndentConfig, LexicalConfig};
+use crate::helpers::CoordType;
+use crate::highlight::{Language, Span, Tokenizer, TokenizerState};
+use crate::ini;
+
+const BUILTIN_LANGUAGES: &str = r#"
+[c]
+extensions = c h cc cpp cxx hh hpp hxx
+keywords = auto break case char const continue default do double else enum extern float for goto if
+keywords = inline int long register return short signed sizeof static struct switch typedef union
+keywords = unsigned void volatile while class namespace template typename public private protected
+line_comment = //
+block_comment = /* */
+quotes = "'
+escape = \
+number_chars = .
+indent_increase = {[(
+indent_decrease = }])
+
+[rust]
+extensions = rs
+keywords = as async await break const continue crate dyn else enum extern false fn for if impl in
+keywords = let loop match mod move mut pub ref return self Self static struct super trait true type
+keywords = unsafe use where while
+line_comment = //
+block_comment = /* */
+quotes = "
+escape = \
+multiline_strings = true
+number_chars = .
+indent_increase = {[(
+indent_decrease = }])
+
+[python]
+extensions = py pyw
+interpreters = python python3
+keywords = False None True and as assert async await break class continue def del elif else except
+keywords = finally for from global if import in is lambda nonlocal not or pass raise return try while
+keywords = with yield
+line_comment = #
+quotes = "'
+escape = \
+number_chars = .
+indent_increase = :{[(
+indent_decrease = }])
+
+[shell]
+extensions = sh bash zsh
+filenames = .bashrc .bash_profile .profile .zshrc
+interpreters = sh bash zsh dash ksh
+keywords = if then else elif fi case esac for while until do done in function return local export
+line_comment = #
+quotes = "'
+escape = \
+multiline_strings = true
+indent_increase = {(
+
+[toml]
+extensions = toml
+filenames = Cargo.lock
+keywords = true false
+line_comment = #
+quotes = "'
+escape = \
+number_chars = .:+-
+indent_increase = [{
+indent_decrease = ]}
+
+[json]
+extensions = json jsonc
+keywords = true false null
+line_comment = //
+block_comment = /* */
+quotes = "
+escape = \
+number_chars = .+-
+indent_increase = {[
+indent_decrease = }]
+
+[makefile]
+extensions = mk
+filenames = Makefile makefile GNUmakefile
+keywords = ifeq ifneq ifdef ifndef else endif include define endef export
+line_comment = #
+
+[batch]
+extensions = bat cmd
+keywords = call cd cls do echo else endlocal errorlevel exist exit for goto if in not off on set
+keywords = setlocal shift
+line_comment = ::
+quotes = "
+escape = ^
+
+; Not a language as such, but files that must be saved exactly as they are, see save_policy.
+[binary]
+extensions = bin exe dll so o a obj lib class jar zip gz png jpg jpeg gif ico pdf
+"#;
+
+/// A problem found while loading language definitions.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The 1-based line number within the loaded text.
+    pub line: usize,
+    pub message: String,
+}
+
+/// A language, as loaded from its definition.
+#[derive(Default, Debug)]
+pub struct LanguageDefinition {
+    pub name: String,
+    pub extensions: Vec<String>,
+    pub filenames: Vec<String>,
+    /// The names of the interpreters that identify the language in a shebang line.
+    pub interpreters: Vec<String>,
+    /// Sorted, so that they can be binary searched.
+    pub keywords: Vec<String>,
+    pub line_comment: String,
+    pub block_comment: Option<(String, String)>,
+    pub quotes: String,
+    pub escape: Option<u8>,
+    pub multiline_strings: bool,
+    pub number_chars: String,
+    pub indent_increase: String,
+    pub indent_decrease: String,
+}
+
+impl LanguageDefinition {
+    /// Returns the settings needed for [`crate::buffer::compute_auto_indent`].
+    pub fn auto_indent_config(
+        &self,
+        tab_size: CoordType,
+        indent_with_tabs: bool,
+    ) -> AutoIndentConfig<'_> {
+        AutoIndentConfig {
+            tab_size,
+            indent_with_tabs,
+            increase_after: self.indent_increase.as_bytes(),
+            decrease_before: self.indent_decrease.as_bytes(),
+        }
+    }
+
+    /// Returns the settings needed for [`crate::buffer::find_matching_bracket`].
+    pub fn lexical_config(&self) -> LexicalConfig<'_> {
+        LexicalConfig {
+            line_comment: &self.line_comment,
+            quotes: &self.quotes,
+            escape: self.escape,
+        }
+    }
+
+    fn is_keyword(&self, word: &[u8]) -> bool {
+        self.keywords.binary_search_by(|kw| kw.as_bytes().cmp(word)).is_ok()
+    }
+}
+
+impl Tokenizer for LanguageDefinition {
+    fn line(&self, text: &[u8], enter: TokenizerState) -> (Vec<Span>, TokenizerState) {
+        let language = Language {
+            keywords: &[],
+            line_comment: &self.line_comment,
+            block_comment: self
+                .block_comment
+                .as_ref()
+                .map(|(beg, end)| (beg.as_str(), end.as_str())),
+            quotes: &self.quotes,
+            escape: self.escape,
+            multiline_strings: self.multiline_strings,
+            number_chars: &self.number_chars,
+        };
+        language.line_with_keywords(text, enter, &|word| self.is_keyword(word))
+    }
+}
+
+/// The set of known languages.
+pub struct Languages {
+    definitions: Vec<LanguageDefinition>,
+    /// Maps exact file names to indices into `definitions`.
+    by_filename: HashMap<String, usize>,
+    /// Maps lowercase extensions to indices into `definitions`.
+    by_extension: HashMap<String, usize>,
+    /// Maps interpreter names to indices into `definitions`.
+    by_interpreter: HashMap<String, usize>,
+}
+
+impl Languages {
+    /// Creates a set with only the built-in languages.
+    pub fn new() -> Self {
+        let mut languages = Self::empty();
+        let diagnostics = languages.load(BUILTIN_LANGUAGES);
+        debug_assert!(diagnostics.is_empty(), "{diagnostics:?}");
+        languages
+    }
+
+    fn empty() -> Self {
+        Self {
+            definitions: Vec::new(),
+            by_filename: HashMap::new(),
+            by_extension: HashMap::new(),
+            by_interpreter: HashMap::new(),
+        }
+    }
+
+    pub fn definitions(&self) -> &[LanguageDefinition] {
+        &self.definitions
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&LanguageDefinition> {
+        self.definitions.iter().find(|d| d.name == name)
+    }
+
+    /// Loads the definitions in `text`, replacing existing ones of the same name.
+    ///
+    /// Malformed lines are skipped and reported via the returned list.
+    /// A malformed section header skips the entire section.
+    pub fn load(&mut self, text: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut current: Option<LanguageDefinition> = None;
+        let mut skipping = false;
+
+        for (line_no, line, kind) in ini::lines(text) {
+            let mut error =
+                |message: String| diagnostics.push(Diagnostic { line: line_no, message });
+
+            let pair = match kind {
+                ini::Line::Pair(key, value) => Some((key, value)),
+                ini::Line::Invalid => None,
+                ini::Line::Section(name) => {
+                    if let Some(def) = current.take() {
+                        self.insert(def);
+                    }
+                    match name {
+                        Some(name) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+                            current = Some(LanguageDefinition {
+                                name: name.to_string(),
+                                ..Default::default()
+                            });
+                            skipping = false;
+                        }
+                        _ => {
+                            error(format!("invalid section header: {line}"));
+                            skipping = true;
+                        }
+                    }
+                    continue;
+                }
+            };
+
+            if skipping {
+                continue;
+            }
+            let Some(def) = current.as_mut() else {
+                error("expected a [section] before the first key".to_string());
+                skipping = true;
+                continue;
+            };
+            let Some((key, value)) = pair else {
+                error(format!("expected key = value: {line}"));
+                continue;
+            };
+            let list = || value.split_whitespace().map(str::to_string);
+
+            match key {
+                "extensions" => def
+                    .extensions
+                    .extend(list().map(|ext| ext.trim_start_matches('.').to_ascii_lowercase())),
+                "filenames" => def.filenames.extend(list()),
+                "interpreters" => def.interpreters.extend(list()),
+                "keywords" => def.keywords.extend(list()),
+                "line_comment" => def.line_comment = value.to_string(),
+                "block_comment" => match value.split_whitespace().collect::<Vec<_>>()[..] {
+                    [beg, end] => def.block_comment = Some((beg.to_string(), end.to_string())),
+                    _ => error(format!("block_comment needs exactly two delimiters: {value}")),
+                },
+                "quotes" => {
+                    if value.is_ascii() {
+                        def.quotes = value.to_string();
+                    } else {
+                        error(format!("quotes must be ASCII: {value}"));
+                    }
+                }
+                "escape" => match value.as_bytes() {
+                    [c] if c.is_ascii() => def.escape = Some(*c),
+                    [] => def.escape = None,
+                    _ => error(format!("escape must be a single ASCII character: {value}")),
+                },
+                "multiline_strings" => match value {
+                    "true" => def.multiline_strings = true,
+                    "false" => def.multiline_strings = false,
+                    _ => error(format!("expected true or false: {value}")),
+                },
+                "number_chars" => def.number_chars = value.to_string(),
+                "indent_increase" => def.indent_increase = value.to_string(),
+                "indent_decrease" => def.indent_decrease = value.to_string(),
+                _ => error(format!("unknown key: {key}")),
+            }
+        }
+
+        if let Some(def) = current.take() {
+            self.insert(def);
+        }
+        diagnostics
+    }
+
+    fn insert(&mut self, mut def: LanguageDefinition) {
+        def.keywords.sort_unstable();
+        def.keywords.dedup();
+
+        let idx = match self.definitions.iter().position(|d| d.name == def.name) {
+            Some(idx) => {
+                // Drop the mappings of the definition we're replacing.
+                for map in [&mut self.by_filename, &mut self.by_extension, &mut self.by_interpreter]
+                {
+                    map.retain(|_, &mut i| i != idx);
+                }
+                self.definitions[idx] = def;
+                idx
+            }
+            None => {
+                self.definitions.push(def);
+                self.definitions.len() - 1
+            }
+        };
+
+        let def = &self.definitions[idx];
+        for name in &def.filenames {
+            self.by_filename.insert(name.clone(), idx);
+        }
+        for ext in &def.extensions {
+            self.by_extension.insert(ext.clone(), idx);
+        }
+        for name in &def.interpreters {
+            self.by_interpreter.insert(name.clone(), idx);
+        }
+    }
+
+    /// Returns the language of the file at `path`.
+    ///
+    /// An exact file name match takes precedence over the extension,
+    /// which takes precedence over the shebang in the `first_line` of the file.
+    pub fn language_for_path(&self, path: &Path, first_line: &[u8]) -> Option<&LanguageDefinition> {
+        let file_name = path.file_name().and_then(|n| n.to_str());
+        let extension = path.extension().and_then(|e| e.to_str());
+
+        let idx = file_name
+            .and_then(|name| self.by_filename.get(name))
+            .or_else(|| extension.and_then(|ext| self.by_extension.get(&ext.to_ascii_lowercase())))
+            .or_else(|| shebang_interpreter(first_line).and_then(|name| self.interpreter(name)))?;
+        Some(&self.definitions[*idx])
+    }
+
+    fn interpreter(&self, name: &str) -> Option<&usize> {
+        // "python3.12" should still find "python3" or "python".
+        self.by_interpreter.get(name).or_else(|| {
+            let name = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+            self.by_interpreter.get(name)
+        })
+    }
+}
+
+/// Extracts the interpreter name from a shebang line, like `#!/usr/bin/env python3`.
+fn shebang_interpreter(first_line: &[u8]) -> Option<&str> {
+    let line = first_line.strip_prefix(b"#!")?;
+    let line = std::str::from_utf8(line).ok()?;
+    let mut args = line.split_whitespace();
+    let mut program = args.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = args.find(|arg| !arg.starts_with('-') && !arg.contains('='))?;
+    }
+    Some(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlight::StyleId;
+
+    fn name_for(languages: &Languages, path: &str, first_line: &str) -> Option<String> {
+        languages.language_for_path(Path::new(path), first_line.as_bytes()).map(|d| d.name.clone())
+    }
+
+    #[test]
+    fn test_builtin() {
+        let languages = Languages::new();
+        let rust = languages.by_name("rust").unwrap();
+        assert!(rust.keywords.is_sorted());
+        assert!(rust.is_keyword(b"fn"));
+        assert!(!rust.is_keyword(b"fun"));
+
+        let (spans, _) = rust.line(b"fn main() { 42 }", TokenizerState::default());
+        assert_eq!(spans.len(), 2);
+        assert_eq!(spans[0].style, StyleId::Keyword);
+        assert_eq!(spans[1].style, StyleId::Number);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let mut languages = Languages::new();
+        assert_eq!(name_for(&languages, "src/main.rs", ""), Some("rust".into()));
+        assert_eq!(name_for(&languages, "FOO.TOML", ""), Some("toml".into()));
+        assert_eq!(name_for(&languages, "Cargo.lock", ""), Some("toml".into()));
+        assert_eq!(name_for(&languages, "README", ""), None);
+        // The extension beats the shebang.
+        assert_eq!(name_for(&languages, "foo.rs", "#!/bin/sh"), Some("rust".into()));
+
+        // The exact file name beats the extension.
+        let diagnostics = languages.load("[special]\nfilenames = build.rs\n");
+        assert!(diagnostics.is_empty());
+        assert_eq!(name_for(&languages, "build.rs", ""), Some("special".into()));
+        assert_eq!(name_for(&languages, "lib.rs", ""), Some("rust".into()));
+
+        // User definitions replace built-in ones.
+        languages.load("[rust]\nextensions = rsx\n");
+        assert_eq!(name_for(&languages, "lib.rs", ""), None);
+        assert_eq!(name_for(&languages, "lib.rsx", ""), Some("rust".into()));
+        assert_eq!(languages.definitions().iter().filter(|d| d.name == "rust").count(), 1);
+    }
+
+    #[test]
+    fn test_shebang() {
+        let languages = Languages::new();
+        assert_eq!(name_for(&languages, "script", "#!/bin/bash"), Some("shell".into()));
+        assert_eq!(name_for(&languages, "script", "#!/usr/bin/env python3"), Some("python".into()));
+        assert_eq!(
+            name_for(&languages, "script", "#!/usr/bin/env -S python3.12 -u"),
+            Some("python".into())
+        );
+        assert_eq!(name_for(&languages, "script", "#!/usr/bin/perl"), None);
+        assert_eq!(name_for(&languages, "script", "# python"), None);
+    }
+
+    #[test]
+    fn test_malformed() {
+        let mut languages = Languages::new();
+        let count = languages.definitions().len();
+        let diagnostics = languages.load(
+            "orphan = 1\n\
+             [bad name]\n\
+             extensions = bad\n\
+             [ok]\n\
+             extensions = ok\n\
+             no equals sign\n\
+             block_comment = /*\n\
+             escape = ab\n\
+             colour = red\n\
+             multiline_strings = yes\n\
+             line_comment = --\n",
+        );
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.line).collect();
+        assert_eq!(lines, [1, 2, 6, 7, 8, 9, 10]);
+
+        // The valid parts were still loaded.
+        assert_eq!(languages.definitions().len(), count + 1);
+        assert_eq!(name_for(&languages, "x.bad", ""), None);
+        let ok = languages.by_name("ok").unwrap();
+        assert_eq!(name_for(&languages, "x.ok", ""), Some("ok".into()));
+        assert_eq!(ok.line_comment, "--");
+        assert_eq!(ok.block_comment, None);
+    }
+
+    #[test]
+    fn test_semicolon_value() {
+        let mut languages = Languages::new();
+        let diagnostics = languages.load(
+            "; Only a leading semicolon starts a comment.\n\
+             [asm]\n\
+             extensions = asm\n\
+             line_comment = ;\n",
+        );
+        assert!(diagnostics.is_empty());
+        assert_eq!(languages.by_name("asm").unwrap().line_comment, ";");
+    }
+}
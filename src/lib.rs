@@ -17,21 +17,49 @@
 pub mod arena;
 
 pub mod apperr;
+pub mod autosave;
+pub mod backup;
 pub mod base64;
 pub mod buffer;
 pub mod cell;
 pub mod clipboard;
+pub mod columns;
+pub mod config;
+pub mod content_hash;
+pub mod damage;
+pub mod diff;
 pub mod document;
+pub mod duplicates;
 pub mod framebuffer;
 pub mod fuzzy;
+pub mod gutter;
 pub mod hash;
 pub mod helpers;
+pub mod highlight;
+pub mod history;
 pub mod icu;
+pub mod ini;
 pub mod input;
+pub mod json;
+pub mod languages;
+pub mod links;
+pub mod mem_report;
 pub mod oklab;
 pub mod path;
+pub mod position;
+pub mod recent;
+pub mod save_policy;
+pub mod session;
 pub mod simd;
+pub mod spell;
+pub mod state_file;
+pub mod streaming;
+pub mod swap;
 pub mod sys;
+#[cfg(test)]
+mod testing;
 pub mod tui;
 pub mod unicode;
 pub mod vt;
+pub mod watcher;
+pub mod word_index;
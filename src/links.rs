@@ -0,0 +1,341 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Finds URLs and file paths in lines of text, for opening them and underlining them.
+//!
+//! Text is first split into tokens at whitespace and quotes. A token containing a
+//! `http://`, `https://` or `file://` URL yields that URL. Otherwise, a token that looks
+//! like a path, because it contains a path separator or ends in a `:line:column` suffix,
+//! yields a path, but only if the caller-supplied existence check agrees.
+//! That keeps prose like "either/or" from being underlined.
+
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::helpers::Point;
+use crate::position::parse_path_position;
+
+const SCHEMES: [&[u8]; 3] = [b"http://", b"https://", b"file://"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkTarget {
+    Url(String),
+    Path {
+        path: PathBuf,
+        /// The 0-based position of a `:line` or `:line:column` suffix.
+        position: Option<Point>,
+    },
+}
+
+/// A link within a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    /// The byte range within the line, including any `:line:column` suffix.
+    pub range: Range<usize>,
+    pub target: LinkTarget,
+}
+
+/// Returns the link at `offset` in `line`, which must not contain a newline.
+/// An offset right after the link counts, too, since that's where the cursor ends up
+/// after typing it. `exists` is called for paths. See the module documentation.
+pub fn link_at(line: &[u8], offset: usize, exists: &dyn Fn(&Path) -> bool) -> Option<Link> {
+    let offset = offset.min(line.len());
+    let beg = line[..offset].iter().rposition(|&b| is_separator(b)).map_or(0, |i| i + 1);
+    let end =
+        line[offset..].iter().position(|&b| is_separator(b)).map_or(line.len(), |i| offset + i);
+    let link = classify(line, beg..end, exists)?;
+    if link.range.start <= offset && offset <= link.range.end { Some(link) } else { None }
+}
+
+/// Returns all links in `line`, which must not contain a newline.
+pub fn find_links(line: &[u8], exists: &dyn Fn(&Path) -> bool) -> Vec<Link> {
+    let mut links = Vec::new();
+    let mut beg = 0;
+
+    while beg < line.len() {
+        let end = line[beg..].iter().position(|&b| is_separator(b)).map_or(line.len(), |i| beg + i);
+        if beg < end
+            && let Some(link) = classify(line, beg..end, exists)
+        {
+            links.push(link);
+        }
+        beg = end + 1;
+    }
+
+    links
+}
+
+fn is_separator(b: u8) -> bool {
+    matches!(b, b' ' | b'\t' | b'\r' | b'"' | b'\'' | b'`' | b'<' | b'>')
+}
+
+/// Returns the link within the token `line[range]`, if any.
+fn classify(line: &[u8], range: Range<usize>, exists: &dyn Fn(&Path) -> bool) -> Option<Link> {
+    let token = &line[range.clone()];
+
+    // Anything before the scheme, like the "[text](" of a Markdown link, isn't part of the URL.
+    if let Some(beg) = find_url_start(token) {
+        let end = beg + trim_trailing_punctuation(&token[beg..]);
+        let scheme_len = SCHEMES.iter().find(|s| starts_with_ignore_case(&token[beg..], s))?.len();
+        if end - beg <= scheme_len {
+            return None;
+        }
+        let url = String::from_utf8_lossy(&token[beg..end]).into_owned();
+        return Some(Link {
+            range: range.start + beg..range.start + end,
+            target: LinkTarget::Url(url),
+        });
+    }
+
+    let beg = token.iter().position(|&b| !matches!(b, b'(' | b'[' | b'{')).unwrap_or(token.len());
+    let end = beg + trim_trailing_punctuation(&token[beg..]);
+    if beg == end {
+        return None;
+    }
+
+    let text = String::from_utf8_lossy(&token[beg..end]);
+    let (path, position) = parse_path_position(Path::new(&*text));
+    let has_separator =
+        path.as_os_str().as_encoded_bytes().iter().any(|&b| b == b'/' || b == b'\\');
+    let has_extension = path.extension().is_some();
+    if !has_separator && !(position.is_some() && has_extension) {
+        return None;
+    }
+    if !exists(path) {
+        return None;
+    }
+
+    Some(Link {
+        range: range.start + beg..range.start + end,
+        target: LinkTarget::Path { path: path.to_path_buf(), position },
+    })
+}
+
+/// Returns the offset of the first URL scheme in `token`, which must start a word.
+fn find_url_start(token: &[u8]) -> Option<usize> {
+    (0..token.len()).find(|&i| {
+        (i == 0 || !token[i - 1].is_ascii_alphanumeric())
+            && SCHEMES.iter().any(|s| starts_with_ignore_case(&token[i..], s))
+    })
+}
+
+fn starts_with_ignore_case(text: &[u8], prefix: &[u8]) -> bool {
+    text.len() >= prefix.len() && text[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+/// Returns the length of `text` without punctuation that more likely ends the sentence
+/// than the link, such as a trailing period or an unbalanced closing parenthesis.
+fn trim_trailing_punctuation(text: &[u8]) -> usize {
+    let mut len = text.len();
+
+    while len > 0 {
+        let open = match text[len - 1] {
+            b'.' | b',' | b';' | b':' | b'!' | b'?' => {
+                len -= 1;
+                continue;
+            }
+            b')' => b'(',
+            b']' => b'[',
+            b'}' => b'{',
+            _ => break,
+        };
+        let close = text[len - 1];
+        let s = &text[..len];
+        let opened = s.iter().filter(|&&b| b == open).count();
+        let closed = s.iter().filter(|&&b| b == close).count();
+        if closed <= opened {
+            break;
+        }
+        len -= 1;
+    }
+
+    len
+}
+
+#[derive(Default, Clone)]
+struct CachedLine {
+    links: Vec<Link>,
+    dirty: bool,
+}
+
+/// Caches the links of each line of a document, for underlining them in the viewport.
+///
+/// Like the [`crate::highlight::HighlightCache`], the owner is expected to report all edits via
+/// [`LinkCache::line_changed`], [`LinkCache::lines_inserted`] and [`LinkCache::lines_removed`].
+/// Unlike it, lines are only scanned once they're visible, via [`LinkCache::update`].
+#[derive(Default)]
+pub struct LinkCache {
+    lines: Vec<CachedLine>,
+}
+
+impl LinkCache {
+    /// Creates a cache for a document with the given number of lines.
+    pub fn new(line_count: usize) -> Self {
+        let mut cache = Self::default();
+        cache.lines_inserted(0, line_count);
+        cache
+    }
+
+    /// Returns the links of the given line, as of the last [`LinkCache::update`] that covered it.
+    pub fn links_for_line(&self, line: usize) -> &[Link] {
+        self.lines.get(line).map_or(&[], |l| &l.links[..])
+    }
+
+    /// Marks the given line as needing to be scanned again.
+    pub fn line_changed(&mut self, line: usize) {
+        if let Some(l) = self.lines.get_mut(line) {
+            l.dirty = true;
+        }
+    }
+
+    /// Inserts `count` new lines before the line `at`.
+    pub fn lines_inserted(&mut self, at: usize, count: usize) {
+        let at = at.min(self.lines.len());
+        let line = CachedLine { links: Vec::new(), dirty: true };
+        self.lines.splice(at..at, std::iter::repeat_n(line, count));
+    }
+
+    /// Removes the lines `at..at + count`.
+    pub fn lines_removed(&mut self, at: usize, count: usize) {
+        let at = at.min(self.lines.len());
+        let end = (at + count).min(self.lines.len());
+        self.lines.drain(at..end);
+    }
+
+    /// Scans the lines in `range` that need it, usually those in the viewport.
+    /// `text` is called with a line index and needs to append the contents of that line
+    /// (without newline) to the given `Vec`.
+    ///
+    /// Returns the number of lines that were scanned.
+    pub fn update(
+        &mut self,
+        range: Range<usize>,
+        exists: &dyn Fn(&Path) -> bool,
+        mut text: impl FnMut(usize, &mut Vec<u8>),
+    ) -> usize {
+        let end = range.end.min(self.lines.len());
+        let beg = range.start.min(end);
+        let mut buf = Vec::new();
+        let mut scanned = 0;
+
+        for (i, line) in self.lines[beg..end].iter_mut().enumerate() {
+            if line.dirty {
+                buf.clear();
+                text(beg + i, &mut buf);
+                *line = CachedLine { links: find_links(&buf, exists), dirty: false };
+                scanned += 1;
+            }
+        }
+
+        scanned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exists(path: &Path) -> bool {
+        ["src/main.rs", "file.rs", "a/b", r"C:\Users\me\notes.txt", r"C:\src\lib.rs"]
+            .iter()
+            .any(|p| Path::new(p) == path)
+    }
+
+    fn url(line: &str, offset: usize) -> Option<&str> {
+        let link = link_at(line.as_bytes(), offset, &exists)?;
+        assert!(matches!(link.target, LinkTarget::Url(_)));
+        Some(&line[link.range])
+    }
+
+    fn path(line: &str, offset: usize) -> Option<(String, Option<Point>, &str)> {
+        let link = link_at(line.as_bytes(), offset, &exists)?;
+        match link.target {
+            LinkTarget::Path { path, position } => {
+                Some((path.to_string_lossy().into_owned(), position, &line[link.range]))
+            }
+            LinkTarget::Url(_) => panic!("expected a path"),
+        }
+    }
+
+    #[test]
+    fn test_urls() {
+        let line = "See https://example.com/search?q=a+b&lang=en#top. Or not.";
+        assert_eq!(url(line, 10), Some("https://example.com/search?q=a+b&lang=en#top"));
+        assert_eq!(url(line, 4), Some("https://example.com/search?q=a+b&lang=en#top"));
+        assert_eq!(url(line, 3), None);
+        assert_eq!(url(line, 53), None);
+
+        assert_eq!(url("(at HTTP://x.org/a,b), done", 10), Some("HTTP://x.org/a,b"));
+        assert_eq!(url("file:///tmp/x.txt", 0), Some("file:///tmp/x.txt"));
+        assert_eq!(url("<http://a.b/c>", 5), Some("http://a.b/c"));
+        assert_eq!(url("https://", 2), None);
+        assert_eq!(url("xhttp://a.b", 2), None);
+    }
+
+    #[test]
+    fn test_markdown_links() {
+        let line = "[docs](https://en.wikipedia.org/wiki/Rust_(language)) and [x](http://a.b).";
+        assert_eq!(url(line, 10), Some("https://en.wikipedia.org/wiki/Rust_(language)"));
+        assert_eq!(url(line, 66), Some("http://a.b"));
+        // The link text isn't part of the URL.
+        assert_eq!(url(line, 2), None);
+    }
+
+    #[test]
+    fn test_paths() {
+        assert_eq!(
+            path("open src/main.rs now", 8),
+            Some(("src/main.rs".into(), None, "src/main.rs"))
+        );
+        assert_eq!(
+            path("error at file.rs:10:3: oops", 12),
+            Some(("file.rs".into(), Some(Point { x: 2, y: 9 }), "file.rs:10:3"))
+        );
+        assert_eq!(
+            path("(see src/main.rs:7)", 8),
+            Some(("src/main.rs".into(), Some(Point { x: 0, y: 6 }), "src/main.rs:7"))
+        );
+
+        // Only existing paths are links.
+        assert_eq!(path("either/or", 3), None);
+        assert_eq!(path("a/b", 1), Some(("a/b".into(), None, "a/b")));
+        // Words without separators aren't even checked.
+        assert_eq!(path("file.rs", 2), None);
+    }
+
+    #[test]
+    fn test_windows_paths() {
+        assert_eq!(
+            path(r#"copied to "C:\Users\me\notes.txt"."#, 15),
+            Some((r"C:\Users\me\notes.txt".into(), None, r"C:\Users\me\notes.txt"))
+        );
+        assert_eq!(
+            path(r"C:\src\lib.rs:12:5: warning", 3),
+            Some((r"C:\src\lib.rs".into(), Some(Point { x: 4, y: 11 }), r"C:\src\lib.rs:12:5"))
+        );
+    }
+
+    #[test]
+    fn test_cache() {
+        let mut lines = vec!["see http://a.b", "nothing here", "src/main.rs:1"];
+        let mut cache = LinkCache::new(lines.len());
+
+        let text = |i: usize, buf: &mut Vec<u8>| buf.extend_from_slice(lines[i].as_bytes());
+        assert_eq!(cache.update(0..2, &exists, text), 2);
+        assert_eq!(cache.links_for_line(0).len(), 1);
+        assert!(cache.links_for_line(2).is_empty());
+
+        // Only lines that weren't scanned yet are scanned.
+        assert_eq!(cache.update(0..3, &exists, text), 1);
+        assert_eq!(cache.links_for_line(2)[0].range, 0..13);
+
+        lines.remove(0);
+        lines[0] = "at http://x.y";
+        cache.lines_removed(0, 1);
+        cache.line_changed(0);
+        let text = |i: usize, buf: &mut Vec<u8>| buf.extend_from_slice(lines[i].as_bytes());
+        assert_eq!(cache.update(0..10, &exists, text), 1);
+        assert_eq!(cache.links_for_line(0)[0].range, 3..13);
+        assert_eq!(cache.links_for_line(1)[0].range, 0..13);
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Reports how much memory the editor's data structures use.
+//!
+//! Each structure reports the bytes its live data occupies separately from the capacity
+//! it has allocated but doesn't use. A large amount of the latter points at
+//! structures that would benefit from being shrunk.
+
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::mem;
+
+use crate::arena::ArenaString;
+
+/// A node in a memory usage tree.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryReport {
+    pub name: &'static str,
+    /// Bytes occupied by live data, not counting the children.
+    pub used: usize,
+    /// Bytes allocated but unused, not counting the children.
+    pub spare: usize,
+    pub children: Vec<MemoryReport>,
+}
+
+/// Implemented by types that can report their memory usage.
+pub trait MemoryUsage {
+    fn memory_usage(&self) -> MemoryReport;
+}
+
+impl MemoryReport {
+    pub fn leaf(name: &'static str, used: usize, spare: usize) -> Self {
+        Self { name, used, spare, children: Vec::new() }
+    }
+
+    pub fn node(name: &'static str, children: Vec<MemoryReport>) -> Self {
+        Self { name, used: 0, spare: 0, children }
+    }
+
+    /// Changes the name of the node. Handy for naming the report of a field.
+    pub fn named(mut self, name: &'static str) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// The bytes used by this node and all of its children.
+    pub fn total_used(&self) -> usize {
+        self.used + self.children.iter().map(|c| c.total_used()).sum::<usize>()
+    }
+
+    /// The bytes allocated but unused by this node and all of its children.
+    pub fn total_spare(&self) -> usize {
+        self.spare + self.children.iter().map(|c| c.total_spare()).sum::<usize>()
+    }
+
+    /// Renders the tree as an indented table, one node per line, with totals for each node.
+    pub fn render(&self, out: &mut ArenaString) {
+        self.render_impl(out, 0);
+    }
+
+    fn render_impl(&self, out: &mut ArenaString, depth: usize) {
+        let indent = depth * 2;
+        let name_width = 24usize.saturating_sub(indent);
+        _ = writeln!(
+            out,
+            "{:indent$}{:<name_width$} {:>10} used {:>10} spare",
+            "",
+            self.name,
+            self.total_used(),
+            self.total_spare(),
+        );
+        for child in &self.children {
+            child.render_impl(out, depth + 1);
+        }
+    }
+}
+
+/// Only counts the elements themselves, not any memory they point to.
+impl<T> MemoryUsage for Vec<T> {
+    fn memory_usage(&self) -> MemoryReport {
+        let size = mem::size_of::<T>();
+        MemoryReport::leaf("Vec", self.len() * size, (self.capacity() - self.len()) * size)
+    }
+}
+
+/// Only counts the elements themselves, not any memory they point to.
+impl<T> MemoryUsage for VecDeque<T> {
+    fn memory_usage(&self) -> MemoryReport {
+        let size = mem::size_of::<T>();
+        MemoryReport::leaf("VecDeque", self.len() * size, (self.capacity() - self.len()) * size)
+    }
+}
+
+impl MemoryUsage for String {
+    fn memory_usage(&self) -> MemoryReport {
+        MemoryReport::leaf("String", self.len(), self.capacity() - self.len())
+    }
+}
+
+impl MemoryUsage for ArenaString<'_> {
+    fn memory_usage(&self) -> MemoryReport {
+        MemoryReport::leaf("ArenaString", self.len(), self.capacity() - self.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::arena::scratch_arena;
+    use crate::clipboard::ClipboardRing;
+
+    #[test]
+    fn test_collections() {
+        let mut v: Vec<u32> = Vec::with_capacity(10);
+        v.extend([1, 2, 3]);
+        let report = v.memory_usage();
+        assert_eq!((report.used, report.spare), (12, 28));
+
+        let mut s = String::with_capacity(16);
+        s.push_str("hello");
+        let report = s.memory_usage();
+        assert_eq!((report.used, report.spare), (5, 11));
+    }
+
+    #[test]
+    fn test_clipboard_ring() {
+        let mut hello = Vec::with_capacity(8);
+        hello.extend_from_slice(b"hello");
+        let mut ring = ClipboardRing::new(4);
+        ring.push(hello);
+        ring.push(b"world".to_vec());
+
+        let report = ring.memory_usage();
+        assert_eq!(report.children.len(), 3);
+        assert_eq!(report.children[1], MemoryReport::leaf("entry", 5, 0));
+        assert_eq!(report.children[2], MemoryReport::leaf("entry", 5, 3));
+
+        let entries = &report.children[0];
+        assert_eq!(entries.used, 2 * mem::size_of::<Vec<u8>>());
+        assert_eq!(report.total_used(), entries.used + 10);
+        assert_eq!(report.total_spare(), entries.spare + 3);
+    }
+
+    #[test]
+    fn test_tree() {
+        let report = MemoryReport {
+            name: "root",
+            used: 1,
+            spare: 2,
+            children: vec![
+                MemoryReport::leaf("a", 10, 20),
+                MemoryReport::node(
+                    "b",
+                    vec![MemoryReport::leaf("c", 100, 200), MemoryReport::leaf("d", 1000, 0)],
+                ),
+            ],
+        };
+        assert_eq!(report.total_used(), 1111);
+        assert_eq!(report.total_spare(), 222);
+        assert_eq!(report.children[1].total_used(), 1100);
+
+        let scratch = scratch_arena(None);
+        let mut out = ArenaString::new_in(&scratch);
+        report.render(&mut out);
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].starts_with("root "));
+        assert!(lines[0].contains("1111 used"));
+        assert!(lines[2].starts_with("  b "));
+        assert!(lines[3].starts_with("    c "));
+        assert!(lines[3].contains("200 spare"));
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Conversions between byte offsets and line/column positions.
+//!
+//! What a "column" is depends on who's asking: Compilers tend to count bytes or chars,
+//! LSP counts UTF-16 units (not supported here), and users count what they see.
+//! Lines and columns are 0-based. Newlines, including the CR of a CRLF, aren't columns.
+
+use std::ffi::OsStr;
+use std::path::Path;
+
+use crate::document::ReadableDocument;
+use crate::helpers::{CoordType, Point};
+use crate::unicode::{Cursor, MeasurementConfig};
+
+/// The unit in which columns are counted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnUnit {
+    Bytes,
+    /// Unicode scalar values.
+    Chars,
+    /// Grapheme clusters, which is what [`Cursor::logical_pos`] uses.
+    Graphemes,
+    /// Terminal cells, taking the width of wide characters and tabs into account.
+    Cells {
+        tab_size: CoordType,
+    },
+}
+
+/// What [`position_to_offset`] does with positions past the end of a line or the document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClampPolicy {
+    /// Move the position to the end of the line or the last line.
+    Clamp,
+    /// Fail the conversion.
+    Error,
+}
+
+/// Converts a byte offset into a position with columns in the given `unit`.
+/// Offsets past the end of the document are clamped to it.
+pub fn offset_to_position(doc: &dyn ReadableDocument, offset: usize, unit: ColumnUnit) -> Point {
+    let cursor = measurement(doc, unit).goto_offset(offset);
+    let y = cursor.logical_pos.y;
+
+    let x = match unit {
+        ColumnUnit::Bytes => (cursor.offset - line_start(doc, y).offset) as CoordType,
+        ColumnUnit::Chars => count_chars(doc, line_start(doc, y).offset, cursor.offset),
+        ColumnUnit::Graphemes => cursor.logical_pos.x,
+        ColumnUnit::Cells { .. } => cursor.column,
+    };
+
+    Point { x, y }
+}
+
+/// Converts a position with columns in the given `unit` into a byte offset.
+///
+/// Returns `None` if the position is out of range and `clamp` is [`ClampPolicy::Error`].
+/// A byte column in the middle of a character is out of range as well.
+pub fn position_to_offset(
+    doc: &dyn ReadableDocument,
+    pos: Point,
+    unit: ColumnUnit,
+    clamp: ClampPolicy,
+) -> Option<usize> {
+    let clamping = clamp == ClampPolicy::Clamp;
+    if (pos.x < 0 || pos.y < 0) && !clamping {
+        return None;
+    }
+
+    let mut cfg = measurement(doc, unit);
+    let mut beg = cfg.goto_logical(Point { x: 0, y: pos.y.max(0) });
+    if beg.logical_pos.y < pos.y {
+        if !clamping {
+            return None;
+        }
+        // We ended up at the end of the last line, but we want its start.
+        cfg = measurement(doc, unit);
+        beg = cfg.goto_logical(Point { x: 0, y: beg.logical_pos.y });
+    }
+
+    let y = beg.logical_pos.y;
+    let x = pos.x.max(0);
+    let end = cfg.goto_logical(Point { x: CoordType::MAX, y });
+
+    let (offset, in_range) = match unit {
+        ColumnUnit::Bytes => {
+            let mut offset = (beg.offset + x as usize).min(end.offset);
+            let in_range = beg.offset + x as usize <= end.offset && is_char_boundary(doc, offset);
+            // Invalid UTF-8 may have no char boundary before the column within the line.
+            while offset > beg.offset && !is_char_boundary(doc, offset) {
+                offset -= 1;
+            }
+            (offset, in_range)
+        }
+        ColumnUnit::Chars => {
+            let offset = skip_chars(doc, beg.offset, end.offset, x);
+            let in_range = count_chars(doc, beg.offset, offset) == x;
+            (offset, in_range)
+        }
+        ColumnUnit::Graphemes => {
+            let cursor = measurement(doc, unit).with_cursor(beg).goto_logical(Point { x, y });
+            (cursor.offset, cursor.logical_pos.x == x)
+        }
+        ColumnUnit::Cells { .. } => {
+            let cursor = measurement(doc, unit).with_cursor(beg).goto_visual(Point { x, y });
+            (cursor.offset, cursor.offset < end.offset || cursor.column >= x)
+        }
+    };
+
+    if in_range || clamping { Some(offset) } else { None }
+}
+
+/// Splits a trailing `:line` or `:line:column` off a path, as used by compilers.
+/// The numbers are 1-based, but the returned position is 0-based.
+///
+/// Paths that would be empty after stripping the suffix, like ":123", are returned as is.
+pub fn parse_path_position(path: &Path) -> (&Path, Option<Point>) {
+    fn parse(s: &[u8]) -> Option<CoordType> {
+        if s.is_empty() {
+            return None;
+        }
+
+        let mut num: CoordType = 0;
+        for &b in s {
+            if !b.is_ascii_digit() {
+                return None;
+            }
+            let digit = (b - b'0') as CoordType;
+            num = num.checked_mul(10)?.checked_add(digit)?;
+        }
+        Some(num)
+    }
+
+    fn find_colon_rev(bytes: &[u8], offset: usize) -> Option<usize> {
+        (0..offset.min(bytes.len())).rev().find(|&i| bytes[i] == b':')
+    }
+
+    let bytes = path.as_os_str().as_encoded_bytes();
+    let colend = match find_colon_rev(bytes, bytes.len()) {
+        // Reject filenames that would result in an empty filename after stripping off the :line:char suffix.
+        // For instance, a filename like ":123:456" will not be processed by this function.
+        Some(colend) if colend > 0 => colend,
+        _ => return (path, None),
+    };
+
+    let last = match parse(&bytes[colend + 1..]) {
+        Some(last) => last,
+        None => return (path, None),
+    };
+    let last = (last - 1).max(0);
+    let mut len = colend;
+    let mut goto = Point { x: 0, y: last };
+
+    if let Some(colbeg) = find_colon_rev(bytes, colend) {
+        // Same here: Don't allow empty filenames.
+        if colbeg != 0
+            && let Some(first) = parse(&bytes[colbeg + 1..colend])
+        {
+            let first = (first - 1).max(0);
+            len = colbeg;
+            goto = Point { x: last, y: first };
+        }
+    }
+
+    // Strip off the :line:char suffix.
+    let path = &bytes[..len];
+    let path = unsafe { OsStr::from_encoded_bytes_unchecked(path) };
+    let path = Path::new(path);
+    (path, Some(goto))
+}
+
+fn measurement(doc: &dyn ReadableDocument, unit: ColumnUnit) -> MeasurementConfig<'_> {
+    let cfg = MeasurementConfig::new(doc);
+    match unit {
+        ColumnUnit::Cells { tab_size } => cfg.with_tab_size(tab_size),
+        _ => cfg,
+    }
+}
+
+fn line_start(doc: &dyn ReadableDocument, y: CoordType) -> Cursor {
+    MeasurementConfig::new(doc).goto_logical(Point { x: 0, y })
+}
+
+fn is_char_boundary(doc: &dyn ReadableDocument, offset: usize) -> bool {
+    doc.read_forward(offset).first().is_none_or(|&b| b & 0xC0 != 0x80)
+}
+
+/// Counts the chars between `beg` and `end`.
+fn count_chars(doc: &dyn ReadableDocument, beg: usize, end: usize) -> CoordType {
+    let mut count = 0;
+    let mut off = beg;
+    while off < end {
+        let chunk = doc.read_forward(off);
+        if chunk.is_empty() {
+            break;
+        }
+        let chunk = &chunk[..chunk.len().min(end - off)];
+        count += chunk.iter().filter(|&&b| b & 0xC0 != 0x80).count();
+        off += chunk.len();
+    }
+    count as CoordType
+}
+
+/// Returns the offset after skipping `count` chars from `beg`, but at most `end`.
+fn skip_chars(doc: &dyn ReadableDocument, beg: usize, end: usize, mut count: CoordType) -> usize {
+    let mut off = beg;
+    while off < end {
+        let chunk = doc.read_forward(off);
+        if chunk.is_empty() {
+            break;
+        }
+        let chunk = &chunk[..chunk.len().min(end - off)];
+        for (i, &b) in chunk.iter().enumerate() {
+            if b & 0xC0 != 0x80 {
+                if count == 0 {
+                    return off + i;
+                }
+                count -= 1;
+            }
+        }
+        off += chunk.len();
+    }
+    end
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tab, CJK and a combining mark: "a", "\t", "中", "e\u{301}", "x"
+    const TEXT: &str = "a\t中e\u{301}x\r\nsecond";
+    const CELLS: ColumnUnit = ColumnUnit::Cells { tab_size: 4 };
+
+    #[test]
+    fn test_columns() {
+        let doc = TEXT.as_bytes();
+        // (byte offset, bytes, chars, graphemes, cells)
+        let columns = [
+            (0, 0, 0, 0, 0), // a
+            (1, 1, 1, 1, 1), // \t
+            (2, 2, 2, 2, 4), // 中
+            (5, 5, 3, 3, 6), // e
+            (8, 8, 5, 4, 7), // x
+            (9, 9, 6, 5, 8), // end of line, before the CR
+        ];
+
+        for (offset, bytes, chars, graphemes, cells) in columns {
+            for (unit, x) in [
+                (ColumnUnit::Bytes, bytes),
+                (ColumnUnit::Chars, chars),
+                (ColumnUnit::Graphemes, graphemes),
+                (CELLS, cells),
+            ] {
+                let pos = Point { x, y: 0 };
+                assert_eq!(offset_to_position(&doc, offset, unit), pos, "{unit:?} {offset}");
+                assert_eq!(
+                    position_to_offset(&doc, pos, unit, ClampPolicy::Error),
+                    Some(offset),
+                    "{unit:?} {pos:?}"
+                );
+            }
+        }
+
+        let second = Point { x: 2, y: 1 };
+        assert_eq!(offset_to_position(&doc, 13, ColumnUnit::Chars), second);
+        assert_eq!(position_to_offset(&doc, second, CELLS, ClampPolicy::Error), Some(13));
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let doc = TEXT.as_bytes();
+        let past_eol = Point { x: 100, y: 0 };
+        let past_eof = Point { x: 3, y: 5 };
+
+        for unit in [ColumnUnit::Bytes, ColumnUnit::Chars, ColumnUnit::Graphemes, CELLS] {
+            assert_eq!(position_to_offset(&doc, past_eol, unit, ClampPolicy::Error), None);
+            assert_eq!(position_to_offset(&doc, past_eol, unit, ClampPolicy::Clamp), Some(9));
+            assert_eq!(position_to_offset(&doc, past_eof, unit, ClampPolicy::Error), None);
+            assert_eq!(position_to_offset(&doc, past_eof, unit, ClampPolicy::Clamp), Some(14));
+            assert_eq!(
+                position_to_offset(&doc, Point { x: -1, y: -1 }, unit, ClampPolicy::Clamp),
+                Some(0)
+            );
+        }
+
+        // Byte columns in the middle of a character.
+        let mid = Point { x: 3, y: 0 };
+        assert_eq!(position_to_offset(&doc, mid, ColumnUnit::Bytes, ClampPolicy::Error), None);
+        assert_eq!(position_to_offset(&doc, mid, ColumnUnit::Bytes, ClampPolicy::Clamp), Some(2));
+
+        assert_eq!(offset_to_position(&doc, 1000, ColumnUnit::Bytes), Point { x: 6, y: 1 });
+    }
+
+    #[test]
+    fn test_leading_continuation_bytes() {
+        let bytes = ColumnUnit::Bytes;
+        for (doc, y, beg) in [(&b"\x80\x80ab"[..], 0, 0), (&b"x\n\x80\x80y"[..], 1, 2)] {
+            let pos = Point { x: 1, y };
+            assert_eq!(position_to_offset(&doc, pos, bytes, ClampPolicy::Error), None);
+            assert_eq!(position_to_offset(&doc, pos, bytes, ClampPolicy::Clamp), Some(beg));
+        }
+    }
+
+    #[test]
+    fn test_parse_path_position() {
+        fn parse(s: &str) -> (&str, Option<Point>) {
+            let (p, g) = parse_path_position(Path::new(s));
+            (p.to_str().unwrap(), g)
+        }
+
+        assert_eq!(parse("123"), ("123", None));
+        assert_eq!(parse("abc"), ("abc", None));
+        assert_eq!(parse(":123"), (":123", None));
+        assert_eq!(parse("abc:123"), ("abc", Some(Point { x: 0, y: 122 })));
+        assert_eq!(parse("45:123"), ("45", Some(Point { x: 0, y: 122 })));
+        assert_eq!(parse(":45:123"), (":45", Some(Point { x: 0, y: 122 })));
+        assert_eq!(parse("abc:45:123"), ("abc", Some(Point { x: 122, y: 44 })));
+        assert_eq!(parse("abc:def:123"), ("abc:def", Some(Point { x: 0, y: 122 })));
+        assert_eq!(parse("1:2:3"), ("1", Some(Point { x: 2, y: 1 })));
+        assert_eq!(parse("::3"), (":", Some(Point { x: 0, y: 2 })));
+        assert_eq!(parse("1::3"), ("1:", Some(Point { x: 0, y: 2 })));
+        assert_eq!(parse(""), ("", None));
+        assert_eq!(parse(":"), (":", None));
+        assert_eq!(parse("::"), ("::", None));
+        assert_eq!(parse("a:1"), ("a", Some(Point { x: 0, y: 0 })));
+        assert_eq!(parse("1:a"), ("1:a", None));
+        assert_eq!(parse("file.txt:10"), ("file.txt", Some(Point { x: 0, y: 9 })));
+        assert_eq!(parse("file.txt:10:5"), ("file.txt", Some(Point { x: 4, y: 9 })));
+    }
+}
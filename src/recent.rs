@@ -0,0 +1,272 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The list of recently opened files, most recent first.
+//!
+//! Paths are canonicalized, so that opening the same file via a symlink
+//! or a relative path doesn't add a second entry. Files that no longer exist are pruned
+//! lazily via [`RecentFiles::prune_missing`], a few at a time, instead of checking all of them at startup.
+//!
+//! The list is saved as a line-based text file:
+//! ```text
+//! edit-recent 1
+//! 1700000000000000000 10 42 /home/user/foo.txt
+//! ```
+//! Each line holds the time the file was last opened, the cursor position and the path.
+
+use std::path::{Path, PathBuf};
+
+use crate::helpers::{CoordType, Point};
+use crate::{apperr, path, state_file, sys};
+
+const HEADER: &str = "edit-recent";
+const VERSION: u32 = 1;
+
+/// What's remembered about a recently opened file.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecentFileMeta {
+    /// The last cursor position.
+    pub cursor: Point,
+    /// When it was last opened, in nanoseconds since the UNIX epoch.
+    pub opened: u64,
+}
+
+/// A bounded list of recently opened files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecentFiles {
+    /// Most recent first.
+    entries: Vec<(PathBuf, RecentFileMeta)>,
+    capacity: usize,
+    /// Where [`RecentFiles::prune_missing`] continues checking.
+    prune_pos: usize,
+}
+
+impl RecentFiles {
+    pub fn new(capacity: usize) -> Self {
+        Self { entries: Vec::new(), capacity: capacity.max(1), prune_pos: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Moves `path` to the front of the list, or adds it there, with the given `meta`.
+    /// The least recently opened file is dropped if the list is full.
+    pub fn touch(&mut self, path: &Path, meta: RecentFileMeta) {
+        let path = canonicalize(path);
+        if let Some(i) = self.position(&path) {
+            self.entries.remove(i);
+        }
+        self.entries.insert(0, (path, meta));
+        self.entries.truncate(self.capacity);
+        self.prune_pos = 0;
+    }
+
+    /// Returns the entry for `path`, if any.
+    pub fn get(&self, path: &Path) -> Option<&RecentFileMeta> {
+        self.position(&canonicalize(path)).map(|i| &self.entries[i].1)
+    }
+
+    /// Removes `path` from the list. Returns whether it was in it.
+    pub fn remove(&mut self, path: &Path) -> bool {
+        let Some(i) = self.position(&canonicalize(path)) else {
+            return false;
+        };
+        self.entries.remove(i);
+        if self.prune_pos > i {
+            self.prune_pos -= 1;
+        }
+        true
+    }
+
+    /// The files, most recently opened first.
+    pub fn iter_recent(&self) -> impl Iterator<Item = (&Path, &RecentFileMeta)> {
+        self.entries.iter().map(|(p, m)| (p.as_path(), m))
+    }
+
+    /// Checks up to `limit` entries whether their file still exists and removes them if not.
+    /// Each call continues where the last one stopped, so the UI can spread the checks over frames.
+    /// Returns the number of removed entries.
+    pub fn prune_missing(&mut self, limit: usize) -> usize {
+        let mut removed = 0;
+
+        for _ in 0..limit.min(self.entries.len()) {
+            if self.prune_pos >= self.entries.len() {
+                self.prune_pos = 0;
+            }
+            if self.entries[self.prune_pos].0.exists() {
+                self.prune_pos += 1;
+            } else {
+                self.entries.remove(self.prune_pos);
+                removed += 1;
+            }
+        }
+
+        removed
+    }
+
+    /// Reads the list from the file at `path`.
+    ///
+    /// Malformed lines are skipped. Fails with [`apperr::APP_RECENT_FILES_INVALID`]
+    /// if the file isn't a recent files list at all.
+    pub fn load(path: &Path, capacity: usize) -> apperr::Result<Self> {
+        let text = state_file::read(path, HEADER, apperr::APP_RECENT_FILES_INVALID)?;
+        let mut recent = Self::new(capacity);
+
+        for line in state_file::lines(&text) {
+            let mut it = line.splitn(4, |&c| c == b' ');
+            let (Some(opened), Some(x), Some(y), Some(path)) =
+                (it.next(), it.next(), it.next(), it.next())
+            else {
+                continue;
+            };
+            let (Some(opened), Some(x), Some(y)) = (
+                state_file::parse(opened),
+                state_file::parse::<CoordType>(x),
+                state_file::parse::<CoordType>(y),
+            ) else {
+                continue;
+            };
+            if recent.entries.len() < recent.capacity {
+                let meta = RecentFileMeta { cursor: Point { x, y }, opened };
+                let path = state_file::path_from_bytes(state_file::unescape(path));
+                recent.entries.push((path, meta));
+            }
+        }
+
+        Ok(recent)
+    }
+
+    /// Writes the list to the file at `path`.
+    pub fn save(&self, path: &Path) -> apperr::Result<()> {
+        let mut out = Vec::new();
+        for (p, meta) in &self.entries {
+            let prefix = format!("{} {} {} ", meta.opened, meta.cursor.x, meta.cursor.y);
+            out.extend_from_slice(prefix.as_bytes());
+            state_file::escape(&mut out, state_file::path_to_bytes(p));
+            out.push(b'\n');
+        }
+        state_file::write(path, HEADER, VERSION, &out)
+    }
+
+    fn position(&self, path: &Path) -> Option<usize> {
+        self.entries.iter().position(|(p, _)| p == path)
+    }
+}
+
+/// Resolves symlinks and relative paths. Falls back to normalizing
+/// the path if the file doesn't exist (anymore), so that it can still be removed.
+fn canonicalize(path: &Path) -> PathBuf {
+    sys::canonicalize(path).unwrap_or_else(|_| match std::env::current_dir() {
+        Ok(cwd) => path::normalize(&cwd.join(path)),
+        Err(_) => path.to_path_buf(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    /// Returns the directory along with its canonical path, which is what the list stores.
+    fn temp_dir(name: &str) -> (TempDir, PathBuf) {
+        let dir = TempDir::new(name);
+        let path = sys::canonicalize(dir.path()).unwrap();
+        (dir, path)
+    }
+
+    fn meta(opened: u64) -> RecentFileMeta {
+        RecentFileMeta { cursor: Point { x: 1, y: 2 }, opened }
+    }
+
+    fn paths(recent: &RecentFiles) -> Vec<&Path> {
+        recent.iter_recent().map(|(p, _)| p).collect()
+    }
+
+    #[test]
+    fn test_touch_dedup() {
+        let (_tmp, dir) = temp_dir("dedup");
+        let a = dir.join("a.txt");
+        let b = dir.join("b.txt");
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+
+        let mut recent = RecentFiles::new(10);
+        recent.touch(&a, meta(1));
+        recent.touch(&b, meta(2));
+        // The same file via a detour through the parent directory.
+        recent.touch(&dir.join("..").join(dir.file_name().unwrap()).join("a.txt"), meta(3));
+        #[cfg(unix)]
+        {
+            let link = dir.join("link.txt");
+            std::os::unix::fs::symlink(&b, &link).unwrap();
+            recent.touch(&link, meta(4));
+        }
+
+        assert_eq!(recent.len(), 2);
+        let expected = if cfg!(unix) { [&b, &a] } else { [&a, &b] };
+        assert_eq!(paths(&recent), expected);
+        assert_eq!(recent.get(&a), Some(&meta(3)));
+
+        assert!(recent.remove(&a));
+        assert!(!recent.remove(&a));
+        assert_eq!(recent.len(), 1);
+    }
+
+    #[test]
+    fn test_bound() {
+        let (_tmp, dir) = temp_dir("bound");
+        let mut recent = RecentFiles::new(3);
+        for i in 0..5 {
+            recent.touch(&dir.join(format!("{i}")), meta(i));
+        }
+        let names: Vec<_> = recent.iter_recent().map(|(p, _)| p.file_name().unwrap()).collect();
+        assert_eq!(names, ["4", "3", "2"]);
+    }
+
+    #[test]
+    fn test_prune_missing() {
+        let (_tmp, dir) = temp_dir("prune");
+        let mut recent = RecentFiles::new(10);
+        for i in 0..6 {
+            let path = dir.join(format!("{i}"));
+            if i % 2 == 0 {
+                fs::write(&path, "").unwrap();
+            }
+            recent.touch(&path, meta(i));
+        }
+
+        // The checks are spread over several calls.
+        assert_eq!(recent.prune_missing(2), 1);
+        assert_eq!(recent.len(), 5);
+        assert_eq!(recent.prune_missing(10), 2);
+        assert_eq!(recent.prune_missing(10), 0);
+        let names: Vec<_> = recent.iter_recent().map(|(p, _)| p.file_name().unwrap()).collect();
+        assert_eq!(names, ["4", "2", "0"]);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let (_tmp, dir) = temp_dir("roundtrip");
+        let mut recent = RecentFiles::new(10);
+        recent.touch(&dir.join("a b\\c\nd.txt"), meta(1));
+        recent
+            .touch(&dir.join("e.txt"), RecentFileMeta { cursor: Point { x: 7, y: 9 }, opened: 2 });
+
+        let path = dir.join("recent");
+        recent.save(&path).unwrap();
+        assert_eq!(RecentFiles::load(&path, 10).unwrap(), recent);
+
+        // A smaller bound drops the oldest entries.
+        assert_eq!(RecentFiles::load(&path, 1).unwrap().len(), 1);
+
+        fs::write(&path, "something else\n").unwrap();
+        assert_eq!(RecentFiles::load(&path, 10), Err(apperr::APP_RECENT_FILES_INVALID));
+    }
+}
@@ -0,0 +1,482 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! What happens to newlines, the encoding and the final newline when a document is saved.
+//!
+//! The policy of a document is resolved from three layers, each overriding the previous one:
+//! 1. Built-in defaults for some languages, e.g. LF for shell scripts and CRLF for batch files.
+//! 2. The `save` setting of the user's [`Config`], globally and per language.
+//! 3. A directive within the first or last [`MODELINE_LINES`] lines of the document,
+//!    introduced by `edit:`, typically in a comment:
+//!
+//! ```text
+//! # edit: eol=lf noeol
+//! ```
+//!
+//! Directives and the `save` setting use the same syntax: Space-separated `key=value` items.
+//! * `eol=lf` or `eol=crlf` converts all newlines.
+//! * `encoding=<name>` saves with the given encoding. Spaces in its name are written as `-`,
+//!   e.g. `encoding=utf-8-bom`.
+//! * `final_newline=true` adds a missing final newline, `final_newline=false` (or `noeol`)
+//!   removes it.
+//! * `raw` (or `raw=true`) disables all of the above, for binary-ish files.
+//!
+//! Unknown keys and conflicting values within one layer are reported as [`Diagnostic`]s.
+
+use std::fmt;
+use std::ops::Range;
+
+use crate::buffer::TextBuffer;
+use crate::config::{Config, Diagnostic, Severity};
+use crate::icu;
+
+/// Only this many lines at the start and at the end of a document are searched for a directive.
+pub const MODELINE_LINES: usize = 5;
+/// Longer lines aren't searched for a directive. They're unlikely to be comments.
+pub const MODELINE_MAX_LEN: usize = 256;
+
+/// Built-in defaults, by language name.
+const BUILTIN: &[(&str, SavePolicy)] = &[
+    ("batch", SavePolicy { eol: Some(Eol::Crlf), ..SavePolicy::KEEP }),
+    ("binary", SavePolicy { raw: Some(true), ..SavePolicy::KEEP }),
+    ("makefile", SavePolicy { eol: Some(Eol::Lf), final_newline: Some(true), ..SavePolicy::KEEP }),
+    ("shell", SavePolicy { eol: Some(Eol::Lf), final_newline: Some(true), ..SavePolicy::KEEP }),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eol {
+    Lf,
+    Crlf,
+}
+
+/// See the module documentation. `None` means that the layer doesn't specify it,
+/// and if no layer does, the document is left as it is in that regard.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SavePolicy {
+    pub eol: Option<Eol>,
+    pub encoding: Option<&'static str>,
+    pub final_newline: Option<bool>,
+    /// If `Some(true)`, the other fields are ignored.
+    pub raw: Option<bool>,
+}
+
+impl SavePolicy {
+    /// The policy that doesn't change anything.
+    pub const KEEP: Self = Self { eol: None, encoding: None, final_newline: None, raw: None };
+
+    /// Whether newlines are converted, or the final newline is added or removed.
+    pub fn has_transforms(&self) -> bool {
+        self.raw != Some(true) && (self.eol.is_some() || self.final_newline.is_some())
+    }
+
+    /// Parses the items of a directive or the `save` setting.
+    /// Problems are added to `diagnostics`, using the given 1-based `line`.
+    pub fn parse(text: &str, line: usize, diagnostics: &mut Vec<Diagnostic>) -> Self {
+        let mut policy = Self::default();
+        let mut report =
+            |severity, message| diagnostics.push(Diagnostic { line, severity, message });
+
+        for item in text.split_whitespace() {
+            let (key, value) = match item {
+                "noeol" => ("final_newline", "false"),
+                "raw" => ("raw", "true"),
+                _ => match item.split_once('=') {
+                    Some(kv) => kv,
+                    None => {
+                        report(Severity::Warning, format!("unknown save directive: {item}"));
+                        continue;
+                    }
+                },
+            };
+
+            let item = match key {
+                "eol" => match value {
+                    "lf" => Ok(Self { eol: Some(Eol::Lf), ..Self::KEEP }),
+                    "crlf" => Ok(Self { eol: Some(Eol::Crlf), ..Self::KEEP }),
+                    _ => Err(format!("expected lf or crlf: {value}")),
+                },
+                "encoding" => match find_encoding(value) {
+                    Some(encoding) => Ok(Self { encoding: Some(encoding), ..Self::KEEP }),
+                    None => Err(format!("unknown encoding: {value}")),
+                },
+                "final_newline" => {
+                    parse_bool(value).map(|v| Self { final_newline: Some(v), ..Self::KEEP })
+                }
+                "raw" => parse_bool(value).map(|v| Self { raw: Some(v), ..Self::KEEP }),
+                _ => {
+                    report(Severity::Warning, format!("unknown save directive: {key}"));
+                    continue;
+                }
+            };
+
+            match item {
+                Ok(item) => policy.merge_checked(&item, &mut report),
+                Err(message) => report(Severity::Error, format!("{key}: {message}")),
+            }
+        }
+
+        if policy.raw == Some(true) && (policy.eol.is_some() || policy.final_newline.is_some()) {
+            report(Severity::Warning, "raw disables eol and final_newline".to_string());
+        }
+
+        policy
+    }
+
+    /// Overrides the fields that `other` specifies.
+    pub fn merge(&mut self, other: &Self) {
+        self.eol = other.eol.or(self.eol);
+        self.encoding = other.encoding.or(self.encoding);
+        self.final_newline = other.final_newline.or(self.final_newline);
+        self.raw = other.raw.or(self.raw);
+    }
+
+    /// Like [`SavePolicy::merge`], but reports fields that are set to different values,
+    /// since they're from the same layer.
+    fn merge_checked(&mut self, other: &Self, report: &mut impl FnMut(Severity, String)) {
+        fn conflicts<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+            a.is_some() && b.is_some() && a != b
+        }
+
+        for (key, conflict) in [
+            ("eol", conflicts(self.eol, other.eol)),
+            ("encoding", conflicts(self.encoding, other.encoding)),
+            ("final_newline", conflicts(self.final_newline, other.final_newline)),
+            ("raw", conflicts(self.raw, other.raw)),
+        ] {
+            if conflict {
+                report(
+                    Severity::Warning,
+                    format!("conflicting values for {key}, the last one wins"),
+                );
+            }
+        }
+        self.merge(other);
+    }
+}
+
+/// Formats the policy in the syntax [`SavePolicy::parse`] accepts.
+impl fmt::Display for SavePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut items = Vec::new();
+        if let Some(eol) = self.eol {
+            items.push(format!("eol={}", if eol == Eol::Lf { "lf" } else { "crlf" }));
+        }
+        if let Some(encoding) = self.encoding {
+            items.push(format!("encoding={}", encoding.replace(' ', "-")));
+        }
+        if let Some(final_newline) = self.final_newline {
+            items.push(format!("final_newline={final_newline}"));
+        }
+        if let Some(raw) = self.raw {
+            items.push(format!("raw={raw}"));
+        }
+        f.write_str(&items.join(" "))
+    }
+}
+
+/// Returns the built-in policy for the given language.
+pub fn builtin_policy(language: Option<&str>) -> SavePolicy {
+    language
+        .and_then(|l| BUILTIN.iter().find(|(name, _)| *name == l))
+        .map_or(SavePolicy::KEEP, |(_, policy)| *policy)
+}
+
+/// Searches the first and last [`MODELINE_LINES`] lines of `tb` for directives and merges them.
+/// If there are several, the later ones override the earlier ones.
+///
+/// Only those lines are read, so that this stays cheap for large documents.
+pub fn parse_modeline(tb: &TextBuffer, diagnostics: &mut Vec<Diagnostic>) -> SavePolicy {
+    let line_count = tb.logical_line_count() as usize;
+    // (1-based line number, line)
+    let mut lines = Vec::new();
+
+    let mut beg = 0;
+    for line in 1..=MODELINE_LINES.min(line_count) {
+        let end = find_newline_fwd(tb, beg);
+        lines.push((line, read_line(tb, beg..end)));
+        beg = end + 1;
+    }
+
+    let first_tail = lines.len();
+    let mut end = tb.text_length();
+    for line in (lines.len() + 1..=line_count).rev().take(MODELINE_LINES) {
+        let beg = find_newline_bwd(tb, end);
+        lines.push((line, read_line(tb, beg..end)));
+        end = beg.saturating_sub(1);
+    }
+    lines[first_tail..].reverse();
+
+    let mut found = Vec::new();
+    for (line, text) in lines {
+        let Some(text) = text.as_deref().and_then(|t| str::from_utf8(t).ok()) else {
+            continue;
+        };
+        if let Some(directive) = find_directive(text) {
+            found.push((line, SavePolicy::parse(directive, line, diagnostics)));
+        }
+    }
+
+    let mut policy = SavePolicy::default();
+    for (line, p) in found {
+        policy.merge_checked(&p, &mut |severity, message| {
+            diagnostics.push(Diagnostic { line, severity, message })
+        });
+    }
+    policy
+}
+
+/// Resolves the policy for the document `tb` in the given language.
+/// Returns it along with the problems found in the directives of `tb`.
+pub fn resolve(
+    config: &Config,
+    language: Option<&str>,
+    tb: &TextBuffer,
+) -> (SavePolicy, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let mut policy = builtin_policy(language);
+    policy.merge(&config.effective(language).save);
+    policy.merge(&parse_modeline(tb, &mut diagnostics));
+    (policy, diagnostics)
+}
+
+/// Returns the offset of the next newline at or after `off`, or the end of the document.
+fn find_newline_fwd(tb: &TextBuffer, mut off: usize) -> usize {
+    loop {
+        let chunk = tb.read_forward(off);
+        if chunk.is_empty() {
+            return off;
+        }
+        if let Some(i) = chunk.iter().position(|&c| c == b'\n') {
+            return off + i;
+        }
+        off += chunk.len();
+    }
+}
+
+/// Returns the offset after the last newline before `off`, or 0.
+fn find_newline_bwd(tb: &TextBuffer, mut off: usize) -> usize {
+    loop {
+        let chunk = tb.read_backward(off);
+        if chunk.is_empty() {
+            return 0;
+        }
+        if let Some(i) = chunk.iter().rposition(|&c| c == b'\n') {
+            return off - chunk.len() + i + 1;
+        }
+        off -= chunk.len();
+    }
+}
+
+/// Returns the text in `range`, or `None` if it's longer than [`MODELINE_MAX_LEN`].
+fn read_line(tb: &TextBuffer, range: Range<usize>) -> Option<Vec<u8>> {
+    if range.len() > MODELINE_MAX_LEN {
+        return None;
+    }
+    let mut text = Vec::with_capacity(range.len());
+    let mut off = range.start;
+    while off < range.end {
+        let chunk = tb.read_forward(off);
+        if chunk.is_empty() {
+            break;
+        }
+        let chunk = &chunk[..chunk.len().min(range.end - off)];
+        text.extend_from_slice(chunk);
+        off += chunk.len();
+    }
+    Some(text)
+}
+
+/// Returns the items of an `edit:` directive in `line`, if it has one.
+/// The marker must start a word, so that e.g. "credit:" doesn't count.
+fn find_directive(line: &str) -> Option<&str> {
+    let idx = line.match_indices("edit:").map(|(i, _)| i).find(|&i| {
+        i == 0 || line[..i].ends_with(|c: char| c.is_whitespace() || c.is_ascii_punctuation())
+    })?;
+    let rest = line[idx + 5..].trim();
+    // Drop the end of a block comment.
+    let rest = rest.strip_suffix("*/").or_else(|| rest.strip_suffix("-->")).unwrap_or(rest);
+    Some(rest.trim_end())
+}
+
+fn find_encoding(name: &str) -> Option<&'static str> {
+    let encodings = icu::get_available_encodings();
+    encodings
+        .preferred
+        .iter()
+        .chain(encodings.all)
+        .find(|e| names_match(e.label, name) || names_match(e.canonical, name))
+        .map(|e| e.canonical)
+}
+
+/// Compares encoding names case-insensitively, with `-` matching a space.
+fn names_match(name: &str, value: &str) -> bool {
+    let normalize = |b: u8| if b == b' ' { b'-' } else { b.to_ascii_lowercase() };
+    name.len() == value.len()
+        && name.bytes().zip(value.bytes()).all(|(a, b)| normalize(a) == normalize(b))
+}
+
+fn parse_bool(value: &str) -> Result<bool, String> {
+    match value {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => Err(format!("expected true or false: {value}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::languages::Languages;
+    use crate::testing::{buffer_from_unnormalized as buffer, contents};
+
+    fn diagnostic(line: usize, severity: Severity, message: &str) -> Diagnostic {
+        Diagnostic { line, severity, message: message.to_string() }
+    }
+
+    fn modeline(text: &str) -> (SavePolicy, Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+        (parse_modeline(&buffer(text), &mut diagnostics), diagnostics)
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut diagnostics = Vec::new();
+        let policy =
+            SavePolicy::parse("eol=crlf  final_newline=true encoding=utf-8", 3, &mut diagnostics);
+        assert_eq!(diagnostics, []);
+        assert_eq!(
+            policy,
+            SavePolicy {
+                eol: Some(Eol::Crlf),
+                encoding: Some("UTF-8"),
+                final_newline: Some(true),
+                raw: None
+            }
+        );
+        assert_eq!(policy.to_string(), "eol=crlf encoding=UTF-8 final_newline=true");
+
+        let policy = SavePolicy::parse(
+            "eol=lf eol=crlf noeol tabs eol=cr wrap=on encoding=klingon",
+            7,
+            &mut diagnostics,
+        );
+        assert_eq!(policy.eol, Some(Eol::Crlf));
+        assert_eq!(policy.final_newline, Some(false));
+        assert_eq!(
+            diagnostics,
+            [
+                diagnostic(7, Severity::Warning, "conflicting values for eol, the last one wins"),
+                diagnostic(7, Severity::Warning, "unknown save directive: tabs"),
+                diagnostic(7, Severity::Error, "eol: expected lf or crlf: cr"),
+                diagnostic(7, Severity::Warning, "unknown save directive: wrap"),
+                diagnostic(7, Severity::Error, "encoding: unknown encoding: klingon"),
+            ]
+        );
+
+        diagnostics.clear();
+        let policy = SavePolicy::parse("raw eol=lf", 1, &mut diagnostics);
+        assert!(!policy.has_transforms());
+        assert_eq!(
+            diagnostics,
+            [diagnostic(1, Severity::Warning, "raw disables eol and final_newline")]
+        );
+    }
+
+    #[test]
+    fn test_modeline() {
+        assert_eq!(
+            modeline("#!/bin/sh\n# edit: eol=lf noeol\necho hi\n").0,
+            SavePolicy { eol: Some(Eol::Lf), final_newline: Some(false), ..Default::default() }
+        );
+        assert_eq!(modeline("/* edit: eol=crlf */\n").0.eol, Some(Eol::Crlf));
+        assert_eq!(modeline("<!-- edit: raw -->").0.raw, Some(true));
+        // The marker must start a word.
+        assert_eq!(modeline("credit: eol=crlf").0, SavePolicy::KEEP);
+
+        // A directive at the end overrides the one at the start, but they conflict.
+        let (policy, diagnostics) =
+            modeline("// edit: eol=lf\na\nb\nc\nd\ne\nf\n// edit: eol=crlf");
+        assert_eq!(policy.eol, Some(Eol::Crlf));
+        assert_eq!(
+            diagnostics,
+            [diagnostic(8, Severity::Warning, "conflicting values for eol, the last one wins")]
+        );
+    }
+
+    #[test]
+    fn test_modeline_limits() {
+        // Only the first and last few lines are searched.
+        let mut text = "x\n".repeat(MODELINE_LINES);
+        text.push_str("# edit: eol=crlf\n");
+        text.push_str(&"x\n".repeat(MODELINE_LINES));
+        assert_eq!(modeline(&text).0, SavePolicy::KEEP);
+        text.pop();
+        text.push_str("\n# edit: eol=crlf");
+        assert_eq!(modeline(&text).0.eol, Some(Eol::Crlf));
+
+        // Lines around the gap of the buffer are read as a whole.
+        let mut tb = buffer("x\n# edit: eol=crlf\ny\n");
+        tb.cursor_move_to_logical(crate::helpers::Point { x: 7, y: 1 });
+        tb.write_raw(b" ");
+        let mut diagnostics = Vec::new();
+        assert_eq!(parse_modeline(&tb, &mut diagnostics).eol, Some(Eol::Crlf));
+
+        // Huge lines are skipped.
+        let long = format!("# edit: eol=lf {}", "x".repeat(MODELINE_MAX_LEN));
+        assert_eq!(modeline(&long).0, SavePolicy::KEEP);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let config = Config::parse(
+            "save = final_newline=true\n\
+             [lang.shell]\n\
+             save = eol=crlf\n\
+             [lang.rust]\n\
+             save = encoding=utf-8-bom\n",
+        );
+        assert_eq!(config.diagnostics(), []);
+
+        // Built-in defaults.
+        let (policy, _) = resolve(&Config::default(), Some("shell"), &buffer(""));
+        assert_eq!((policy.eol, policy.final_newline), (Some(Eol::Lf), Some(true)));
+        assert!(!resolve(&Config::default(), Some("binary"), &buffer("")).0.has_transforms());
+        assert_eq!(resolve(&Config::default(), Some("rust"), &buffer("")).0, SavePolicy::KEEP);
+
+        // The user's config overrides them.
+        let (policy, _) = resolve(&config, Some("shell"), &buffer(""));
+        assert_eq!((policy.eol, policy.final_newline), (Some(Eol::Crlf), Some(true)));
+        let (policy, _) = resolve(&config, Some("rust"), &buffer(""));
+        assert_eq!((policy.encoding, policy.final_newline), (Some("UTF-8 BOM"), Some(true)));
+
+        // A directive in the document overrides both.
+        let (policy, diagnostics) =
+            resolve(&config, Some("shell"), &buffer("# edit: eol=lf noeol bogus\n"));
+        assert_eq!((policy.eol, policy.final_newline), (Some(Eol::Lf), Some(false)));
+        assert_eq!(
+            diagnostics,
+            [diagnostic(1, Severity::Warning, "unknown save directive: bogus")]
+        );
+    }
+
+    #[test]
+    fn test_builtin_languages() {
+        let languages = Languages::new();
+        let language =
+            |path: &str| languages.language_for_path(Path::new(path), b"").map(|l| l.name.as_str());
+
+        let mut tb = buffer("@echo off\necho hi\n");
+        let (policy, _) = resolve(&Config::default(), language("foo.bat"), &tb);
+        tb.apply_save_policy(&policy).unwrap();
+        assert!(tb.is_crlf());
+        assert_eq!(contents(&tb), "@echo off\r\necho hi\r\n");
+
+        let mut tb = buffer("\x7fELF\n\r\n");
+        let (policy, _) = resolve(&Config::default(), language("foo.so"), &tb);
+        assert_eq!(policy.raw, Some(true));
+        tb.apply_save_policy(&policy).unwrap();
+        assert_eq!(contents(&tb), "\x7fELF\n\r\n");
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Saving and restoring the editor session.
+//!
+//! The session file is a line-based text file. The first line identifies the format and its version.
+//! All following lines are `key = value` pairs, with `[file]` lines starting a new open file:
+//! ```text
+//! edit-session 1
+//! search = foo
+//! layout.split = vertical
+//! [file]
+//! path = /home/user/foo.txt
+//! cursor = 10 42
+//! fingerprint = 1234 1700000000000000000 9876543210
+//! ```
+//! Unknown keys are ignored, so that older versions can load files written by newer ones.
+
+use std::fs::File;
+use std::io::Read as _;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::hash::hash;
+use crate::helpers::{CoordType, Point};
+use crate::{apperr, state_file};
+
+const HEADER: &str = "edit-session";
+const VERSION: u32 = 1;
+/// The number of bytes at the start of a file that go into its [`FileFingerprint`].
+const HASH_PREFIX_LEN: usize = 4096;
+
+/// Everything that's restored when reopening the editor.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct SessionState {
+    pub files: Vec<SessionFile>,
+    /// The search history, oldest first.
+    pub search_history: Vec<String>,
+    /// Arbitrary key/value pairs describing the window layout. They're opaque to this module.
+    pub layout: Vec<(String, String)>,
+}
+
+/// An open file and its view state.
+#[derive(Default, Debug, PartialEq, Eq)]
+pub struct SessionFile {
+    pub path: PathBuf,
+    pub cursor: Point,
+    pub scroll: Point,
+    pub selection: Option<(Point, Point)>,
+    /// Identifies the file contents the positions above refer to.
+    pub fingerprint: FileFingerprint,
+}
+
+/// A cheap way to tell whether a file changed: its size, modification time and a hash of its first few KiB.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileFingerprint {
+    pub size: u64,
+    /// Nanoseconds since the UNIX epoch.
+    pub mtime: u64,
+    pub hash_prefix: u64,
+}
+
+impl FileFingerprint {
+    pub fn of(path: &Path) -> apperr::Result<Self> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos() as u64);
+
+        let mut prefix = Vec::with_capacity(HASH_PREFIX_LEN);
+        (&mut file).take(HASH_PREFIX_LEN as u64).read_to_end(&mut prefix)?;
+
+        Ok(Self { size: metadata.len(), mtime, hash_prefix: hash(0, &prefix) })
+    }
+}
+
+/// Whether the view state of a [`SessionFile`] can be restored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    /// The file is unchanged and the positions can be restored.
+    Unchanged,
+    /// The file changed since, and the positions may point anywhere.
+    Changed,
+    /// The file doesn't exist anymore.
+    Missing,
+}
+
+impl SessionFile {
+    /// Compares the file on disk against the stored fingerprint.
+    pub fn status(&self) -> FileStatus {
+        match FileFingerprint::of(&self.path) {
+            Ok(fp) if fp == self.fingerprint => FileStatus::Unchanged,
+            Ok(_) => FileStatus::Changed,
+            Err(_) => FileStatus::Missing,
+        }
+    }
+}
+
+/// Writes the `session` to the file at `path`.
+pub fn save_session(path: &Path, session: &SessionState) -> apperr::Result<()> {
+    let mut out = Vec::new();
+
+    for entry in &session.search_history {
+        push_pair(&mut out, b"search", entry.as_bytes());
+    }
+    for (key, value) in &session.layout {
+        out.extend_from_slice(b"layout.");
+        push_pair(&mut out, key.as_bytes(), value.as_bytes());
+    }
+    for file in &session.files {
+        out.extend_from_slice(b"[file]\n");
+        push_pair(&mut out, b"path", state_file::path_to_bytes(&file.path));
+        push_pair(&mut out, b"cursor", format!("{} {}", file.cursor.x, file.cursor.y).as_bytes());
+        push_pair(&mut out, b"scroll", format!("{} {}", file.scroll.x, file.scroll.y).as_bytes());
+        if let Some((beg, end)) = file.selection {
+            let value = format!("{} {} {} {}", beg.x, beg.y, end.x, end.y);
+            push_pair(&mut out, b"selection", value.as_bytes());
+        }
+        let fp = &file.fingerprint;
+        let value = format!("{} {} {}", fp.size, fp.mtime, fp.hash_prefix);
+        push_pair(&mut out, b"fingerprint", value.as_bytes());
+    }
+
+    state_file::write(path, HEADER, VERSION, &out)
+}
+
+/// Reads the session from the file at `path`.
+///
+/// Unknown keys and malformed values are skipped. Fails with
+/// [`apperr::APP_SESSION_INVALID`] if the file isn't a session file at all.
+pub fn load_session(path: &Path) -> apperr::Result<SessionState> {
+    let text = state_file::read(path, HEADER, apperr::APP_SESSION_INVALID)?;
+    let mut session = SessionState::default();
+    let mut file: Option<SessionFile> = None;
+
+    for line in state_file::lines(&text) {
+        if line == b"[file]" {
+            session.files.extend(file.take());
+            file = Some(SessionFile::default());
+            continue;
+        }
+
+        // Keys and values have their `=` escaped, so the first ` = ` is the separator.
+        let Some(i) = line.windows(3).position(|w| w == b" = ") else {
+            continue;
+        };
+        let (key, value) = (&line[..i], &line[i + 3..]);
+
+        match (key, file.as_mut()) {
+            (b"search", None) => session.search_history.push(state_file::unescape_str(value)),
+            (key, None) if key.starts_with(b"layout.") => {
+                let key = state_file::unescape_str(&key[b"layout.".len()..]);
+                session.layout.push((key, state_file::unescape_str(value)));
+            }
+            (b"path", Some(f)) => f.path = state_file::path_from_bytes(state_file::unescape(value)),
+            (b"cursor", Some(f)) => {
+                if let Some(p) = parse_points::<1>(value) {
+                    f.cursor = p[0];
+                }
+            }
+            (b"scroll", Some(f)) => {
+                if let Some(p) = parse_points::<1>(value) {
+                    f.scroll = p[0];
+                }
+            }
+            (b"selection", Some(f)) => {
+                f.selection = parse_points::<2>(value).map(|p| (p[0], p[1]));
+            }
+            (b"fingerprint", Some(f)) => {
+                if let Some([size, mtime, hash_prefix]) = parse_numbers(value) {
+                    f.fingerprint = FileFingerprint { size, mtime, hash_prefix };
+                }
+            }
+            _ => {}
+        }
+    }
+
+    session.files.extend(file);
+    session.files.retain(|f| !f.path.as_os_str().is_empty());
+    Ok(session)
+}
+
+/// Appends `key = value`, with both escaped.
+fn push_pair(out: &mut Vec<u8>, key: &[u8], value: &[u8]) {
+    state_file::escape(out, key);
+    out.extend_from_slice(b" = ");
+    state_file::escape(out, value);
+    out.push(b'\n');
+}
+
+fn parse_numbers<const N: usize>(value: &[u8]) -> Option<[u64; N]> {
+    let mut res = [0; N];
+    let mut it = value.split(|&c| c == b' ').filter(|s| !s.is_empty());
+    for slot in &mut res {
+        *slot = state_file::parse(it.next()?)?;
+    }
+    Some(res)
+}
+
+fn parse_points<const N: usize>(value: &[u8]) -> Option<[Point; N]> {
+    let mut res = [Point::default(); N];
+    let mut it = value.split(|&c| c == b' ').filter(|s| !s.is_empty());
+    for p in &mut res {
+        let x: CoordType = state_file::parse(it.next()?)?;
+        let y: CoordType = state_file::parse(it.next()?)?;
+        *p = Point { x, y };
+    }
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_roundtrip() {
+        let session = SessionState {
+            files: vec![
+                SessionFile {
+                    path: PathBuf::from("/tmp/a b\\c\nd.txt"),
+                    cursor: Point { x: 3, y: 10 },
+                    scroll: Point { x: 0, y: 5 },
+                    selection: Some((Point { x: 1, y: 2 }, Point { x: 3, y: 4 })),
+                    fingerprint: FileFingerprint { size: 1, mtime: 2, hash_prefix: u64::MAX },
+                },
+                SessionFile { path: PathBuf::from("/tmp/b.txt"), ..Default::default() },
+            ],
+            search_history: vec!["foo".into(), "line\nbreak = \\".into()],
+            layout: vec![("split".into(), "vertical".into()), ("a = b".into(), "= c =".into())],
+        };
+
+        let dir = TempDir::new("session");
+        let path = dir.join("roundtrip");
+        save_session(&path, &session).unwrap();
+        let loaded = load_session(&path);
+        assert_eq!(loaded.unwrap(), session);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_non_utf8_path() {
+        use std::os::unix::ffi::OsStrExt as _;
+
+        let session = SessionState {
+            files: vec![SessionFile {
+                path: PathBuf::from(std::ffi::OsStr::from_bytes(b"/tmp/caf\xE9.txt")),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let dir = TempDir::new("session");
+        let path = dir.join("non-utf8");
+        save_session(&path, &session).unwrap();
+        assert_eq!(load_session(&path).unwrap(), session);
+    }
+
+    #[test]
+    fn test_file_status() {
+        let dir = TempDir::new("session");
+        let path = dir.join("status.txt");
+        fs::write(&path, "hello").unwrap();
+
+        let mut file = SessionFile {
+            path: path.clone(),
+            fingerprint: FileFingerprint::of(&path).unwrap(),
+            ..Default::default()
+        };
+        assert_eq!(file.status(), FileStatus::Unchanged);
+
+        file.fingerprint.hash_prefix ^= 1;
+        assert_eq!(file.status(), FileStatus::Changed);
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(file.status(), FileStatus::Missing);
+    }
+
+    #[test]
+    fn test_newer_version() {
+        let dir = TempDir::new("session");
+        let path = dir.join("newer");
+        fs::write(
+            &path,
+            "edit-session 7\n\
+             search = foo\n\
+             theme = dark\n\
+             [file]\n\
+             path = /a\n\
+             cursor = 1 2\n\
+             folds = 1-5 8-9\n\
+             scroll = garbage\n\
+             [file]\n\
+             cursor = 1 2\n",
+        )
+        .unwrap();
+        let loaded = load_session(&path);
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.search_history, ["foo"]);
+        // The file without a path is dropped.
+        assert_eq!(loaded.files.len(), 1);
+        assert_eq!(loaded.files[0].path, Path::new("/a"));
+        assert_eq!(loaded.files[0].cursor, Point { x: 1, y: 2 });
+        assert_eq!(loaded.files[0].scroll, Point::default());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let dir = TempDir::new("session");
+        let path = dir.join("invalid");
+        fs::write(&path, "not a session\n").unwrap();
+        let loaded = load_session(&path);
+        assert_eq!(loaded, Err(apperr::APP_SESSION_INVALID));
+    }
+}
@@ -0,0 +1,353 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Finds misspelled words in prose and comments.
+//!
+//! [`Words`] extracts the candidate words from a range of text, skipping things that
+//! aren't meant to be words, like URLs. Each of them is then looked up in a [`Dictionary`],
+//! which is a plain word list. Suggestions for corrections aren't offered (yet).
+
+use std::fs;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::apperr;
+use crate::unicode::Utf8Chars;
+
+/// What [`Words`] skips over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WordOptions {
+    /// Tokens containing `://` or starting with `www.`.
+    pub urls: bool,
+    /// `0x1F` and runs of hex digits that contain at least one digit, like `cafe42`.
+    pub hex_numbers: bool,
+    /// Identifiers like `snake_case`.
+    pub underscored: bool,
+}
+
+impl Default for WordOptions {
+    fn default() -> Self {
+        Self { urls: true, hex_numbers: true, underscored: true }
+    }
+}
+
+/// An iterator over the byte ranges of the words in a text.
+///
+/// A word is a run of letters and digits, which may contain apostrophes (`don't`),
+/// but doesn't start or end with them. Words made up of digits only are skipped.
+pub struct Words<'a> {
+    text: &'a [u8],
+    off: usize,
+    end: usize,
+    options: WordOptions,
+}
+
+impl<'a> Words<'a> {
+    /// Iterates over the words in `range` of `text`. The range is widened to the surrounding
+    /// whitespace first, so that a word that was split by an edit is checked as a whole.
+    pub fn new(text: &'a [u8], range: Range<usize>, options: WordOptions) -> Self {
+        let beg =
+            text[..range.start].iter().rposition(u8::is_ascii_whitespace).map_or(0, |i| i + 1);
+        let end = text[range.end..]
+            .iter()
+            .position(u8::is_ascii_whitespace)
+            .map_or(text.len(), |i| range.end + i);
+        Self { text, off: beg, end, options }
+    }
+
+    fn char_at(&self, off: usize) -> Option<(char, usize)> {
+        if off >= self.end {
+            return None;
+        }
+        let mut it = Utf8Chars::new(self.text, off);
+        let ch = it.next()?;
+        Some((ch, it.offset()))
+    }
+
+    fn at_token_start(&self, off: usize) -> bool {
+        off == 0 || self.text[off - 1].is_ascii_whitespace()
+    }
+
+    fn is_url(&self, off: usize) -> bool {
+        let token = &self.text[off..self.token_end(off)];
+        token.starts_with(b"www.") || token.windows(3).any(|w| w == b"://")
+    }
+
+    fn token_end(&self, off: usize) -> usize {
+        self.text[off..self.end]
+            .iter()
+            .position(u8::is_ascii_whitespace)
+            .map_or(self.end, |i| off + i)
+    }
+
+    fn should_skip(&self, word: &[u8]) -> bool {
+        if word.iter().all(u8::is_ascii_digit) {
+            return true;
+        }
+        if self.options.underscored && word.contains(&b'_') {
+            return true;
+        }
+        if self.options.hex_numbers {
+            let digits = match word {
+                [b'0', b'x' | b'X', rest @ ..] if !rest.is_empty() => {
+                    return rest.iter().all(u8::is_ascii_hexdigit);
+                }
+                _ => word,
+            };
+            if digits.iter().all(u8::is_ascii_hexdigit) && digits.iter().any(u8::is_ascii_digit) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl Iterator for Words<'_> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // Find the start of the next word, skipping URLs along the way.
+            let beg = loop {
+                if self.off >= self.end {
+                    return None;
+                }
+                if self.options.urls && self.at_token_start(self.off) && self.is_url(self.off) {
+                    self.off = self.token_end(self.off);
+                    continue;
+                }
+                let (ch, next) = self.char_at(self.off)?;
+                if is_word_char(ch) {
+                    break self.off;
+                }
+                self.off = next;
+            };
+
+            // Apostrophes only count if they're followed by another letter.
+            let mut end = beg;
+            while let Some((ch, next)) = self.char_at(end) {
+                if is_word_char(ch)
+                    || (is_apostrophe(ch)
+                        && self.char_at(next).is_some_and(|(c, _)| is_word_char(c)))
+                {
+                    end = next;
+                } else {
+                    break;
+                }
+            }
+            self.off = end;
+
+            if !self.should_skip(&self.text[beg..end]) {
+                return Some(beg..end);
+            }
+        }
+    }
+}
+
+fn is_word_char(ch: char) -> bool {
+    ch.is_alphanumeric() || ch == '_'
+}
+
+fn is_apostrophe(ch: char) -> bool {
+    ch == '\'' || ch == '\u{2019}'
+}
+
+/// A sorted list of correctly spelled words.
+///
+/// Lookups follow the usual capitalization rules: A lowercase entry also matches its
+/// capitalized and uppercase forms (`the`, `The`, `THE`), while a capitalized one
+/// (`Paris`) must be capitalized. Title case is accepted anywhere, not just at the
+/// start of a sentence, since headings and names use it as well.
+#[derive(Debug, Default, Clone)]
+pub struct Dictionary {
+    words: Vec<String>,
+}
+
+impl Dictionary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a word list with one word per line. Empty lines and lines starting with `#` are ignored.
+    pub fn from_word_list(list: &str) -> Self {
+        let mut words: Vec<_> = list
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(str::to_string)
+            .collect();
+        words.sort_unstable();
+        words.dedup();
+        Self { words }
+    }
+
+    /// Loads a word list from `path`. See [`Dictionary::from_word_list`].
+    pub fn load(path: &Path) -> apperr::Result<Self> {
+        Ok(Self::from_word_list(&fs::read_to_string(path)?))
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    pub fn insert(&mut self, word: &str) {
+        if let Err(i) = self.words.binary_search_by(|w| w.as_str().cmp(word)) {
+            self.words.insert(i, word.to_string());
+        }
+    }
+
+    /// Returns whether `word` is spelled correctly. Typographic apostrophes match plain ones,
+    /// and possessives (`John's`) are accepted if the word without the `'s` is.
+    pub fn contains(&self, word: &str) -> bool {
+        if word.contains('\u{2019}') {
+            return self.contains(&word.replace('\u{2019}', "'"));
+        }
+        if self.contains_cased(word) {
+            return true;
+        }
+        let stem = word.strip_suffix("'s").or_else(|| word.strip_suffix("'S"));
+        stem.is_some_and(|stem| !stem.is_empty() && self.contains_cased(stem))
+    }
+
+    fn contains_cased(&self, word: &str) -> bool {
+        if self.contains_exact(word) {
+            return true;
+        }
+
+        let mut chars = word.chars();
+        let Some(first) = chars.next() else {
+            return false;
+        };
+        let rest = chars.as_str();
+        let is_upper = !word.chars().any(char::is_lowercase);
+        let is_title = first.is_uppercase() && !rest.chars().any(char::is_uppercase);
+
+        if (is_upper || is_title) && self.contains_exact(&word.to_lowercase()) {
+            return true;
+        }
+        if is_upper {
+            // "PARIS" for "Paris".
+            let title: String = first.to_uppercase().chain(rest.to_lowercase().chars()).collect();
+            return self.contains_exact(&title);
+        }
+        false
+    }
+
+    fn contains_exact(&self, word: &str) -> bool {
+        self.words.binary_search_by(|w| w.as_str().cmp(word)).is_ok()
+    }
+}
+
+/// A [`Dictionary`] along with the words the user chose to ignore in this session.
+#[derive(Debug, Default, Clone)]
+pub struct SpellChecker {
+    pub dictionary: Dictionary,
+    pub options: WordOptions,
+    ignored: Dictionary,
+}
+
+impl SpellChecker {
+    pub fn new(dictionary: Dictionary) -> Self {
+        Self { dictionary, options: WordOptions::default(), ignored: Dictionary::new() }
+    }
+
+    /// Stops reporting `word` until the checker is dropped.
+    /// The same capitalization rules as for the dictionary apply.
+    pub fn ignore(&mut self, word: &str) {
+        self.ignored.insert(word);
+    }
+
+    /// Returns the byte ranges of the misspelled words in `range` of `text`,
+    /// which can be passed on for highlighting. See [`Words::new`] for how `range` is widened.
+    pub fn check(&self, text: &[u8], range: Range<usize>) -> Vec<Range<usize>> {
+        Words::new(text, range, self.options)
+            .filter(|r| {
+                // Words only consist of valid UTF-8, since U+FFFD isn't alphanumeric.
+                let word = str::from_utf8(&text[r.clone()]).unwrap_or_default();
+                !self.dictionary.contains(word) && !self.ignored.contains(word)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker(words: &str) -> SpellChecker {
+        SpellChecker::new(Dictionary::from_word_list(words))
+    }
+
+    fn misspelled<'a>(checker: &SpellChecker, text: &'a str) -> Vec<&'a str> {
+        checker.check(text.as_bytes(), 0..text.len()).into_iter().map(|r| &text[r]).collect()
+    }
+
+    #[test]
+    fn test_case() {
+        let c = checker("hello\nParis\niPhone\n# comment\n\n");
+        assert_eq!(c.dictionary.len(), 3);
+        assert_eq!(
+            misspelled(&c, "Hello HELLO hello hELLO Paris PARIS paris iPhone iphone"),
+            ["hELLO", "paris", "iphone"]
+        );
+    }
+
+    #[test]
+    fn test_apostrophes() {
+        let c = checker("don't\nJohn\nquoted\ndogs\ncafé");
+        assert_eq!(misspelled(&c, "John's don’t 'quoted' dogs' isn't café's"), ["isn't"]);
+
+        let mut c = c;
+        c.ignore("isn't");
+        assert_eq!(misspelled(&c, "Isn't it?"), ["it"]);
+    }
+
+    #[test]
+    fn test_split_by_edit() {
+        let c = checker("say\nhello\nworld");
+        let text = "say hello world";
+        // Checking just a part of a word checks all of it.
+        assert_eq!(c.check(text.as_bytes(), 6..7), []);
+
+        // A space was typed into the middle of "hello". Only the inserted range is rechecked.
+        let text = "say hel lo world";
+        let ranges = c.check(text.as_bytes(), 7..8);
+        assert_eq!(ranges, [4..7, 8..10]);
+    }
+
+    #[test]
+    fn test_skipping() {
+        let c = checker("see\nand\nmy");
+        let text = "see https://exampel.com/foo_bar and www.tpyo.org, my_var 0xDEADBEEF cafe42 1234 mp3 deadbeef wrod";
+        assert_eq!(misspelled(&c, text), ["mp3", "deadbeef", "wrod"]);
+
+        let mut c = c;
+        c.options = WordOptions { urls: false, hex_numbers: false, underscored: false };
+        assert_eq!(
+            misspelled(&c, text),
+            [
+                "https",
+                "exampel",
+                "com",
+                "foo_bar",
+                "www",
+                "tpyo",
+                "org",
+                "my_var",
+                "0xDEADBEEF",
+                "cafe42",
+                "mp3",
+                "deadbeef",
+                "wrod"
+            ]
+        );
+
+        // A range that starts within a URL still skips it.
+        let c = checker("");
+        assert_eq!(c.check(b"x https://a.b/cc", 10..12), []);
+    }
+}
@@ -0,0 +1,167 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The format shared by the small files the editor keeps its state in,
+//! i.e. the session, the prompt history and the recent files list.
+//!
+//! Their first line holds a header identifying the file and a version, e.g. `edit-session 1`.
+//! The rest is up to the caller, but is meant to be lines of [`escape`]d text.
+//! The files are handled as bytes, so that paths which aren't valid UTF-8 survive a roundtrip.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::apperr;
+
+/// Reads the file at `path` and returns everything after its header line.
+///
+/// Fails with `invalid` if the file doesn't start with `header` and a valid version.
+/// Newer versions are accepted, so that older versions of the editor can load files
+/// written by newer ones. It's up to their format to stay compatible.
+pub fn read(path: &Path, header: &str, invalid: apperr::Error) -> apperr::Result<Vec<u8>> {
+    let mut text = fs::read(path)?;
+
+    let end = text.iter().position(|&c| c == b'\n').unwrap_or(text.len());
+    let version = text[..end]
+        .strip_prefix(header.as_bytes())
+        .and_then(|v| str::from_utf8(v).ok())
+        .and_then(|v| v.trim().parse::<u32>().ok());
+    if !matches!(version, Some(1..)) {
+        return Err(invalid);
+    }
+
+    text.drain(..(end + 1).min(text.len()));
+    Ok(text)
+}
+
+/// Writes `body` with a header line made of `header` and `version` to the file at `path`.
+///
+/// The contents go to a temporary file first which is then renamed over `path`,
+/// so that a crash can't leave a half-written file behind. The temporary file is named
+/// after the process, so that several instances of the editor don't write into the same one.
+pub fn write(path: &Path, header: &str, version: u32, body: &[u8]) -> apperr::Result<()> {
+    let mut text = format!("{header} {version}\n").into_bytes();
+    text.extend_from_slice(body);
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", std::process::id()));
+
+    let res = fs::write(&tmp, text).and_then(|_| fs::rename(&tmp, path));
+    if res.is_err() {
+        _ = fs::remove_file(&tmp);
+    }
+    Ok(res?)
+}
+
+/// Splits `text` into lines. A trailing `\r` is removed from each.
+pub fn lines(text: &[u8]) -> impl Iterator<Item = &[u8]> {
+    text.split(|&c| c == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty())
+}
+
+/// Appends `s` to `out`, with backslashes, newlines and `=` escaped,
+/// so that it fits on a single line and can be used on either side of a ` = `.
+pub fn escape(out: &mut Vec<u8>, s: &[u8]) {
+    for &c in s {
+        match c {
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'=' => out.extend_from_slice(b"\\="),
+            _ => out.push(c),
+        }
+    }
+}
+
+/// The inverse of [`escape`].
+pub fn unescape(s: &[u8]) -> Vec<u8> {
+    let mut res = Vec::with_capacity(s.len());
+    let mut it = s.iter();
+    while let Some(&c) = it.next() {
+        if c != b'\\' {
+            res.push(c);
+            continue;
+        }
+        match it.next() {
+            Some(b'n') => res.push(b'\n'),
+            Some(b'r') => res.push(b'\r'),
+            Some(&c) => res.push(c),
+            None => {}
+        }
+    }
+    res
+}
+
+/// Like [`unescape`], but for text that was valid UTF-8 when it was written.
+pub fn unescape_str(s: &[u8]) -> String {
+    into_string(unescape(s))
+}
+
+/// Parses a number or similar from a part of a line.
+pub fn parse<T: FromStr>(s: &[u8]) -> Option<T> {
+    str::from_utf8(s).ok()?.parse().ok()
+}
+
+/// Returns the bytes [`path_from_bytes`] turns back into `path`.
+pub fn path_to_bytes(path: &Path) -> &[u8] {
+    path.as_os_str().as_encoded_bytes()
+}
+
+/// The inverse of [`path_to_bytes`].
+///
+/// On Windows the bytes can't be validated, so anything that isn't
+/// valid UTF-8 is replaced with U+FFFD, like all other text in these files.
+pub fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt as _;
+        PathBuf::from(std::ffi::OsString::from_vec(bytes))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(into_string(bytes))
+    }
+}
+
+/// Replaces invalid UTF-8 with U+FFFD, without copying valid text.
+fn into_string(bytes: Vec<u8>) -> String {
+    String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_escape() {
+        let mut out = Vec::new();
+        escape(&mut out, b"a = b\\c\r\nd\xFF");
+        assert_eq!(out, b"a \\= b\\\\c\\r\\nd\xFF");
+        assert!(!out.contains(&b'\n'));
+        assert_eq!(unescape(&out), b"a = b\\c\r\nd\xFF");
+    }
+
+    #[test]
+    fn test_read_write() {
+        let dir = TempDir::new("state-file");
+        let path = dir.join("state");
+        write(&path, "edit-test", 1, b"foo\n").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"edit-test 1\nfoo\n");
+        assert_eq!(read(&path, "edit-test", apperr::APP_SESSION_INVALID), Ok(b"foo\n".to_vec()));
+
+        // Nothing but the file itself is left behind.
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        for text in ["edit-test 0\n", "edit-test\n", "edit-other 1\n", ""] {
+            fs::write(&path, text).unwrap();
+            let res = read(&path, "edit-test", apperr::APP_SESSION_INVALID);
+            assert_eq!(res, Err(apperr::APP_SESSION_INVALID), "{text:?}");
+        }
+
+        fs::write(&path, "edit-test 7").unwrap();
+        assert_eq!(read(&path, "edit-test", apperr::APP_SESSION_INVALID), Ok(Vec::new()));
+    }
+}
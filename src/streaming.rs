@@ -0,0 +1,399 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Read-only access to files that are too large to load into memory.
+//!
+//! [`StreamingBuffer`] keeps a small number of fixed-size chunks of the file in memory
+//! and reads the others on demand, evicting the least recently used one.
+//! Jumping to the end of a file thus only reads the last chunk.
+//!
+//! Line numbers are tracked lazily: Whenever a chunk is read, its newlines are counted.
+//! Once all chunks before an offset were counted, its line number is known.
+//! [`StreamingBuffer::index_more`] counts the remaining ones a few chunks at a time.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::apperr;
+use crate::helpers::KIBI;
+use crate::simd::memchr2;
+
+/// The default size of the chunks that are loaded at a time.
+pub const STREAMING_CHUNK_SIZE: usize = 64 * KIBI;
+/// The default number of chunks kept in memory.
+pub const STREAMING_CHUNK_COUNT: usize = 16;
+
+/// Returns whether a file of `file_len` bytes should be opened read-only with a
+/// [`StreamingBuffer`] instead of being loaded, given the memory `budget` for it.
+pub fn needs_streaming(file_len: u64, budget: usize) -> bool {
+    file_len > budget as u64
+}
+
+/// A read-only view of a large file. See the module documentation.
+pub struct StreamingBuffer {
+    file: File,
+    len: usize,
+    chunk_size: usize,
+    chunk_count: usize,
+    /// The loaded chunks and their index, most recently used first.
+    chunks: Vec<(usize, Vec<u8>)>,
+    /// How often a chunk was read from the file.
+    loads: usize,
+    /// The number of newlines in each chunk, if it was read before.
+    chunk_lines: Vec<Option<usize>>,
+    /// The line number at the start of each chunk, for as many chunks as possible
+    /// from the start of the file on. Has one more entry than chunks were counted.
+    line_anchors: Vec<usize>,
+}
+
+impl StreamingBuffer {
+    pub fn open(path: &Path) -> apperr::Result<Self> {
+        Self::open_with(path, STREAMING_CHUNK_SIZE, STREAMING_CHUNK_COUNT)
+    }
+
+    /// Opens the file at `path`, keeping up to `chunk_count` chunks of `chunk_size` in memory.
+    pub fn open_with(path: &Path, chunk_size: usize, chunk_count: usize) -> apperr::Result<Self> {
+        let file = File::open(path)?;
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::FileTooLarge))?;
+        let chunk_size = chunk_size.max(1);
+
+        Ok(Self {
+            file,
+            len,
+            chunk_size,
+            chunk_count: chunk_count.max(1),
+            chunks: Vec::new(),
+            loads: 0,
+            chunk_lines: vec![None; len.div_ceil(chunk_size)],
+            line_anchors: vec![0],
+        })
+    }
+
+    /// The length of the file in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How often a chunk was read from the file. Useful to check that the cache works.
+    pub fn chunk_loads(&self) -> usize {
+        self.loads
+    }
+
+    /// The buffer is read-only. This always fails with [`apperr::APP_READ_ONLY_STREAMING`].
+    pub fn replace(&mut self, _range: Range<usize>, _replacement: &[u8]) -> apperr::Result<()> {
+        Err(apperr::APP_READ_ONLY_STREAMING)
+    }
+
+    /// Returns the bytes from `off` up to the end of its chunk.
+    /// The result is only empty at the end of the file.
+    pub fn read(&mut self, off: usize) -> apperr::Result<&[u8]> {
+        let off = off.min(self.len);
+        if off == self.len {
+            return Ok(&[]);
+        }
+
+        let idx = off / self.chunk_size;
+        let beg = idx * self.chunk_size;
+        let chunk = self.load(idx)?;
+        Ok(&chunk[off - beg..])
+    }
+
+    /// Returns the offset of the start of the line containing `off`.
+    pub fn line_start(&mut self, off: usize) -> apperr::Result<usize> {
+        let mut end = off.min(self.len);
+
+        while end > 0 {
+            let idx = (end - 1) / self.chunk_size;
+            let beg = idx * self.chunk_size;
+            let chunk = self.load(idx)?;
+            if let Some(i) = chunk[..end - beg].iter().rposition(|&b| b == b'\n') {
+                return Ok(beg + i + 1);
+            }
+            end = beg;
+        }
+
+        Ok(0)
+    }
+
+    /// Returns the offset of the start of the line after the one containing `off`,
+    /// or the end of the file if it's the last line.
+    pub fn next_line_start(&mut self, off: usize) -> apperr::Result<usize> {
+        let mut off = off.min(self.len);
+
+        while off < self.len {
+            let chunk = self.read(off)?;
+            let i = memchr2(b'\n', b'\n', chunk, 0);
+            if i < chunk.len() {
+                return Ok(off + i + 1);
+            }
+            off += chunk.len();
+        }
+
+        Ok(self.len)
+    }
+
+    /// Finds the first occurrence of `needle` at or after `from`.
+    ///
+    /// To find the next one, call it again with the start of the match plus one.
+    /// Only the chunks between `from` and the match are read.
+    pub fn find(&mut self, needle: &[u8], from: usize) -> apperr::Result<Option<usize>> {
+        let Some(&first) = needle.first() else {
+            return Ok(Some(from.min(self.len)));
+        };
+
+        // The end of the previous chunk, in case a match spans across chunks.
+        let mut window = Vec::new();
+        let mut window_off = from.min(self.len);
+        let mut off = window_off;
+
+        while off < self.len {
+            let chunk = self.read(off)?;
+            off += chunk.len();
+            window.extend_from_slice(chunk);
+
+            let mut i = 0;
+            loop {
+                i = memchr2(first, first, &window, i);
+                if i + needle.len() > window.len() {
+                    break;
+                }
+                if window[i..].starts_with(needle) {
+                    return Ok(Some(window_off + i));
+                }
+                i += 1;
+            }
+
+            let keep = window.len().min(needle.len() - 1);
+            window.drain(..window.len() - keep);
+            window_off = off - keep;
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the line number of the line containing `off`,
+    /// if all chunks up to it have been read before.
+    pub fn line_of_offset(&mut self, off: usize) -> apperr::Result<Option<usize>> {
+        let off = off.min(self.len);
+        let idx = off / self.chunk_size;
+        let Some(&line) = self.line_anchors.get(idx) else {
+            return Ok(None);
+        };
+
+        let beg = idx * self.chunk_size;
+        if off == beg {
+            return Ok(Some(line));
+        }
+        if idx + 1 >= self.line_anchors.len() {
+            return Ok(None);
+        }
+        let chunk = self.load(idx)?;
+        Ok(Some(line + count_newlines(&chunk[..off - beg])))
+    }
+
+    /// Returns the offset of the start of the given line,
+    /// if all chunks up to it have been read before.
+    pub fn offset_of_line(&mut self, line: usize) -> apperr::Result<Option<usize>> {
+        if line == 0 {
+            return Ok(Some(0));
+        }
+
+        // The last chunk that starts at or before the line. Since the line may start
+        // anywhere within that chunk, the chunk after it must be counted as well.
+        let idx = self.line_anchors.partition_point(|&l| l < line).saturating_sub(1);
+        if idx + 1 >= self.line_anchors.len() {
+            return Ok(None);
+        }
+
+        let beg = idx * self.chunk_size;
+        let mut remaining = line - self.line_anchors[idx];
+        let chunk = self.load(idx)?;
+        let mut i = 0;
+        while remaining > 0 {
+            i = memchr2(b'\n', b'\n', chunk, i) + 1;
+            remaining -= 1;
+        }
+        Ok(Some(beg + i))
+    }
+
+    /// Reads up to `max_chunks` chunks whose lines haven't been counted yet.
+    /// Returns whether all line numbers are known now.
+    pub fn index_more(&mut self, max_chunks: usize) -> apperr::Result<bool> {
+        for _ in 0..max_chunks {
+            let idx = self.line_anchors.len() - 1;
+            if idx >= self.chunk_lines.len() {
+                break;
+            }
+            self.load(idx)?;
+        }
+        Ok(self.line_anchors.len() > self.chunk_lines.len())
+    }
+
+    /// Returns the chunk with index `idx`, reading it if needed.
+    fn load(&mut self, idx: usize) -> apperr::Result<&[u8]> {
+        if let Some(i) = self.chunks.iter().position(|c| c.0 == idx) {
+            self.chunks[..=i].rotate_right(1);
+            return Ok(&self.chunks[0].1);
+        }
+
+        let mut data = if self.chunks.len() >= self.chunk_count {
+            self.chunks.pop().unwrap().1
+        } else {
+            Vec::with_capacity(self.chunk_size)
+        };
+
+        let beg = idx * self.chunk_size;
+        let len = self.chunk_size.min(self.len - beg);
+        data.resize(len, 0);
+        self.file.seek(SeekFrom::Start(beg as u64))?;
+        self.file.read_exact(&mut data)?;
+        self.loads += 1;
+
+        if self.chunk_lines[idx].is_none() {
+            self.chunk_lines[idx] = Some(count_newlines(&data));
+            while let Some(&Some(lines)) = self.chunk_lines.get(self.line_anchors.len() - 1) {
+                let last = *self.line_anchors.last().unwrap();
+                self.line_anchors.push(last + lines);
+            }
+        }
+
+        self.chunks.insert(0, (idx, data));
+        Ok(&self.chunks[0].1)
+    }
+}
+
+fn count_newlines(data: &[u8]) -> usize {
+    data.iter().filter(|&&b| b == b'\n').count()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    const CHUNK: usize = 4 * KIBI;
+
+    /// Writes lines of varying length, so that they span across chunks at different points.
+    fn temp_file(name: &str) -> (TempDir, PathBuf, Vec<u8>) {
+        let dir = TempDir::new("streaming");
+        let mut data = Vec::new();
+        let mut i = 0;
+        while data.len() < 256 * CHUNK {
+            data.extend_from_slice(format!("line {i} {}\n", "x".repeat(i % 97)).as_bytes());
+            i += 1;
+        }
+        let path = dir.write(name, &data);
+        (dir, path, data)
+    }
+
+    /// The offsets at which lines start, computed the simple way.
+    fn line_starts(data: &[u8]) -> Vec<usize> {
+        std::iter::once(0)
+            .chain(data.iter().enumerate().filter(|&(_, &b)| b == b'\n').map(|(i, _)| i + 1))
+            .collect()
+    }
+
+    #[test]
+    fn test_scroll_to_end() {
+        let (_dir, path, data) = temp_file("scroll");
+        let line_starts = line_starts(&data);
+        let mut sb = StreamingBuffer::open_with(&path, CHUNK, 4).unwrap();
+        assert_eq!(sb.len(), data.len());
+
+        assert_eq!(sb.read(0).unwrap(), &data[..CHUNK]);
+        assert_eq!(sb.chunk_loads(), 1);
+
+        // Showing the last 10 lines only reads the last chunk or two.
+        let mut off = sb.len();
+        for _ in 0..10 {
+            off = sb.line_start(off - 1).unwrap();
+        }
+        assert!(sb.chunk_loads() <= 3);
+        let line = line_starts.len() - 11;
+        assert_eq!(off, line_starts[line]);
+        assert_eq!(sb.next_line_start(off).unwrap(), line_starts[line + 1]);
+
+        // The line numbers there aren't known yet.
+        assert_eq!(sb.line_of_offset(off).unwrap(), None);
+
+        // Chunks that are still cached aren't read again.
+        let loads = sb.chunk_loads();
+        sb.read(0).unwrap();
+        sb.read(sb.len() - 1).unwrap();
+        assert_eq!(sb.chunk_loads(), loads);
+
+        assert_eq!(sb.replace(0..1, b"x"), Err(apperr::APP_READ_ONLY_STREAMING));
+    }
+
+    #[test]
+    fn test_find() {
+        let (_dir, path, mut data) = temp_file("find");
+        // Place needles across chunk boundaries.
+        let needle = b"NEEDLE";
+        let positions = [100 * CHUNK - 3, 100 * CHUNK + 10, 200 * CHUNK - 1];
+        for &pos in &positions {
+            data[pos..pos + needle.len()].copy_from_slice(needle);
+        }
+        fs::write(&path, &data).unwrap();
+
+        let mut sb = StreamingBuffer::open_with(&path, CHUNK, 4).unwrap();
+        let mut found = Vec::new();
+        let mut from = 0;
+        while let Some(pos) = sb.find(needle, from).unwrap() {
+            found.push(pos);
+            from = pos + 1;
+        }
+        assert_eq!(found, positions);
+
+        // Resuming a search only reads from where it left off.
+        let loads = sb.chunk_loads();
+        assert_eq!(sb.find(needle, positions[2] - 5).unwrap(), Some(positions[2]));
+        assert!(sb.chunk_loads() - loads <= 2);
+    }
+
+    #[test]
+    fn test_line_index() {
+        let (_dir, path, data) = temp_file("lines");
+        let line_starts = line_starts(&data);
+        let line_of = |off: usize| line_starts.partition_point(|&s| s <= off) - 1;
+
+        let mut sb = StreamingBuffer::open_with(&path, CHUNK, 4).unwrap();
+
+        // Visit the first 10 chunks.
+        let mut off = 0;
+        while off < 10 * CHUNK {
+            off += sb.read(off).unwrap().len();
+        }
+
+        for off in (0..10 * CHUNK).step_by(37) {
+            assert_eq!(sb.line_of_offset(off).unwrap(), Some(line_of(off)), "offset {off}");
+        }
+        assert_eq!(sb.line_of_offset(10 * CHUNK).unwrap(), Some(line_of(10 * CHUNK)));
+        assert_eq!(sb.line_of_offset(10 * CHUNK + 1).unwrap(), None);
+
+        for (line, &start) in line_starts[..line_of(9 * CHUNK)].iter().enumerate() {
+            assert_eq!(sb.offset_of_line(line).unwrap(), Some(start), "line {line}");
+        }
+        assert_eq!(sb.offset_of_line(line_of(20 * CHUNK)).unwrap(), None);
+
+        // Count the rest in steps.
+        let mut steps = 0;
+        while !sb.index_more(50).unwrap() {
+            steps += 1;
+        }
+        assert_eq!(steps, 4);
+        let last = line_starts.len() - 1;
+        assert_eq!(sb.line_of_offset(data.len()).unwrap(), Some(last));
+        assert_eq!(sb.offset_of_line(last).unwrap(), Some(line_starts[last]));
+    }
+}
@@ -0,0 +1,314 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Swap files for recovering unsaved changes after a crash.
+//!
+//! A swap file starts with [`MAGIC`], followed by a sequence of records:
+//! ```text
+//! kind: u8 | payload length: u32 LE | payload | checksum: u64 LE
+//! ```
+//! The checksum covers the kind, length and payload. A snapshot record contains the entire
+//! document. A delta record contains an edit relative to the previous state:
+//! ```text
+//! offset: u64 LE | deleted length: u64 LE | inserted text
+//! ```
+//! Records are only ever appended. If the editor crashes while writing one, the
+//! last record may be incomplete, which is why recovery stops at the first bad record.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+use crate::apperr;
+use crate::buffer::JournalEntry;
+use crate::hash::hash;
+
+const MAGIC: &[u8; 8] = b"EDITSWP1";
+const RECORD_SNAPSHOT: u8 = 1;
+const RECORD_DELTA: u8 = 2;
+const HEADER_LEN: usize = 1 + 4;
+const CHECKSUM_LEN: usize = 8;
+
+/// Returns the path of the swap file for the document at `path`.
+/// For `dir/file.txt` that's `dir/.file.txt.swp`.
+pub fn swap_path_for(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".swp");
+    path.with_file_name(name)
+}
+
+/// Appends records to a swap file.
+pub struct SwapWriter {
+    file: File,
+    path: PathBuf,
+    buf: Vec<u8>,
+}
+
+impl SwapWriter {
+    /// Creates (or truncates) the swap file at `path`.
+    pub fn create(path: &Path) -> apperr::Result<Self> {
+        let mut file = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+        file.write_all(MAGIC)?;
+        Ok(Self { file, path: path.to_path_buf(), buf: Vec::new() })
+    }
+
+    /// Appends a record containing the entire document `text`.
+    /// Recovery will start from the last snapshot.
+    pub fn append_snapshot(&mut self, text: &[u8]) -> apperr::Result<()> {
+        self.append(RECORD_SNAPSHOT, &[text])
+    }
+
+    /// Appends a record that replaces `deleted` bytes at `offset` with `inserted`.
+    pub fn append_delta(
+        &mut self,
+        offset: usize,
+        deleted: usize,
+        inserted: &[u8],
+    ) -> apperr::Result<()> {
+        let offset = (offset as u64).to_le_bytes();
+        let deleted = (deleted as u64).to_le_bytes();
+        self.append(RECORD_DELTA, &[&offset, &deleted, inserted])
+    }
+
+    /// Appends a delta record for each of the `edits`, see [`crate::buffer::TextBuffer::take_journal`].
+    pub fn append_journal(&mut self, edits: &[JournalEntry]) -> apperr::Result<()> {
+        for e in edits {
+            self.append_delta(e.offset, e.deleted, &e.inserted)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes all records to disk.
+    pub fn sync(&mut self) -> apperr::Result<()> {
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    /// Deletes the swap file. Call this after the document was saved or closed without changes.
+    pub fn remove(self) -> apperr::Result<()> {
+        drop(self.file);
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+
+    fn append(&mut self, kind: u8, parts: &[&[u8]]) -> apperr::Result<()> {
+        let len: usize = parts.iter().map(|p| p.len()).sum();
+        let len = u32::try_from(len).map_err(|_| apperr::APP_SWAP_RECORD_TOO_LARGE)?;
+
+        // The record is assembled in memory first, so that it's written with a single call.
+        // This makes it less likely for a crash to leave a partial record behind.
+        self.buf.clear();
+        self.buf.push(kind);
+        self.buf.extend_from_slice(&len.to_le_bytes());
+        for part in parts {
+            self.buf.extend_from_slice(part);
+        }
+        let checksum = hash(0, &self.buf);
+        self.buf.extend_from_slice(&checksum.to_le_bytes());
+
+        self.file.write_all(&self.buf)?;
+        Ok(())
+    }
+}
+
+/// The document contents recovered from a swap file.
+pub struct Recovery {
+    pub text: Vec<u8>,
+    /// The number of records that were replayed.
+    pub records: usize,
+    /// True if the file ended in a partial or corrupt record, which was skipped.
+    pub truncated: bool,
+}
+
+/// Reads the swap file at `path`, if any, and replays its contents.
+///
+/// Returns `None` if there's no swap file or if it doesn't contain a snapshot.
+pub fn recover(path: &Path) -> apperr::Result<Option<Recovery>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    Ok(recover_from_bytes(&data))
+}
+
+/// Replays the contents of a swap file. See [`recover`].
+pub fn recover_from_bytes(data: &[u8]) -> Option<Recovery> {
+    let mut data = data.strip_prefix(MAGIC)?;
+    let mut text = None;
+    let mut records = 0;
+    let mut truncated = false;
+
+    while !data.is_empty() {
+        let Some((kind, payload, rest)) = read_record(data) else {
+            truncated = true;
+            break;
+        };
+
+        match kind {
+            RECORD_SNAPSHOT => text = Some(payload.to_vec()),
+            RECORD_DELTA => {
+                let Some(text) = text.as_mut() else {
+                    truncated = true;
+                    break;
+                };
+                if !apply_delta(text, payload) {
+                    truncated = true;
+                    break;
+                }
+            }
+            _ => {
+                truncated = true;
+                break;
+            }
+        }
+
+        records += 1;
+        data = rest;
+    }
+
+    text.map(|text| Recovery { text, records, truncated })
+}
+
+/// Splits off the first record of `data` and verifies its checksum.
+fn read_record(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let header = data.get(..HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[1..].try_into().unwrap()) as usize;
+    let end = HEADER_LEN.checked_add(len)?;
+    let record = data.get(..end)?;
+    let checksum = data.get(end..end.checked_add(CHECKSUM_LEN)?)?;
+
+    if hash(0, record).to_le_bytes() != checksum {
+        return None;
+    }
+    Some((header[0], &record[HEADER_LEN..], &data[end + CHECKSUM_LEN..]))
+}
+
+fn apply_delta(text: &mut Vec<u8>, payload: &[u8]) -> bool {
+    if payload.len() < 16 {
+        return false;
+    }
+    let offset = u64::from_le_bytes(payload[..8].try_into().unwrap());
+    let deleted = u64::from_le_bytes(payload[8..16].try_into().unwrap());
+    let inserted = &payload[16..];
+
+    let Ok(offset) = usize::try_from(offset) else {
+        return false;
+    };
+    let Some(end) = usize::try_from(deleted).ok().and_then(|d| offset.checked_add(d)) else {
+        return false;
+    };
+    if end > text.len() {
+        return false;
+    }
+
+    text.splice(offset..end, inserted.iter().copied());
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::CursorMovement;
+    use crate::helpers::Point;
+    use crate::testing::{TempDir, buffer_from, contents};
+
+    /// Writes a swap file via `f`, returns its contents and deletes it.
+    fn build(f: impl FnOnce(&mut SwapWriter)) -> Vec<u8> {
+        let dir = TempDir::new("swap");
+        let path = dir.join("file.swp");
+        let mut writer = SwapWriter::create(&path).unwrap();
+        f(&mut writer);
+        let data = fs::read(&path).unwrap();
+        writer.remove().unwrap();
+        assert!(!path.exists());
+        data
+    }
+
+    #[test]
+    fn test_swap_path() {
+        assert_eq!(swap_path_for(Path::new("/a/b.txt")), Path::new("/a/.b.txt.swp"));
+        assert_eq!(swap_path_for(Path::new("b")), Path::new(".b.swp"));
+    }
+
+    #[test]
+    fn test_replay() {
+        let data = build(|w| {
+            w.append_snapshot(b"hello world").unwrap();
+            w.append_delta(5, 6, b", swap").unwrap();
+            w.append_snapshot(b"fresh").unwrap();
+            w.append_delta(5, 0, b"er").unwrap();
+            w.append_delta(0, 1, b"").unwrap();
+        });
+
+        let recovery = recover_from_bytes(&data).unwrap();
+        assert_eq!(recovery.text, b"resher");
+        assert_eq!(recovery.records, 5);
+        assert!(!recovery.truncated);
+    }
+
+    #[test]
+    fn test_journal() {
+        let mut tb = buffer_from("hello\nworld\n");
+        tb.set_journaling(true);
+
+        tb.cursor_move_to_logical(Point { x: 5, y: 0 });
+        tb.write_canon(b", dear");
+        tb.delete(CursorMovement::Word, -1);
+        tb.undo();
+        tb.redo();
+        tb.cursor_move_to_logical(Point { x: 0, y: 1 });
+        tb.write_raw(b"big\n");
+        tb.normalize_newlines(true);
+        tb.select_all();
+        tb.write_canon(b"gone");
+        tb.undo();
+
+        let edits = tb.take_journal();
+        assert!(!edits.is_empty());
+        let data = build(|w| {
+            w.append_snapshot(b"hello\nworld\n").unwrap();
+            w.append_journal(&edits).unwrap();
+        });
+        assert_eq!(recover_from_bytes(&data).unwrap().text, contents(&tb).as_bytes());
+        assert!(tb.take_journal().is_empty());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let data = build(|w| {
+            w.append_snapshot(b"hello").unwrap();
+            w.append_delta(5, 0, b" world").unwrap();
+            w.append_delta(0, 0, b">> ").unwrap();
+        });
+
+        // Cutting the file anywhere in the last record recovers everything before it.
+        let last_record_len = HEADER_LEN + 16 + 3 + CHECKSUM_LEN;
+        for cut in 1..=last_record_len {
+            let recovery = recover_from_bytes(&data[..data.len() - cut]).unwrap();
+            assert_eq!(recovery.text, b"hello world");
+            assert_eq!(recovery.records, 2);
+            assert_eq!(recovery.truncated, cut != last_record_len);
+        }
+
+        // A corrupted byte invalidates its record and everything after it.
+        let mut corrupt = data.clone();
+        let len = corrupt.len();
+        corrupt[len - last_record_len - 10] ^= 1;
+        let recovery = recover_from_bytes(&corrupt).unwrap();
+        assert_eq!(recovery.text, b"hello");
+        assert!(recovery.truncated);
+
+        // Without a complete snapshot, there's nothing to recover.
+        assert!(recover_from_bytes(&data[..MAGIC.len() + 3]).is_none());
+        assert!(recover_from_bytes(b"garbage").is_none());
+    }
+
+    #[test]
+    fn test_recover_missing_file() {
+        let path = std::env::temp_dir().join("edit-swap-test-does-not-exist");
+        assert!(recover(&path).unwrap().is_none());
+    }
+}
@@ -0,0 +1,81 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Helpers shared by the unit tests.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::buffer::TextBuffer;
+
+/// A fresh directory below the system's temporary directory, deleted with its contents on drop.
+///
+/// Its name contains the process ID and a counter, so that tests running in parallel,
+/// in this process or another, never share one.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` only makes the directory easier to recognize.
+    pub fn new(name: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path =
+            std::env::temp_dir().join(format!("edit-test-{}-{id}-{name}", std::process::id()));
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Returns the path of `name` within the directory.
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.0.join(name)
+    }
+
+    /// Writes `contents` to the file `name` within the directory and returns its path.
+    pub fn write(&self, name: &str, contents: &[u8]) -> PathBuf {
+        let path = self.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Creates a buffer containing `text`, with its newlines normalized to LF.
+/// The cursor ends up at the end of the text.
+pub fn buffer_from(text: &str) -> TextBuffer {
+    let mut tb = TextBuffer::new(true).unwrap();
+    tb.set_crlf(false);
+    tb.write_raw(text.as_bytes());
+    tb
+}
+
+/// Like [`buffer_from`], but the newlines are kept as they are.
+pub fn buffer_from_unnormalized(text: &str) -> TextBuffer {
+    let mut tb = TextBuffer::new(true).unwrap();
+    tb.set_crlf(false);
+    tb.reload_from_bytes(text.as_bytes());
+    tb
+}
+
+/// Returns the contents of `tb`. Unlike [`TextBuffer::save_as_string`],
+/// this doesn't mark the buffer as clean.
+pub fn contents(tb: &TextBuffer) -> String {
+    let mut out = Vec::new();
+    loop {
+        let chunk = tb.read_forward(out.len());
+        if chunk.is_empty() {
+            break;
+        }
+        out.extend_from_slice(chunk);
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
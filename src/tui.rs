@@ -553,6 +553,7 @@ impl Tui {
                 }
             }
             Some(Input::Paste(paste)) => {
+                let paste = input::sanitize_paste(&paste);
                 let clipboard = self.clipboard_mut();
                 clipboard.write(paste);
                 clipboard.mark_as_synchronized();
@@ -1190,7 +1191,7 @@ impl Tui {
                 match &node.content {
                     NodeContent::Text(content) => {
                         result.push_repeat(' ', depth * 2);
-                        _ = write!(result, "  text:         \"{}\"\r\n", &content.text);
+                        _ = write!(result, "  text:         \"{}\"\r\n", content.text);
                     }
                     NodeContent::Textarea(content) => {
                         let tb = content.buffer.borrow();
@@ -2844,12 +2845,10 @@ impl<'a> Context<'a, '_> {
                     InputMouseState::Release => {
                         sc.scroll_offset_y_drag_start = CoordType::MIN;
                     }
-                    InputMouseState::Scroll => {
-                        if container_rect.contains(self.tui.mouse_position) {
-                            sc.scroll_offset.x += self.input_scroll_delta.x;
-                            sc.scroll_offset.y += self.input_scroll_delta.y;
-                            self.set_input_consumed();
-                        }
+                    InputMouseState::Scroll if container_rect.contains(self.tui.mouse_position) => {
+                        sc.scroll_offset.x += self.input_scroll_delta.x;
+                        sc.scroll_offset.y += self.input_scroll_delta.y;
+                        self.set_input_consumed();
                     }
                     _ => {}
                 }
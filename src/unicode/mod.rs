@@ -6,6 +6,8 @@
 mod measurement;
 mod tables;
 mod utf8;
+mod visualize;
 
 pub use measurement::*;
 pub use utf8::*;
+pub use visualize::*;
@@ -0,0 +1,273 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Turns a line of raw bytes into something that can be printed to the terminal.
+//!
+//! C0 controls are shown in caret notation (`^A`, `^?` for DEL), C1 controls as `<U+85>`
+//! and bytes that aren't valid UTF-8 as `<9F>`. Non-breaking spaces and invisible characters
+//! can be replaced with visible markers. Since that makes a single byte take up several columns,
+//! [`VisualLine`] remembers for each column which byte of the source it came from,
+//! which allows mapping cursor positions and selections back and forth.
+
+use std::fmt::Write as _;
+use std::ops::Range;
+
+use super::{MeasurementConfig, Utf8Chars};
+use crate::helpers::{CoordType, Point};
+
+/// How [`VisualLine::push_bytes`] shows characters that would otherwise be invisible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VisualizeConfig {
+    pub tab_size: CoordType,
+    /// Replaces U+00A0 and U+202F, if set.
+    pub nbsp: Option<char>,
+    /// Replaces zero-width spaces, word joiners and BOMs, if set.
+    /// Zero-width joiners are left alone, since they're part of many emoji.
+    pub zero_width: Option<char>,
+}
+
+impl Default for VisualizeConfig {
+    fn default() -> Self {
+        Self { tab_size: 4, nbsp: Some('⍽'), zero_width: Some('¦') }
+    }
+}
+
+/// What a column of a [`VisualLine`] shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellKind {
+    Text,
+    /// A control character in caret or `<U+..>` notation.
+    Control,
+    /// An invalid byte in `<..>` notation.
+    Invalid,
+    /// A marker for a non-breaking space or an invisible character.
+    Marker,
+}
+
+/// A single column of a [`VisualLine`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VisualCell {
+    /// The offset of the source character this column belongs to.
+    /// All columns of a wide or escaped character have the same offset.
+    pub offset: usize,
+    pub kind: CellKind,
+}
+
+/// A line ready to be printed, with a mapping from its columns to the source bytes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct VisualLine {
+    text: String,
+    cells: Vec<VisualCell>,
+    /// The source offset just past the last pushed byte.
+    end: usize,
+}
+
+impl VisualLine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cells.clear();
+        self.end = 0;
+    }
+
+    /// The text to print.
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// One entry per column of [`VisualLine::text`].
+    pub fn cells(&self) -> &[VisualCell] {
+        &self.cells
+    }
+
+    /// Appends `bytes`, which start at `offset` in the source.
+    /// When a line is pushed in several parts, they must not split characters.
+    pub fn push_bytes(&mut self, bytes: &[u8], offset: usize, config: &VisualizeConfig) {
+        let mut it = Utf8Chars::new(bytes, 0);
+        // The start of the current run of regular characters.
+        let mut run_beg = 0;
+
+        if self.cells.is_empty() {
+            self.end = offset;
+        }
+
+        loop {
+            let beg = it.offset();
+            let Some(ch) = it.next() else {
+                break;
+            };
+            let end = it.offset();
+            let off = offset + beg;
+
+            let invalid = ch == '\u{FFFD}' && &bytes[beg..end] != "\u{FFFD}".as_bytes();
+            let special = invalid
+                || ch < ' '
+                || ('\x7f'..='\u{9f}').contains(&ch)
+                || (config.nbsp.is_some() && is_nbsp(ch))
+                || (config.zero_width.is_some() && is_zero_width(ch));
+            if !special {
+                continue;
+            }
+
+            self.push_run(&bytes[run_beg..beg], offset + run_beg);
+            run_beg = end;
+
+            if invalid {
+                for (i, b) in bytes[beg..end].iter().enumerate() {
+                    self.push_escape(
+                        format_args!("<{b:02X}>"),
+                        offset + beg + i,
+                        CellKind::Invalid,
+                    );
+                }
+            } else if ch == '\t' {
+                let tab_size = config.tab_size.max(1) as usize;
+                let width = tab_size - self.cells.len() % tab_size;
+                for _ in 0..width {
+                    self.text.push(' ');
+                    self.cells.push(VisualCell { offset: off, kind: CellKind::Text });
+                }
+            } else if ch < ' ' || ch == '\x7f' {
+                let caret = (ch as u8 ^ 0x40) as char;
+                self.push_escape(format_args!("^{caret}"), off, CellKind::Control);
+            } else if ch <= '\u{9f}' {
+                self.push_escape(format_args!("<U+{:02X}>", ch as u32), off, CellKind::Control);
+            } else {
+                let marker = if is_nbsp(ch) { config.nbsp } else { config.zero_width };
+                self.text.push(marker.unwrap());
+                self.cells.push(VisualCell { offset: off, kind: CellKind::Marker });
+            }
+        }
+
+        self.push_run(&bytes[run_beg..], offset + run_beg);
+        self.end = offset + bytes.len();
+    }
+
+    /// Returns the column at which a cursor at the source `offset` is shown.
+    pub fn column_of_offset(&self, offset: usize) -> CoordType {
+        self.cells.partition_point(|c| c.offset < offset) as CoordType
+    }
+
+    /// Returns the source offset of the character shown at `column`.
+    /// Columns within a wide or escaped character map to its start.
+    pub fn offset_of_column(&self, column: CoordType) -> usize {
+        match self.cells.get(column.max(0) as usize) {
+            Some(cell) => cell.offset,
+            None => self.end,
+        }
+    }
+
+    /// Returns the columns that show the source bytes in `range`, e.g. to highlight a selection.
+    pub fn columns_of_range(&self, range: Range<usize>) -> Range<CoordType> {
+        self.column_of_offset(range.start)..self.column_of_offset(range.end)
+    }
+
+    /// Appends regular text, which may contain wide characters and grapheme clusters.
+    fn push_run(&mut self, run: &[u8], offset: usize) {
+        let mut cfg = MeasurementConfig::new(&run);
+        let mut prev = cfg.goto_offset(0);
+
+        loop {
+            let next = cfg.goto_logical(Point { x: prev.logical_pos.x + 1, y: 0 });
+            if next.offset == prev.offset {
+                break;
+            }
+
+            // SAFETY: The run only consists of valid UTF-8, since invalid bytes are special.
+            self.text.push_str(unsafe { str::from_utf8_unchecked(&run[prev.offset..next.offset]) });
+            for _ in prev.visual_pos.x..next.visual_pos.x {
+                self.cells.push(VisualCell { offset: offset + prev.offset, kind: CellKind::Text });
+            }
+            prev = next;
+        }
+    }
+
+    fn push_escape(&mut self, args: std::fmt::Arguments, offset: usize, kind: CellKind) {
+        let len = self.text.len();
+        _ = self.text.write_fmt(args);
+        // All escapes are ASCII and thus one column per byte.
+        for _ in len..self.text.len() {
+            self.cells.push(VisualCell { offset, kind });
+        }
+    }
+}
+
+fn is_nbsp(ch: char) -> bool {
+    matches!(ch, '\u{a0}' | '\u{202f}')
+}
+
+fn is_zero_width(ch: char) -> bool {
+    matches!(ch, '\u{200b}' | '\u{200c}' | '\u{2060}' | '\u{feff}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn visualize(bytes: &[u8], config: &VisualizeConfig) -> VisualLine {
+        let mut line = VisualLine::new();
+        line.push_bytes(bytes, 0, config);
+        line
+    }
+
+    #[test]
+    fn test_escapes() {
+        let config = VisualizeConfig::default();
+        let line = visualize(b"a\x00b\x7f\tc\xC2\x85\xE2\x82 \xEF\xBF\xBD", &config);
+        assert_eq!(line.text(), "a^@b^?  c<U+85><E2><82> \u{FFFD}");
+
+        let kinds: Vec<_> = line.cells().iter().map(|c| c.kind).collect();
+        assert_eq!(kinds[1..3], [CellKind::Control; 2]);
+        assert_eq!(kinds[9..15], [CellKind::Control; 6]);
+        assert_eq!(kinds[15..23], [CellKind::Invalid; 8]);
+        assert_eq!(line.cells().len(), 25);
+
+        // Markers can be configured or turned off.
+        let text = "a\u{a0}b\u{200b}c\u{200d}";
+        assert_eq!(visualize(text.as_bytes(), &config).text(), "a⍽b¦c\u{200d}");
+        let config = VisualizeConfig { nbsp: Some('_'), zero_width: None, ..config };
+        assert_eq!(visualize(text.as_bytes(), &config).text(), "a_b\u{200b}c\u{200d}");
+    }
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let line = visualize(b"a\x01b\x9Fc", &VisualizeConfig::default());
+        assert_eq!(line.text(), "a^Ab<9F>c");
+
+        let columns: Vec<_> = (0..=5).map(|off| line.column_of_offset(off)).collect();
+        assert_eq!(columns, [0, 1, 3, 4, 8, 9]);
+        for off in 0..=5 {
+            assert_eq!(line.offset_of_column(line.column_of_offset(off)), off);
+        }
+
+        // Clicking anywhere on an escape puts the cursor in front of it.
+        let offsets: Vec<_> = (0..=9).map(|col| line.offset_of_column(col)).collect();
+        assert_eq!(offsets, [0, 1, 1, 2, 3, 3, 3, 3, 4, 5]);
+        assert_eq!(line.offset_of_column(100), 5);
+    }
+
+    #[test]
+    fn test_selection() {
+        let line = visualize(b"a\x01b\xC2\x9F\xF0\x9F\x99\x82\x9Fc", &VisualizeConfig::default());
+        assert_eq!(line.text(), "a^Ab<U+9F>🙂<9F>c");
+
+        // Selecting "^A" and "b" covers exactly their columns.
+        assert_eq!(line.columns_of_range(1..3), 1..4);
+        // The C1 control, the emoji and the invalid byte.
+        assert_eq!(line.columns_of_range(3..10), 4..16);
+        let cells = &line.cells()[4..16];
+        assert!(cells.iter().all(|c| (3..10).contains(&c.offset)));
+        assert_eq!(line.cells()[16].offset, 10);
+
+        // Lines can be assembled from several chunks.
+        let mut chunked = VisualLine::new();
+        let bytes = b"a\x01b\xC2\x9F\xF0\x9F\x99\x82\x9Fc";
+        let config = VisualizeConfig::default();
+        chunked.push_bytes(&bytes[..5], 0, &config);
+        chunked.push_bytes(&bytes[5..], 5, &config);
+        assert_eq!(chunked, line);
+    }
+}
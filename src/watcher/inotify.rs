@@ -0,0 +1,192 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::ffi::{CStr, CString, OsStr};
+use std::io;
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use super::{EventQueue, WatchEvent, WatchEventKind, Watcher};
+use crate::apperr;
+
+const MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_MODIFY
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_MOVED_FROM
+    | libc::IN_MOVED_TO;
+
+struct Watched {
+    path: PathBuf,
+    /// The watch descriptor of its directory.
+    wd: libc::c_int,
+    name: PathBuf,
+}
+
+/// Uses inotify to watch the directories that contain the watched files.
+///
+/// Renames are only detected between watched directories.
+/// A file that was moved elsewhere is reported as deleted.
+pub struct InotifyWatcher {
+    fd: OwnedFd,
+    watched: Vec<Watched>,
+}
+
+impl InotifyWatcher {
+    pub fn new() -> apperr::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(Self { fd: unsafe { OwnedFd::from_raw_fd(fd) }, watched: Vec::new() })
+    }
+
+    fn find(&self, wd: libc::c_int, name: &OsStr) -> Option<&Watched> {
+        self.watched.iter().find(|w| w.wd == wd && w.name.as_os_str() == name)
+    }
+
+    fn dir_of(&self, wd: libc::c_int) -> Option<&Path> {
+        self.watched.iter().find(|w| w.wd == wd).and_then(|w| w.path.parent())
+    }
+}
+
+impl Watcher for InotifyWatcher {
+    fn watch(&mut self, path: &Path) -> apperr::Result<()> {
+        if self.watched.iter().any(|w| w.path == path) {
+            return Ok(());
+        }
+
+        let name = path
+            .file_name()
+            .ok_or(apperr::Error::from(io::Error::from(io::ErrorKind::InvalidInput)))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = CString::new(dir.as_os_str().as_bytes())
+            .map_err(|_| apperr::Error::from(io::Error::from(io::ErrorKind::InvalidInput)))?;
+
+        // Watching the same directory twice returns the same descriptor.
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), dir.as_ptr(), MASK) };
+        if wd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        self.watched.push(Watched { path: path.to_path_buf(), wd, name: PathBuf::from(name) });
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) {
+        let Some(i) = self.watched.iter().position(|w| w.path == path) else {
+            return;
+        };
+        let wd = self.watched.remove(i).wd;
+        if !self.watched.iter().any(|w| w.wd == wd) {
+            unsafe { libc::inotify_rm_watch(self.fd.as_raw_fd(), wd) };
+        }
+    }
+
+    fn poll(&mut self, queue: &mut EventQueue) {
+        // Aligned for `libc::inotify_event`.
+        let mut buf = [0u64; 512];
+        // Files that were moved away, by cookie, until the matching `IN_MOVED_TO` shows up.
+        let mut moved_from: Vec<(u32, PathBuf)> = Vec::new();
+
+        loop {
+            let len = unsafe {
+                libc::read(self.fd.as_raw_fd(), buf.as_mut_ptr() as *mut _, size_of_val(&buf))
+            };
+            if len <= 0 {
+                break;
+            }
+
+            let bytes = unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, len as _) };
+            let mut off = 0;
+
+            while off + size_of::<libc::inotify_event>() <= bytes.len() {
+                let event = unsafe {
+                    (bytes.as_ptr().add(off) as *const libc::inotify_event).read_unaligned()
+                };
+                let name_beg = off + size_of::<libc::inotify_event>();
+                let name_end = (name_beg + event.len as usize).min(bytes.len());
+                let name = CStr::from_bytes_until_nul(&bytes[name_beg..name_end])
+                    .map(|s| OsStr::from_bytes(s.to_bytes()))
+                    .unwrap_or_default();
+                off = name_end;
+
+                if event.mask & libc::IN_MOVED_TO != 0
+                    && let Some(i) = moved_from.iter().position(|m| m.0 == event.cookie)
+                    && let Some(dir) = self.dir_of(event.wd)
+                {
+                    let from = moved_from.swap_remove(i).1;
+                    let kind = WatchEventKind::Renamed(dir.join(name));
+                    queue.push(WatchEvent { path: from, kind });
+                }
+
+                let Some(w) = self.find(event.wd, name) else {
+                    continue;
+                };
+
+                if event.mask & libc::IN_MOVED_FROM != 0 {
+                    moved_from.push((event.cookie, w.path.clone()));
+                } else if event.mask & libc::IN_DELETE != 0 {
+                    queue.push(WatchEvent { path: w.path.clone(), kind: WatchEventKind::Deleted });
+                } else {
+                    queue.push(WatchEvent { path: w.path.clone(), kind: WatchEventKind::Modified });
+                }
+            }
+        }
+
+        // Moved to a directory that isn't watched.
+        for (_, path) in moved_from {
+            queue.push(WatchEvent { path, kind: WatchEventKind::Deleted });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_events() {
+        let dir = TempDir::new("inotify");
+        let path = dir.join("foo.txt");
+        let to = dir.join("bar.txt");
+        fs::write(&path, "hello").unwrap();
+
+        let mut watcher = InotifyWatcher::new().unwrap();
+        let mut queue = EventQueue::new(8);
+        watcher.watch(&path).unwrap();
+
+        for i in 0..5 {
+            fs::write(&path, "x".repeat(i)).unwrap();
+        }
+        watcher.poll(&mut queue);
+        assert_eq!(
+            queue.pop(),
+            Some(WatchEvent { path: path.clone(), kind: WatchEventKind::Modified })
+        );
+        assert_eq!(queue.pop(), None);
+
+        fs::rename(&path, &to).unwrap();
+        watcher.poll(&mut queue);
+        assert_eq!(
+            queue.pop(),
+            Some(WatchEvent { path: path.clone(), kind: WatchEventKind::Renamed(to.clone()) })
+        );
+
+        fs::write(&path, "again").unwrap();
+        fs::remove_file(&path).unwrap();
+        watcher.poll(&mut queue);
+        assert_eq!(
+            queue.pop(),
+            Some(WatchEvent { path: path.clone(), kind: WatchEventKind::Deleted })
+        );
+    }
+}
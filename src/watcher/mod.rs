@@ -0,0 +1,139 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Detecting external changes to open files.
+//!
+//! A [`Watcher`] delivers [`WatchEvent`]s into an [`EventQueue`], which the main loop drains
+//! once per frame. The queue is bounded and coalesces events: A burst of writes to the same file
+//! results in a single pending event for it, and if too many files change at once, the queue
+//! overflows and the main loop should simply check all of them.
+//!
+//! [`PollingWatcher`] works everywhere. With the `inotify` feature, [`InotifyWatcher`]
+//! is available on Linux. [`new_watcher`] picks the best one.
+
+#[cfg(all(target_os = "linux", feature = "inotify"))]
+mod inotify;
+mod polling;
+
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+#[cfg(all(target_os = "linux", feature = "inotify"))]
+pub use inotify::*;
+pub use polling::*;
+
+use crate::apperr;
+
+/// What happened to a watched file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WatchEventKind {
+    /// The contents changed, or the file was (re)created.
+    Modified,
+    Deleted,
+    /// The file was moved to the given path.
+    Renamed(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// The path as it was passed to [`Watcher::watch`].
+    pub path: PathBuf,
+    pub kind: WatchEventKind,
+}
+
+/// A bounded queue of [`WatchEvent`]s with at most one pending event per path.
+pub struct EventQueue {
+    events: VecDeque<WatchEvent>,
+    capacity: usize,
+    overflowed: bool,
+}
+
+impl EventQueue {
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        Self { events: VecDeque::with_capacity(capacity), capacity, overflowed: false }
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Adds an event. If there's already one pending for the same path, it's replaced,
+    /// since only the latest state of the file matters. If the queue is full, the event
+    /// is dropped and the queue is marked as overflowed.
+    pub fn push(&mut self, event: WatchEvent) {
+        if let Some(pending) = self.events.iter_mut().find(|e| e.path == event.path) {
+            pending.kind = event.kind;
+        } else if self.events.len() < self.capacity {
+            self.events.push_back(event);
+        } else {
+            self.overflowed = true;
+        }
+    }
+
+    /// Removes the oldest event.
+    pub fn pop(&mut self) -> Option<WatchEvent> {
+        self.events.pop_front()
+    }
+
+    /// Returns whether events were dropped since the last call.
+    /// If so, the caller should check all watched files itself.
+    pub fn take_overflow(&mut self) -> bool {
+        std::mem::take(&mut self.overflowed)
+    }
+}
+
+/// A source of [`WatchEvent`]s.
+pub trait Watcher {
+    /// Starts watching the file at `path`. Watching it again does nothing.
+    fn watch(&mut self, path: &Path) -> apperr::Result<()>;
+
+    /// Stops watching the file at `path`.
+    fn unwatch(&mut self, path: &Path);
+
+    /// Pushes the events that happened since the last call into `queue`.
+    /// Doesn't block. Should be called about once per frame.
+    fn poll(&mut self, queue: &mut EventQueue);
+}
+
+/// Returns the platform's watcher if it's available and otherwise a [`PollingWatcher`].
+pub fn new_watcher() -> Box<dyn Watcher> {
+    #[cfg(all(target_os = "linux", feature = "inotify"))]
+    if let Ok(watcher) = InotifyWatcher::new() {
+        return Box::new(watcher);
+    }
+
+    Box::new(PollingWatcher::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(path: &str, kind: WatchEventKind) -> WatchEvent {
+        WatchEvent { path: PathBuf::from(path), kind }
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut queue = EventQueue::new(2);
+
+        queue.push(event("a", WatchEventKind::Modified));
+        queue.push(event("b", WatchEventKind::Modified));
+        queue.push(event("a", WatchEventKind::Deleted));
+        assert_eq!(queue.len(), 2);
+        assert!(!queue.take_overflow());
+
+        queue.push(event("c", WatchEventKind::Modified));
+        assert!(queue.take_overflow());
+        assert!(!queue.take_overflow());
+
+        assert_eq!(queue.pop(), Some(event("a", WatchEventKind::Deleted)));
+        assert_eq!(queue.pop(), Some(event("b", WatchEventKind::Modified)));
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use super::{EventQueue, WatchEvent, WatchEventKind, Watcher};
+use crate::content_hash::{ChangeStatus, ContentHashes};
+use crate::{apperr, sys};
+
+struct Watched {
+    path: PathBuf,
+    /// Size and modification time as of the last check. Only if they change,
+    /// the contents are hashed, because touching a file doesn't count as modifying it.
+    stat: Option<(u64, SystemTime)>,
+    hashes: ContentHashes,
+    /// Used to find the file again after it was renamed.
+    id: Option<sys::FileId>,
+    present: bool,
+}
+
+impl Watched {
+    fn refresh(&mut self) {
+        self.stat = stat(&self.path);
+        self.hashes = ContentHashes::of_file(&self.path).unwrap_or_default();
+        self.id = sys::file_id(None, &self.path).ok();
+        self.present = self.stat.is_some();
+    }
+}
+
+/// Checks the watched files for changes on each [`Watcher::poll`].
+///
+/// Renames are only detected within the same directory. A file that was moved
+/// elsewhere is reported as deleted.
+pub struct PollingWatcher {
+    watched: Vec<Watched>,
+}
+
+impl PollingWatcher {
+    pub fn new() -> Self {
+        Self { watched: Vec::new() }
+    }
+}
+
+impl Watcher for PollingWatcher {
+    fn watch(&mut self, path: &Path) -> apperr::Result<()> {
+        if !self.watched.iter().any(|w| w.path == path) {
+            let mut watched = Watched {
+                path: path.to_path_buf(),
+                stat: None,
+                hashes: ContentHashes::default(),
+                id: None,
+                present: false,
+            };
+            watched.refresh();
+            self.watched.push(watched);
+        }
+        Ok(())
+    }
+
+    fn unwatch(&mut self, path: &Path) {
+        self.watched.retain(|w| w.path != path);
+    }
+
+    fn poll(&mut self, queue: &mut EventQueue) {
+        for w in &mut self.watched {
+            let stat = stat(&w.path);
+            if stat == w.stat {
+                continue;
+            }
+
+            let kind = if !w.present {
+                WatchEventKind::Modified
+            } else {
+                match w.hashes.check_file(&w.path) {
+                    ChangeStatus::Unchanged => {
+                        w.stat = stat;
+                        continue;
+                    }
+                    ChangeStatus::Changed => WatchEventKind::Modified,
+                    // It exists, but can't be read, e.g. due to its permissions. Try again later.
+                    ChangeStatus::Unreadable if stat.is_some() => continue,
+                    ChangeStatus::Unreadable => match find_renamed(w) {
+                        Some(to) => WatchEventKind::Renamed(to),
+                        None => WatchEventKind::Deleted,
+                    },
+                }
+            };
+
+            w.refresh();
+            queue.push(WatchEvent { path: w.path.clone(), kind });
+        }
+    }
+}
+
+fn stat(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// Looks for the file `w` used to refer to in the same directory.
+fn find_renamed(w: &Watched) -> Option<PathBuf> {
+    let id = w.id.as_ref()?;
+    let dir = w.path.parent()?;
+    let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| path.is_file() && sys::file_id(None, path).is_ok_and(|other| &other == id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    fn setup(name: &str) -> (TempDir, PathBuf, PollingWatcher, EventQueue) {
+        let dir = TempDir::new(name);
+        let path = dir.join("foo.txt");
+        fs::write(&path, "hello").unwrap();
+        let mut watcher = PollingWatcher::new();
+        watcher.watch(&path).unwrap();
+        (dir, path, watcher, EventQueue::new(8))
+    }
+
+    fn drain(queue: &mut EventQueue) -> Vec<WatchEvent> {
+        std::iter::from_fn(|| queue.pop()).collect()
+    }
+
+    #[test]
+    fn test_modify() {
+        let (_dir, path, mut watcher, mut queue) = setup("modify");
+
+        watcher.poll(&mut queue);
+        assert!(queue.is_empty());
+
+        fs::write(&path, "hello world").unwrap();
+        watcher.poll(&mut queue);
+        assert_eq!(
+            drain(&mut queue),
+            [WatchEvent { path: path.clone(), kind: WatchEventKind::Modified }]
+        );
+
+        // Rewriting the same contents isn't a modification.
+        fs::write(&path, "hello world").unwrap();
+        watcher.poll(&mut queue);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_delete() {
+        let (_dir, path, mut watcher, mut queue) = setup("delete");
+
+        fs::remove_file(&path).unwrap();
+        watcher.poll(&mut queue);
+        assert_eq!(
+            drain(&mut queue),
+            [WatchEvent { path: path.clone(), kind: WatchEventKind::Deleted }]
+        );
+        watcher.poll(&mut queue);
+        assert!(queue.is_empty());
+
+        // Recreating it counts as a modification.
+        fs::write(&path, "again").unwrap();
+        watcher.poll(&mut queue);
+        assert_eq!(
+            drain(&mut queue),
+            [WatchEvent { path: path.clone(), kind: WatchEventKind::Modified }]
+        );
+    }
+
+    #[test]
+    fn test_rename() {
+        let (dir, path, mut watcher, mut queue) = setup("rename");
+        let to = dir.join("bar.txt");
+
+        fs::rename(&path, &to).unwrap();
+        watcher.poll(&mut queue);
+        assert_eq!(
+            drain(&mut queue),
+            [WatchEvent { path: path.clone(), kind: WatchEventKind::Renamed(to) }]
+        );
+    }
+
+    #[test]
+    fn test_burst() {
+        let (dir, path, mut watcher, mut queue) = setup("burst");
+        let other = dir.join("other.txt");
+        fs::write(&other, "other").unwrap();
+        watcher.watch(&other).unwrap();
+
+        // Several writes to the same file, which the main loop didn't get around to handling.
+        for i in 0..5 {
+            fs::write(&path, "x".repeat(i + 10)).unwrap();
+            watcher.poll(&mut queue);
+        }
+        fs::remove_file(&other).unwrap();
+        watcher.poll(&mut queue);
+
+        assert_eq!(
+            drain(&mut queue),
+            [
+                WatchEvent { path: path.clone(), kind: WatchEventKind::Modified },
+                WatchEvent { path: other.clone(), kind: WatchEventKind::Deleted },
+            ]
+        );
+
+        // Unwatched files aren't reported anymore.
+        watcher.unwatch(&path);
+        fs::write(&path, "unwatched").unwrap();
+        watcher.poll(&mut queue);
+        assert!(queue.is_empty());
+    }
+}
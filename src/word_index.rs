@@ -0,0 +1,325 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An index of the words in a document, for completing words as they're typed.
+//!
+//! Like the [`crate::highlight::HighlightCache`], the index keeps track of each line,
+//! so that after an edit only the changed lines need to be scanned again.
+//! Words are reference counted across lines: Once the last line containing a word
+//! is removed or changed, the word isn't suggested anymore.
+
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+
+use crate::unicode::Utf8Chars;
+
+/// Limits that keep the index small for huge files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WordIndexOptions {
+    /// Shorter words aren't worth completing and aren't indexed.
+    pub min_len: usize,
+    /// Once the index contains this many distinct words, new ones are ignored.
+    pub max_words: usize,
+}
+
+impl Default for WordIndexOptions {
+    fn default() -> Self {
+        Self { min_len: 3, max_words: 50_000 }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct WordEntry {
+    /// The number of occurrences in the document.
+    count: usize,
+    /// The [`WordIndex::generation`] in which the word was last seen in an edited line.
+    last_seen: u64,
+}
+
+#[derive(Debug, Default, Clone)]
+struct IndexedLine {
+    /// The words counted for this line. They may be outdated, if the line is dirty.
+    words: Vec<Box<str>>,
+    dirty: bool,
+}
+
+/// Indexes the words of a document. See the module documentation.
+///
+/// The owner is expected to report all edits via [`WordIndex::line_changed`],
+/// [`WordIndex::lines_inserted`] and [`WordIndex::lines_removed`],
+/// and to call [`WordIndex::update`] before calling [`WordIndex::complete`].
+#[derive(Debug, Default, Clone)]
+pub struct WordIndex {
+    options: WordIndexOptions,
+    lines: Vec<IndexedLine>,
+    words: BTreeMap<Box<str>, WordEntry>,
+    dirty_count: usize,
+    /// Incremented on each [`WordIndex::update`], to rank recently typed words higher.
+    generation: u64,
+}
+
+impl WordIndex {
+    /// Creates an index for a document with the given number of lines.
+    /// All of them are scanned on the first [`WordIndex::update`].
+    pub fn new(line_count: usize, options: WordIndexOptions) -> Self {
+        let mut index = Self { options, ..Default::default() };
+        index.lines_inserted(0, line_count);
+        index
+    }
+
+    /// The number of distinct words in the index.
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Marks the given line as needing to be scanned again.
+    pub fn line_changed(&mut self, line: usize) {
+        if let Some(l) = self.lines.get_mut(line)
+            && !l.dirty
+        {
+            l.dirty = true;
+            self.dirty_count += 1;
+        }
+    }
+
+    /// Inserts `count` new lines before the line `at`.
+    pub fn lines_inserted(&mut self, at: usize, count: usize) {
+        let at = at.min(self.lines.len());
+        let line = IndexedLine { words: Vec::new(), dirty: true };
+        self.lines.splice(at..at, std::iter::repeat_n(line, count));
+        self.dirty_count += count;
+    }
+
+    /// Removes the lines `at..at + count` and their words.
+    pub fn lines_removed(&mut self, at: usize, count: usize) {
+        let at = at.min(self.lines.len());
+        let end = (at + count).min(self.lines.len());
+        for line in self.lines.drain(at..end) {
+            if line.dirty {
+                self.dirty_count -= 1;
+            }
+            for word in &line.words {
+                release(&mut self.words, word);
+            }
+        }
+    }
+
+    /// Scans all lines that need it. `text` is called with a line index
+    /// and needs to append the contents of that line to the given `Vec`.
+    ///
+    /// Returns the number of lines that were scanned.
+    pub fn update(&mut self, mut text: impl FnMut(usize, &mut Vec<u8>)) -> usize {
+        if self.dirty_count == 0 {
+            return 0;
+        }
+
+        self.generation += 1;
+        let mut buf = Vec::new();
+        let mut scanned = 0;
+
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            if !line.dirty {
+                continue;
+            }
+
+            for word in line.words.drain(..) {
+                release(&mut self.words, &word);
+            }
+
+            buf.clear();
+            text(i, &mut buf);
+            for word in words(&buf, self.options.min_len) {
+                if let Some(entry) = self.words.get_mut(word) {
+                    entry.count += 1;
+                    entry.last_seen = self.generation;
+                } else if self.words.len() < self.options.max_words {
+                    self.words
+                        .insert(word.into(), WordEntry { count: 1, last_seen: self.generation });
+                } else {
+                    continue;
+                }
+                line.words.push(word.into());
+            }
+
+            line.dirty = false;
+            scanned += 1;
+        }
+
+        self.dirty_count = 0;
+        scanned
+    }
+
+    /// Returns up to `limit` words that start with `prefix`, but aren't equal to it.
+    /// The most frequent words come first, and among those the most recently edited ones.
+    pub fn complete(&self, prefix: &str, limit: usize) -> Vec<String> {
+        let mut matches: Vec<_> = self
+            .words
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(word, _)| word.starts_with(prefix))
+            .filter(|(word, _)| word.len() > prefix.len())
+            .collect();
+        // A stable sort keeps equally ranked words in alphabetical order.
+        matches.sort_by_key(|(_, e)| (Reverse(e.count), Reverse(e.last_seen)));
+        matches.into_iter().take(limit).map(|(word, _)| word.to_string()).collect()
+    }
+}
+
+/// Decrements the count of `word` and removes it once it reaches zero.
+fn release(words: &mut BTreeMap<Box<str>, WordEntry>, word: &str) {
+    if let Some(entry) = words.get_mut(word) {
+        entry.count -= 1;
+        if entry.count == 0 {
+            words.remove(word);
+        }
+    }
+}
+
+/// Returns the identifier-like words in `text` that are at least `min_len` characters long.
+/// Words can't start with a digit, so that numbers aren't indexed.
+fn words(text: &[u8], min_len: usize) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut it = Utf8Chars::new(text, 0);
+    let mut beg = 0;
+    let mut chars = 0;
+
+    loop {
+        let off = it.offset();
+        let ch = it.next();
+        if let Some(ch) = ch
+            && (ch.is_alphanumeric() || ch == '_')
+        {
+            if chars == 0 {
+                beg = off;
+            }
+            chars += 1;
+            continue;
+        }
+
+        // Only valid UTF-8 is alphanumeric, so this can't fail.
+        if chars > 0
+            && chars >= min_len
+            && let Ok(word) = str::from_utf8(&text[beg..off])
+            && !word.starts_with(|c: char| c.is_numeric())
+        {
+            words.push(word);
+        }
+        chars = 0;
+
+        if ch.is_none() {
+            return words;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A document as a list of lines, which keeps a [`WordIndex`] up to date.
+    struct Doc {
+        lines: Vec<&'static str>,
+        index: WordIndex,
+    }
+
+    impl Doc {
+        fn new(lines: &[&'static str]) -> Self {
+            let mut doc = Self {
+                lines: lines.to_vec(),
+                index: WordIndex::new(lines.len(), WordIndexOptions::default()),
+            };
+            doc.update();
+            doc
+        }
+
+        fn update(&mut self) -> usize {
+            let lines = &self.lines;
+            self.index.update(|i, buf| buf.extend_from_slice(lines[i].as_bytes()))
+        }
+
+        fn set_line(&mut self, line: usize, text: &'static str) {
+            self.lines[line] = text;
+            self.index.line_changed(line);
+            self.update();
+        }
+
+        fn remove_line(&mut self, line: usize) {
+            self.lines.remove(line);
+            self.index.lines_removed(line, 1);
+            self.update();
+        }
+    }
+
+    #[test]
+    fn test_words() {
+        assert_eq!(
+            words("let foo_bar = 42 + x1 * 0xff; // Größe über".as_bytes(), 3),
+            ["let", "foo_bar", "Größe", "über"]
+        );
+    }
+
+    #[test]
+    fn test_remove_last_occurrence() {
+        let mut doc = Doc::new(&["fn parse_header()", "parse_body();", "parse_header();"]);
+        assert_eq!(doc.index.complete("parse", 10), ["parse_header", "parse_body"]);
+
+        doc.remove_line(1);
+        assert_eq!(doc.index.complete("parse", 10), ["parse_header"]);
+
+        // One of two occurrences is gone, but the other one is still there.
+        doc.remove_line(0);
+        assert_eq!(doc.index.complete("parse", 10), ["parse_header"]);
+        doc.remove_line(0);
+        assert!(doc.index.complete("parse", 10).is_empty());
+        assert!(doc.index.is_empty());
+    }
+
+    #[test]
+    fn test_edit_line() {
+        let mut doc = Doc::new(&["let counter = 0;", "counter += 1;", "total"]);
+        assert_eq!(doc.index.len(), 3);
+
+        doc.set_line(1, "count_all += 1;");
+        assert_eq!(doc.index.complete("coun", 10), ["count_all", "counter"]);
+
+        doc.set_line(0, "let value = 0;");
+        assert_eq!(doc.index.complete("coun", 10), ["count_all"]);
+        assert_eq!(doc.index.complete("val", 10), ["value"]);
+
+        // Only the edited line was scanned again.
+        doc.lines[2] = "totally";
+        doc.index.line_changed(2);
+        assert_eq!(doc.update(), 1);
+        assert_eq!(doc.index.complete("tot", 10), ["totally"]);
+    }
+
+    #[test]
+    fn test_ranking() {
+        let mut doc = Doc::new(&["render render", "renderer", "rendering", "render_all"]);
+        // "render" is the most frequent. The others are equally frequent and sorted alphabetically.
+        assert_eq!(
+            doc.index.complete("ren", 10),
+            ["render", "render_all", "renderer", "rendering"]
+        );
+        assert_eq!(doc.index.complete("ren", 2), ["render", "render_all"]);
+        // The prefix itself isn't suggested.
+        assert_eq!(doc.index.complete("render", 10), ["render_all", "renderer", "rendering"]);
+
+        // Among equally frequent words, the most recently edited one wins.
+        doc.set_line(2, "rendering ");
+        assert_eq!(doc.index.complete("render", 10), ["rendering", "render_all", "renderer"]);
+    }
+
+    #[test]
+    fn test_limits() {
+        let options = WordIndexOptions { min_len: 4, max_words: 2 };
+        let mut index = WordIndex::new(1, options);
+        index.update(|_, buf| buf.extend_from_slice(b"abc alpha beta gamma alpha"));
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.complete("", 10), ["alpha", "beta"]);
+    }
+}